@@ -0,0 +1,13 @@
+-- Only a confirmed address is trusted to identify the user, ie. to claim shopping list invitations or receive mail
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+-- Accounts from before confirmations existed were never asked to confirm, they keep the access they had
+UPDATE users SET email_verified = TRUE WHERE NOT service_account;
+
+-- Addresses waiting for their owner to follow the emailed link, a user has at most one pending
+CREATE TABLE email_confirmations (
+    token_hash TEXT PRIMARY KEY,
+    user_id INT NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Purged users whose price reports the products service hasn't anonymized yet, retried until it succeeds
+CREATE TABLE purged_users (
+    user_id INT PRIMARY KEY,
+    purged_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,6 @@
+-- Finished OIDC logins waiting for the frontend to redeem them for tokens, only the hash of the code is stored
+CREATE TABLE oidc_login_codes (
+    code_hash TEXT PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
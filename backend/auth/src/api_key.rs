@@ -0,0 +1,128 @@
+/*
+  Name: api_key.rs
+
+  Description:
+  Long-lived API keys for service accounts, such as scrapers, accepted in place of a bearer access token. A key looks
+  like `gw_<prefix>_<secret>`, the prefix is stored in the clear to find the key and the whole key only as a SHA-256
+  hash. A key grants exactly the permissions it was created with, independent of any roles.
+
+  Side Effects:
+  - Other services verify keys by connecting to the auth database directly (products through `AUTH_DATABASE_URL`)
+    rather than through an endpoint, so `api_keys` and `users` are a schema shared with them. Their role needs
+    `SELECT` on both tables and `UPDATE` on `api_keys.last_used_at`, and migrations touching those tables must stay
+    compatible with the deployed services.
+*/
+
+use crate::errors::ServiceError;
+use crate::models::*;
+use crate::schema::*;
+use crate::{Claims, Pool};
+use actix_web::web;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+
+const KEY_PREFIX: &str = "gw_";
+
+/// How stale `last_used_at` may get before a request updates it, so busy keys don't write on every request
+const LAST_USED_RESOLUTION_MINUTES: i64 = 1;
+
+/// Whether the bearer token is an API key rather than a JWT
+pub fn is_api_key(token: &str) -> bool {
+  token.starts_with(KEY_PREFIX)
+}
+
+/// Generates a new key, returning its lookup prefix and the full key to hand out once
+pub fn generate() -> (String, String) {
+  let prefix = rand::random::<[u8; 6]>()
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect::<String>();
+  let key = format!("{}{}_{}", KEY_PREFIX, prefix, crate::oidc::random_token());
+  (prefix, key)
+}
+
+pub fn hash(key: &str) -> String {
+  format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn parse_prefix(key: &str) -> Option<&str> {
+  key
+    .strip_prefix(KEY_PREFIX)
+    .and_then(|rest| rest.split_once('_'))
+    .map(|(prefix, _)| prefix)
+}
+
+/// Checks API keys against the auth database, register it as app data to accept keys in [`crate::get_claims`]
+#[derive(Clone)]
+pub struct ApiKeyVerifier {
+  pool: Pool,
+}
+
+impl ApiKeyVerifier {
+  pub fn new(pool: Pool) -> Self {
+    ApiKeyVerifier { pool }
+  }
+
+  /// Resolves the key to claims for its service account, rejecting unknown, revoked and expired keys
+  pub async fn verify(&self, key: String) -> Result<Claims, ServiceError> {
+    let pool = self.pool.clone();
+    web::block(move || verify(&pool, &key)).await.map_err(|e| {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    })?
+  }
+}
+
+fn verify(pool: &Pool, key: &str) -> Result<Claims, ServiceError> {
+  let invalid = || ServiceError::BadRequest("Invalid API key".to_string());
+
+  let prefix = parse_prefix(key).ok_or_else(invalid)?;
+  let mut conn = pool.get().map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  let (api_key, user) = api_keys::table
+    .inner_join(users::table)
+    .filter(api_keys::prefix.eq(prefix))
+    .filter(api_keys::revoked_at.is_null())
+    .filter(users::deleted.eq(false))
+    .filter(users::service_account.eq(true))
+    .select((ApiKey::as_select(), User::as_select()))
+    .first::<(ApiKey, User)>(&mut conn)
+    .optional()?
+    .ok_or_else(invalid)?;
+
+  let now = Utc::now().naive_utc();
+  if api_key.key_hash != hash(key) || api_key.expires_at.is_some_and(|expires_at| expires_at <= now) {
+    return Err(invalid());
+  }
+
+  let stale = now - Duration::minutes(LAST_USED_RESOLUTION_MINUTES);
+  if api_key.last_used_at.is_none_or(|last_used_at| last_used_at < stale) {
+    diesel::update(api_keys::table.find(api_key.id))
+      .set(api_keys::last_used_at.eq(now))
+      .execute(&mut conn)?;
+  }
+
+  let iat = Utc::now().timestamp();
+  let exp = Utc::now()
+    .checked_add_signed(Duration::minutes(60))
+    .expect("valid timestamp")
+    .timestamp();
+
+  Ok(
+    Claims::builder()
+      .exp(exp as usize)
+      .iat(iat as usize)
+      .iss("auth".to_owned())
+      .nbf(iat as usize)
+      .sub(user.id)
+      .permissions(api_key.permissions)
+      .email(user.email)
+      .email_verified(user.email_verified)
+      .username(user.username)
+      .build(),
+  )
+}
@@ -0,0 +1,246 @@
+/*
+  Name: audit.rs
+
+  Description:
+  Append-only audit log of privileged actions, such as deletions, bulk imports and credential changes. Both services
+  keep an `audit_log` table of the same layout in their own database, created by running `MIGRATIONS` alongside their
+  own, and share the code to write and query it and the admin endpoint from here. Entries are written in the same
+  transaction as the change they describe, and the table rejects updates and deletes.
+*/
+
+use crate::errors::ServiceError;
+use crate::models::*;
+use crate::schema::*;
+use crate::scopes::*;
+use crate::{Authorized, Pool};
+use actix_web::web::ServiceConfig;
+use actix_web::{dev::Payload, get, web, FromRequest, HttpRequest, HttpResponse};
+use bon::builder;
+use common_rs::graphql::{Connection as GraphConnection, Node, PageInfo, PaginationParams};
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use serde::{Deserialize, Serialize};
+use std::future::{ready, Ready};
+use validator_rs::openapi_security;
+
+/// Creates the `audit_log` table, run by each service on its own database
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./audit_migrations/");
+
+pub const V1_PATH: &str = "/api/v1/audit_log";
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(web::scope(V1_PATH).service(get_audit_log));
+  }
+}
+
+/// Who a request came from, recorded alongside each entry
+#[derive(Debug, Clone, Default)]
+pub struct RequestMetadata {
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+}
+
+impl FromRequest for RequestMetadata {
+  type Error = std::convert::Infallible;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    ready(Ok(RequestMetadata {
+      // honours `Forwarded`/`X-Forwarded-For`, the services are only reachable through the gateway
+      ip: req.connection_info().realip_remote_addr().map(ToString::to_string),
+      user_agent: req
+        .headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string),
+    }))
+  }
+}
+
+/// Serializes the state of a resource for the `before`/`after` columns
+pub fn snapshot(value: &impl Serialize) -> Option<serde_json::Value> {
+  serde_json::to_value(value)
+    .inspect_err(|e| log::error!("Failed to snapshot audited resource: {}", e))
+    .ok()
+}
+
+/// Appends an entry, call it with the connection of the transaction making the change
+#[builder]
+pub fn record(
+  #[builder(start_fn)] conn: &mut PgConnection,
+  #[builder(start_fn)] metadata: &RequestMetadata,
+  actor_id: i32,
+  action: &str,
+  resource: &str,
+  #[builder(into)] resource_id: Option<String>,
+  before: Option<serde_json::Value>,
+  after: Option<serde_json::Value>,
+) -> Result<(), diesel::result::Error> {
+  diesel::insert_into(audit_log::table)
+    .values(NewAuditLogEntry {
+      actor_id,
+      action,
+      resource,
+      resource_id,
+      before,
+      after,
+      ip: metadata.ip.as_deref(),
+      user_agent: metadata.user_agent.as_deref(),
+    })
+    .execute(conn)?;
+  Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogFilters {
+  pub actor_id: Option<i32>,
+  pub action: Option<String>,
+  pub resource: Option<String>,
+  pub resource_id: Option<String>,
+  /// Only entries at or after this time
+  pub since: Option<chrono::DateTime<chrono::Utc>>,
+  /// Only entries before this time
+  pub until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub fn query(
+  conn: &mut PgConnection,
+  filters: AuditLogFilters,
+  params: PaginationParams<i64>,
+) -> Result<GraphConnection<AuditLogEntry>, ServiceError> {
+  let mut query = audit_log::table.into_boxed();
+  if let Some(actor_id) = filters.actor_id {
+    query = query.filter(audit_log::actor_id.eq(actor_id));
+  }
+  if let Some(action) = filters.action {
+    query = query.filter(audit_log::action.eq(action));
+  }
+  if let Some(resource) = filters.resource {
+    query = query.filter(audit_log::resource.eq(resource));
+  }
+  if let Some(resource_id) = filters.resource_id {
+    query = query.filter(audit_log::resource_id.eq(resource_id));
+  }
+  if let Some(since) = filters.since {
+    query = query.filter(audit_log::created_at.ge(since.naive_utc()));
+  }
+  if let Some(until) = filters.until {
+    query = query.filter(audit_log::created_at.lt(until.naive_utc()));
+  }
+
+  // Validate pagination parameters
+  if params.first.is_some() && params.last.is_some() {
+    return Err(ServiceError::BadRequest(
+      "`first` and `last` can't be combined".to_string(),
+    ));
+  }
+
+  if params.after.is_some() && params.before.is_some() {
+    return Err(ServiceError::BadRequest(
+      "`after` and `before` can't be combined".to_string(),
+    ));
+  }
+
+  let limit = params.first.or(params.last).unwrap_or(20).clamp(1, 100);
+  let is_forward = params.first.is_some();
+
+  match (is_forward, params.after, params.before) {
+    // Forward pagination
+    (true, after, None) => {
+      if let Some(after_id) = after {
+        query = query.filter(audit_log::id.gt(after_id));
+      }
+      query = query.order(audit_log::id.asc()).limit(limit as i64 + 1);
+    }
+    // Backward pagination
+    (false, None, before) => {
+      if let Some(before_id) = before {
+        query = query.filter(audit_log::id.lt(before_id));
+      }
+      query = query.order(audit_log::id.desc()).limit(limit as i64 + 1);
+    }
+    _ => {
+      return Err(ServiceError::BadRequest(
+        "`after` pages with `first` and `before` with `last`".to_string(),
+      ))
+    }
+  }
+
+  let mut entries = query
+    .load::<AuditLogEntry>(conn)
+    .inspect_err(|e| log::error!("Failed to query the audit log: {}", e))?;
+
+  // Check for additional pages
+  let has_additional = entries.len() as i64 > limit.into();
+  if has_additional {
+    entries.pop();
+  }
+
+  let cursor_fn = |entry: &AuditLogEntry| entry.id.to_string();
+  let start_cursor = entries.first().map(cursor_fn);
+  let end_cursor = entries.last().map(cursor_fn);
+
+  let edges = entries
+    .into_iter()
+    .map(|entry| Node {
+      cursor: cursor_fn(&entry),
+      node: entry,
+    })
+    .collect();
+
+  Ok(GraphConnection {
+    edges,
+    page_info: PageInfo {
+      has_next_page: if is_forward {
+        has_additional
+      } else {
+        params.before.is_some()
+      },
+      has_prev_page: if is_forward {
+        params.after.is_some()
+      } else {
+        has_additional
+      },
+      start_cursor,
+      end_cursor,
+    },
+  })
+}
+
+/// Entries of the audit log of this service
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = GraphConnection<AuditLogEntry>),
+        (status = BAD_REQUEST, description = "Conflicting pagination parameters"),
+    ),
+    params(
+        ("first" = Option<i32>, Query, description = "Number of items after cursor"),
+        ("after" = Option<i64>, Query, description = "Cursor for forward pagination"),
+        ("last" = Option<i32>, Query, description = "Number of items before cursor"),
+        ("before" = Option<i64>, Query, description = "Cursor for backward pagination"),
+        ("actor_id" = Option<i32>, Query, description = "Only actions performed by this user"),
+        ("action" = Option<String>, Query, description = "Only this action, ie. `delete`"),
+        ("resource" = Option<String>, Query, description = "Only actions on this kind of resource, ie. `user`"),
+        ("resource_id" = Option<String>, Query, description = "Only actions on this resource"),
+        ("since" = Option<String>, Query, description = "RFC 3339 timestamp, only entries at or after it"),
+        ("until" = Option<String>, Query, description = "RFC 3339 timestamp, only entries before it"),
+    ),
+)]
+#[get("")]
+pub async fn get_audit_log(
+  db: web::Data<Pool>,
+  _claims: Authorized<ReadAll>,
+  filters: web::Query<AuditLogFilters>,
+  pagination: web::Query<PaginationParams<i64>>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let entries = web::block(move || {
+    let mut conn = db.get().unwrap();
+    query(&mut conn, filters.into_inner(), pagination.into_inner())
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(entries))
+}
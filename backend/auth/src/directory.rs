@@ -0,0 +1,55 @@
+/*
+  Name: directory.rs
+
+  Description:
+  Lets other services look people up by username, e.g. products resolving a shopping list invitation addressed to a
+  username to the email it is matched by.
+
+  Side Effects:
+  - Like `api_key`, this reads `users` straight from the auth database, products connects through
+    `AUTH_DATABASE_URL`.
+*/
+
+use crate::errors::ServiceError;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web;
+use diesel::prelude::*;
+
+/// Finds users in the auth database, register it as app data where users are addressed by username
+#[derive(Clone)]
+pub struct UserDirectory {
+  pool: Pool,
+}
+
+impl UserDirectory {
+  pub fn new(pool: Pool) -> Self {
+    UserDirectory { pool }
+  }
+
+  /// Lowercased email of the person with `username`, `None` for service accounts, deleted users and addresses that
+  /// were never confirmed, as those can't identify anyone
+  pub async fn confirmed_email(&self, username: String) -> Result<Option<String>, ServiceError> {
+    let pool = self.pool.clone();
+    web::block(move || {
+      let mut conn = pool.get().map_err(|e| {
+        log::error!("Error: {}", e);
+        ServiceError::InternalServerError
+      })?;
+      let email = users::table
+        .filter(users::username.eq(username))
+        .filter(users::deleted.eq(false))
+        .filter(users::service_account.eq(false))
+        .filter(users::email_verified.eq(true))
+        .select(users::email)
+        .first::<String>(&mut conn)
+        .optional()?;
+      Ok(email.map(|email| email.trim().to_lowercase()))
+    })
+    .await
+    .map_err(|e| {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    })?
+  }
+}
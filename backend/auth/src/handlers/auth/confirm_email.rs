@@ -0,0 +1,42 @@
+/*
+  Name: confirm_email.rs
+
+  Description:
+  The endpoint handler for `/api/v1/auth/confirm-email`, where the link mailed by `auth::email_confirmation` lands
+*/
+
+use crate::models::*;
+use actix_web::{post, web, HttpResponse};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ConfirmEmailRequest {
+  /// The `token` query parameter of the emailed link
+  pub token: String,
+}
+
+/// Makes the confirmed address the user's email. Tokens issued before still carry the old email, refresh to update
+/// them.
+#[utoipa::path(
+  context_path = super::V1_PATH,
+  request_body = ConfirmEmailRequest,
+  responses(
+    (status = OK, body = UserResponse),
+    (status = BAD_REQUEST, description = "Invalid or expired confirmation token"),
+    (status = CONFLICT, description = "Email is already in use"),
+  ),
+)]
+#[post("/confirm-email")]
+pub(crate) async fn confirm_email(
+  db: web::Data<crate::Pool>,
+  body: web::Json<ConfirmEmailRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user = web::block(move || {
+    let mut conn = db.get().unwrap();
+    auth::email_confirmation::confirm(&mut conn, &body.token)
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(UserResponse::from(user)))
+}
@@ -21,12 +21,23 @@ use crate::models::*;
 use crate::schema::*;
 use actix_web::http::header;
 use actix_web::options;
-use actix_web::{post, web, HttpResponse};
-use bcrypt::verify;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use auth::password::{hash_password, verify_password};
 use diesel::prelude::*;
 use serde::Deserialize;
 use utoipa::ToSchema;
 
+/// Unknown and soft-deleted users get the same response as a wrong password, so accounts can't be enumerated
+fn failed_to_find_user(err: diesel::result::Error) -> ServiceError {
+  match err {
+    diesel::result::Error::NotFound => ServiceError::Unauthorized,
+    err => {
+      log::error!("Failed to find user: {}", err);
+      ServiceError::InternalServerError
+    }
+  }
+}
+
 #[utoipa::path(
   context_path = super::V1_PATH,
   responses(
@@ -34,33 +45,32 @@ use utoipa::ToSchema;
       ("authorization" = String),
       ("x-refresh-token" = String),
     )),
+    (status = UNAUTHORIZED, description = "Invalid credentials"),
   ),
 )]
 #[post("/login")]
 pub async fn login_route(
+  req: HttpRequest,
   db: web::Data<crate::Pool>,
   credentials: web::Json<LoginRequest>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let users_maybe: Result<(User, Vec<PermissionName>, web::Json<LoginRequest>), ServiceError> = web::block(move || {
+  let user_agent = super::user_agent(&req);
+  let users_maybe: Result<(User, Vec<PermissionName>, Session), ServiceError> = web::block(move || {
     let mut conn = db.get().unwrap();
 
     let user = match (&credentials.email, &credentials.username) {
       (Some(email), _) => users::table
         .filter(users::email.eq(&email))
+        .filter(users::deleted.eq(false))
+        .filter(users::service_account.eq(false))
         .first::<User>(&mut conn)
-        .map_err(|err| {
-          log::error!("Failed to find user: {}", err);
-          // todo @codyduong, do we want to expose a proper response like not found?
-          ServiceError::InternalServerError
-        })?,
+        .map_err(failed_to_find_user)?,
       (_, Some(username)) => users::table
         .filter(users::username.eq(&username))
+        .filter(users::deleted.eq(false))
+        .filter(users::service_account.eq(false))
         .first::<User>(&mut conn)
-        .map_err(|err| {
-          log::error!("Failed to find user: {}", err);
-          // todo @codyduong, do we want to expose a proper response like not found?
-          ServiceError::InternalServerError
-        })?,
+        .map_err(failed_to_find_user)?,
       (None, None) => {
         log::error!("Missing required field: `email` or `password`");
         Err(ServiceError::BadRequest(
@@ -69,41 +79,55 @@ pub async fn login_route(
       }
     };
 
+    let verification = verify_password(&credentials.password, &user.password_hash)?;
+    if !verification.valid {
+      return Err(ServiceError::Unauthorized);
+    }
+    if verification.needs_rehash {
+      // Upgrade the stored hash to the configured algorithm now that we have the plaintext
+      diesel::update(users::table.find(user.id))
+        .set(users::password_hash.eq(hash_password(&credentials.password)?))
+        .execute(&mut conn)
+        .map_err(|err| {
+          log::error!("Failed to rehash password: {}", err);
+          ServiceError::InternalServerError
+        })?;
+    }
+
     let perms = auth::get_permissions(&mut conn, user.id).map_err(|err| {
       log::error!("Failed to get permissions: {}", err);
       ServiceError::InternalServerError
     })?;
 
-    Ok((user, perms, credentials))
+    let session = auth::create_session(&mut conn, user.id, user_agent.as_deref()).map_err(|err| {
+      log::error!("Failed to create session: {}", err);
+      ServiceError::InternalServerError
+    })?;
+
+    Ok((user, perms, session))
   })
   .await?;
 
-  let (user, perms, credentials) = users_maybe?;
+  let (user, perms, session) = users_maybe?;
 
-  match verify(&credentials.password, &user.password_hash) {
-    Ok(_) => {
-      let (access_token, refresh_token) = auth::create_jwt()
-        .user_id(user.id)
-        .permissions(perms)
-        .email(user.email)
-        .username(user.username)
-        .call()
-        .map_err(|_| ServiceError::InternalServerError)?;
+  let (access_token, refresh_token) = auth::create_jwt()
+    .user_id(user.id)
+    .permissions(perms)
+    .email(user.email)
+    .email_verified(user.email_verified)
+    .username(user.username)
+    .session_id(session.id)
+    .call()
+    .map_err(|_| ServiceError::InternalServerError)?;
 
-      let mut res = HttpResponse::Ok();
+  let mut res = HttpResponse::Ok();
 
-      res.append_header((header::AUTHORIZATION, format!("Bearer {}", access_token)));
-      if let Some(refresh_token) = refresh_token {
-        res.append_header(("x-refresh-token", refresh_token));
-      }
-
-      Ok(res.finish())
-    }
-    Err(e) => {
-      log::error!("Failed to login: {}", e);
-      Err(ServiceError::InternalServerError.into())
-    }
+  res.append_header((header::AUTHORIZATION, format!("Bearer {}", access_token)));
+  if let Some(refresh_token) = refresh_token {
+    res.append_header(("x-refresh-token", refresh_token));
   }
+
+  Ok(res.finish())
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -22,10 +22,16 @@
   - 2025-04-13 - @codyduong - refactor
 */
 
+use actix_web::http::header;
 use actix_web::web::{self, ServiceConfig};
+use actix_web::HttpRequest;
 
+mod confirm_email;
+pub use confirm_email::*;
 mod login;
 pub use login::*;
+mod oidc;
+pub use oidc::*;
 mod refresh;
 pub use refresh::*;
 mod register;
@@ -39,7 +45,21 @@ pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
       web::scope(V1_PATH)
         .service(login_route)
         .service(register_route)
-        .service(refresh_route),
+        .service(refresh_route)
+        .service(get_oidc_providers)
+        .service(oidc_authorize)
+        .service(oidc_callback)
+        .service(oidc_token)
+        .service(confirm_email),
     );
   }
 }
+
+/// The client's `User-Agent`, recorded on new sessions so users can tell them apart
+pub(crate) fn user_agent(req: &HttpRequest) -> Option<String> {
+  req
+    .headers()
+    .get(header::USER_AGENT)
+    .and_then(|value| value.to_str().ok())
+    .map(str::to_owned)
+}
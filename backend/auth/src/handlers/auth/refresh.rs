@@ -17,13 +17,41 @@ use actix_web::{post, web, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use auth::models::User;
 use auth::Pool;
-use chrono::Utc;
+use chrono::{Duration, Utc};
+use diesel::ExpressionMethods;
 use diesel::QueryDsl;
 use diesel::RunQueryDsl;
 
 fn db_get_user_by_id(pool: web::Data<Pool>, user_id: i32) -> Result<User, diesel::result::Error> {
   let mut conn = pool.get().unwrap();
-  users::table.find(user_id).get_result::<User>(&mut conn)
+  users::table
+    .find(user_id)
+    .filter(users::deleted.eq(false))
+    .filter(users::service_account.eq(false))
+    .get_result::<User>(&mut conn)
+}
+
+/// Extends an active session to match the refresh token about to be issued, returns false if it was revoked or expired
+fn db_touch_session(pool: web::Data<Pool>, user_id: i32, session_id: i32) -> Result<bool, diesel::result::Error> {
+  let mut conn = pool.get().unwrap();
+  let expires_at = Utc::now()
+    .checked_add_signed(Duration::days(auth::REFRESH_TOKEN_LIFETIME_DAYS))
+    .expect("valid timestamp")
+    .naive_utc();
+
+  diesel::update(
+    sessions::table
+      .find(session_id)
+      .filter(sessions::user_id.eq(user_id))
+      .filter(sessions::revoked_at.is_null())
+      .filter(sessions::expires_at.gt(diesel::dsl::now)),
+  )
+  .set((
+    sessions::last_used_at.eq(diesel::dsl::now),
+    sessions::expires_at.eq(expires_at),
+  ))
+  .execute(&mut conn)
+  .map(|updated| updated == 1)
 }
 
 // YOU MUST post to this endpoint with a refresh token as Bearer. Not with an access token
@@ -61,6 +89,21 @@ pub(crate) async fn refresh_route(
   // any user can grant themselves a refresh token, -todo restrict based on create:token permission
   // - @codyduong
   let user_id = claims.sub;
+  let session_id = claims.sid;
+
+  // tokens issued before sessions existed carry no `sid`, those simply run out at `exp`
+  if let Some(session_id) = session_id {
+    let db = db.clone();
+    let refreshed = web::block(move || db_touch_session(db, user_id, session_id))
+      .await?
+      .map_err(|e| {
+        log::error!("Server error: {:?}", e);
+        ServiceError::InternalServerError
+      })?;
+    if !refreshed {
+      return Err(ServiceError::Forbidden.into());
+    }
+  }
 
   // rather than trust the claim to get permissions, instead regenerate permissions...
   // TODO, it should restrict to the same request or less, otherwise if a user attempts more permissions
@@ -80,16 +123,24 @@ pub(crate) async fn refresh_route(
     .await??
   };
 
-  let user = web::block(move || db_get_user_by_id(db, user_id)).await?.map_err(|e| {
-    log::debug!("Server error: {:?}", e);
-    ServiceError::InternalServerError
-  })?;
+  let user = web::block(move || db_get_user_by_id(db, user_id))
+    .await?
+    .map_err(|e| match e {
+      // soft-deleted users can't refresh, even with a token issued before the deletion
+      diesel::result::Error::NotFound => ServiceError::Forbidden,
+      e => {
+        log::debug!("Server error: {:?}", e);
+        ServiceError::InternalServerError
+      }
+    })?;
 
   let jwt = auth::create_jwt()
     .email(user.email)
+    .email_verified(user.email_verified)
     .username(user.username)
     .user_id(user_id)
     .permissions(perms)
+    .maybe_session_id(session_id)
     .call()
     .map_err(|e| {
       log::debug!("Server error: {:?}", e);
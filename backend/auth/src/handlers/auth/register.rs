@@ -19,8 +19,9 @@ use crate::errors::ServiceError;
 use crate::models::*;
 use crate::schema::*;
 use actix_web::http::header;
-use actix_web::{post, web, HttpResponse};
-use bcrypt::hash;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use auth::email_confirmation::Mailer;
+use auth::password::{hash_password, PasswordPolicy};
 use diesel::{insert_into, prelude::*};
 use serde::Deserialize;
 use utoipa::ToSchema;
@@ -32,11 +33,14 @@ use utoipa::ToSchema;
       ("authorization" = String),
       ("x-refresh-token" = String),
     )),
+    (status = BAD_REQUEST, description = "Password does not meet the password policy"),
   ),
 )]
 #[post("/register")]
 pub(crate) async fn register_route(
+  req: HttpRequest,
   db: web::Data<crate::Pool>,
+  mailer: web::Data<Mailer>,
   new_user: web::Json<RegisterRequest>,
 ) -> Result<HttpResponse, actix_web::Error> {
   // guard against registering reserved names
@@ -49,11 +53,10 @@ pub(crate) async fn register_route(
   //   return Err(ServiceError::InternalServerError)?;
   // }
 
-  let hashed = hash(&new_user.password, 10).map_err(|err| {
-    log::error!("Failed to hash: {}", err);
-    ServiceError::InternalServerError
-  })?;
+  PasswordPolicy::from_env().validate(&new_user.password)?;
+  let hashed = hash_password(&new_user.password)?;
   let new_user = new_user.into_inner();
+  let user_agent = super::user_agent(&req);
 
   let res = {
     let new_user = new_user.clone();
@@ -74,23 +77,30 @@ pub(crate) async fn register_route(
           .first::<User>(conn)?;
 
         let perms = auth::get_permissions(conn, user.id)?;
+        let session = auth::create_session(conn, user.id, user_agent.as_deref())?;
+        // the account works right away, but the address isn't trusted until its owner follows the mailed link
+        let confirmation_token = auth::email_confirmation::issue(conn, user.id, &user.email)?;
 
         let res = auth::create_jwt()
           .user_id(user.id)
           .permissions(perms)
           .email(user.email)
+          .email_verified(user.email_verified)
           .username(user.username)
+          .session_id(session.id)
           .call()
           .map_err(|_| diesel::result::Error::RollbackTransaction)?;
 
-        diesel::result::QueryResult::Ok(res)
+        diesel::result::QueryResult::Ok((res, confirmation_token))
       })
     })
     .await?
   };
 
   match res {
-    Ok((access_token, refresh_token)) => {
+    Ok(((access_token, refresh_token), confirmation_token)) => {
+      mailer.send_confirmation(&new_user.email, &confirmation_token).await;
+
       let mut res = HttpResponse::Ok();
 
       res.append_header((header::AUTHORIZATION, format!("Bearer {}", access_token)));
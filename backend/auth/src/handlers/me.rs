@@ -0,0 +1,524 @@
+/*
+  Name: me.rs
+
+  Description:
+  Self-service endpoints under `/api/v1/me`, letting any authenticated user manage their own account without the
+  `read:user`/`update:user` admin permissions required by `handlers::users`. Service accounts are rejected with
+  `403 Forbidden`, they are managed through `handlers::service_accounts`.
+*/
+
+use crate::errors::ServiceError;
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::http::header;
+use actix_web::web::ServiceConfig;
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
+use auth::email_confirmation::Mailer;
+use auth::password::{hash_password, verify_password, PasswordPolicy, NO_PASSWORD};
+use auth::Authorized;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/me";
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_me)
+        .service(update_me)
+        .service(delete_me)
+        .service(change_password)
+        .service(get_sessions)
+        .service(delete_session)
+        .service(get_identities)
+        .service(link_identity)
+        .service(unlink_identity),
+    );
+  }
+}
+
+/// Service accounts, and the API keys that authenticate as them, can't manage an account through `/me`
+fn get_current_user(conn: &mut PgConnection, user_id: i32) -> Result<User, ServiceError> {
+  let user = users::table
+    .find(user_id)
+    .filter(users::deleted.eq(false))
+    .first::<User>(conn)
+    .optional()?
+    .ok_or(ServiceError::NotFound(Some("User not found".to_string())))?;
+  if user.service_account {
+    return Err(ServiceError::Forbidden);
+  }
+  Ok(user)
+}
+
+/// Sensitive changes re-verify the caller with their current password, a leaked access token alone is not enough.
+/// Accounts created through an identity provider have no password until they set one, their session is all there is.
+fn verify_current_password(user: &User, password: Option<&str>) -> Result<(), ServiceError> {
+  if user.password_hash == NO_PASSWORD {
+    return Ok(());
+  }
+  if verify_password(password.unwrap_or_default(), &user.password_hash)?.valid {
+    Ok(())
+  } else {
+    Err(ServiceError::Unauthorized)
+  }
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = UserResponse),
+        (status = NOT_FOUND, description = "User not found")
+    ),
+)]
+#[get("")]
+pub(crate) async fn get_me(db: web::Data<Pool>, claims: Authorized<()>) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+
+  let (user, pending_email) = web::block(move || {
+    let mut conn = db.get().unwrap();
+    let user = get_current_user(&mut conn, user_id)?;
+    let pending_email = auth::email_confirmation::pending_email(&mut conn, user_id)?;
+    Ok::<_, ServiceError>((user, pending_email))
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(UserResponse {
+    pending_email,
+    ..UserResponse::from(user)
+  }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateMeRequest {
+  /// Required unless the account was created through an identity provider and has no password yet
+  pub current_password: Option<String>,
+  pub username: Option<String>,
+  /// The new address is mailed a confirmation link, `email` only changes once the link is followed
+  pub email: Option<String>,
+}
+
+/// Rejects blank usernames and anything that isn't an email address
+fn validate_update(body: &UpdateMeRequest) -> Result<(), ServiceError> {
+  if body
+    .username
+    .as_ref()
+    .is_some_and(|username| username.trim().is_empty())
+  {
+    return Err(ServiceError::BadRequest("`username` can't be empty".to_string()));
+  }
+  if body
+    .email
+    .as_ref()
+    .is_some_and(|email| email.parse::<lettre::Address>().is_err())
+  {
+    return Err(ServiceError::BadRequest(
+      "`email` isn't a valid email address".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    request_body = UpdateMeRequest,
+    responses(
+        (status = OK, body = UserResponse, headers(
+          ("authorization" = String),
+          ("x-refresh-token" = String),
+        )),
+        (status = BAD_REQUEST, description = "Empty username or invalid email"),
+        (status = UNAUTHORIZED, description = "Current password is incorrect"),
+        (status = CONFLICT, description = "Username or email is already in use")
+    ),
+)]
+#[patch("")]
+pub(crate) async fn update_me(
+  db: web::Data<Pool>,
+  mailer: web::Data<Mailer>,
+  claims: Authorized<()>,
+  body: web::Json<UpdateMeRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let session_id = claims.sid;
+  validate_update(&body)?;
+
+  let (user, pending_email, confirmation, perms) = web::block(move || {
+    let mut conn = db.get().unwrap();
+    let user = get_current_user(&mut conn, user_id)?;
+    verify_current_password(&user, body.current_password.as_deref())?;
+
+    conn.transaction(|conn| {
+      let user = match &body.username {
+        Some(username) => {
+          let taken = users::table
+            .filter(users::username.eq(username))
+            .filter(users::id.ne(user_id))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+          if taken {
+            return Err(ServiceError::Conflict("Username is already in use".to_string()));
+          }
+          diesel::update(users::table.find(user_id))
+            .set(users::username.eq(username))
+            .get_result::<User>(conn)
+            .map_err(|e| match e {
+              diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => {
+                ServiceError::Conflict("Username is already in use".to_string())
+              }
+              e => e.into(),
+            })?
+        }
+        None => user,
+      };
+
+      // the current address stays in place until the new one is confirmed, so a stolen session can't take it over
+      let confirmation = match &body.email {
+        Some(email) if *email == user.email && user.email_verified => {
+          diesel::delete(email_confirmations::table.filter(email_confirmations::user_id.eq(user_id))).execute(conn)?;
+          None
+        }
+        Some(email) => {
+          let taken = users::table
+            .filter(users::email.eq(email))
+            .filter(users::id.ne(user_id))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+          if taken {
+            return Err(ServiceError::Conflict("Email is already in use".to_string()));
+          }
+          let token = auth::email_confirmation::issue(conn, user_id, email)?;
+          Some((email.clone(), token))
+        }
+        None => None,
+      };
+
+      let pending_email = auth::email_confirmation::pending_email(conn, user_id)?;
+      let perms = auth::get_permissions(conn, user.id)?;
+      Ok((user, pending_email, confirmation, perms))
+    })
+  })
+  .await??;
+
+  if let Some((email, token)) = confirmation {
+    mailer.send_confirmation(&email, &token).await;
+  }
+
+  // the access token embeds the username, reissue it so the caller isn't left with stale claims
+  let (access_token, refresh_token) = auth::create_jwt()
+    .user_id(user.id)
+    .permissions(perms)
+    .email(user.email.clone())
+    .email_verified(user.email_verified)
+    .username(user.username.clone())
+    .maybe_session_id(session_id)
+    .call()
+    .map_err(|_| ServiceError::InternalServerError)?;
+
+  let mut res = HttpResponse::Ok();
+  res.append_header((header::AUTHORIZATION, format!("Bearer {}", access_token)));
+  if let Some(refresh_token) = refresh_token {
+    res.append_header(("x-refresh-token", refresh_token));
+  }
+
+  Ok(res.json(UserResponse {
+    pending_email,
+    ..UserResponse::from(user)
+  }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+  /// Required unless the account was created through an identity provider and has no password yet
+  pub current_password: Option<String>,
+  pub new_password: String,
+}
+
+/// Changing the password signs out every other session
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = NO_CONTENT),
+        (status = BAD_REQUEST, description = "New password does not meet the password policy"),
+        (status = UNAUTHORIZED, description = "Current password is incorrect")
+    ),
+)]
+#[put("/password")]
+pub(crate) async fn change_password(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+  body: web::Json<ChangePasswordRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let session_id = claims.sid;
+
+  PasswordPolicy::from_env().validate(&body.new_password)?;
+
+  web::block(move || {
+    let mut conn = db.get().unwrap();
+    let user = get_current_user(&mut conn, user_id)?;
+    verify_current_password(&user, body.current_password.as_deref())?;
+    let password_hash = hash_password(&body.new_password)?;
+
+    conn.transaction(|conn| {
+      diesel::update(users::table.find(user_id))
+        .set(users::password_hash.eq(password_hash))
+        .execute(conn)?;
+      auth::revoke_sessions(conn, user_id, session_id)?;
+      Ok::<(), ServiceError>(())
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeleteMeRequest {
+  /// Required unless the account was created through an identity provider and has no password yet
+  pub current_password: Option<String>,
+}
+
+/// Soft deletes the account and signs out every session
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    request_body = DeleteMeRequest,
+    responses(
+        (status = NO_CONTENT),
+        (status = UNAUTHORIZED, description = "Current password is incorrect")
+    ),
+)]
+#[delete("")]
+pub(crate) async fn delete_me(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+  body: web::Json<DeleteMeRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+
+  web::block(move || {
+    let mut conn = db.get().unwrap();
+    let user = get_current_user(&mut conn, user_id)?;
+    verify_current_password(&user, body.current_password.as_deref())?;
+
+    conn.transaction(|conn| {
+      diesel::update(users::table.find(user_id))
+        .set((users::deleted.eq(true), users::deleted_at.eq(diesel::dsl::now)))
+        .execute(conn)?;
+      auth::revoke_sessions(conn, user_id, None)?;
+      Ok::<(), ServiceError>(())
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = Vec<SessionResponse>),
+    ),
+)]
+#[get("/sessions")]
+pub(crate) async fn get_sessions(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let session_id = claims.sid;
+
+  let sessions = web::block(move || {
+    let mut conn = db.get().unwrap();
+    get_current_user(&mut conn, user_id)?;
+    sessions::table
+      .filter(sessions::user_id.eq(user_id))
+      .filter(sessions::revoked_at.is_null())
+      .filter(sessions::expires_at.gt(diesel::dsl::now))
+      .order(sessions::last_used_at.desc())
+      .load::<Session>(&mut conn)
+      .map_err(ServiceError::from)
+  })
+  .await??;
+
+  let sessions = sessions
+    .into_iter()
+    .map(|session| SessionResponse {
+      current: Some(session.id) == session_id,
+      id: session.id,
+      created_at: session.created_at,
+      last_used_at: session.last_used_at,
+      expires_at: session.expires_at,
+      user_agent: session.user_agent,
+    })
+    .collect::<Vec<_>>();
+
+  Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Signs out a session, its refresh token stops working though already issued access tokens remain valid until `exp`
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = NO_CONTENT),
+        (status = NOT_FOUND, description = "Session not found")
+    ),
+)]
+#[delete("/sessions/{id}")]
+pub(crate) async fn delete_session(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let session_id = id.into_inner();
+
+  let revoked = web::block(move || {
+    let mut conn = db.get().unwrap();
+    get_current_user(&mut conn, user_id)?;
+    diesel::update(
+      sessions::table
+        .find(session_id)
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::revoked_at.is_null()),
+    )
+    .set(sessions::revoked_at.eq(diesel::dsl::now))
+    .execute(&mut conn)
+    .map_err(ServiceError::from)
+  })
+  .await??;
+
+  if revoked == 0 {
+    return Err(ServiceError::NotFound(Some("Session not found".to_string())).into());
+  }
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = Vec<UserIdentityResponse>),
+    ),
+)]
+#[get("/identities")]
+pub(crate) async fn get_identities(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+
+  let identities = web::block(move || {
+    let mut conn = db.get().unwrap();
+    get_current_user(&mut conn, user_id)?;
+    user_identities::table
+      .filter(user_identities::user_id.eq(user_id))
+      .order(user_identities::created_at.asc())
+      .load::<UserIdentity>(&mut conn)
+      .map_err(ServiceError::from)
+  })
+  .await??;
+
+  Ok(
+    HttpResponse::Ok().json(
+      identities
+        .into_iter()
+        .map(UserIdentityResponse::from)
+        .collect::<Vec<_>>(),
+    ),
+  )
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LinkIdentityResponse {
+  /// Send the user here, the provider's callback links the identity to this account. Make the request with
+  /// credentials so the browser keeps the login's state cookie, the callback is rejected without it.
+  pub authorization_url: String,
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = LinkIdentityResponse),
+        (status = NOT_FOUND, description = "Unknown identity provider")
+    ),
+    params(
+        ("provider" = String, Path, description = "Provider name, as listed by `GET /api/v1/auth/oidc`"),
+    ),
+)]
+#[post("/identities/{provider}")]
+pub(crate) async fn link_identity(
+  provider: web::Path<String>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let provider = super::auth::get_provider(&provider)?;
+  let pool = db.clone();
+  web::block(move || get_current_user(&mut pool.get().unwrap(), user_id)).await??;
+  let (url, state_cookie) = super::auth::start_login(db, &provider, Some(user_id)).await?;
+
+  Ok(HttpResponse::Ok().cookie(state_cookie).json(LinkIdentityResponse {
+    authorization_url: url.to_string(),
+  }))
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = NO_CONTENT),
+        (status = NOT_FOUND, description = "Identity not found"),
+        (status = CONFLICT, description = "The identity is the only way to log in to this account")
+    ),
+)]
+#[delete("/identities/{id}")]
+pub(crate) async fn unlink_identity(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let identity_id = id.into_inner();
+
+  web::block(move || {
+    let mut conn = db.get().unwrap();
+    let user = get_current_user(&mut conn, user_id)?;
+
+    conn.transaction(|conn| {
+      let identities = user_identities::table
+        .filter(user_identities::user_id.eq(user_id))
+        .select(user_identities::id)
+        .load::<i32>(conn)?;
+      if !identities.contains(&identity_id) {
+        return Err(ServiceError::NotFound(Some("Identity not found".to_string())));
+      }
+      if user.password_hash == NO_PASSWORD && identities.len() == 1 {
+        return Err(ServiceError::Conflict(
+          "Set a password before unlinking the only way to log in".to_string(),
+        ));
+      }
+
+      diesel::delete(user_identities::table.find(identity_id)).execute(conn)?;
+      Ok(())
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::NoContent().finish())
+}
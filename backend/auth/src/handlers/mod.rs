@@ -14,4 +14,6 @@
 */
 
 pub mod auth;
+pub mod me;
+pub mod service_accounts;
 pub mod users;
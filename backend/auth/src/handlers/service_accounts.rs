@@ -0,0 +1,323 @@
+/*
+  Name: service_accounts.rs
+
+  Description:
+  Admin endpoints under `/api/v1/service_accounts`, managing non-interactive accounts such as scrapers and the API
+  keys they authenticate with. Service accounts have no password and can't log in, a key grants only the permissions
+  it was created with.
+*/
+
+use crate::errors::ServiceError;
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{delete, get, post, web, HttpResponse};
+use auth::audit::{snapshot, RequestMetadata};
+use auth::password::NO_PASSWORD;
+use auth::scopes::*;
+use auth::Authorized;
+use diesel::prelude::*;
+use serde::Deserialize;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/service_accounts";
+
+/// Email domain of service accounts, reserved by RFC 2606 so it can never receive mail or clash with a real user
+const SERVICE_ACCOUNT_EMAIL_DOMAIN: &str = "service-accounts.invalid";
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_service_accounts)
+        .service(create_service_account)
+        .service(get_api_keys)
+        .service(create_api_key)
+        .service(revoke_api_key),
+    );
+  }
+}
+
+fn get_service_account(conn: &mut PgConnection, user_id: i32) -> Result<User, ServiceError> {
+  users::table
+    .find(user_id)
+    .filter(users::service_account.eq(true))
+    .filter(users::deleted.eq(false))
+    .first::<User>(conn)
+    .optional()?
+    .ok_or(ServiceError::NotFound(Some("Service account not found".to_string())))
+}
+
+/// Whether the caller may hand out `permission`, either directly or through the matching `<action>:all`
+fn can_grant(granted: &[PermissionName], permission: &PermissionName) -> bool {
+  let action = permission.as_str().split(':').next().unwrap_or_default();
+  let all = PermissionName::from_str(&format!("{}:all", action)).ok();
+
+  granted
+    .iter()
+    .any(|granted| granted == permission || Some(granted) == all.as_ref())
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = Vec<UserResponse>),
+    ),
+)]
+#[get("")]
+pub(crate) async fn get_service_accounts(
+  db: web::Data<Pool>,
+  _claims: Authorized<Or<ReadAll, ReadUser>>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let accounts = web::block(move || {
+    let mut conn = db.get().unwrap();
+    users::table
+      .filter(users::service_account.eq(true))
+      .filter(users::deleted.eq(false))
+      .order(users::id.asc())
+      .load::<User>(&mut conn)
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(accounts.into_iter().map(UserResponse::from).collect::<Vec<_>>()))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateServiceAccountRequest {
+  pub username: String,
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    request_body(content = CreateServiceAccountRequest, content_type = "application/json"),
+    responses(
+        (status = CREATED, body = UserResponse),
+        (status = CONFLICT, description = "Username is already taken"),
+    ),
+)]
+#[post("")]
+pub(crate) async fn create_service_account(
+  db: web::Data<Pool>,
+  claims: Authorized<Or<CreateAll, CreateUser>>,
+  metadata: RequestMetadata,
+  body: web::Json<CreateServiceAccountRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let username = body.into_inner().username;
+  if username.trim().is_empty() {
+    return Err(ServiceError::BadRequest("Username must not be empty".to_string()).into());
+  }
+
+  let user = web::block(move || {
+    let mut conn = db.get().unwrap();
+    let email = format!("{}@{}", username, SERVICE_ACCOUNT_EMAIL_DOMAIN);
+    conn.transaction(|conn| {
+      let user = diesel::insert_into(users::table)
+        .values((
+          NewUser {
+            email: &email,
+            username: Some(&username),
+            password_hash: NO_PASSWORD,
+          },
+          users::service_account.eq(true),
+        ))
+        .get_result::<User>(conn)
+        .map_err(|e| match e {
+          diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => {
+            ServiceError::Conflict("Username is already taken".to_string())
+          }
+          e => e.into(),
+        })?;
+
+      let user = UserResponse::from(user);
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("create")
+        .resource("user")
+        .resource_id(user.id.to_string())
+        .maybe_after(snapshot(&user))
+        .call()?;
+      Ok::<_, ServiceError>(user)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Created().json(user))
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = Vec<ApiKeyResponse>),
+        (status = NOT_FOUND, description = "Service account not found"),
+    ),
+    params(
+        ("id" = i32, Path, description = "Service account id"),
+    ),
+)]
+#[get("/{id}/keys")]
+pub(crate) async fn get_api_keys(
+  user_id: web::Path<i32>,
+  db: web::Data<Pool>,
+  _claims: Authorized<Or<ReadAll, ReadUser>>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let keys = web::block(move || {
+    let mut conn = db.get().unwrap();
+    let account = get_service_account(&mut conn, *user_id)?;
+    ApiKey::belonging_to(&account)
+      .order(api_keys::id.asc())
+      .load::<ApiKey>(&mut conn)
+      .map_err(ServiceError::from)
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(keys.into_iter().map(ApiKeyResponse::from).collect::<Vec<_>>()))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+  pub name: String,
+  /// Must be a subset of the caller's own permissions
+  pub permissions: Vec<PermissionName>,
+  /// Never expires if omitted
+  pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Issues a new key, the response is the only time the full key is revealed
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    request_body(content = CreateApiKeyRequest, content_type = "application/json"),
+    responses(
+        (status = CREATED, body = CreatedApiKeyResponse),
+        (status = BAD_REQUEST, description = "Requested permissions the caller doesn't have"),
+        (status = NOT_FOUND, description = "Service account not found"),
+    ),
+    params(
+        ("id" = i32, Path, description = "Service account id"),
+    ),
+)]
+#[post("/{id}/keys")]
+pub(crate) async fn create_api_key(
+  user_id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<CreateAll, CreateUser>>,
+  metadata: RequestMetadata,
+  body: web::Json<CreateApiKeyRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let body = body.into_inner();
+  let actor_id = claims.sub;
+
+  // a key must never be able to do more than the admin who issued it
+  let ungrantable = body
+    .permissions
+    .iter()
+    .filter(|permission| !can_grant(&claims.permissions, permission))
+    .map(|permission| permission.as_str())
+    .collect::<Vec<_>>();
+  if !ungrantable.is_empty() {
+    return Err(ServiceError::BadRequest(format!("Can't grant permissions: {}", ungrantable.join(", "))).into());
+  }
+  if body
+    .expires_at
+    .is_some_and(|expires_at| expires_at <= chrono::Utc::now())
+  {
+    return Err(ServiceError::BadRequest("`expires_at` must be in the future".to_string()).into());
+  }
+
+  let (api_key, key) = web::block(move || {
+    let mut conn = db.get().unwrap();
+    let account = get_service_account(&mut conn, *user_id)?;
+
+    let (prefix, key) = auth::api_key::generate();
+    let api_key = conn.transaction(|conn| {
+      let api_key = diesel::insert_into(api_keys::table)
+        .values(NewApiKey {
+          user_id: account.id,
+          name: &body.name,
+          prefix: &prefix,
+          key_hash: &auth::api_key::hash(&key),
+          permissions: &body.permissions,
+          expires_at: body.expires_at.map(|expires_at| expires_at.naive_utc()),
+        })
+        .get_result::<ApiKey>(conn)?;
+
+      let api_key = ApiKeyResponse::from(api_key);
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("create")
+        .resource("api_key")
+        .resource_id(api_key.id.to_string())
+        .maybe_after(snapshot(&api_key))
+        .call()?;
+      Ok::<_, diesel::result::Error>(api_key)
+    })?;
+    Ok::<_, ServiceError>((api_key, key))
+  })
+  .await??;
+
+  Ok(HttpResponse::Created().json(CreatedApiKeyResponse { api_key, key }))
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = NO_CONTENT),
+        (status = NOT_FOUND, description = "No active key with this id"),
+    ),
+    params(
+        ("id" = i32, Path, description = "Service account id"),
+        ("key_id" = i32, Path, description = "API key id"),
+    ),
+)]
+#[delete("/{id}/keys/{key_id}")]
+pub(crate) async fn revoke_api_key(
+  path: web::Path<(i32, i32)>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<DeleteAll, DeleteUser>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let (user_id, key_id) = path.into_inner();
+  let actor_id = claims.sub;
+
+  web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let api_key = api_keys::table
+        .find(key_id)
+        .filter(api_keys::user_id.eq(user_id))
+        .filter(api_keys::revoked_at.is_null())
+        .for_update()
+        .first::<ApiKey>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound(Some("No active key with this id".to_string())))?;
+
+      let revoked = diesel::update(api_keys::table.find(api_key.id))
+        .set(api_keys::revoked_at.eq(diesel::dsl::now))
+        .get_result::<ApiKey>(conn)?;
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("revoke")
+        .resource("api_key")
+        .resource_id(key_id.to_string())
+        .maybe_before(snapshot(&ApiKeyResponse::from(api_key)))
+        .maybe_after(snapshot(&ApiKeyResponse::from(revoked)))
+        .call()?;
+      Ok::<_, ServiceError>(())
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::NoContent().finish())
+}
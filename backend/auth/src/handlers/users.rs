@@ -8,7 +8,10 @@ use actix_web::post;
 use actix_web::put;
 use actix_web::web::ServiceConfig;
 use actix_web::{web, HttpResponse};
-use actix_web_httpauth::extractors::bearer::BearerAuth;
+use auth::audit::{snapshot, RequestMetadata};
+use auth::password::{hash_password, PasswordPolicy};
+use auth::scopes::*;
+use auth::Authorized;
 use common_rs::graphql::Connection as GraphConnection;
 use common_rs::graphql::Node;
 use common_rs::graphql::PageInfo;
@@ -18,26 +21,34 @@ use diesel::Connection;
 use diesel::ExpressionMethods;
 use diesel::JoinOnDsl;
 use diesel::NullableExpressionMethods;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
 use diesel::{QueryDsl, RunQueryDsl};
 use serde::Deserialize;
+use serde::Serialize;
 use std::vec::Vec;
 use utoipa::ToSchema;
+use validator_rs::openapi_security;
+use validator_rs::trace::Trace;
+use validator_rs::Scope;
 use validator_rs::ValidatorBuilder;
 
 pub(crate) const V1_PATH: &str = "/api/v1/users";
 
 pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
   |config: &mut ServiceConfig| {
-    config.service(web::scope("/api/v1")).service(delete_users);
-
     config.service(
       web::scope(V1_PATH)
         .service(get_user)
         .service(get_users)
         .service(upsert_user)
         .service(create_users)
-        .service(delete_user),
+        .service(delete_user)
+        .service(restore_user)
+        .service(explain_user),
     );
+
+    config.service(delete_users);
   }
 }
 
@@ -49,6 +60,8 @@ pub struct GetUsersParams {
   include_roles: bool,
   #[serde(default)]
   include_permissions: bool,
+  #[serde(default)]
+  include_deleted: bool,
 }
 
 fn db_get_paginated_users_with_options(
@@ -57,6 +70,9 @@ fn db_get_paginated_users_with_options(
 ) -> Result<GraphConnection<UserResponse>, diesel::result::Error> {
   let mut conn = pool.get().unwrap();
   let mut query = users::table.into_boxed();
+  if !options.include_deleted {
+    query = query.filter(users::deleted.eq(false));
+  }
   let params = options.pagination_params;
   let id_column = users::id;
   let cursor_fn = |x: &UserResponse| x.id.to_string();
@@ -192,6 +208,7 @@ fn db_get_paginated_users_with_options(
   })
 }
 
+#[openapi_security]
 #[utoipa::path(
     context_path = V1_PATH,
     responses(
@@ -204,22 +221,15 @@ fn db_get_paginated_users_with_options(
         ("before" = Option<i32>, Query, description = "Cursor for backward pagination"),
         ("include_roles" = Option<bool>, Query, description = "Include roles in response"),
         ("include_permissions" = Option<bool>, Query, description = "Include permissions in response"),
+        ("include_deleted" = Option<bool>, Query, description = "Include soft-deleted users"),
     ),
-    security(
-        ("http" = [])
-    )
 )]
 #[get("")]
 pub(crate) async fn get_users(
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  _claims: Authorized<Or<ReadAll, ReadUser>>,
   query: web::Query<GetUsersParams>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::ReadAll, PermissionName::ReadUser])
-    .validate(&claims.permissions)?;
-
   let result = web::block(move || db_get_paginated_users_with_options(db, query.into_inner())).await?;
 
   match result {
@@ -241,39 +251,40 @@ pub struct UserQueryOptions {
   include_roles: bool,
   #[serde(default = "default_true")]
   include_permissions: bool,
+  #[serde(default)]
+  include_deleted: bool,
 }
 
+#[openapi_security]
 #[utoipa::path(
     context_path = V1_PATH,
     responses(
-        (status = OK, body = UserResponse)
+        (status = OK, body = UserResponse),
+        (status = NOT_FOUND, description = "User not found")
     ),
     params(
         ("id" = i32, Path, description = "User id"),
         ("include_roles" = Option<bool>, Query, description = "Include roles in response"),
         ("include_permissions" = Option<bool>, Query, description = "Include permissions in response"),
+        ("include_deleted" = Option<bool>, Query, description = "Include soft-deleted users"),
     ),
-    security(
-        ("http" = [])
-    )
 )]
 #[get("/{id}")]
 pub(crate) async fn get_user(
   user_id: web::Path<i32>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  _claims: Authorized<Or<ReadAll, ReadUser>>,
   query: web::Query<UserQueryOptions>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::ReadAll, PermissionName::ReadUser])
-    .validate(&claims.permissions)?;
-
   let query_options = query.into_inner();
   let result = web::block(move || {
     let mut conn = db.get().unwrap();
 
-    let user = users::table.find(*user_id).get_result::<User>(&mut conn)?;
+    let mut user_query = users::table.find(*user_id).into_boxed();
+    if !query_options.include_deleted {
+      user_query = user_query.filter(users::deleted.eq(false));
+    }
+    let user = user_query.get_result::<User>(&mut conn)?;
     let mut response = UserResponse::from(user);
 
     if query_options.include_roles {
@@ -304,9 +315,12 @@ pub(crate) async fn get_user(
     Ok::<_, diesel::result::Error>(response)
   })
   .await?
-  .map_err(|e| {
-    log::error!("Error: {}", e);
-    ServiceError::InternalServerError
+  .map_err(|e| match e {
+    diesel::result::Error::NotFound => ServiceError::NotFound(Some("User not found".to_string())),
+    e => {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    }
   })?;
 
   Ok(HttpResponse::Ok().json(result))
@@ -329,25 +343,15 @@ pub enum UserInput {
 pub struct NewUserDTO {
   pub email: String,
   pub username: String,
-  pub password_hash: String,
+  /// Plaintext password, checked against the password policy and hashed by the server
+  pub password: String,
 }
 
-impl From<UserInput> for Vec<NewUser<'static>> {
+impl From<UserInput> for Vec<NewUserDTO> {
   fn from(val: UserInput) -> Self {
     match val {
-      UserInput::Single(user) => vec![NewUser {
-        email: Box::leak(user.email.into_boxed_str()),
-        username: Some(Box::leak(user.username.into_boxed_str())),
-        password_hash: Box::leak(user.password_hash.into_boxed_str()),
-      }],
-      UserInput::Multiple(users) => users
-        .into_iter()
-        .map(|user| NewUser {
-          email: Box::leak(user.email.into_boxed_str()),
-          username: Some(Box::leak(user.username.into_boxed_str())),
-          password_hash: Box::leak(user.password_hash.into_boxed_str()),
-        })
-        .collect(),
+      UserInput::Single(user) => vec![user],
+      UserInput::Multiple(users) => users,
     }
   }
 }
@@ -357,36 +361,53 @@ pub struct DeleteUsersRequest {
   pub ids: Vec<i32>,
 }
 
+fn record_user_change(
+  conn: &mut PgConnection,
+  metadata: &RequestMetadata,
+  actor_id: i32,
+  action: &str,
+  before: Option<User>,
+  after: User,
+) -> Result<UserResponse, diesel::result::Error> {
+  let after = UserResponse::from(after);
+  auth::audit::record(conn, metadata)
+    .actor_id(actor_id)
+    .action(action)
+    .resource("user")
+    .resource_id(after.id.to_string())
+    .maybe_before(before.map(UserResponse::from).as_ref().and_then(snapshot))
+    .maybe_after(snapshot(&after))
+    .call()?;
+  Ok(after)
+}
+
+#[openapi_security]
 #[utoipa::path(
     context_path = V1_PATH,
     request_body = NewUserDTO,
     responses(
         (status = CREATED, body = ()),
+        (status = BAD_REQUEST, description = "Password does not meet the password policy"),
         (status = CONFLICT, description = "User already exists when upsert=false")
     ),
     params(
         ("upsert" = Option<bool>, Query, description = "Allow upsert if user exists"),
     ),
-    security(
-        ("http" = [])
-    )
 )]
 #[put("/{id}")]
 pub(crate) async fn upsert_user(
   user_id: web::Path<i32>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<Or<CreateAll, CreateUser>>,
+  metadata: RequestMetadata,
   query: web::Query<UpsertQueryParams>,
   body: web::Json<NewUserDTO>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-
+  let actor_id = claims.sub;
   // todo validate they have update if they are upserting
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::CreateAll, PermissionName::CreateUser])
-    .validate(&claims.permissions)?;
-
+  PasswordPolicy::from_env().validate(&body.password)?;
   web::block(move || {
+    let password_hash = hash_password(&body.password)?;
     let mut conn = db.get().unwrap();
     conn.transaction(|conn| {
       let existing_user = users::table.find(*user_id).get_result::<User>(conn);
@@ -394,26 +415,29 @@ pub(crate) async fn upsert_user(
       match (existing_user, query.upsert) {
         (Ok(user), _) => {
           // Update existing user
-          diesel::update(users::table.find(user.id))
+          let updated = diesel::update(users::table.find(user.id))
             .set((
               users::email.eq(&body.email),
+              users::email_verified.eq(user.email_verified && user.email == body.email),
               users::username.eq(&body.username),
-              users::password_hash.eq(&body.password_hash),
+              users::password_hash.eq(&password_hash),
               // users::updated_at.eq(diesel::dsl::now),
             ))
-            .execute(conn)?;
-          Ok(user.id)
+            .get_result::<User>(conn)?;
+          record_user_change(conn, &metadata, actor_id, "update", Some(user), updated)?;
+          Ok(*user_id)
         }
         (Err(diesel::result::Error::NotFound), true) => {
           // Insert new user with specified ID
-          diesel::insert_into(users::table)
+          let created = diesel::insert_into(users::table)
             .values(NewUser {
               // id: Some(*user_id),
               email: &body.email,
               username: Some(&body.username),
-              password_hash: &body.password_hash,
+              password_hash: &password_hash,
             })
-            .execute(conn)?;
+            .get_result::<User>(conn)?;
+          record_user_change(conn, &metadata, actor_id, "create", None, created)?;
           Ok(*user_id)
         }
         (Err(diesel::result::Error::NotFound), false) => {
@@ -429,64 +453,83 @@ pub(crate) async fn upsert_user(
   Ok(HttpResponse::Ok().json(()))
 }
 
+#[openapi_security]
 #[utoipa::path(
     context_path = V1_PATH,
     request_body = UserInput,
     responses(
         // (status = CREATED, body = Vec<UserResponse>),
         (status = CREATED, body = ()),
+        (status = BAD_REQUEST, description = "Password does not meet the password policy"),
         (status = CONFLICT, description = "User already exists when upsert=false")
     ),
     params(
         ("upsert" = Option<bool>, Query, description = "Allow upsert if user exists"),
     ),
-    security(
-        ("http" = [])
-    )
 )]
 #[post("")]
 pub(crate) async fn create_users(
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<Or<CreateAll, CreateUser>>,
+  metadata: RequestMetadata,
   query: web::Query<UpsertQueryParams>,
   body: web::Json<UserInput>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-
+  let actor_id = claims.sub;
   // todo validate they have update if they are upserting
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::CreateAll, PermissionName::CreateUser])
-    .validate(&claims.permissions)?;
-
-  let new_users: Vec<NewUser> = body.into_inner().into();
+  let new_users: Vec<NewUserDTO> = body.into_inner().into();
+  let policy = PasswordPolicy::from_env();
+  for user in &new_users {
+    policy.validate(&user.password)?;
+  }
   let upsert = query.upsert;
 
   web::block(move || {
+    let password_hashes = new_users
+      .iter()
+      .map(|user| hash_password(&user.password))
+      .collect::<Result<Vec<_>, _>>()?;
+    let new_users = new_users
+      .iter()
+      .zip(&password_hashes)
+      .map(|(user, password_hash)| NewUser {
+        email: &user.email,
+        username: Some(&user.username),
+        password_hash,
+      })
+      .collect::<Vec<_>>();
     let mut conn = db.get().unwrap();
-    conn.transaction(|conn| {
-      if upsert {
-        diesel::insert_into(users::table)
-          .values(&new_users)
-          .on_conflict(users::id)
-          .do_update()
-          .set((
-            users::username.eq(excluded(users::username)),
-            users::password_hash.eq(excluded(users::password_hash)),
-            // users::updated_at.eq(diesel::dsl::now),
-          ))
-          .execute(conn)?;
-      } else {
-        diesel::insert_into(users::table).values(&new_users).execute(conn)?;
-      }
+    conn
+      .transaction(|conn| {
+        let imported = if upsert {
+          diesel::insert_into(users::table)
+            .values(&new_users)
+            .on_conflict(users::id)
+            .do_update()
+            .set((
+              users::username.eq(excluded(users::username)),
+              users::password_hash.eq(excluded(users::password_hash)),
+              // users::updated_at.eq(diesel::dsl::now),
+            ))
+            .get_results::<User>(conn)?
+        } else {
+          diesel::insert_into(users::table)
+            .values(&new_users)
+            .get_results::<User>(conn)?
+        };
 
-      Ok::<(), diesel::result::Error>(())
-    })
+        for user in imported {
+          record_user_change(conn, &metadata, actor_id, "import", None, user)?;
+        }
+
+        Ok::<(), diesel::result::Error>(())
+      })
+      .map_err(|e| {
+        log::error!("Error: {}", e);
+        ServiceError::InternalServerError
+      })
   })
-  .await?
-  .map_err(|e| {
-    log::error!("Error: {}", e);
-    ServiceError::InternalServerError
-  })?;
+  .await??;
 
   // Return all created/updated users
   // let users = web::block(move || {
@@ -504,32 +547,41 @@ pub(crate) async fn create_users(
   Ok(HttpResponse::Created().json(()))
 }
 
+#[openapi_security]
 #[utoipa::path(
     context_path = V1_PATH,
     responses(
         (status = NO_CONTENT),
         (status = NOT_FOUND, description = "User not found")
     ),
-    security(
-        ("http" = [])
-    )
 )]
 #[delete("/{id}")]
 pub(crate) async fn delete_user(
   user_id: web::Path<i32>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<Or<DeleteAll, DeleteUser>>,
+  metadata: RequestMetadata,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::DeleteAll, PermissionName::DeleteUser])
-    .validate(&claims.permissions)?;
-
+  let actor_id = claims.sub;
   web::block(move || {
     let mut conn = db.get().unwrap();
-    diesel::update(users::table.find(*user_id))
-      .set((users::deleted.eq(true), users::deleted_at.eq(diesel::dsl::now)))
-      .execute(&mut conn)
+    conn.transaction(|conn| {
+      let Some(user) = users::table
+        .find(*user_id)
+        .filter(users::deleted.eq(false))
+        .for_update()
+        .first::<User>(conn)
+        .optional()?
+      else {
+        return Ok(());
+      };
+
+      let deleted = diesel::update(users::table.find(user.id))
+        .set((users::deleted.eq(true), users::deleted_at.eq(diesel::dsl::now)))
+        .get_result::<User>(conn)?;
+      record_user_change(conn, &metadata, actor_id, "delete", Some(user), deleted)?;
+      Ok::<_, diesel::result::Error>(())
+    })
   })
   .await?
   .map_err(|e| {
@@ -540,6 +592,7 @@ pub(crate) async fn delete_user(
   Ok(HttpResponse::NoContent().finish())
 }
 
+#[openapi_security]
 #[utoipa::path(
     context_path = "/api/v1/",
     request_body = DeleteUsersRequest,
@@ -547,26 +600,36 @@ pub(crate) async fn delete_user(
         (status = NO_CONTENT),
         (status = NOT_FOUND, description = "One or more users not found")
     ),
-    security(
-        ("http" = [])
-    )
 )]
 #[post("/delete-users")]
 pub(crate) async fn delete_users(
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<Or<DeleteAll, DeleteUser>>,
+  metadata: RequestMetadata,
   body: web::Json<DeleteUsersRequest>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::DeleteAll, PermissionName::DeleteUser])
-    .validate(&claims.permissions)?;
-
+  let actor_id = claims.sub;
   web::block(move || {
     let mut conn = db.get().unwrap();
-    diesel::update(users::table.filter(users::id.eq_any(&body.ids)))
-      .set((users::deleted.eq(true), users::deleted_at.eq(diesel::dsl::now)))
-      .execute(&mut conn)
+    conn.transaction(|conn| {
+      let mut users = users::table
+        .filter(users::id.eq_any(&body.ids))
+        .filter(users::deleted.eq(false))
+        .for_update()
+        .load::<User>(conn)?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect::<std::collections::HashMap<_, _>>();
+
+      let deleted = diesel::update(users::table.filter(users::id.eq_any(users.keys())))
+        .set((users::deleted.eq(true), users::deleted_at.eq(diesel::dsl::now)))
+        .get_results::<User>(conn)?;
+      for user in deleted {
+        let before = users.remove(&user.id);
+        record_user_change(conn, &metadata, actor_id, "delete", before, user)?;
+      }
+      Ok::<_, diesel::result::Error>(())
+    })
   })
   .await?
   .map_err(|e| {
@@ -576,3 +639,119 @@ pub(crate) async fn delete_users(
 
   Ok(HttpResponse::NoContent().finish())
 }
+
+/// Undoes a soft delete, only possible until the account is purged
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = UserResponse),
+        (status = NOT_FOUND, description = "No soft-deleted user with this id")
+    ),
+    params(
+        ("id" = i32, Path, description = "User id"),
+    ),
+)]
+#[post("/{id}/restore")]
+pub(crate) async fn restore_user(
+  user_id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<UpdateAll, UpdateUser>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let user = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let user = users::table
+        .find(*user_id)
+        .filter(users::deleted.eq(true))
+        .for_update()
+        .first::<User>(conn)?;
+
+      let restored = diesel::update(users::table.find(user.id))
+        .set((
+          users::deleted.eq(false),
+          users::deleted_at.eq(None::<chrono::NaiveDateTime>),
+        ))
+        .get_result::<User>(conn)?;
+      record_user_change(conn, &metadata, actor_id, "restore", Some(user), restored)
+    })
+  })
+  .await?
+  .map_err(|e| match e {
+    diesel::result::Error::NotFound => ServiceError::NotFound(Some("No soft-deleted user with this id".to_string())),
+    e => {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    }
+  })?;
+
+  Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExplainParams {
+  scope: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ExplainResponse {
+  pub allowed: bool,
+  pub permissions: Vec<PermissionName>,
+  /// Permissions that were required but missing, or present but forbidden
+  pub failures: Vec<PermissionName>,
+  #[schema(value_type = Object)]
+  pub trace: Trace<PermissionName>,
+}
+
+#[openapi_security]
+#[utoipa::path(
+    context_path = V1_PATH,
+    responses(
+        (status = OK, body = ExplainResponse),
+        (status = BAD_REQUEST, description = "Invalid scope expression"),
+        (status = NOT_FOUND, description = "User not found")
+    ),
+    params(
+        ("id" = i32, Path, description = "User id"),
+        ("scope" = String, Query, description = "Permission expression to check, eg. `create:all | create:product`"),
+    ),
+)]
+#[get("/{id}/explain")]
+pub(crate) async fn explain_user(
+  user_id: web::Path<i32>,
+  db: web::Data<Pool>,
+  _claims: Authorized<Or<ReadAll, ReadUser>>,
+  query: web::Query<ExplainParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let scope: Scope<PermissionName> = query
+    .scope
+    .parse()
+    .map_err(|err: validator_rs::expr::ParseError| ServiceError::BadRequest(err.to_string()))?;
+
+  let result = web::block(move || {
+    let mut conn = db.get().unwrap();
+    users::table.find(*user_id).get_result::<User>(&mut conn)?;
+    auth::get_permissions(&mut conn, *user_id)
+  })
+  .await?;
+
+  let permissions = match result {
+    Ok(permissions) => permissions,
+    Err(diesel::result::Error::NotFound) => return Err(ServiceError::NotFound(Some("User not found".into())).into()),
+    Err(err) => {
+      log::error!("{}", err);
+      return Err(ServiceError::InternalServerError.into());
+    }
+  };
+
+  let trace = ValidatorBuilder::new().with_scope(scope).explain(&permissions)?;
+
+  Ok(HttpResponse::Ok().json(ExplainResponse {
+    allowed: trace.passed,
+    failures: trace.failures().into_iter().cloned().collect(),
+    permissions,
+    trace,
+  }))
+}
@@ -12,13 +12,21 @@
   - 2025-02-16 - Cody Duong - add comments
 */
 
+pub mod api_key;
+pub mod audit;
+pub mod directory;
+pub mod email_confirmation;
 pub mod errors;
 pub mod models;
+pub mod oidc;
+pub mod password;
 pub mod schema;
+pub mod scopes;
 pub type Pool = diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>;
 
 use crate::models::*;
 use crate::schema::*;
+use actix_web::FromRequest;
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use bon::builder;
 use bon::Builder;
@@ -28,6 +36,7 @@ use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::PooledConnection;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use validator_rs::actix::Authenticator;
 
 #[derive(Debug, Serialize, Deserialize, Builder)]
 pub struct Claims {
@@ -37,10 +46,16 @@ pub struct Claims {
   pub iss: String,         // Issuer
   pub nbf: usize,          // Not before
   pub sub: i32,            // Subject (user id)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sid: Option<i32>, // Session id
 
   // Custom claims
   pub permissions: Vec<PermissionName>,
   pub email: String,
+  /// Whether the user confirmed `email`, an unconfirmed address may belong to someone else
+  #[serde(default)]
+  #[builder(default)]
+  pub email_verified: bool,
   #[builder(required)]
   pub username: Option<String>,
 }
@@ -53,11 +68,16 @@ pub struct RefreshClaims {
   pub iss: String,         // Issuer
   pub nbf: usize,          // Not before
   pub sub: i32,            // Subject (user id)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sid: Option<i32>, // Session id
 
   // Custom claims
   pub permissions: Vec<PermissionName>,
 }
 
+/// How long a refresh token, and the session backing it, stays valid
+pub const REFRESH_TOKEN_LIFETIME_DAYS: i64 = 7;
+
 // WHENEVER THIS IS MODIFIED BE SURE YOU DON'T BREAK ANYTHING IN OUR GATEWAY
 // CTRL+F: 97f13b61-0eaf-4d0a-9285-df32d3546949
 // -@codyduong
@@ -66,7 +86,9 @@ pub fn create_jwt(
   user_id: i32,
   permissions: Vec<PermissionName>,
   email: String,
+  #[builder(default)] email_verified: bool,
   #[builder(required)] username: Option<String>,
+  session_id: Option<i32>,
 ) -> Result<(String, Option<String>), jsonwebtoken::errors::Error> {
   let secret_key = std::env::var("SECRET_KEY").expect("SECRET_KEY must be set");
 
@@ -84,14 +106,16 @@ pub fn create_jwt(
     .iss("auth".to_owned())
     .nbf(iat as usize)
     .sub(user_id)
+    .maybe_sid(session_id)
     .permissions(permissions.clone())
     .email(email)
+    .email_verified(email_verified)
     .username(username)
     .build();
 
   let exp = Utc::now()
     // We should use iat as a mechanism instead for long-lived tokens, but TODO -@codyduong
-    .checked_add_signed(Duration::days(7))
+    .checked_add_signed(Duration::days(REFRESH_TOKEN_LIFETIME_DAYS))
     .expect("valid timestamp")
     .timestamp();
 
@@ -102,6 +126,7 @@ pub fn create_jwt(
     .iss("auth".to_owned())
     .nbf(iat as usize)
     .sub(user_id)
+    .maybe_sid(session_id)
     .permissions(permissions)
     .build();
 
@@ -151,11 +176,81 @@ pub fn get_permissions(
     .load::<PermissionName>(conn)
 }
 
-pub fn get_claims(auth: &BearerAuth) -> Result<Claims, actix_web::Error> {
+/// Starts a session for a fresh login, its id is embedded in the issued tokens as `sid`
+pub fn create_session(
+  conn: &mut PgConnection,
+  user_id: i32,
+  user_agent: Option<&str>,
+) -> Result<Session, diesel::result::Error> {
+  let expires_at = Utc::now()
+    .checked_add_signed(Duration::days(REFRESH_TOKEN_LIFETIME_DAYS))
+    .expect("valid timestamp")
+    .naive_utc();
+
+  diesel::insert_into(sessions::table)
+    .values(NewSession {
+      user_id,
+      expires_at,
+      user_agent,
+    })
+    .get_result(conn)
+}
+
+/// Revokes every active session of the user, except `keep` if given
+pub fn revoke_sessions(
+  conn: &mut PgConnection,
+  user_id: i32,
+  keep: Option<i32>,
+) -> Result<usize, diesel::result::Error> {
+  diesel::update(
+    sessions::table
+      .filter(sessions::user_id.eq(user_id))
+      .filter(sessions::revoked_at.is_null())
+      .filter(sessions::id.ne_all(keep.into_iter().collect::<Vec<_>>())),
+  )
+  .set(sessions::revoked_at.eq(diesel::dsl::now))
+  .execute(conn)
+}
+
+/// Decodes the bearer token, or resolves it through the registered [`api_key::ApiKeyVerifier`] if it is an API key
+pub async fn get_claims(
+  verifier: Option<actix_web::web::Data<api_key::ApiKeyVerifier>>,
+  auth: BearerAuth,
+) -> Result<Claims, actix_web::Error> {
   let token = auth.token().to_string();
 
+  if api_key::is_api_key(&token) {
+    let verifier = verifier.ok_or_else(|| {
+      log::warn!("Rejected an API key, this service has no ApiKeyVerifier configured");
+      errors::ServiceError::BadRequest("Invalid token".to_string())
+    })?;
+    return verifier.verify(token).await.map_err(Into::into);
+  }
+
   match decode_jwt(&token) {
     Ok(token_data) => Ok(token_data),
     Err(_) => Err(errors::ServiceError::BadRequest("Invalid token".to_string()).into()),
   }
 }
+
+/// Authenticates requests with the bearer access token issued by `create_jwt`, or a service account's API key
+pub struct JwtAuthenticator;
+
+impl Authenticator for JwtAuthenticator {
+  type Claims = Claims;
+  type Permission = PermissionName;
+  type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Claims, actix_web::Error>>>>;
+
+  fn authenticate(req: &actix_web::HttpRequest) -> Self::Future {
+    let verifier = req.app_data::<actix_web::web::Data<api_key::ApiKeyVerifier>>().cloned();
+    let auth = BearerAuth::from_request(req, &mut actix_web::dev::Payload::None).into_inner();
+    Box::pin(async move { get_claims(verifier, auth?).await })
+  }
+
+  fn permissions(claims: &Self::Claims) -> &[Self::Permission] {
+    &claims.permissions
+  }
+}
+
+/// Decodes the bearer token and validates its permissions against `R`, see [`scopes`] for the available requirements
+pub type Authorized<R> = validator_rs::actix::Authorized<R, JwtAuthenticator>;
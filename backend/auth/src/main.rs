@@ -29,6 +29,7 @@ use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::{SwaggerUi, Url};
 
 mod handlers;
+mod purge;
 mod seed;
 
 #[cfg(debug_assertions)]
@@ -66,22 +67,49 @@ async fn main() -> std::io::Result<()> {
 
   let mut conn = pool.get().unwrap();
   conn.run_pending_migrations(MIGRATIONS).unwrap();
+  conn.run_pending_migrations(auth::audit::MIGRATIONS).unwrap();
 
   seed::run(pool.clone());
 
+  actix_rt::spawn(purge::run(pool.clone()));
+
+  let mailer = Data::new(auth::email_confirmation::Mailer::from_env().expect("Failed to configure SMTP"));
+
   #[derive(OpenApi)]
   #[openapi(
     modifiers(&SecurityAddon),
     paths(
+      auth::audit::get_audit_log,
       handlers::auth::login_route,
       handlers::auth::refresh_route,
       handlers::auth::register_route,
+      handlers::auth::get_oidc_providers,
+      handlers::auth::oidc_authorize,
+      handlers::auth::oidc_callback,
+      handlers::auth::oidc_token,
+      handlers::auth::confirm_email,
+      handlers::me::get_me,
+      handlers::me::update_me,
+      handlers::me::delete_me,
+      handlers::me::change_password,
+      handlers::me::get_sessions,
+      handlers::me::delete_session,
+      handlers::me::get_identities,
+      handlers::me::link_identity,
+      handlers::me::unlink_identity,
       handlers::users::get_user,
       handlers::users::get_users,
       handlers::users::upsert_user,
       handlers::users::create_users,
       handlers::users::delete_user,
       handlers::users::delete_users,
+      handlers::users::restore_user,
+      handlers::users::explain_user,
+      handlers::service_accounts::get_service_accounts,
+      handlers::service_accounts::create_service_account,
+      handlers::service_accounts::get_api_keys,
+      handlers::service_accounts::create_api_key,
+      handlers::service_accounts::revoke_api_key,
     )
   )]
   struct ApiDoc;
@@ -109,7 +137,7 @@ async fn main() -> std::io::Result<()> {
   HttpServer::new(move || {
     let cors = Cors::default()
       .allowed_origin_fn(|origin, _req_head| ALLOWED_ORIGINS.iter().any(|&i| i == origin))
-      .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+      .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"])
       .allowed_headers(vec!["Content-Type", "Authorization", "b3", "traceparent"])
       .expose_headers(vec!["Authorization", "x-refresh-token"])
       .supports_credentials()
@@ -119,7 +147,12 @@ async fn main() -> std::io::Result<()> {
       .wrap(Logger::default())
       .wrap(cors)
       .app_data(Data::new(pool.clone()))
+      .app_data(Data::new(auth::api_key::ApiKeyVerifier::new(pool.clone())))
+      .app_data(mailer.clone())
       .configure(handlers::auth::configure())
+      .configure(handlers::me::configure())
+      .configure(auth::audit::configure())
+      .configure(handlers::service_accounts::configure())
       .configure(handlers::users::configure())
       .service(
         SwaggerUi::new("/swagger-ui/{_:.*}").urls(vec![(Url::new("api", "/api-docs/openapi.json"), ApiDoc::openapi())]),
@@ -0,0 +1,89 @@
+/*
+  Name: api_key.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Diesel ORM must be installed and properly configured.
+  - PostgreSQL must be used as the database.
+  - The `api_keys` table must exist in the database.
+*/
+
+use super::PermissionName;
+use common_rs::to_rfc3339;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A long-lived credential of a service account, only the hash of the secret is stored
+#[derive(Queryable, Identifiable, Selectable, Associations, Debug)]
+#[diesel(belongs_to(super::User))]
+#[diesel(table_name = crate::schema::api_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ApiKey {
+  pub id: i32,
+  pub user_id: i32,
+  pub name: String,
+  pub prefix: String,
+  pub key_hash: String,
+  pub permissions: Vec<PermissionName>,
+  pub created_at: chrono::NaiveDateTime,
+  pub expires_at: Option<chrono::NaiveDateTime>,
+  pub last_used_at: Option<chrono::NaiveDateTime>,
+  pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::api_keys)]
+pub struct NewApiKey<'a> {
+  pub user_id: i32,
+  pub name: &'a str,
+  pub prefix: &'a str,
+  pub key_hash: &'a str,
+  pub permissions: &'a [PermissionName],
+  pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyResponse {
+  pub id: i32,
+  pub user_id: i32,
+  pub name: String,
+  /// Leading part of the key, enough to recognize it without revealing the secret
+  pub prefix: String,
+  pub permissions: Vec<PermissionName>,
+  #[serde(with = "to_rfc3339")]
+  pub created_at: chrono::NaiveDateTime,
+  #[serde(with = "to_rfc3339::option")]
+  pub expires_at: Option<chrono::NaiveDateTime>,
+  #[serde(with = "to_rfc3339::option")]
+  pub last_used_at: Option<chrono::NaiveDateTime>,
+  #[serde(with = "to_rfc3339::option")]
+  pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+  fn from(key: ApiKey) -> Self {
+    ApiKeyResponse {
+      id: key.id,
+      user_id: key.user_id,
+      name: key.name,
+      prefix: key.prefix,
+      permissions: key.permissions,
+      created_at: key.created_at,
+      expires_at: key.expires_at,
+      last_used_at: key.last_used_at,
+      revoked_at: key.revoked_at,
+    }
+  }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedApiKeyResponse {
+  #[serde(flatten)]
+  pub api_key: ApiKeyResponse,
+  /// The full key, only ever returned here
+  pub key: String,
+}
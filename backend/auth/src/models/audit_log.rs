@@ -0,0 +1,50 @@
+/*
+  Name: audit_log.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Diesel ORM must be installed and properly configured.
+  - PostgreSQL must be used as the database.
+  - The `audit_log` table must exist in the database.
+*/
+
+use common_rs::to_rfc3339;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Queryable, Selectable, Serialize, ToSchema, Debug)]
+#[diesel(table_name = crate::schema::audit_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditLogEntry {
+  pub id: i64,
+  #[serde(with = "to_rfc3339")]
+  pub created_at: chrono::NaiveDateTime,
+  /// `Claims.sub` of whoever performed the action, `0` for the services themselves
+  pub actor_id: i32,
+  pub action: String,
+  pub resource: String,
+  pub resource_id: Option<String>,
+  #[schema(value_type = Option<Object>)]
+  pub before: Option<serde_json::Value>,
+  #[schema(value_type = Option<Object>)]
+  pub after: Option<serde_json::Value>,
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::audit_log)]
+pub struct NewAuditLogEntry<'a> {
+  pub actor_id: i32,
+  pub action: &'a str,
+  pub resource: &'a str,
+  pub resource_id: Option<String>,
+  pub before: Option<serde_json::Value>,
+  pub after: Option<serde_json::Value>,
+  pub ip: Option<&'a str>,
+  pub user_agent: Option<&'a str>,
+}
@@ -0,0 +1,37 @@
+/*
+  Name: email_confirmation.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Diesel ORM must be installed and properly configured.
+  - PostgreSQL must be used as the database.
+  - The `email_confirmations` table must exist in the database.
+*/
+
+use diesel::prelude::*;
+
+/// An address waiting to be confirmed, only the hash of the emailed token is stored
+#[derive(Queryable, Identifiable, Selectable, Associations, Debug)]
+#[diesel(belongs_to(super::User))]
+#[diesel(primary_key(token_hash))]
+#[diesel(table_name = crate::schema::email_confirmations)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct EmailConfirmation {
+  pub token_hash: String,
+  pub user_id: i32,
+  pub email: String,
+  pub created_at: chrono::NaiveDateTime,
+  pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::email_confirmations)]
+pub struct NewEmailConfirmation<'a> {
+  pub token_hash: &'a str,
+  pub user_id: i32,
+  pub email: &'a str,
+  pub expires_at: chrono::NaiveDateTime,
+}
@@ -18,6 +18,16 @@
     glob style here
 */
 
+mod api_key;
+pub use api_key::*;
+mod audit_log;
+pub use audit_log::*;
+mod email_confirmation;
+pub use email_confirmation::*;
+mod oidc_login_code;
+pub use oidc_login_code::*;
+mod oidc_login_state;
+pub use oidc_login_state::*;
 mod permission;
 pub use permission::*;
 mod role_to_permission;
@@ -25,7 +35,11 @@ mod role_to_permission;
 use role_to_permission::*;
 mod role;
 pub use role::*;
+mod session;
+pub use session::*;
 mod user_to_role;
 pub use user_to_role::*;
 mod user;
 pub use user::*;
+mod user_identity;
+pub use user_identity::*;
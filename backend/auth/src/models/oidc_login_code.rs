@@ -0,0 +1,31 @@
+/*
+  Name: oidc_login_code.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Diesel ORM must be installed and properly configured.
+  - PostgreSQL must be used as the database.
+  - The `oidc_login_codes` table must exist in the database.
+*/
+
+use diesel::prelude::*;
+
+/// A finished OIDC login the frontend hasn't redeemed for tokens yet, only the hash of the code is stored
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::oidc_login_codes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct OidcLoginCode {
+  pub code_hash: String,
+  pub user_id: i32,
+  pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::oidc_login_codes)]
+pub struct NewOidcLoginCode<'a> {
+  pub code_hash: &'a str,
+  pub user_id: i32,
+}
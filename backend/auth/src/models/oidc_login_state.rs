@@ -0,0 +1,38 @@
+/*
+  Name: oidc_login_state.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Diesel ORM must be installed and properly configured.
+  - PostgreSQL must be used as the database.
+  - The `oidc_login_states` table must exist in the database.
+*/
+
+use diesel::prelude::*;
+
+/// An OIDC login waiting for the provider to redirect back, keyed by the `state` parameter
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::oidc_login_states)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct OidcLoginState {
+  pub state: String,
+  pub provider: String,
+  pub code_verifier: String,
+  pub nonce: String,
+  /// Set when an authenticated user is linking the provider rather than logging in with it
+  pub link_user_id: Option<i32>,
+  pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::oidc_login_states)]
+pub struct NewOidcLoginState<'a> {
+  pub state: &'a str,
+  pub provider: &'a str,
+  pub code_verifier: &'a str,
+  pub nonce: &'a str,
+  pub link_user_id: Option<i32>,
+}
@@ -22,15 +22,19 @@
 
 use diesel::{
   deserialize::{self, FromSql, FromSqlRow},
+  expression::AsExpression,
   pg::{Pg, PgValue},
   prelude::*,
   serialize::{self, IsNull, Output, ToSql},
 };
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::io::Write;
-use utoipa::{schema, ToSchema};
+use std::str::FromStr;
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, FromSqlRow, Clone, ToSchema)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, FromSqlRow, AsExpression, Clone, ToSchema)]
+#[diesel(sql_type = diesel::sql_types::Text)]
 pub enum PermissionName {
   #[serde(rename = "create:all")]
   #[schema(rename = "create:all")]
@@ -98,70 +102,90 @@ pub enum PermissionName {
   DeleteUser,
 }
 
+impl PermissionName {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      PermissionName::CreateAll => "create:all",
+      PermissionName::ReadAll => "read:all",
+      PermissionName::UpdateAll => "update:all",
+      PermissionName::DeleteAll => "delete:all",
+
+      PermissionName::CreateProduct => "create:product",
+      PermissionName::ReadProduct => "read:product",
+      PermissionName::UpdateProduct => "update:product",
+      PermissionName::DeleteProduct => "delete:product",
+
+      PermissionName::CreateMarketplace => "create:marketplace",
+      PermissionName::ReadMarketplace => "read:marketplace",
+      PermissionName::UpdateMarketplace => "update:marketplace",
+      PermissionName::DeleteMarketplace => "delete:marketplace",
+
+      PermissionName::CreatePriceReport => "create:price_report",
+      PermissionName::ReadPriceReport => "read:price_report",
+      PermissionName::UpdatePriceReport => "update:price_report",
+      PermissionName::DeletePriceReport => "delete:price_report",
+
+      PermissionName::CreateUser => "create:user",
+      PermissionName::ReadUser => "read:user",
+      PermissionName::UpdateUser => "update:user",
+      PermissionName::DeleteUser => "delete:user",
+    }
+  }
+}
+
+impl fmt::Display for PermissionName {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for PermissionName {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "create:all" => Ok(PermissionName::CreateAll),
+      "read:all" => Ok(PermissionName::ReadAll),
+      "update:all" => Ok(PermissionName::UpdateAll),
+      "delete:all" => Ok(PermissionName::DeleteAll),
+
+      "create:product" => Ok(PermissionName::CreateProduct),
+      "read:product" => Ok(PermissionName::ReadProduct),
+      "update:product" => Ok(PermissionName::UpdateProduct),
+      "delete:product" => Ok(PermissionName::DeleteProduct),
+
+      "create:marketplace" => Ok(PermissionName::CreateMarketplace),
+      "read:marketplace" => Ok(PermissionName::ReadMarketplace),
+      "update:marketplace" => Ok(PermissionName::UpdateMarketplace),
+      "delete:marketplace" => Ok(PermissionName::DeleteMarketplace),
+
+      "create:price_report" => Ok(PermissionName::CreatePriceReport),
+      "read:price_report" => Ok(PermissionName::ReadPriceReport),
+      "update:price_report" => Ok(PermissionName::UpdatePriceReport),
+      "delete:price_report" => Ok(PermissionName::DeletePriceReport),
+
+      "create:user" => Ok(PermissionName::CreateUser),
+      "read:user" => Ok(PermissionName::ReadUser),
+      "update:user" => Ok(PermissionName::UpdateUser),
+      "delete:user" => Ok(PermissionName::DeleteUser),
+
+      _ => Err("Unrecognized enum variant".to_string()),
+    }
+  }
+}
+
 // todo im sure we can write a proc macro to impl this based on strum?
 // LOL, as if this project wasn't complicated enough -- @codyduong
 impl ToSql<diesel::sql_types::Text, Pg> for PermissionName {
   fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
-    match *self {
-      PermissionName::CreateAll => out.write_all(b"create:all")?,
-      PermissionName::ReadAll => out.write_all(b"read:all")?,
-      PermissionName::UpdateAll => out.write_all(b"update:all")?,
-      PermissionName::DeleteAll => out.write_all(b"delete:all")?,
-
-      PermissionName::CreateProduct => out.write_all(b"create:product")?,
-      PermissionName::ReadProduct => out.write_all(b"read:product")?,
-      PermissionName::UpdateProduct => out.write_all(b"update:product")?,
-      PermissionName::DeleteProduct => out.write_all(b"delete:product")?,
-
-      PermissionName::CreateMarketplace => out.write_all(b"create:marketplace")?,
-      PermissionName::ReadMarketplace => out.write_all(b"read:marketplace")?,
-      PermissionName::UpdateMarketplace => out.write_all(b"update:marketplace")?,
-      PermissionName::DeleteMarketplace => out.write_all(b"delete:marketplace")?,
-
-      PermissionName::CreatePriceReport => out.write_all(b"create:price_report")?,
-      PermissionName::ReadPriceReport => out.write_all(b"read:price_report")?,
-      PermissionName::UpdatePriceReport => out.write_all(b"update:price_report")?,
-      PermissionName::DeletePriceReport => out.write_all(b"delete:price_report")?,
-
-      PermissionName::CreateUser => out.write_all(b"create:user")?,
-      PermissionName::ReadUser => out.write_all(b"read:user")?,
-      PermissionName::UpdateUser => out.write_all(b"update:user")?,
-      PermissionName::DeleteUser => out.write_all(b"delete:user")?,
-    }
+    out.write_all(self.as_str().as_bytes())?;
     Ok(IsNull::No)
   }
 }
 
 impl FromSql<diesel::sql_types::Text, Pg> for PermissionName {
   fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
-    match bytes.as_bytes() {
-      b"create:all" => Ok(PermissionName::CreateAll),
-      b"read:all" => Ok(PermissionName::ReadAll),
-      b"update:all" => Ok(PermissionName::UpdateAll),
-      b"delete:all" => Ok(PermissionName::DeleteAll),
-
-      b"create:product" => Ok(PermissionName::CreateProduct),
-      b"read:product" => Ok(PermissionName::ReadProduct),
-      b"update:product" => Ok(PermissionName::UpdateProduct),
-      b"delete:product" => Ok(PermissionName::DeleteProduct),
-
-      b"create:marketplace" => Ok(PermissionName::CreateMarketplace),
-      b"read:marketplace" => Ok(PermissionName::ReadMarketplace),
-      b"update:marketplace" => Ok(PermissionName::UpdateMarketplace),
-      b"delete:marketplace" => Ok(PermissionName::DeleteMarketplace),
-
-      b"create:price_report" => Ok(PermissionName::CreatePriceReport),
-      b"read:price_report" => Ok(PermissionName::ReadPriceReport),
-      b"update:price_report" => Ok(PermissionName::UpdatePriceReport),
-      b"delete:price_report" => Ok(PermissionName::DeletePriceReport),
-
-      b"create:user" => Ok(PermissionName::CreateUser),
-      b"read:user" => Ok(PermissionName::ReadUser),
-      b"update:user" => Ok(PermissionName::UpdateUser),
-      b"delete:user" => Ok(PermissionName::DeleteUser),
-
-      _ => Err("Unrecognized enum variant".into()),
-    }
+    Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
   }
 }
 
@@ -0,0 +1,54 @@
+/*
+  Name: session.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Diesel ORM must be installed and properly configured.
+  - PostgreSQL must be used as the database.
+  - The `sessions` table must exist in the database.
+*/
+
+use common_rs::to_rfc3339;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A login, backing the refresh tokens issued from it. Revoking the session stops those tokens from refreshing.
+#[derive(Queryable, Identifiable, Selectable, Associations, Debug)]
+#[diesel(belongs_to(super::User))]
+#[diesel(table_name = crate::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Session {
+  pub id: i32,
+  pub user_id: i32,
+  pub created_at: chrono::NaiveDateTime,
+  pub last_used_at: chrono::NaiveDateTime,
+  pub expires_at: chrono::NaiveDateTime,
+  pub revoked_at: Option<chrono::NaiveDateTime>,
+  pub user_agent: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::sessions)]
+pub struct NewSession<'a> {
+  pub user_id: i32,
+  pub expires_at: chrono::NaiveDateTime,
+  pub user_agent: Option<&'a str>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionResponse {
+  pub id: i32,
+  #[serde(with = "to_rfc3339")]
+  pub created_at: chrono::NaiveDateTime,
+  #[serde(with = "to_rfc3339")]
+  pub last_used_at: chrono::NaiveDateTime,
+  #[serde(with = "to_rfc3339")]
+  pub expires_at: chrono::NaiveDateTime,
+  pub user_agent: Option<String>,
+  /// Whether this is the session the request was made with
+  pub current: bool,
+}
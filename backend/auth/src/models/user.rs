@@ -38,12 +38,19 @@ pub struct User {
   pub deleted_at: Option<chrono::NaiveDateTime>,
   pub password_hash: String,
   pub username: Option<String>,
+  pub service_account: bool,
+  pub email_verified: bool,
 }
 
 #[derive(Serialize, ToSchema, Clone)]
 pub struct UserResponse {
   pub id: i32,
   pub email: String,
+  pub email_verified: bool,
+  /// An address the user changed their email to, taking effect once they confirm it
+  #[schema(nullable, required = false)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub pending_email: Option<String>,
   pub username: Option<String>,
   #[serde(with = "to_rfc3339")]
   pub created_at: chrono::NaiveDateTime,
@@ -52,6 +59,7 @@ pub struct UserResponse {
   pub deleted: bool,
   #[serde(with = "to_rfc3339::option")]
   pub deleted_at: Option<chrono::NaiveDateTime>,
+  pub service_account: bool,
   #[schema(nullable, required = false)]
   #[serde(skip_serializing_if = "Option::is_none")]
   pub roles: Option<Vec<super::Role>>,
@@ -65,11 +73,14 @@ impl From<User> for UserResponse {
     Self {
       id: user.id,
       email: user.email,
+      email_verified: user.email_verified,
+      pending_email: None,
       username: user.username,
       created_at: user.created_at,
       // updated_at: user.updated_at,
       deleted: user.deleted,
       deleted_at: user.deleted_at,
+      service_account: user.service_account,
       roles: None,
       permissions: None,
     }
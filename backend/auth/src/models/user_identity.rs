@@ -0,0 +1,64 @@
+/*
+  Name: user_identity.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Diesel ORM must be installed and properly configured.
+  - PostgreSQL must be used as the database.
+  - The `user_identities` table must exist in the database.
+*/
+
+use common_rs::to_rfc3339;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// An external OIDC account linked to a user, identified by the provider's `sub` claim
+#[derive(Queryable, Identifiable, Selectable, Associations, Debug)]
+#[diesel(belongs_to(super::User))]
+#[diesel(table_name = crate::schema::user_identities)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserIdentity {
+  pub id: i32,
+  pub user_id: i32,
+  pub provider: String,
+  pub subject: String,
+  pub email: Option<String>,
+  pub created_at: chrono::NaiveDateTime,
+  pub last_login_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::user_identities)]
+pub struct NewUserIdentity<'a> {
+  pub user_id: i32,
+  pub provider: &'a str,
+  pub subject: &'a str,
+  pub email: Option<&'a str>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserIdentityResponse {
+  pub id: i32,
+  pub provider: String,
+  pub email: Option<String>,
+  #[serde(with = "to_rfc3339")]
+  pub created_at: chrono::NaiveDateTime,
+  #[serde(with = "to_rfc3339::option")]
+  pub last_login_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<UserIdentity> for UserIdentityResponse {
+  fn from(identity: UserIdentity) -> Self {
+    Self {
+      id: identity.id,
+      provider: identity.provider,
+      email: identity.email,
+      created_at: identity.created_at,
+      last_login_at: identity.last_login_at,
+    }
+  }
+}
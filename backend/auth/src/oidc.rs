@@ -0,0 +1,239 @@
+/*
+  Name: oidc.rs
+
+  Description:
+  OpenID Connect relying party, authorization code flow with PKCE. Providers are configured through the environment,
+  `OIDC_PROVIDERS` lists their names and each one reads `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID`,
+  `OIDC_<NAME>_REDIRECT_URI` and optionally `OIDC_<NAME>_CLIENT_SECRET` and `OIDC_<NAME>_SCOPES`. After the callback
+  the browser is sent to `OIDC_LOGIN_REDIRECT_URL` with a one-time `code` to redeem for tokens.
+*/
+
+use crate::errors::ServiceError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// How long a started login may take before its state is rejected
+pub const LOGIN_STATE_LIFETIME_MINUTES: i64 = 10;
+
+/// How long the frontend has to redeem the code it was redirected with
+pub const LOGIN_CODE_LIFETIME_SECONDS: i64 = 60;
+
+/// Holds the login's state in the browser that started it, the callback must come back with the same state
+pub const STATE_COOKIE: &str = "oidc_state";
+
+const DEFAULT_LOGIN_REDIRECT_URL: &str = "http://localhost:3000/oidc/callback";
+
+/// The frontend page finished logins are redirected to with `?code=`
+pub fn login_redirect_url() -> String {
+  std::env::var("OIDC_LOGIN_REDIRECT_URL").unwrap_or_else(|_| DEFAULT_LOGIN_REDIRECT_URL.to_string())
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+  pub name: String,
+  pub issuer: String,
+  pub client_id: String,
+  pub client_secret: Option<String>,
+  pub redirect_uri: String,
+  pub scopes: String,
+}
+
+/// The subset of the discovery document needed for the code flow
+#[derive(Debug, Deserialize)]
+pub struct ProviderMetadata {
+  pub issuer: String,
+  pub authorization_endpoint: String,
+  pub token_endpoint: String,
+  pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+  id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+  pub sub: String,
+  pub email: Option<String>,
+  #[serde(default)]
+  pub email_verified: bool,
+  pub nonce: Option<String>,
+}
+
+fn provider_error(provider: &str, err: impl std::fmt::Display) -> ServiceError {
+  log::error!("OIDC provider `{}` failed: {}", provider, err);
+  ServiceError::InternalServerError
+}
+
+/// Names of the providers listed in `OIDC_PROVIDERS`
+pub fn provider_names() -> Vec<String> {
+  std::env::var("OIDC_PROVIDERS")
+    .unwrap_or_default()
+    .split(',')
+    .map(|name| name.trim().to_lowercase())
+    .filter(|name| !name.is_empty())
+    .collect()
+}
+
+impl OidcProvider {
+  /// Reads the provider's configuration, `None` if it isn't listed in `OIDC_PROVIDERS` or is missing a required value
+  pub fn from_env(name: &str) -> Option<Self> {
+    let name = name.to_lowercase();
+    if !provider_names().contains(&name) {
+      return None;
+    }
+
+    let var = |key: &str| std::env::var(format!("OIDC_{}_{}", name.to_uppercase(), key)).ok();
+    let provider = OidcProvider {
+      issuer: var("ISSUER")?.trim_end_matches('/').to_string(),
+      client_id: var("CLIENT_ID")?,
+      client_secret: var("CLIENT_SECRET"),
+      redirect_uri: var("REDIRECT_URI")?,
+      scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+      name,
+    };
+    Some(provider)
+  }
+
+  pub async fn discover(&self) -> Result<ProviderMetadata, ServiceError> {
+    let metadata = reqwest::get(format!("{}/.well-known/openid-configuration", self.issuer))
+      .await
+      .and_then(|res| res.error_for_status())
+      .map_err(|err| provider_error(&self.name, err))?
+      .json::<ProviderMetadata>()
+      .await
+      .map_err(|err| provider_error(&self.name, err))?;
+
+    if metadata.issuer.trim_end_matches('/') != self.issuer {
+      return Err(provider_error(
+        &self.name,
+        format!("discovery document is for issuer `{}`", metadata.issuer),
+      ));
+    }
+    Ok(metadata)
+  }
+
+  pub fn authorization_url(
+    &self,
+    metadata: &ProviderMetadata,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+  ) -> Result<Url, ServiceError> {
+    Url::parse_with_params(
+      &metadata.authorization_endpoint,
+      &[
+        ("response_type", "code"),
+        ("client_id", &self.client_id),
+        ("redirect_uri", &self.redirect_uri),
+        ("scope", &self.scopes),
+        ("state", state),
+        ("nonce", nonce),
+        ("code_challenge", &code_challenge(code_verifier)),
+        ("code_challenge_method", "S256"),
+      ],
+    )
+    .map_err(|err| provider_error(&self.name, err))
+  }
+
+  /// Redeems the authorization code and returns the verified claims of the id token
+  pub async fn exchange_code(
+    &self,
+    metadata: &ProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+    nonce: &str,
+  ) -> Result<IdTokenClaims, ServiceError> {
+    let mut form = vec![
+      ("grant_type", "authorization_code"),
+      ("code", code),
+      ("redirect_uri", &self.redirect_uri),
+      ("client_id", &self.client_id),
+      ("code_verifier", code_verifier),
+    ];
+    if let Some(client_secret) = &self.client_secret {
+      form.push(("client_secret", client_secret));
+    }
+
+    let res = reqwest::Client::new()
+      .post(&metadata.token_endpoint)
+      .form(&form)
+      .send()
+      .await
+      .map_err(|err| provider_error(&self.name, err))?;
+    if !res.status().is_success() {
+      log::warn!("OIDC provider `{}` rejected the code: {}", self.name, res.status());
+      return Err(ServiceError::Unauthorized);
+    }
+    let token = res
+      .json::<TokenResponse>()
+      .await
+      .map_err(|err| provider_error(&self.name, err))?;
+
+    self.verify_id_token(metadata, &token.id_token, nonce).await
+  }
+
+  async fn verify_id_token(
+    &self,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+  ) -> Result<IdTokenClaims, ServiceError> {
+    let invalid = |err: &dyn std::fmt::Display| {
+      log::warn!("Invalid id token from `{}`: {}", self.name, err);
+      ServiceError::Unauthorized
+    };
+
+    let header = decode_header(id_token).map_err(|err| invalid(&err))?;
+    // symmetric algorithms would let anyone holding the client secret mint tokens, only accept provider signed ones
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+      return Err(invalid(&format!("unsupported algorithm {:?}", header.alg)));
+    }
+
+    let jwks = reqwest::get(&metadata.jwks_uri)
+      .await
+      .and_then(|res| res.error_for_status())
+      .map_err(|err| provider_error(&self.name, err))?
+      .json::<JwkSet>()
+      .await
+      .map_err(|err| provider_error(&self.name, err))?;
+    let jwk = match &header.kid {
+      Some(kid) => jwks.find(kid),
+      None => jwks.keys.first(),
+    }
+    .ok_or_else(|| invalid(&"no matching signing key"))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|err| invalid(&err))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&self.issuer, &metadata.issuer]);
+    validation.set_audience(&[&self.client_id]);
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+      .map_err(|err| invalid(&err))?
+      .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+      return Err(invalid(&"nonce mismatch"));
+    }
+    Ok(claims)
+  }
+}
+
+/// A random url-safe token with 256 bits of entropy, used for the state, nonce and PKCE code verifier
+pub fn random_token() -> String {
+  URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// Login codes are stored hashed, like API keys and email confirmations
+pub fn hash_code(code: &str) -> String {
+  format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+fn code_challenge(code_verifier: &str) -> String {
+  URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
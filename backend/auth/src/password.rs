@@ -0,0 +1,268 @@
+/*
+  Name: password.rs
+
+  Description:
+  Server-side password hashing and the password policy shared by registration and the admin user APIs
+*/
+
+use crate::errors::ServiceError;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+  Bcrypt,
+  Argon2id,
+}
+
+impl HashAlgorithm {
+  /// Reads `PASSWORD_HASH_ALGORITHM` (`bcrypt` or `argon2id`), defaulting to bcrypt
+  pub fn from_env() -> Self {
+    match std::env::var("PASSWORD_HASH_ALGORITHM").as_deref() {
+      Ok("argon2id") => HashAlgorithm::Argon2id,
+      Ok("bcrypt") | Err(_) => HashAlgorithm::Bcrypt,
+      Ok(other) => {
+        log::warn!("Unknown PASSWORD_HASH_ALGORITHM `{}`, falling back to bcrypt", other);
+        HashAlgorithm::Bcrypt
+      }
+    }
+  }
+
+  fn of_hash(hash: &str) -> Option<Self> {
+    if hash.starts_with("$argon2id$") {
+      Some(HashAlgorithm::Argon2id)
+    } else if hash.starts_with("$2") {
+      Some(HashAlgorithm::Bcrypt)
+    } else {
+      None
+    }
+  }
+}
+
+fn bcrypt_cost() -> u32 {
+  std::env::var("BCRYPT_COST")
+    .ok()
+    .and_then(|cost| cost.parse().ok())
+    .unwrap_or(10)
+}
+
+/// Stored for accounts created through an identity provider, it is not a valid hash so no password ever matches it
+pub const NO_PASSWORD: &str = "!";
+
+/// Hashes `password` with the configured algorithm
+pub fn hash_password(password: &str) -> Result<String, ServiceError> {
+  hash_with(password, HashAlgorithm::from_env())
+}
+
+fn hash_with(password: &str, algorithm: HashAlgorithm) -> Result<String, ServiceError> {
+  match algorithm {
+    HashAlgorithm::Bcrypt => bcrypt::hash(password, bcrypt_cost()).map_err(|err| {
+      log::error!("Failed to hash: {}", err);
+      ServiceError::InternalServerError
+    }),
+    HashAlgorithm::Argon2id => Argon2::default()
+      .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+      .map(|hash| hash.to_string())
+      .map_err(|err| {
+        log::error!("Failed to hash: {}", err);
+        ServiceError::InternalServerError
+      }),
+  }
+}
+
+pub struct Verification {
+  pub valid: bool,
+  /// The hash was made with an algorithm other than the configured one and should be replaced
+  pub needs_rehash: bool,
+}
+
+/// Verifies `password` against a bcrypt or Argon2id `hash`, whichever algorithm produced it
+pub fn verify_password(password: &str, hash: &str) -> Result<Verification, ServiceError> {
+  verify_with(password, hash, HashAlgorithm::from_env())
+}
+
+/// Verifies like [`verify_password`], flagging hashes not made with `configured` for a rehash
+fn verify_with(password: &str, hash: &str, configured: HashAlgorithm) -> Result<Verification, ServiceError> {
+  if hash == NO_PASSWORD {
+    return Ok(Verification {
+      valid: false,
+      needs_rehash: false,
+    });
+  }
+
+  let algorithm = HashAlgorithm::of_hash(hash).ok_or_else(|| {
+    log::error!("Unrecognized password hash format");
+    ServiceError::InternalServerError
+  })?;
+
+  let valid = match algorithm {
+    HashAlgorithm::Bcrypt => bcrypt::verify(password, hash).map_err(|err| {
+      log::error!("Failed to verify: {}", err);
+      ServiceError::InternalServerError
+    })?,
+    HashAlgorithm::Argon2id => {
+      let hash = PasswordHash::new(hash).map_err(|err| {
+        log::error!("Failed to parse hash: {}", err);
+        ServiceError::InternalServerError
+      })?;
+      Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+    }
+  };
+
+  Ok(Verification {
+    valid,
+    needs_rehash: valid && algorithm != configured,
+  })
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+  pub min_length: usize,
+  pub max_length: usize,
+  pub require_uppercase: bool,
+  pub require_lowercase: bool,
+  pub require_digit: bool,
+  pub require_symbol: bool,
+}
+
+impl PasswordPolicy {
+  /// Reads `PASSWORD_MIN_LENGTH`, `PASSWORD_MAX_LENGTH` and `PASSWORD_REQUIRE_{UPPERCASE,LOWERCASE,DIGIT,SYMBOL}`.
+  /// The max length defaults to 72 since bcrypt ignores anything past 72 bytes.
+  pub fn from_env() -> Self {
+    let usize_var = |name: &str, default: usize| {
+      std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+    };
+    let bool_var = |name: &str| std::env::var(name).is_ok_and(|value| value == "true" || value == "1");
+
+    PasswordPolicy {
+      min_length: usize_var("PASSWORD_MIN_LENGTH", 8),
+      max_length: usize_var("PASSWORD_MAX_LENGTH", 72),
+      require_uppercase: bool_var("PASSWORD_REQUIRE_UPPERCASE"),
+      require_lowercase: bool_var("PASSWORD_REQUIRE_LOWERCASE"),
+      require_digit: bool_var("PASSWORD_REQUIRE_DIGIT"),
+      require_symbol: bool_var("PASSWORD_REQUIRE_SYMBOL"),
+    }
+  }
+
+  /// Returns every rule the password breaks as a single `BadRequest`
+  pub fn validate(&self, password: &str) -> Result<(), ServiceError> {
+    let mut violations = Vec::new();
+
+    if password.chars().count() < self.min_length {
+      violations.push(format!("at least {} characters", self.min_length));
+    }
+    if password.len() > self.max_length {
+      violations.push(format!("at most {} bytes", self.max_length));
+    }
+    if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+      violations.push("an uppercase letter".to_string());
+    }
+    if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+      violations.push("a lowercase letter".to_string());
+    }
+    if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+      violations.push("a digit".to_string());
+    }
+    if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric()) {
+      violations.push("a symbol".to_string());
+    }
+
+    if violations.is_empty() {
+      Ok(())
+    } else {
+      Err(ServiceError::BadRequest(format!(
+        "Password must contain {}",
+        violations.join(", ")
+      )))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn policy() -> PasswordPolicy {
+    PasswordPolicy {
+      min_length: 8,
+      max_length: 72,
+      require_uppercase: true,
+      require_lowercase: true,
+      require_digit: true,
+      require_symbol: true,
+    }
+  }
+
+  fn violation(password: &str) -> String {
+    match policy().validate(password) {
+      Err(ServiceError::BadRequest(message)) => message,
+      _ => panic!("`{}` should break the policy", password),
+    }
+  }
+
+  #[test]
+  fn test_bcrypt_needs_rehash() {
+    let hash = bcrypt::hash("hunter22", 4).unwrap();
+
+    let verification = verify_with("hunter22", &hash, HashAlgorithm::Argon2id).unwrap();
+    assert!(verification.valid);
+    assert!(verification.needs_rehash);
+
+    let verification = verify_with("hunter22", &hash, HashAlgorithm::Bcrypt).unwrap();
+    assert!(verification.valid);
+    assert!(!verification.needs_rehash);
+
+    // a wrong password is never worth a rehash
+    let verification = verify_with("hunter23", &hash, HashAlgorithm::Argon2id).unwrap();
+    assert!(!verification.valid);
+    assert!(!verification.needs_rehash);
+  }
+
+  #[test]
+  fn test_argon2id_round_trip() {
+    let hash = hash_with("hunter22", HashAlgorithm::Argon2id).unwrap();
+    assert!(hash.starts_with("$argon2id$"));
+
+    let verification = verify_with("hunter22", &hash, HashAlgorithm::Argon2id).unwrap();
+    assert!(verification.valid);
+    assert!(!verification.needs_rehash);
+    assert!(
+      verify_with("hunter22", &hash, HashAlgorithm::Bcrypt)
+        .unwrap()
+        .needs_rehash
+    );
+    assert!(!verify_with("hunter23", &hash, HashAlgorithm::Argon2id).unwrap().valid);
+  }
+
+  #[test]
+  fn test_no_password_never_verifies() {
+    for password in ["", NO_PASSWORD, "hunter22"] {
+      let verification = verify_password(password, NO_PASSWORD).unwrap();
+      assert!(!verification.valid);
+      assert!(!verification.needs_rehash);
+    }
+  }
+
+  #[test]
+  fn test_policy_violations() {
+    assert!(policy().validate("Hunter2!").is_ok());
+
+    assert_eq!(violation("Hunt2!"), "Password must contain at least 8 characters");
+    assert_eq!(
+      violation(&format!("Hunter2!{}", "a".repeat(65))),
+      "Password must contain at most 72 bytes"
+    );
+    assert_eq!(violation("hunter22!"), "Password must contain an uppercase letter");
+    assert_eq!(violation("HUNTER22!"), "Password must contain a lowercase letter");
+    assert_eq!(violation("Hunterrr!"), "Password must contain a digit");
+    assert_eq!(violation("Hunter222"), "Password must contain a symbol");
+    assert_eq!(
+      violation("hunter"),
+      "Password must contain at least 8 characters, an uppercase letter, a digit, a symbol"
+    );
+  }
+}
@@ -0,0 +1,132 @@
+/*
+  Name: purge.rs
+
+  Description:
+  Scheduled hard delete of users that have been soft-deleted for longer than the retention period
+  (`USER_RETENTION_DAYS`, default 30). Their price reports in the products service are anonymized afterwards, the
+  purged ids wait in `purged_users` until that succeeds and are retried on every run.
+
+  Side Effects:
+  - Permanently deletes rows from `users`, cascading to `users_to_roles` and `sessions`.
+  - Adds the purged ids to `purged_users`, removed once their price reports are anonymized.
+  - Appends a `purge` entry per user to `audit_log`, attributed to user id `0`.
+  - Rewrites `price_reports.created_by` in the products service.
+*/
+
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web;
+use auth::audit::{snapshot, RequestMetadata};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+fn retention_days() -> i64 {
+  std::env::var("USER_RETENTION_DAYS")
+    .ok()
+    .and_then(|days| days.parse().ok())
+    .unwrap_or(30)
+}
+
+fn products_uri() -> String {
+  std::env::var("PRODUCTS_URI")
+    .unwrap_or_else(|_| "http://localhost:8082".to_string())
+    .trim_end_matches('/')
+    .to_string()
+}
+
+pub(super) async fn run(pool: Pool) {
+  let mut interval = actix_rt::time::interval(PURGE_INTERVAL);
+  loop {
+    interval.tick().await;
+    match purge_expired_users(&pool).await {
+      Ok(0) => {}
+      Ok(purged) => log::info!("Purged {} soft-deleted users", purged),
+      Err(err) => log::error!("Failed to purge soft-deleted users: {}", err),
+    }
+  }
+}
+
+async fn purge_expired_users(pool: &Pool) -> anyhow::Result<usize> {
+  let cutoff = (Utc::now() - Duration::days(retention_days())).naive_utc();
+
+  let (purged, pending) = {
+    let pool = pool.clone();
+    web::block(move || {
+      let mut conn = pool.get()?;
+      let purged = conn.transaction(|conn| {
+        // deleting first means a user restored in the meantime is never anonymized
+        let purged = diesel::delete(
+          users::table
+            .filter(users::deleted.eq(true))
+            .filter(users::deleted_at.lt(cutoff)),
+        )
+        .get_results::<User>(conn)?;
+
+        diesel::insert_into(purged_users::table)
+          .values(
+            purged
+              .iter()
+              .map(|user| purged_users::user_id.eq(user.id))
+              .collect::<Vec<_>>(),
+          )
+          .on_conflict_do_nothing()
+          .execute(conn)?;
+
+        let count = purged.len();
+        for user in purged {
+          auth::audit::record(conn, &RequestMetadata::default())
+            .actor_id(0)
+            .action("purge")
+            .resource("user")
+            .resource_id(user.id.to_string())
+            .maybe_before(snapshot(&UserResponse::from(user)))
+            .call()?;
+        }
+        Ok::<_, diesel::result::Error>(count)
+      })?;
+
+      // includes users purged by earlier runs whose anonymization failed
+      let pending = purged_users::table
+        .select(purged_users::user_id)
+        .load::<i32>(&mut conn)?;
+      Ok::<_, anyhow::Error>((purged, pending))
+    })
+    .await??
+  };
+
+  if !pending.is_empty() {
+    anonymize(pool, pending).await?;
+  }
+
+  Ok(purged)
+}
+
+/// Anonymizes the price reports of purged users in the products service, and forgets them once it succeeded
+async fn anonymize(pool: &Pool, user_ids: Vec<i32>) -> anyhow::Result<()> {
+  // the products service only trusts our JWTs, so issue ourselves a short lived one scoped to user deletion
+  let (token, _) = crate::create_jwt()
+    .user_id(0)
+    .permissions(vec![PermissionName::DeleteUser])
+    .email(String::new())
+    .username(Some("auth".to_string()))
+    .call()?;
+
+  reqwest::Client::new()
+    .post(format!("{}/api/v1/price_report/anonymize", products_uri()))
+    .bearer_auth(token)
+    .json(&serde_json::json!({ "user_ids": user_ids }))
+    .send()
+    .await?
+    .error_for_status()?;
+
+  let pool = pool.clone();
+  web::block(move || {
+    diesel::delete(purged_users::table.filter(purged_users::user_id.eq_any(&user_ids))).execute(&mut pool.get()?)?;
+    Ok::<_, anyhow::Error>(())
+  })
+  .await??;
+  Ok(())
+}
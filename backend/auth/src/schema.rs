@@ -1,5 +1,64 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        prefix -> Text,
+        key_hash -> Text,
+        permissions -> Array<Text>,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Int8,
+        created_at -> Timestamp,
+        actor_id -> Int4,
+        action -> Text,
+        resource -> Text,
+        resource_id -> Nullable<Text>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    email_confirmations (token_hash) {
+        token_hash -> Text,
+        user_id -> Int4,
+        email -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_login_codes (code_hash) {
+        code_hash -> Text,
+        user_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_login_states (state) {
+        state -> Text,
+        provider -> Text,
+        code_verifier -> Text,
+        nonce -> Text,
+        link_user_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     permissions (id) {
         id -> Int4,
@@ -7,6 +66,13 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    purged_users (user_id) {
+        user_id -> Int4,
+        purged_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     roles (id) {
         id -> Int4,
@@ -21,6 +87,30 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        created_at -> Timestamp,
+        last_used_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    user_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> Text,
+        subject -> Text,
+        email -> Nullable<Text>,
+        created_at -> Timestamp,
+        last_login_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -30,6 +120,8 @@ diesel::table! {
         deleted_at -> Nullable<Timestamp>,
         password_hash -> Text,
         username -> Nullable<Text>,
+        service_account -> Bool,
+        email_verified -> Bool,
     }
 }
 
@@ -44,7 +136,23 @@ diesel::table! {
 
 diesel::joinable!(roles_to_permissions -> permissions (permission_id));
 diesel::joinable!(roles_to_permissions -> roles (role_id));
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(email_confirmations -> users (user_id));
+diesel::joinable!(oidc_login_codes -> users (user_id));
+diesel::joinable!(oidc_login_states -> users (link_user_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(user_identities -> users (user_id));
 diesel::joinable!(users_to_roles -> roles (role_id));
 diesel::joinable!(users_to_roles -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(permissions, roles, roles_to_permissions, users, users_to_roles,);
+diesel::allow_tables_to_appear_in_same_query!(
+  api_keys,
+  audit_log,
+  email_confirmations,
+  permissions,
+  roles,
+  roles_to_permissions,
+  sessions,
+  users,
+  users_to_roles,
+);
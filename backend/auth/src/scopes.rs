@@ -0,0 +1,38 @@
+/*
+  Name: scopes.rs
+
+  Description:
+  Type level permission requirements for use with the `Authorized` extractor, ie.
+  `Authorized<Or<CreateAll, CreateProduct>>`
+*/
+
+use crate::models::PermissionName;
+pub use validator_rs::actix::{And, Not, Or};
+
+validator_rs::requirements! {
+  PermissionName;
+  CreateAll => PermissionName::CreateAll, "create:all";
+  ReadAll => PermissionName::ReadAll, "read:all";
+  UpdateAll => PermissionName::UpdateAll, "update:all";
+  DeleteAll => PermissionName::DeleteAll, "delete:all";
+
+  CreateMarketplace => PermissionName::CreateMarketplace, "create:marketplace";
+  ReadMarketplace => PermissionName::ReadMarketplace, "read:marketplace";
+  UpdateMarketplace => PermissionName::UpdateMarketplace, "update:marketplace";
+  DeleteMarketplace => PermissionName::DeleteMarketplace, "delete:marketplace";
+
+  CreatePriceReport => PermissionName::CreatePriceReport, "create:price_report";
+  ReadPriceReport => PermissionName::ReadPriceReport, "read:price_report";
+  UpdatePriceReport => PermissionName::UpdatePriceReport, "update:price_report";
+  DeletePriceReport => PermissionName::DeletePriceReport, "delete:price_report";
+
+  CreateProduct => PermissionName::CreateProduct, "create:product";
+  ReadProduct => PermissionName::ReadProduct, "read:product";
+  UpdateProduct => PermissionName::UpdateProduct, "update:product";
+  DeleteProduct => PermissionName::DeleteProduct, "delete:product";
+
+  CreateUser => PermissionName::CreateUser, "create:user";
+  ReadUser => PermissionName::ReadUser, "read:user";
+  UpdateUser => PermissionName::UpdateUser, "update:user";
+  DeleteUser => PermissionName::DeleteUser, "delete:user";
+}
@@ -56,7 +56,7 @@ pub(super) fn run(pool: crate::Pool) {
 
       // if we are in dev seed with some data
       insert_into(users::table)
-        .values(&test_user)
+        .values((&test_user, users::email_verified.eq(true)))
         .execute(conn)
         .map_err(|err| {
           log::error!("Failed to inset user: {}", err);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS shopping_list_invitations;
+
+ALTER TABLE shopping_list_to_user
+DROP COLUMN IF EXISTS role;
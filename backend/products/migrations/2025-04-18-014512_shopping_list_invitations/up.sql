@@ -0,0 +1,19 @@
+-- Existing members keep full control of their lists
+ALTER TABLE shopping_list_to_user
+ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'owner' CHECK (role IN ('owner', 'editor', 'viewer'));
+
+CREATE TABLE IF NOT EXISTS shopping_list_invitations (
+    id SERIAL PRIMARY KEY,
+    shopping_list_id INTEGER NOT NULL REFERENCES shopping_list(id) ON DELETE CASCADE,
+    -- email or username of the invited user, stored lowercase
+    invitee TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'declined')),
+    invited_by INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_shopping_list_invitations_pending
+ON shopping_list_invitations (shopping_list_id, invitee) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_shopping_list_invitations_invitee ON shopping_list_invitations (invitee);
@@ -0,0 +1,18 @@
+DELETE FROM shopping_list_items WHERE gtin IS NULL;
+
+ALTER TABLE shopping_list_items DROP CONSTRAINT IF EXISTS shopping_list_items_gtin_or_name;
+ALTER TABLE shopping_list_items DROP CONSTRAINT IF EXISTS shopping_list_items_shopping_list_id_gtin_key;
+
+ALTER TABLE shopping_list_items
+DROP COLUMN IF EXISTS name,
+DROP COLUMN IF EXISTS note,
+DROP COLUMN IF EXISTS checked,
+DROP COLUMN IF EXISTS checked_by,
+DROP COLUMN IF EXISTS checked_at,
+DROP COLUMN IF EXISTS aisle,
+DROP COLUMN IF EXISTS position;
+
+ALTER TABLE shopping_list_items DROP CONSTRAINT IF EXISTS shopping_list_items_pkey;
+ALTER TABLE shopping_list_items DROP COLUMN IF EXISTS id;
+ALTER TABLE shopping_list_items ALTER COLUMN gtin SET NOT NULL;
+ALTER TABLE shopping_list_items ADD PRIMARY KEY (shopping_list_id, gtin);
@@ -0,0 +1,21 @@
+-- Free-form items have no GTIN, so items get their own id
+ALTER TABLE shopping_list_items DROP CONSTRAINT IF EXISTS shopping_list_items_pkey;
+ALTER TABLE shopping_list_items ALTER COLUMN shopping_list_id SET NOT NULL;
+ALTER TABLE shopping_list_items ADD COLUMN IF NOT EXISTS id SERIAL PRIMARY KEY;
+ALTER TABLE shopping_list_items ALTER COLUMN gtin DROP NOT NULL;
+
+-- NULL gtins are distinct, so any number of free-form items may share a list
+ALTER TABLE shopping_list_items
+ADD CONSTRAINT shopping_list_items_shopping_list_id_gtin_key UNIQUE (shopping_list_id, gtin);
+
+ALTER TABLE shopping_list_items
+ADD COLUMN IF NOT EXISTS name TEXT,
+ADD COLUMN IF NOT EXISTS note TEXT,
+ADD COLUMN IF NOT EXISTS checked BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN IF NOT EXISTS checked_by INTEGER,
+ADD COLUMN IF NOT EXISTS checked_at TIMESTAMP,
+ADD COLUMN IF NOT EXISTS aisle TEXT,
+ADD COLUMN IF NOT EXISTS position INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE shopping_list_items
+ADD CONSTRAINT shopping_list_items_gtin_or_name CHECK (gtin IS NOT NULL OR name IS NOT NULL);
@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS products_to_images_primary_idx;
+DROP INDEX IF EXISTS products_to_images_gtin_content_hash_idx;
+
+ALTER TABLE products_to_images
+DROP COLUMN IF EXISTS content_hash,
+DROP COLUMN IF EXISTS content_type,
+DROP COLUMN IF EXISTS thumbnail_url,
+DROP COLUMN IF EXISTS is_primary;
@@ -0,0 +1,9 @@
+ALTER TABLE products_to_images
+ADD content_hash TEXT,
+ADD content_type TEXT,
+ADD thumbnail_url TEXT,
+ADD is_primary BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- uploads are deduplicated by the SHA-256 of their content, external urls have no hash
+CREATE UNIQUE INDEX products_to_images_gtin_content_hash_idx ON products_to_images (gtin, content_hash);
+CREATE UNIQUE INDEX products_to_images_primary_idx ON products_to_images (gtin) WHERE is_primary;
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS products_to_categories;
+DROP TABLE IF EXISTS categories;
+DROP FUNCTION IF EXISTS categories_update_descendant_paths();
+DROP FUNCTION IF EXISTS categories_set_path();
@@ -0,0 +1,112 @@
+CREATE TABLE categories (
+    id SERIAL PRIMARY KEY,
+    parent_id INTEGER REFERENCES categories(id) ON DELETE RESTRICT,
+    name TEXT NOT NULL,
+    -- ids from the root down to and including this category, maintained by the triggers below
+    path INTEGER[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- siblings must have distinct names, top level categories are siblings of each other
+CREATE UNIQUE INDEX categories_parent_id_name_idx ON categories (COALESCE(parent_id, 0), name);
+CREATE INDEX categories_path_idx ON categories USING GIN (path);
+
+CREATE OR REPLACE FUNCTION categories_set_path() RETURNS trigger AS $$
+DECLARE
+    parent_path INTEGER[];
+BEGIN
+    IF NEW.parent_id IS NULL THEN
+        NEW.path := ARRAY[NEW.id];
+        RETURN NEW;
+    END IF;
+
+    SELECT path INTO parent_path FROM categories WHERE id = NEW.parent_id;
+    IF NEW.id = ANY(parent_path) THEN
+        RAISE EXCEPTION 'category % can''t be moved below itself', NEW.id USING ERRCODE = 'check_violation';
+    END IF;
+    NEW.path := parent_path || NEW.id;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER categories_set_path_trigger
+BEFORE INSERT OR UPDATE OF parent_id ON categories
+FOR EACH ROW EXECUTE FUNCTION categories_set_path();
+
+-- moving a category moves its whole subtree, the paths set here don't fire the trigger again
+CREATE OR REPLACE FUNCTION categories_update_descendant_paths() RETURNS trigger AS $$
+BEGIN
+    UPDATE categories
+    SET path = NEW.path || path[array_position(path, NEW.id) + 1:]
+    WHERE path @> ARRAY[NEW.id] AND id <> NEW.id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER categories_update_descendant_paths_trigger
+AFTER UPDATE OF parent_id ON categories
+FOR EACH ROW WHEN (OLD.path IS DISTINCT FROM NEW.path)
+EXECUTE FUNCTION categories_update_descendant_paths();
+
+CREATE TABLE products_to_categories (
+    gtin TEXT NOT NULL REFERENCES products(gtin) ON DELETE CASCADE,
+    category_id INTEGER NOT NULL REFERENCES categories(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (gtin, category_id)
+);
+
+CREATE INDEX products_to_categories_category_id_idx ON products_to_categories (category_id);
+
+-- departments seen by the Dillons scraper, plus the Walmart category path of our sample results
+INSERT INTO categories (name)
+VALUES ('Baby'),
+    ('Baking Goods'),
+    ('Bakery'),
+    ('Beauty'),
+    ('Beverages'),
+    ('Breakfast'),
+    ('Canned & Packaged'),
+    ('Cleaning Products'),
+    ('Condiment & Sauces'),
+    ('Dairy'),
+    ('Deli'),
+    ('Electronics'),
+    ('Entertainment'),
+    ('Frozen'),
+    ('Garden & Patio'),
+    ('Gift Cards'),
+    ('Health'),
+    ('International'),
+    ('Kitchen'),
+    ('Meat & Seafood'),
+    ('Natural & Organic'),
+    ('Office, School, & Crafts'),
+    ('Personal Care'),
+    ('Pet Care'),
+    ('Produce'),
+    ('Snacks');
+
+INSERT INTO categories (parent_id, name)
+SELECT parent.id, child.name
+FROM (
+        VALUES ('Beverages', 'Juice'),
+            ('Beverages', 'Coffee & Tea'),
+            ('Beverages', 'Soda & Pop'),
+            ('Beverages', 'Water'),
+            ('Dairy', 'Milk'),
+            ('Dairy', 'Cheese'),
+            ('Dairy', 'Yogurt'),
+            ('Dairy', 'Butter & Margarine'),
+            ('Dairy', 'Eggs'),
+            ('Deli', 'Lunch Kits'),
+            ('Frozen', 'Frozen Meat, Seafood, & Vegetarian'),
+            ('Snacks', 'Candy & Chocolate'),
+            ('Snacks', 'Chips & Crackers')
+    ) AS child (parent, name)
+    JOIN categories parent ON parent.name = child.parent AND parent.parent_id IS NULL;
+
+INSERT INTO categories (parent_id, name)
+SELECT id, 'Frozen Poultry'
+FROM categories
+WHERE name = 'Frozen Meat, Seafood, & Vegetarian';
@@ -0,0 +1,6 @@
+DROP TRIGGER IF EXISTS products_derive_brand_trigger ON products;
+DROP FUNCTION IF EXISTS products_derive_brand();
+DROP FUNCTION IF EXISTS brand_for_gtin(TEXT);
+ALTER TABLE products DROP COLUMN IF EXISTS brand_id;
+DROP TABLE IF EXISTS brand_gs1_prefixes;
+DROP TABLE IF EXISTS brands;
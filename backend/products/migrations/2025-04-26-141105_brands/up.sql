@@ -0,0 +1,46 @@
+CREATE TABLE brands (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    manufacturer TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX brands_name_idx ON brands (LOWER(name));
+
+-- GS1 company prefixes licensed to the brand owner, as the leading digits of a GTIN-13. A UPC-A company prefix gains a
+-- leading zero, UPC 009800 is prefix 0009800.
+CREATE TABLE brand_gs1_prefixes (
+    prefix TEXT PRIMARY KEY CHECK (prefix ~ '^[0-9]{6,12}$'),
+    brand_id INTEGER NOT NULL REFERENCES brands(id) ON DELETE CASCADE
+);
+
+CREATE INDEX brand_gs1_prefixes_brand_id_idx ON brand_gs1_prefixes (brand_id);
+
+ALTER TABLE products
+ADD COLUMN brand_id INTEGER REFERENCES brands(id) ON DELETE SET NULL;
+
+CREATE INDEX products_brand_id_idx ON products (brand_id);
+
+-- The brand owning the longest registered company prefix of the GTIN, prefixes vary from 6 to 12 digits. Every GTIN
+-- is padded to GTIN-14, whose company prefix follows the packaging indicator digit.
+CREATE OR REPLACE FUNCTION brand_for_gtin(gtin TEXT) RETURNS INTEGER AS $$
+    SELECT brand_id
+    FROM brand_gs1_prefixes
+    WHERE SUBSTRING(LPAD(gtin, 14, '0') FROM 2) LIKE prefix || '%'
+    ORDER BY LENGTH(prefix) DESC
+    LIMIT 1;
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION products_derive_brand() RETURNS trigger AS $$
+BEGIN
+    IF NEW.brand_id IS NULL THEN
+        NEW.brand_id := brand_for_gtin(NEW.gtin);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER products_derive_brand_trigger
+BEFORE INSERT OR UPDATE OF gtin, brand_id ON products
+FOR EACH ROW EXECUTE FUNCTION products_derive_brand();
@@ -0,0 +1,4 @@
+DROP FUNCTION IF EXISTS products_search_rank(tsvector, TEXT, TEXT);
+DROP FUNCTION IF EXISTS products_search_query(TEXT);
+DROP INDEX IF EXISTS products_productname_trgm_idx;
+DROP EXTENSION IF EXISTS pg_trgm;
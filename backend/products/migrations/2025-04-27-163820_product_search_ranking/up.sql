@@ -0,0 +1,21 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX products_productname_trgm_idx ON products USING GIN (productname gin_trgm_ops);
+
+-- Every word of the search must prefix a lexeme, so "choc milk" matches "Milk Chocolate" while it is being typed. NULL
+-- if the search has no words.
+CREATE OR REPLACE FUNCTION products_search_query(search TEXT) RETURNS tsquery AS $$
+    SELECT to_tsquery('english', string_agg(quote_literal(word) || ':*', ' & '))
+    FROM regexp_split_to_table(LOWER(search), '[^[:alnum:]]+') AS word
+    WHERE word <> '';
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Full-text matches rank in [1, 2) by cover density, above every fuzzy match of the product name which ranks by its
+-- trigram word similarity in [0, 1]
+CREATE OR REPLACE FUNCTION products_search_rank(search_vector tsvector, productname TEXT, search TEXT) RETURNS REAL AS $$
+    SELECT CASE
+        WHEN search_vector @@ products_search_query(search)
+            THEN 1 + ts_rank_cd(search_vector, products_search_query(search), 32)
+        ELSE word_similarity(search, productname)
+    END;
+$$ LANGUAGE sql IMMUTABLE;
@@ -0,0 +1,2 @@
+DROP FUNCTION IF EXISTS products_suggest_rank(TEXT, TEXT);
+DROP INDEX IF EXISTS products_productname_tsv_idx;
@@ -0,0 +1,11 @@
+-- Suggestions only match the product name, unlike `search_vector` which also covers the description
+CREATE INDEX products_productname_tsv_idx ON products USING GIN (to_tsvector('english', productname));
+
+-- `products_search_rank` over the product name alone
+CREATE OR REPLACE FUNCTION products_suggest_rank(productname TEXT, search TEXT) RETURNS REAL AS $$
+    SELECT CASE
+        WHEN to_tsvector('english', productname) @@ products_search_query(search)
+            THEN 1 + ts_rank_cd(to_tsvector('english', productname), products_search_query(search), 32)
+        ELSE word_similarity(search, productname)
+    END;
+$$ LANGUAGE sql IMMUTABLE;
@@ -0,0 +1,40 @@
+CREATE TABLE price_watches (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    gtin TEXT NOT NULL REFERENCES products(gtin) ON DELETE CASCADE,
+    -- narrows the watch to one marketplace or every marketplace of one company, neither watches them all
+    marketplace_id INTEGER REFERENCES marketplaces(id) ON DELETE CASCADE,
+    company_id INTEGER REFERENCES companies(id) ON DELETE CASCADE,
+    currency CHAR(3) NOT NULL REFERENCES iso_4217(code),
+    target_price NUMERIC CHECK (target_price > 0),
+    percent_drop NUMERIC CHECK (percent_drop > 0 AND percent_drop < 100),
+    -- what `percent_drop` is measured against, the latest price when the watch was created and then each notified price
+    reference_price NUMERIC,
+    -- the watch stays quiet until the price falls below this, and re-arms once a higher price is reported
+    last_notified_price NUMERIC,
+    last_notified_at TIMESTAMP,
+    -- delivery channels besides the in-app inbox
+    email TEXT,
+    webhook_url TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK (target_price IS NOT NULL OR percent_drop IS NOT NULL),
+    CHECK (marketplace_id IS NULL OR company_id IS NULL)
+);
+
+CREATE INDEX price_watches_gtin_idx ON price_watches (gtin);
+CREATE INDEX price_watches_user_id_idx ON price_watches (user_id);
+
+-- The in-app inbox
+CREATE TABLE notifications (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    data JSONB NOT NULL DEFAULT '{}',
+    read_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, id);
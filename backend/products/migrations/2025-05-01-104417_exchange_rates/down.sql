@@ -0,0 +1,8 @@
+DROP FUNCTION convert_price;
+DROP FUNCTION exchange_rate;
+DROP TABLE exchange_rates;
+
+DELETE FROM iso_4217
+WHERE code IN ('AUD', 'CAD', 'CHF', 'CNY', 'EUR', 'GBP', 'INR', 'JPY', 'KRW', 'MXN')
+    AND NOT EXISTS (SELECT 1 FROM price_reports WHERE currency = code)
+    AND NOT EXISTS (SELECT 1 FROM price_watches WHERE currency = code);
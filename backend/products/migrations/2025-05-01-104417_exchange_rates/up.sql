@@ -0,0 +1,42 @@
+INSERT INTO iso_4217 (code, name, numeric_code, minor_unit)
+VALUES
+    ('AUD', 'Australian dollar', 36, 2),
+    ('CAD', 'Canadian dollar', 124, 2),
+    ('CHF', 'Swiss franc', 756, 2),
+    ('CNY', 'Renminbi', 156, 2),
+    ('EUR', 'Euro', 978, 2),
+    ('GBP', 'Pound sterling', 826, 2),
+    ('INR', 'Indian rupee', 356, 2),
+    ('JPY', 'Japanese yen', 392, 0),
+    ('KRW', 'South Korean won', 410, 0),
+    ('MXN', 'Mexican peso', 484, 2)
+ON CONFLICT (code) DO NOTHING;
+
+-- Daily rates against the US dollar, `rate` units of `currency` buy one dollar
+CREATE TABLE exchange_rates (
+    currency CHAR(3) NOT NULL REFERENCES iso_4217(code),
+    date DATE NOT NULL,
+    rate NUMERIC NOT NULL CHECK (rate > 0),
+    PRIMARY KEY (currency, date)
+);
+
+-- Units of `currency` per dollar on `on_date`, the rate of the closest earlier day if that day has none and of the
+-- earliest day if there's none before it, NULL without any rate
+CREATE FUNCTION exchange_rate(currency CHAR(3), on_date DATE) RETURNS NUMERIC AS $$
+    SELECT CASE WHEN currency = 'USD' THEN 1 ELSE (
+        SELECT r.rate
+        FROM exchange_rates r
+        WHERE r.currency = exchange_rate.currency
+        ORDER BY r.date > on_date, abs(r.date - on_date)
+        LIMIT 1
+    ) END
+$$ LANGUAGE SQL STABLE;
+
+-- `price` in `to_currency` at the rates of the day it was reported, rounded to the minor unit of `to_currency`
+CREATE FUNCTION convert_price(price NUMERIC, from_currency CHAR(3), to_currency CHAR(3), reported_at TIMESTAMPTZ)
+RETURNS NUMERIC AS $$
+    SELECT CASE WHEN from_currency = to_currency THEN price ELSE round(
+        price / exchange_rate(from_currency, reported_at::DATE) * exchange_rate(to_currency, reported_at::DATE),
+        (SELECT coalesce(minor_unit, 2) FROM iso_4217 WHERE code = to_currency)
+    ) END
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,5 @@
+DROP TRIGGER current_prices_on_delete ON price_report_to_marketplaces;
+DROP TRIGGER current_prices_on_insert ON price_report_to_marketplaces;
+DROP FUNCTION current_prices_on_delete;
+DROP FUNCTION current_prices_on_insert;
+DROP TABLE current_prices;
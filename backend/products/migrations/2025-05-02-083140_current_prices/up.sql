@@ -0,0 +1,68 @@
+-- The latest price reported for each product at each marketplace, kept current by the triggers on
+-- `price_report_to_marketplaces` below rather than refreshed
+CREATE TABLE current_prices (
+    gtin TEXT NOT NULL REFERENCES products(gtin) ON DELETE CASCADE,
+    marketplace_id INT NOT NULL REFERENCES marketplaces(id) ON DELETE CASCADE,
+    price_report_id BIGINT NOT NULL,
+    reported_at TIMESTAMPTZ NOT NULL,
+    price NUMERIC NOT NULL,
+    currency CHAR(3) NOT NULL REFERENCES iso_4217(code),
+    PRIMARY KEY (gtin, marketplace_id)
+);
+
+INSERT INTO current_prices (gtin, marketplace_id, price_report_id, reported_at, price, currency)
+SELECT DISTINCT ON (r.gtin, l.marketplace_id) r.gtin, l.marketplace_id, r.id, r.reported_at, r.price, r.currency
+FROM price_report_to_marketplaces l
+JOIN price_reports r ON r.id = l.price_report_id AND r.reported_at = l.reported_at
+WHERE r.gtin IS NOT NULL
+ORDER BY r.gtin, l.marketplace_id, r.reported_at DESC, r.id DESC;
+
+-- A report backdated before the current one doesn't replace it
+CREATE FUNCTION current_prices_on_insert() RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO current_prices (gtin, marketplace_id, price_report_id, reported_at, price, currency)
+    SELECT r.gtin, NEW.marketplace_id, r.id, r.reported_at, r.price, r.currency
+    FROM price_reports r
+    WHERE r.id = NEW.price_report_id AND r.reported_at = NEW.reported_at AND r.gtin IS NOT NULL
+    ON CONFLICT (gtin, marketplace_id) DO UPDATE
+    SET price_report_id = EXCLUDED.price_report_id,
+        reported_at = EXCLUDED.reported_at,
+        price = EXCLUDED.price,
+        currency = EXCLUDED.currency
+    WHERE (current_prices.reported_at, current_prices.price_report_id)
+        <= (EXCLUDED.reported_at, EXCLUDED.price_report_id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Deleting the current report falls back to the one before it
+CREATE FUNCTION current_prices_on_delete() RETURNS TRIGGER AS $$
+DECLARE
+    removed current_prices;
+BEGIN
+    DELETE FROM current_prices
+    WHERE price_report_id = OLD.price_report_id
+        AND reported_at = OLD.reported_at
+        AND marketplace_id = OLD.marketplace_id
+    RETURNING * INTO removed;
+
+    IF FOUND THEN
+        INSERT INTO current_prices (gtin, marketplace_id, price_report_id, reported_at, price, currency)
+        SELECT r.gtin, l.marketplace_id, r.id, r.reported_at, r.price, r.currency
+        FROM price_report_to_marketplaces l
+        JOIN price_reports r ON r.id = l.price_report_id AND r.reported_at = l.reported_at
+        WHERE r.gtin = removed.gtin AND l.marketplace_id = removed.marketplace_id
+        ORDER BY r.reported_at DESC, r.id DESC
+        LIMIT 1;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER current_prices_on_insert
+AFTER INSERT ON price_report_to_marketplaces
+FOR EACH ROW EXECUTE FUNCTION current_prices_on_insert();
+
+CREATE TRIGGER current_prices_on_delete
+AFTER DELETE ON price_report_to_marketplaces
+FOR EACH ROW EXECUTE FUNCTION current_prices_on_delete();
@@ -0,0 +1,85 @@
+ALTER TABLE price_reports
+    -- `multi_buy` is `price` for `promotion_quantity` units such as 2 for $5, `sale` and `clearance` are reductions
+    ADD COLUMN promotion_type TEXT CHECK (promotion_type IN ('sale', 'multi_buy', 'clearance')),
+    -- the price without the promotion, paid before or after it or without the loyalty card it needs
+    ADD COLUMN regular_price NUMERIC CHECK (regular_price > 0),
+    -- how many units `price` is for, one if NULL
+    ADD COLUMN promotion_quantity INT CHECK (promotion_quantity > 0),
+    ADD COLUMN loyalty_required BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN valid_from TIMESTAMPTZ,
+    ADD COLUMN valid_until TIMESTAMPTZ,
+    ADD CONSTRAINT price_reports_validity_check CHECK (valid_until > valid_from);
+
+ALTER TABLE current_prices
+    ADD COLUMN promotion_type TEXT,
+    ADD COLUMN regular_price NUMERIC,
+    ADD COLUMN promotion_quantity INT,
+    ADD COLUMN loyalty_required BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN valid_from TIMESTAMPTZ,
+    ADD COLUMN valid_until TIMESTAMPTZ;
+
+-- What one unit costs now per a current price: the price split over its quantity while the promotion runs, the regular
+-- price before or after it or when it needs a loyalty card the shopper doesn't have, NULL if that wasn't reported
+CREATE FUNCTION current_unit_price(cp current_prices, loyalty_member BOOLEAN) RETURNS NUMERIC AS $$
+    SELECT trim_scale(round(CASE
+        WHEN (cp.valid_from IS NOT NULL AND now() < cp.valid_from)
+            OR (cp.valid_until IS NOT NULL AND now() >= cp.valid_until)
+            OR (cp.loyalty_required AND NOT loyalty_member)
+        THEN cp.regular_price
+        ELSE cp.price / COALESCE(cp.promotion_quantity, 1)
+    END, 4))
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION current_prices_on_insert() RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO current_prices (
+        gtin, marketplace_id, price_report_id, reported_at, price, currency,
+        promotion_type, regular_price, promotion_quantity, loyalty_required, valid_from, valid_until
+    )
+    SELECT r.gtin, NEW.marketplace_id, r.id, r.reported_at, r.price, r.currency,
+        r.promotion_type, r.regular_price, r.promotion_quantity, r.loyalty_required, r.valid_from, r.valid_until
+    FROM price_reports r
+    WHERE r.id = NEW.price_report_id AND r.reported_at = NEW.reported_at AND r.gtin IS NOT NULL
+    ON CONFLICT (gtin, marketplace_id) DO UPDATE
+    SET price_report_id = EXCLUDED.price_report_id,
+        reported_at = EXCLUDED.reported_at,
+        price = EXCLUDED.price,
+        currency = EXCLUDED.currency,
+        promotion_type = EXCLUDED.promotion_type,
+        regular_price = EXCLUDED.regular_price,
+        promotion_quantity = EXCLUDED.promotion_quantity,
+        loyalty_required = EXCLUDED.loyalty_required,
+        valid_from = EXCLUDED.valid_from,
+        valid_until = EXCLUDED.valid_until
+    WHERE (current_prices.reported_at, current_prices.price_report_id)
+        <= (EXCLUDED.reported_at, EXCLUDED.price_report_id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION current_prices_on_delete() RETURNS TRIGGER AS $$
+DECLARE
+    removed current_prices;
+BEGIN
+    DELETE FROM current_prices
+    WHERE price_report_id = OLD.price_report_id
+        AND reported_at = OLD.reported_at
+        AND marketplace_id = OLD.marketplace_id
+    RETURNING * INTO removed;
+
+    IF FOUND THEN
+        INSERT INTO current_prices (
+            gtin, marketplace_id, price_report_id, reported_at, price, currency,
+            promotion_type, regular_price, promotion_quantity, loyalty_required, valid_from, valid_until
+        )
+        SELECT r.gtin, l.marketplace_id, r.id, r.reported_at, r.price, r.currency,
+            r.promotion_type, r.regular_price, r.promotion_quantity, r.loyalty_required, r.valid_from, r.valid_until
+        FROM price_report_to_marketplaces l
+        JOIN price_reports r ON r.id = l.price_report_id AND r.reported_at = l.reported_at
+        WHERE r.gtin = removed.gtin AND l.marketplace_id = removed.marketplace_id
+        ORDER BY r.reported_at DESC, r.id DESC
+        LIMIT 1;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
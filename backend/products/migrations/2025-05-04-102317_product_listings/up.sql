@@ -0,0 +1,17 @@
+-- A product's page at an online marketplace, kept current by scrapers
+CREATE TABLE product_listings (
+    gtin TEXT NOT NULL REFERENCES products(gtin) ON DELETE CASCADE,
+    marketplace_id INTEGER NOT NULL REFERENCES online_marketplaces(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    -- the retailer's own id of the item, such as a Walmart item id or a Target TCIN
+    retailer_item_id TEXT,
+    in_stock BOOLEAN NOT NULL,
+    last_checked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (gtin, marketplace_id),
+    UNIQUE (marketplace_id, retailer_item_id)
+);
+
+-- scrapers revisit the listings checked longest ago first
+CREATE INDEX product_listings_last_checked_at_idx ON product_listings (marketplace_id, last_checked_at);
@@ -0,0 +1,87 @@
+/*
+  Name: local.rs
+
+  Description:
+  `BlobStore` on the local filesystem, under `BLOB_STORE_PATH` (default `./blobs`). The content type is kept in a
+  `.content-type` file next to each blob.
+*/
+
+use super::{validate_key, BlobStore};
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+pub struct LocalBlobStore {
+  root: PathBuf,
+}
+
+impl LocalBlobStore {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    LocalBlobStore { root: root.into() }
+  }
+
+  pub fn from_env() -> anyhow::Result<Self> {
+    let root = std::env::var("BLOB_STORE_PATH").unwrap_or_else(|_| "./blobs".to_string());
+    std::fs::create_dir_all(&root)?;
+    Ok(Self::new(root))
+  }
+
+  fn paths(&self, key: &str) -> anyhow::Result<(PathBuf, PathBuf)> {
+    validate_key(key)?;
+    let path = self.root.join(key);
+    let mut content_type_path = path.clone().into_os_string();
+    content_type_path.push(".content-type");
+    Ok((path, content_type_path.into()))
+  }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+  async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+    let (path, content_type_path) = self.paths(key)?;
+    if let Some(parent) = path.parent() {
+      actix_web::rt::task::spawn_blocking({
+        let parent = parent.to_owned();
+        move || std::fs::create_dir_all(parent)
+      })
+      .await??;
+    }
+
+    let content_type = content_type.to_string();
+    actix_web::rt::task::spawn_blocking(move || {
+      std::fs::write(content_type_path, content_type)?;
+      std::fs::write(path, bytes)
+    })
+    .await??;
+    Ok(())
+  }
+
+  async fn get(&self, key: &str) -> anyhow::Result<Option<(Bytes, String)>> {
+    let (path, content_type_path) = self.paths(key)?;
+    actix_web::rt::task::spawn_blocking(move || match std::fs::read(&path) {
+      Ok(bytes) => {
+        let content_type =
+          std::fs::read_to_string(content_type_path).unwrap_or_else(|_| "application/octet-stream".to_string());
+        Ok(Some((Bytes::from(bytes), content_type)))
+      }
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(err.into()),
+    })
+    .await?
+  }
+
+  async fn delete(&self, key: &str) -> anyhow::Result<()> {
+    let (path, content_type_path) = self.paths(key)?;
+    actix_web::rt::task::spawn_blocking(move || {
+      for path in [path, content_type_path] {
+        match std::fs::remove_file(path) {
+          Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+          _ => {}
+        }
+      }
+      Ok(())
+    })
+    .await??;
+    Ok(())
+  }
+}
@@ -0,0 +1,71 @@
+/*
+  Name: mod.rs
+
+  Description:
+  Storage for uploaded files such as product images. `BLOB_STORE` selects the backend, `local` (default) writes to
+  the filesystem and `s3` to any S3-compatible object store, see the implementations for their configuration.
+
+  Postconditions:
+  - Every file under the parent directory `./blob_store` should be exported
+    glob style here
+*/
+
+mod local;
+pub use local::*;
+mod s3;
+pub use s3::*;
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Default of `BLOB_PUBLIC_URL`, blobs are served by `handlers::products_to_images::get_blob` unless the store is
+/// publicly readable on its own
+pub const DEFAULT_BLOB_PUBLIC_URL: &str = "/api/v1/product_to_image/blobs";
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+  /// Stores the blob, replacing any existing blob with the same key
+  async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()>;
+
+  /// The blob and its content type, `None` if there is no blob with this key
+  async fn get(&self, key: &str) -> anyhow::Result<Option<(Bytes, String)>>;
+
+  /// Removes the blob, succeeds if it doesn't exist
+  async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// Where clients can fetch the blob from
+pub fn public_url(key: &str) -> String {
+  let base = std::env::var("BLOB_PUBLIC_URL").unwrap_or_else(|_| DEFAULT_BLOB_PUBLIC_URL.to_string());
+  format!("{}/{}", base.trim_end_matches('/'), key)
+}
+
+pub fn from_env() -> anyhow::Result<Arc<dyn BlobStore>> {
+  match std::env::var("BLOB_STORE").as_deref() {
+    Ok("local") | Err(_) => Ok(Arc::new(LocalBlobStore::from_env()?)),
+    Ok("s3") => Ok(Arc::new(S3BlobStore::from_env()?)),
+    Ok(other) => Err(anyhow::anyhow!(
+      "Unknown BLOB_STORE `{}`, expected `local` or `s3`",
+      other
+    )),
+  }
+}
+
+/// Keys are generated by us, but reject anything that could escape the store's namespace all the same
+pub(crate) fn validate_key(key: &str) -> anyhow::Result<()> {
+  let valid = !key.is_empty()
+    && key.split('/').all(|segment| {
+      !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && segment
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    });
+  if valid {
+    Ok(())
+  } else {
+    Err(anyhow::anyhow!("Invalid blob key `{}`", key))
+  }
+}
@@ -0,0 +1,462 @@
+/*
+  Name: brands.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/brands`. A product belongs to the brand owning the GS1 company prefix of its GTIN,
+  unless a brand was given explicitly when it was imported.
+*/
+
+use crate::handlers::products::{db_get_all_products, max_price, min_price, GetProductsParams};
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use auth::audit::{snapshot, RequestMetadata};
+use auth::errors::ServiceError;
+use auth::scopes::*;
+use auth::Authorized;
+use common_rs::graphql::{GraphConnection, Node, PaginationParams};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::Text;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/brands";
+
+define_sql_function!(fn brand_for_gtin(gtin: Text) -> Nullable<Integer>);
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_brands)
+        .service(get_brand)
+        .service(get_brand_products)
+        .service(create_brand)
+        .service(update_brand)
+        .service(delete_brand),
+    );
+  }
+}
+
+/// Maps the constraint violations of `brands` and `brand_gs1_prefixes` to the errors the client can act on
+fn brand_error(e: DieselError) -> ServiceError {
+  match e {
+    DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+      if info.constraint_name() == Some("brand_gs1_prefixes_pkey") =>
+    {
+      ServiceError::Conflict("GS1 prefix is already registered to a brand".to_string())
+    }
+    DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+      ServiceError::Conflict("A brand with this name already exists".to_string())
+    }
+    DieselError::DatabaseError(DatabaseErrorKind::CheckViolation, _) => {
+      ServiceError::BadRequest("GS1 prefixes must be 6 to 12 digits".to_string())
+    }
+    e => e.into(),
+  }
+}
+
+fn get_by_id(conn: &mut PgConnection, id: i32) -> Result<Brand, ServiceError> {
+  brands::table
+    .find(id)
+    .first::<Brand>(conn)
+    .optional()?
+    .ok_or(ServiceError::NotFound(Some("Brand not found".to_string())))
+}
+
+/// Lowest and highest reported unit price per currency of the products matching `gtins`, grouped by product
+fn db_get_price_ranges(conn: &mut PgConnection, gtins: &[String]) -> QueryResult<Vec<(String, PriceRange)>> {
+  Ok(
+    price_reports::table
+      .filter(price_reports::gtin.eq_any(gtins))
+      .group_by((price_reports::gtin, price_reports::currency))
+      .select((price_reports::gtin, price_reports::currency, min_price(), max_price()))
+      .order((price_reports::gtin.asc(), price_reports::currency.asc()))
+      .load::<(
+        String,
+        String,
+        Option<bigdecimal::BigDecimal>,
+        Option<bigdecimal::BigDecimal>,
+      )>(conn)?
+      .into_iter()
+      .filter_map(|(gtin, currency, min, max)| {
+        Some((
+          gtin,
+          PriceRange {
+            currency,
+            min: min?,
+            max: max?,
+          },
+        ))
+      })
+      .collect(),
+  )
+}
+
+fn db_get_brand_response(conn: &mut PgConnection, brand: Brand) -> Result<BrandResponse, ServiceError> {
+  let gs1_prefixes = brand_gs1_prefixes::table
+    .filter(brand_gs1_prefixes::brand_id.eq(brand.id))
+    .order(brand_gs1_prefixes::prefix.asc())
+    .select(brand_gs1_prefixes::prefix)
+    .load::<String>(conn)?;
+  let product_count = products::table
+    .filter(products::brand_id.eq(brand.id))
+    .count()
+    .get_result::<i64>(conn)?;
+  let price_ranges = price_reports::table
+    .inner_join(products::table)
+    .filter(products::brand_id.eq(brand.id))
+    .group_by(price_reports::currency)
+    .select((price_reports::currency, min_price(), max_price()))
+    .order(price_reports::currency.asc())
+    .load::<(String, Option<bigdecimal::BigDecimal>, Option<bigdecimal::BigDecimal>)>(conn)?
+    .into_iter()
+    .filter_map(|(currency, min, max)| {
+      Some(PriceRange {
+        currency,
+        min: min?,
+        max: max?,
+      })
+    })
+    .collect();
+
+  Ok(BrandResponse {
+    brand,
+    gs1_prefixes,
+    product_count,
+    price_ranges,
+  })
+}
+
+/// Registers `prefixes` to the brand, unbranded products with a matching GTIN join it
+fn db_add_prefixes(conn: &mut PgConnection, brand_id: i32, prefixes: &[String]) -> Result<(), ServiceError> {
+  diesel::insert_into(brand_gs1_prefixes::table)
+    .values(
+      prefixes
+        .iter()
+        .unique()
+        .map(|prefix| BrandGs1Prefix {
+          prefix: prefix.clone(),
+          brand_id,
+        })
+        .collect::<Vec<_>>(),
+    )
+    .execute(conn)
+    .map_err(brand_error)?;
+
+  diesel::update(
+    products::table
+      .filter(products::brand_id.is_null())
+      .filter(brand_for_gtin(products::gtin).is_not_null()),
+  )
+  .set(products::brand_id.eq(brand_for_gtin(products::gtin)))
+  .execute(conn)?;
+  Ok(())
+}
+
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Vec<Brand>),
+  ),
+)]
+#[get("")]
+pub(crate) async fn get_brands(db: web::Data<Pool>) -> Result<HttpResponse, actix_web::Error> {
+  let brands = web::block(move || {
+    let mut conn = db.get().unwrap();
+    brands::table.order(brands::name.asc()).load::<Brand>(&mut conn)
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(brands))
+}
+
+/// The brand page, with the range its products are priced at
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = BrandResponse),
+    (status = NOT_FOUND, description = "Brand not found"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Brand id"),
+  ),
+)]
+#[get("/{id}")]
+pub(crate) async fn get_brand(id: web::Path<i32>, db: web::Data<Pool>) -> Result<HttpResponse, actix_web::Error> {
+  let response = web::block(move || {
+    let mut conn = db.get().unwrap();
+    let brand = get_by_id(&mut conn, *id)?;
+    db_get_brand_response(&mut conn, brand)
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(response))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BrandProductConnection {
+  #[serde(flatten)]
+  pub connection: GraphConnection<BrandProductResponse>,
+}
+
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = BrandProductConnection),
+    (status = NOT_FOUND, description = "Brand not found"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Brand id"),
+    ("first" = Option<i32>, Query, description = "Number of items after cursor"),
+    ("after" = Option<String>, Query, description = "Cursor for forward pagination"),
+    ("last" = Option<i32>, Query, description = "Number of items before cursor"),
+    ("before" = Option<String>, Query, description = "Cursor for backward pagination"),
+  ),
+)]
+#[get("/{id}/products")]
+pub(crate) async fn get_brand_products(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  query: web::Query<PaginationParams<String>>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let response = web::block(move || {
+    let brand = get_by_id(&mut db.get().unwrap(), *id)?;
+    let products = db_get_all_products(
+      db.clone(),
+      GetProductsParams {
+        pagination_params: query.into_inner(),
+        search: None,
+        hide_unpriced: None,
+        minimum_price: None,
+        maximum_price: None,
+        currency: None,
+        loyalty_member: None,
+        sort: None,
+        order: None,
+        company_id: None,
+        category_id: None,
+        brand_id: Some(brand.id),
+      },
+    )
+    .map_err(|e| ServiceError::BadRequest(e.to_string()))?
+    .connection;
+
+    let gtins = products
+      .edges
+      .iter()
+      .map(|edge| edge.node.product.gtin.clone())
+      .collect::<Vec<_>>();
+    let mut price_ranges = db_get_price_ranges(&mut db.get().unwrap(), &gtins)?
+      .into_iter()
+      .into_group_map();
+
+    Ok::<_, ServiceError>(BrandProductConnection {
+      connection: GraphConnection {
+        edges: products
+          .edges
+          .into_iter()
+          .map(|edge| Node {
+            cursor: edge.cursor,
+            node: BrandProductResponse {
+              price_ranges: price_ranges.remove(&edge.node.product.gtin).unwrap_or_default(),
+              product: edge.node,
+            },
+          })
+          .collect(),
+        page_info: products.page_info,
+      },
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(response))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateBrandRequest {
+  pub name: String,
+  pub manufacturer: Option<String>,
+  /// Leading digits of a GTIN-13, a 6 digit UPC-A company prefix such as 009800 is registered as 0009800
+  #[serde(default)]
+  pub gs1_prefixes: Vec<String>,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = CreateBrandRequest, content_type = "application/json"),
+  responses(
+    (status = CREATED, body = BrandResponse),
+    (status = BAD_REQUEST, description = "Invalid GS1 prefix"),
+    (status = 401),
+    (status = CONFLICT, description = "The name or a GS1 prefix is already taken"),
+  ),
+)]
+#[post("")]
+pub(crate) async fn create_brand(
+  db: web::Data<Pool>,
+  claims: Authorized<Or<CreateAll, CreateProduct>>,
+  metadata: RequestMetadata,
+  body: web::Json<CreateBrandRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let body = body.into_inner();
+  if body.name.trim().is_empty() {
+    return Err(ServiceError::BadRequest("Name must not be empty".to_string()).into());
+  }
+
+  let response = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let brand = diesel::insert_into(brands::table)
+        .values(NewBrand {
+          name: &body.name,
+          manufacturer: body.manufacturer.as_deref(),
+        })
+        .get_result::<Brand>(conn)
+        .map_err(brand_error)?;
+      db_add_prefixes(conn, brand.id, &body.gs1_prefixes)?;
+
+      let response = db_get_brand_response(conn, brand)?;
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("create")
+        .resource("brand")
+        .resource_id(response.brand.id.to_string())
+        .maybe_after(snapshot(&response))
+        .call()?;
+      Ok::<_, ServiceError>(response)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Created().json(response))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateBrandRequest {
+  pub name: Option<String>,
+  #[serde(default, with = "::serde_with::rust::double_option")]
+  #[schema(value_type = Option<String>)]
+  pub manufacturer: Option<Option<String>>,
+  /// Replaces every prefix of the brand, products keep the brand they were assigned under a removed prefix
+  pub gs1_prefixes: Option<Vec<String>>,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = UpdateBrandRequest, content_type = "application/json"),
+  responses(
+    (status = OK, body = BrandResponse),
+    (status = BAD_REQUEST, description = "Invalid GS1 prefix"),
+    (status = 401),
+    (status = NOT_FOUND, description = "Brand not found"),
+    (status = CONFLICT, description = "The name or a GS1 prefix is already taken"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Brand id"),
+  ),
+)]
+#[patch("/{id}")]
+pub(crate) async fn update_brand(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<UpdateAll, UpdateProduct>>,
+  metadata: RequestMetadata,
+  body: web::Json<UpdateBrandRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let body = body.into_inner();
+  if body.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+    return Err(ServiceError::BadRequest("Name must not be empty".to_string()).into());
+  }
+
+  let response = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let before = get_by_id(conn, *id)?;
+      let before = db_get_brand_response(conn, before)?;
+      let brand = diesel::update(brands::table.find(before.brand.id))
+        .set(BrandChangeset {
+          name: body.name,
+          manufacturer: body.manufacturer,
+          updated_at: Some(chrono::Utc::now().naive_utc()),
+        })
+        .get_result::<Brand>(conn)
+        .map_err(brand_error)?;
+      if let Some(gs1_prefixes) = body.gs1_prefixes {
+        diesel::delete(brand_gs1_prefixes::table.filter(brand_gs1_prefixes::brand_id.eq(brand.id))).execute(conn)?;
+        db_add_prefixes(conn, brand.id, &gs1_prefixes)?;
+      }
+
+      let response = db_get_brand_response(conn, brand)?;
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("update")
+        .resource("brand")
+        .resource_id(response.brand.id.to_string())
+        .maybe_before(snapshot(&before))
+        .maybe_after(snapshot(&response))
+        .call()?;
+      Ok::<_, ServiceError>(response)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(response))
+}
+
+/// Deletes a brand along with its prefixes, its products are left without a brand
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Brand),
+    (status = 401),
+    (status = NOT_FOUND, description = "Brand not found"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Brand id"),
+  ),
+)]
+#[delete("/{id}")]
+pub(crate) async fn delete_brand(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<DeleteAll, DeleteProduct>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+
+  let brand = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let before = get_by_id(conn, *id)?;
+      let before = db_get_brand_response(conn, before)?;
+      let brand = diesel::delete(brands::table.find(before.brand.id)).get_result::<Brand>(conn)?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("delete")
+        .resource("brand")
+        .resource_id(brand.id.to_string())
+        .maybe_before(snapshot(&before))
+        .call()?;
+      Ok::<_, ServiceError>(brand)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(brand))
+}
@@ -0,0 +1,286 @@
+/*
+  Name: categories.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/categories`, the hierarchical product taxonomy such as "Dairy > Milk". Products
+  are assigned through `handlers::products::put_product_categories`.
+*/
+
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use auth::audit::{snapshot, RequestMetadata};
+use auth::errors::ServiceError;
+use auth::scopes::*;
+use auth::Authorized;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/categories";
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_categories)
+        .service(get_category)
+        .service(create_category)
+        .service(update_category)
+        .service(delete_category),
+    );
+  }
+}
+
+/// Maps the constraint violations of `categories` to the errors the client can act on
+fn category_error(e: DieselError) -> ServiceError {
+  match e {
+    DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+      ServiceError::Conflict("A category with this name already exists at this level".to_string())
+    }
+    DieselError::DatabaseError(DatabaseErrorKind::CheckViolation, _) => {
+      ServiceError::BadRequest("A category can't be moved below itself".to_string())
+    }
+    DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+      ServiceError::BadRequest("Parent category not found".to_string())
+    }
+    e => e.into(),
+  }
+}
+
+fn get_by_id(conn: &mut PgConnection, id: i32) -> Result<Category, ServiceError> {
+  categories::table
+    .find(id)
+    .first::<Category>(conn)
+    .optional()?
+    .ok_or(ServiceError::NotFound(Some("Category not found".to_string())))
+}
+
+/// Every category, depth first so each category directly follows its parent
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Vec<Category>),
+  ),
+)]
+#[get("")]
+pub(crate) async fn get_categories(db: web::Data<Pool>) -> Result<HttpResponse, actix_web::Error> {
+  let categories = web::block(move || {
+    let mut conn = db.get().unwrap();
+    categories::table
+      .order(categories::path.asc())
+      .load::<Category>(&mut conn)
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(categories))
+}
+
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = CategoryResponse),
+    (status = NOT_FOUND, description = "Category not found"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Category id"),
+  ),
+)]
+#[get("/{id}")]
+pub(crate) async fn get_category(id: web::Path<i32>, db: web::Data<Pool>) -> Result<HttpResponse, actix_web::Error> {
+  let response = web::block(move || {
+    let mut conn = db.get().unwrap();
+    let category = get_by_id(&mut conn, *id)?;
+
+    let ancestors = categories::table
+      .filter(categories::id.eq_any(&category.path[..category.path.len() - 1]))
+      .order(categories::path.asc())
+      .load::<Category>(&mut conn)?;
+    let children = categories::table
+      .filter(categories::parent_id.eq(category.id))
+      .order(categories::name.asc())
+      .load::<Category>(&mut conn)?;
+    Ok::<_, ServiceError>(CategoryResponse {
+      category,
+      ancestors,
+      children,
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(response))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = NewCategory, content_type = "application/json"),
+  responses(
+    (status = CREATED, body = Category),
+    (status = BAD_REQUEST, description = "Parent category not found"),
+    (status = 401),
+    (status = CONFLICT, description = "A sibling already has this name"),
+  ),
+)]
+#[post("")]
+pub(crate) async fn create_category(
+  db: web::Data<Pool>,
+  claims: Authorized<Or<CreateAll, CreateProduct>>,
+  metadata: RequestMetadata,
+  body: web::Json<NewCategory>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let new_category = body.into_inner();
+  if new_category.name.trim().is_empty() {
+    return Err(ServiceError::BadRequest("Name must not be empty".to_string()).into());
+  }
+
+  let category = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let category = diesel::insert_into(categories::table)
+        .values(&new_category)
+        .get_result::<Category>(conn)
+        .map_err(category_error)?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("create")
+        .resource("category")
+        .resource_id(category.id.to_string())
+        .maybe_after(snapshot(&category))
+        .call()?;
+      Ok::<_, ServiceError>(category)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Created().json(category))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateCategoryRequest {
+  pub name: Option<String>,
+  /// Moves the category along with its subcategories, `null` makes it a top level category
+  #[serde(default, with = "::serde_with::rust::double_option")]
+  #[schema(value_type = Option<i32>)]
+  pub parent_id: Option<Option<i32>>,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = UpdateCategoryRequest, content_type = "application/json"),
+  responses(
+    (status = OK, body = Category),
+    (status = BAD_REQUEST, description = "Parent category not found, or is the category itself or one of its descendants"),
+    (status = 401),
+    (status = NOT_FOUND, description = "Category not found"),
+    (status = CONFLICT, description = "A sibling already has this name"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Category id"),
+  ),
+)]
+#[patch("/{id}")]
+pub(crate) async fn update_category(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<UpdateAll, UpdateProduct>>,
+  metadata: RequestMetadata,
+  body: web::Json<UpdateCategoryRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let body = body.into_inner();
+  if body.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+    return Err(ServiceError::BadRequest("Name must not be empty".to_string()).into());
+  }
+
+  let category = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let before = get_by_id(conn, *id)?;
+      let category = diesel::update(categories::table.find(before.id))
+        .set(CategoryChangeset {
+          parent_id: body.parent_id,
+          name: body.name,
+          updated_at: Some(chrono::Utc::now().naive_utc()),
+        })
+        .get_result::<Category>(conn)
+        .map_err(category_error)?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("update")
+        .resource("category")
+        .resource_id(category.id.to_string())
+        .maybe_before(snapshot(&before))
+        .maybe_after(snapshot(&category))
+        .call()?;
+      Ok::<_, ServiceError>(category)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(category))
+}
+
+/// Deletes a category without subcategories, its products are unassigned from it
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Category),
+    (status = 401),
+    (status = NOT_FOUND, description = "Category not found"),
+    (status = CONFLICT, description = "Category has subcategories"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Category id"),
+  ),
+)]
+#[delete("/{id}")]
+pub(crate) async fn delete_category(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<DeleteAll, DeleteProduct>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+
+  let category = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let category = diesel::delete(categories::table.find(*id))
+        .get_result::<Category>(conn)
+        .optional()
+        .map_err(|e| match e {
+          DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+            ServiceError::Conflict("Category has subcategories, move or delete them first".to_string())
+          }
+          e => e.into(),
+        })?
+        .ok_or(ServiceError::NotFound(Some("Category not found".to_string())))?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("delete")
+        .resource("category")
+        .resource_id(category.id.to_string())
+        .maybe_before(snapshot(&category))
+        .call()?;
+      Ok::<_, ServiceError>(category)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(category))
+}
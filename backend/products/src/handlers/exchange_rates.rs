@@ -0,0 +1,197 @@
+/*
+  Name: exchange_rates.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/exchange_rates`, the daily rates prices are converted at when another currency
+  is asked for with `?currency=`. Rates are loaded from the CSV file in `EXCHANGE_RATES_FILE` on start and can be
+  replaced by uploading the same format.
+*/
+
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{get, put, web, HttpResponse};
+use auth::audit::RequestMetadata;
+use auth::errors::ServiceError;
+use auth::scopes::*;
+use auth::Authorized;
+use bigdecimal::{BigDecimal, Zero};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::{Bpchar, Numeric, Timestamptz};
+use diesel::upsert::excluded;
+use serde::Deserialize;
+use std::str::FromStr;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/exchange_rates";
+
+/// What prices are compared and bucketed in when no `currency` is given
+pub(crate) const DEFAULT_CURRENCY: &str = "USD";
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_exchange_rates)
+        .service(put_exchange_rates),
+    );
+  }
+}
+
+define_sql_function! {
+  /// `price` in `to_currency` at the rates of the day it was reported, `NULL` without a rate for either currency
+  fn convert_price(price: Numeric, from_currency: Bpchar, to_currency: Bpchar, reported_at: Timestamptz)
+    -> Nullable<Numeric>;
+}
+
+/// The ISO 4217 code `currency` stands for, in any case
+pub(crate) fn db_find_currency(conn: &mut PgConnection, currency: &str) -> Result<String, ServiceError> {
+  iso_4217::table
+    .find(currency.to_uppercase())
+    .select(iso_4217::code)
+    .first::<String>(conn)
+    .optional()?
+    .ok_or_else(|| ServiceError::BadRequest(format!("Unknown currency `{}`", currency)))
+}
+
+/// Rows of `currency,date,rate`, blank lines, `#` comments and a header are skipped
+fn parse_rates(csv: &str) -> Result<Vec<ExchangeRate>, String> {
+  csv
+    .lines()
+    .enumerate()
+    .map(|(index, line)| (index + 1, line.trim()))
+    .filter(|(_, line)| !line.is_empty() && !line.starts_with('#') && !line.starts_with("currency,"))
+    .map(|(number, line)| {
+      let invalid = |reason: &str| format!("Line {}: {}", number, reason);
+      let [currency, date, rate] = line.split(',').map(str::trim).collect::<Vec<_>>()[..] else {
+        return Err(invalid("expected `currency,date,rate`"));
+      };
+      let rate = BigDecimal::from_str(rate).map_err(|_| invalid("`rate` isn't a number"))?;
+      if rate <= BigDecimal::zero() {
+        return Err(invalid("`rate` must be positive"));
+      }
+      Ok(ExchangeRate {
+        currency: currency.to_uppercase(),
+        date: chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid("`date` isn't YYYY-MM-DD"))?,
+        rate,
+      })
+    })
+    .collect()
+}
+
+/// Adds the rates, replacing those of the same currency and day
+fn db_upsert_rates(conn: &mut PgConnection, rates: &[ExchangeRate]) -> QueryResult<usize> {
+  // stay well under the bind parameter limit of Postgres
+  rates.chunks(10_000).try_fold(0, |count, chunk| {
+    Ok(
+      count
+        + diesel::insert_into(exchange_rates::table)
+          .values(chunk)
+          .on_conflict((exchange_rates::currency, exchange_rates::date))
+          .do_update()
+          .set(exchange_rates::rate.eq(excluded(exchange_rates::rate)))
+          .execute(conn)?,
+    )
+  })
+}
+
+fn db_import_rates(conn: &mut PgConnection, csv: &str) -> Result<usize, ServiceError> {
+  let rates = parse_rates(csv).map_err(ServiceError::BadRequest)?;
+  db_upsert_rates(conn, &rates).map_err(|err| match err {
+    DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+      ServiceError::BadRequest("Unknown currency".to_string())
+    }
+    err => err.into(),
+  })
+}
+
+/// Imports the rates of the file at `path`, see `parse_rates` for its format
+pub fn load_file(pool: &Pool, path: &str) -> anyhow::Result<usize> {
+  let csv = std::fs::read_to_string(path)?;
+  let mut conn = pool.get()?;
+  conn
+    .transaction(|conn| db_import_rates(conn, &csv))
+    .map_err(|err| anyhow::anyhow!("{}", err))
+}
+
+#[derive(Deserialize)]
+struct ExchangeRatesParams {
+  /// Defaults to today
+  date: Option<chrono::NaiveDate>,
+}
+
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Vec<ExchangeRate>, description = "The latest rate of each currency on or before `date`"),
+  ),
+  params(
+    ("date" = Option<chrono::NaiveDate>, Query, description = "Day the rates are in effect, defaults to today"),
+  ),
+)]
+#[get("")]
+pub(crate) async fn get_exchange_rates(
+  db: web::Data<Pool>,
+  query: web::Query<ExchangeRatesParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let date = query.date.unwrap_or_else(|| chrono::Utc::now().date_naive());
+
+  let rates = web::block(move || {
+    exchange_rates::table
+      .filter(exchange_rates::date.le(date))
+      .distinct_on(exchange_rates::currency)
+      .order((exchange_rates::currency.asc(), exchange_rates::date.desc()))
+      .load::<ExchangeRate>(&mut db.get().unwrap())
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(rates))
+}
+
+/// Adds daily rates, replacing any already known for the same currency and day
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(
+    content = String,
+    content_type = "text/csv",
+    description = "Rows of `currency,date,rate`, the units of `currency` per US dollar on `date`",
+  ),
+  responses(
+    (status = OK, body = usize, description = "Number of rates imported"),
+    (status = BAD_REQUEST, description = "A malformed row or an unknown currency"),
+    (status = 401),
+  ),
+)]
+#[put("")]
+pub(crate) async fn put_exchange_rates(
+  db: web::Data<Pool>,
+  claims: Authorized<UpdateAll>,
+  metadata: RequestMetadata,
+  body: String,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+
+  let count = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let count = db_import_rates(conn, &body)?;
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("import")
+        .resource("exchange_rate")
+        .after(serde_json::json!({ "count": count }))
+        .call()?;
+      Ok::<_, ServiceError>(count)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(count))
+}
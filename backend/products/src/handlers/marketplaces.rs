@@ -19,9 +19,9 @@ use actix_web::post;
 use actix_web::web;
 use actix_web::web::ServiceConfig;
 use actix_web::HttpResponse;
-use actix_web_httpauth::extractors::bearer::BearerAuth;
 use auth::errors::ServiceError;
-use auth::models::PermissionName;
+use auth::scopes::*;
+use auth::Authorized;
 use diesel::insert_into;
 use diesel::ExpressionMethods;
 use diesel::JoinOnDsl;
@@ -30,7 +30,7 @@ use diesel::RunQueryDsl;
 use diesel::SelectableHelper;
 use serde::Deserialize;
 use std::vec::Vec;
-use validator_rs::ValidatorBuilder;
+use validator_rs::openapi_security;
 
 pub(crate) const V1_PATH: &str = "/api/v1/marketplaces";
 
@@ -230,6 +230,7 @@ fn db_insert_marketplace(pool: web::Data<Pool>, new_marketplace: NewMarketplace)
   Ok(true)
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   request_body(description = "A marketplace",
@@ -242,21 +243,13 @@ fn db_insert_marketplace(pool: web::Data<Pool>, new_marketplace: NewMarketplace)
     (status = 401),
     (status = 500),
   ),
-  security(
-    ("http" = []),
-  ),
 )]
 #[post("")]
 pub(crate) async fn post_marketplace(
   #[allow(unused_variables)] pool: web::Data<Pool>,
   #[allow(unused_variables)] new_marketplace: web::Json<NewMarketplace>,
-  auth: BearerAuth,
+  _claims: Authorized<CreateAll>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_scope(PermissionName::CreateAll)
-    .validate(&claims.permissions)?;
-
   log::error!("This endpoint has not been updated");
   return Err(ServiceError::InternalServerError)?;
 
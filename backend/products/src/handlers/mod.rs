@@ -18,10 +18,18 @@
     glob style here
 */
 
+pub mod brands;
+pub mod categories;
 pub mod companies;
+pub mod exchange_rates;
 pub mod marketplaces;
+pub mod notifications;
 pub mod price_reports;
+pub mod price_watches;
+pub mod product_listings;
 pub mod products;
 pub mod products_to_images;
+pub mod receipts;
 pub mod shopping_lists;
 pub mod units;
+pub mod webhooks;
@@ -0,0 +1,166 @@
+/*
+  Name: notifications.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/notifications`, the caller's in-app inbox written by `notifier::db_add_to_inbox`
+*/
+
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{get, post, web, HttpResponse};
+use auth::errors::ServiceError;
+use auth::Authorized;
+use common_rs::graphql::{GraphConnection, PaginationParams};
+use common_rs::paginate;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Nullable, Timestamp};
+use serde::Deserialize;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/notifications";
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_notifications)
+        .service(mark_all_read)
+        .service(mark_read),
+    );
+  }
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+  #[serde(flatten)]
+  pagination_params: PaginationParams<i64>,
+  #[serde(default)]
+  unread: bool,
+}
+
+fn db_get_notifications(
+  conn: &mut PgConnection,
+  user_id: i32,
+  params: NotificationParams,
+) -> Result<GraphConnection<Notification>, diesel::result::Error> {
+  let query = notifications::table
+    .filter(notifications::user_id.eq(user_id))
+    .filter(notifications::read_at.is_null().or((!params.unread).into_sql::<Bool>()));
+
+  paginate!(
+    query,
+    params.pagination_params,
+    conn,
+    notifications::id,
+    |notification: &Notification| notification.id.to_string(),
+    Notification
+  )
+}
+
+/// Newest first unless paginating forward with `first`
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = GraphConnection<Notification>),
+    (status = 401),
+  ),
+  params(
+    ("first" = Option<i32>, Query, description = "Number of items after cursor"),
+    ("after" = Option<i64>, Query, description = "Cursor for forward pagination"),
+    ("last" = Option<i32>, Query, description = "Number of items before cursor"),
+    ("before" = Option<i64>, Query, description = "Cursor for backward pagination"),
+    ("unread" = Option<bool>, Query, description = "Only notifications that haven't been read"),
+  ),
+)]
+#[get("")]
+pub(crate) async fn get_notifications(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+  query: web::Query<NotificationParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let result = web::block(move || db_get_notifications(&mut db.get().unwrap(), user_id, query.into_inner())).await?;
+
+  match result {
+    Ok(res) => Ok(HttpResponse::Ok().json(res)),
+    Err(err) => {
+      log::error!("{}", err);
+      Ok(Err(ServiceError::InternalServerError)?)
+    }
+  }
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = usize, description = "Number of notifications marked as read"),
+    (status = 401),
+  ),
+)]
+#[post("/read")]
+pub(crate) async fn mark_all_read(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let count = web::block(move || {
+    let mut conn = db.get().unwrap();
+    diesel::update(
+      notifications::table
+        .filter(notifications::user_id.eq(user_id))
+        .filter(notifications::read_at.is_null()),
+    )
+    .set(notifications::read_at.eq(diesel::dsl::now))
+    .execute(&mut conn)
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(count))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Notification),
+    (status = 401),
+    (status = NOT_FOUND, description = "Notification not found"),
+  ),
+  params(
+    ("id" = i64, Path, description = "Notification id"),
+  ),
+)]
+#[post("/{id}/read")]
+pub(crate) async fn mark_read(
+  id: web::Path<i64>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let notification = web::block(move || {
+    let mut conn = db.get().unwrap();
+    // keeps the time it was first read
+    diesel::update(
+      notifications::table
+        .find(*id)
+        .filter(notifications::user_id.eq(user_id)),
+    )
+    .set(notifications::read_at.eq(diesel::dsl::sql::<Nullable<Timestamp>>(
+      "COALESCE(read_at, CURRENT_TIMESTAMP)",
+    )))
+    .get_result::<Notification>(&mut conn)
+    .optional()?
+    .ok_or(ServiceError::NotFound(Some("Notification not found".to_string())))
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(notification))
+}
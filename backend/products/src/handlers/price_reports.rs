@@ -8,14 +8,17 @@ use actix_web::post;
 use actix_web::web;
 use actix_web::web::ServiceConfig;
 use actix_web::HttpResponse;
-use actix_web_httpauth::extractors::bearer::BearerAuth;
 use anyhow::anyhow;
+use auth::audit::RequestMetadata;
 use auth::errors::ServiceError;
-use auth::models::PermissionName;
+use auth::scopes::*;
+use auth::Authorized;
+use bigdecimal::BigDecimal;
 use common_rs::graphql::GraphConnection;
 use common_rs::graphql::Node;
 use common_rs::graphql::PageInfo;
 use common_rs::graphql::PaginationParams;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use diesel::Connection;
 use diesel::ExpressionMethods;
 use diesel::JoinOnDsl;
@@ -27,9 +30,13 @@ use serde::Serialize;
 use serde_with::serde_as;
 use serde_with::DisplayFromStr;
 use utoipa::ToSchema;
-use validator_rs::ValidatorBuilder;
+use validator_rs::openapi_security;
 
+use crate::handlers::exchange_rates::{convert_price, db_find_currency, DEFAULT_CURRENCY};
+use crate::handlers::price_watches::db_evaluate_price_watches;
 use crate::models::PriceResponse;
+use crate::notifier::{Delivery, Notifiers};
+use crate::webhooks;
 
 pub(crate) const V1_PATH: &str = "/api/v1/price_report";
 
@@ -39,7 +46,8 @@ pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
       web::scope(V1_PATH)
         .service(get_price_reports_for_gtin)
         .service(get_price_reports_for_gtins)
-        .service(post_price_report),
+        .service(post_price_report)
+        .service(anonymize_price_reports),
     );
   }
 }
@@ -51,12 +59,30 @@ struct PriceReportParams {
   pagination_params: PaginationParams<i64>,
   #[serde_as(as = "Option<DisplayFromStr>")]
   marketplace_id: Option<i32>,
+  currency: Option<String>,
+}
+
+/// The ISO 4217 code of the currency asked for, if any
+fn resolve_currency(db: &web::Data<Pool>, params: &PriceReportParams) -> Result<Option<String>, ServiceError> {
+  params
+    .currency
+    .as_deref()
+    .map(|currency| db_find_currency(&mut db.get().unwrap(), currency))
+    .transpose()
+}
+
+fn converted_price(currency: &Option<String>, price: Option<BigDecimal>) -> Option<ConvertedPrice> {
+  Some(ConvertedPrice {
+    price: price?,
+    currency: currency.clone()?,
+  })
 }
 
 fn db_get_price_report_for_gtin(
   pool: web::Data<Pool>,
   gtin: &str,
   params: PriceReportParams,
+  currency: Option<String>,
 ) -> anyhow::Result<GraphConnection<PriceResponse>> {
   let mut conn = pool.get()?;
 
@@ -116,6 +142,12 @@ fn db_get_price_report_for_gtin(
       Marketplace::as_select(),
       Option::<PhysicalMarketplace>::as_select(),
       Option::<OnlineMarketplace>::as_select(),
+      convert_price(
+        price_reports::price,
+        price_reports::currency,
+        currency.clone().unwrap_or_else(|| DEFAULT_CURRENCY.to_string()),
+        price_reports::reported_at,
+      ),
     ))
     .load::<(
       PriceReport,
@@ -123,10 +155,11 @@ fn db_get_price_report_for_gtin(
       Marketplace,
       Option<PhysicalMarketplace>,
       Option<OnlineMarketplace>,
+      Option<BigDecimal>,
     )>(&mut conn)?
     .into_iter()
     .map(
-      |(price_report, company, marketplace, physical_marketplace, online_marketplace)| PriceResponse {
+      |(price_report, company, marketplace, physical_marketplace, online_marketplace, converted)| PriceResponse {
         price_report,
         company: company.clone(),
         // TODO we should graphql federation for this nested behavior, not this manual join. w/e -@codyduong
@@ -136,6 +169,7 @@ fn db_get_price_report_for_gtin(
           physical_marketplace,
           online_marketplace,
         },
+        converted: converted_price(&currency, converted),
       },
     )
     .collect();
@@ -185,12 +219,14 @@ fn db_get_price_report_for_gtin(
   context_path = V1_PATH,
   responses(
     (status = OK, body = GraphConnection<PriceResponse>),
+    (status = BAD_REQUEST, description = "Unknown currency"),
     (status = 401),
     (status = 500),
   ),
   params(
     ("gtin" = String, Path, description = "gtin"),
     ("marketplace_id" = Option<String>, Query),
+    ("currency" = Option<String>, Query, description = "ISO 4217 code to also give each price in, converted at the exchange rates of the day it was reported"),
     ("first" = Option<i32>, Query, description = "Number of items after cursor"),
     ("after" = Option<i32>, Query, description = "Cursor for forward pagination"),
     ("last" = Option<i32>, Query, description = "Number of items before cursor"),
@@ -207,14 +243,19 @@ pub(crate) async fn get_price_reports_for_gtin(
   // auth: BearerAuth,
   query: web::Query<PriceReportParams>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let result = { web::block(move || db_get_price_report_for_gtin(db, &gtin.into_inner(), query.into_inner())).await };
+  let result = web::block(move || {
+    let currency = resolve_currency(&db, &query)?;
+    Ok::<_, ServiceError>(db_get_price_report_for_gtin(
+      db,
+      &gtin.into_inner(),
+      query.into_inner(),
+      currency,
+    ))
+  })
+  .await??;
 
   match result {
-    Ok(Ok(res)) => Ok(HttpResponse::Ok().json(res)),
-    Ok(Err(err)) => {
-      log::error!("{}", err);
-      Ok(Err(ServiceError::InternalServerError)?)
-    }
+    Ok(res) => Ok(HttpResponse::Ok().json(res)),
     Err(err) => {
       log::error!("{}", err);
       Ok(Err(ServiceError::InternalServerError)?)
@@ -232,6 +273,7 @@ fn db_get_price_report_for_gtins(
   pool: web::Data<Pool>,
   gtins: Vec<String>,
   params: &PriceReportParams,
+  currency: Option<String>,
 ) -> anyhow::Result<PriceResponses> {
   let mut conn = pool.get()?;
 
@@ -291,6 +333,12 @@ fn db_get_price_report_for_gtins(
       Marketplace::as_select(),
       Option::<PhysicalMarketplace>::as_select(),
       Option::<OnlineMarketplace>::as_select(),
+      convert_price(
+        price_reports::price,
+        price_reports::currency,
+        currency.clone().unwrap_or_else(|| DEFAULT_CURRENCY.to_string()),
+        price_reports::reported_at,
+      ),
     ))
     .load::<(
       PriceReport,
@@ -298,6 +346,7 @@ fn db_get_price_report_for_gtins(
       Marketplace,
       Option<PhysicalMarketplace>,
       Option<OnlineMarketplace>,
+      Option<BigDecimal>,
     )>(&mut conn)?;
 
   // We now have all the PriceResponse records for the given GTINs within the pagination limits.
@@ -305,7 +354,7 @@ fn db_get_price_report_for_gtins(
 
   let mut grouped_results: std::collections::HashMap<String, Vec<PriceResponse>> = std::collections::HashMap::new();
 
-  for (report, company, marketplace, physical_marketplace, online_marketplace) in all_results {
+  for (report, company, marketplace, physical_marketplace, online_marketplace, converted) in all_results {
     #[allow(clippy::unwrap_or_default)]
     grouped_results
       .entry(report.gtin.clone())
@@ -320,6 +369,7 @@ fn db_get_price_report_for_gtins(
           physical_marketplace,
           online_marketplace,
         },
+        converted: converted_price(&currency, converted),
       });
   }
 
@@ -384,6 +434,7 @@ struct GtinsRequest {
   request_body = GtinsRequest,
   params(
     ("marketplace_id" = Option<String>, Query),
+    ("currency" = Option<String>, Query, description = "ISO 4217 code to also give each price in, converted at the exchange rates of the day it was reported"),
     ("first" = Option<i32>, Query, description = "Number of items after cursor"),
     ("after" = Option<i32>, Query, description = "Cursor for forward pagination"),
     ("last" = Option<i32>, Query, description = "Number of items before cursor"),
@@ -391,6 +442,7 @@ struct GtinsRequest {
   ),
   responses(
       (status = OK, body = PriceResponses),
+      (status = BAD_REQUEST, description = "Unknown currency"),
       (status = 401),
       (status = 500),
   ),
@@ -407,14 +459,14 @@ pub(crate) async fn get_price_reports_for_gtins(
 ) -> Result<HttpResponse, actix_web::Error> {
   let gtins = body.gtins.clone();
 
-  let result = { web::block(move || db_get_price_report_for_gtins(db, gtins, &query.into_inner())).await };
+  let result = web::block(move || {
+    let currency = resolve_currency(&db, &query)?;
+    Ok::<_, ServiceError>(db_get_price_report_for_gtins(db, gtins, &query.into_inner(), currency))
+  })
+  .await??;
 
   match result {
-    Ok(Ok(res)) => Ok(HttpResponse::Ok().json(res)),
-    Ok(Err(err)) => {
-      log::error!("{}", err);
-      Ok(Err(ServiceError::InternalServerError)?)
-    }
+    Ok(res) => Ok(HttpResponse::Ok().json(res)),
     Err(err) => {
       log::error!("{}", err);
       Ok(Err(ServiceError::InternalServerError)?)
@@ -426,43 +478,57 @@ pub(crate) fn db_add_price_report<T: Into<Vec<NewPriceReportDSL>>>(
   db: web::Data<Pool>,
   price_reports_union: T,
   user_id: i32,
-) -> anyhow::Result<bool> {
+) -> anyhow::Result<Vec<Delivery>> {
   let mut conn = db.get()?;
 
-  conn.transaction(|conn| {
-    let price_reports_union = Into::<Vec<NewPriceReportDSL>>::into(price_reports_union);
-
-    let price_reports_only: Vec<_> = price_reports_union
-      .clone()
-      .into_iter()
-      .map(Into::<NewPriceReportPublic>::into)
-      .map(|v| v.with_user(user_id))
-      .collect();
-
-    let inserted_reports = diesel::insert_into(price_reports::table)
-      .values(price_reports_only)
-      .get_results::<PriceReport>(conn)?;
-
-    let price_report_to_marketplaces: Vec<PriceReportToMarketplace> = inserted_reports
-      .into_iter()
-      .zip(price_reports_union.into_iter())
-      .map(|(report, other)| PriceReportToMarketplace {
-        price_report_id: report.id,
-        reported_at: report.reported_at,
-        marketplace_id: other.marketplace_id,
-      })
-      .collect();
-
-    diesel::insert_into(price_report_to_marketplaces::table)
-      .values(price_report_to_marketplaces)
-      .execute(conn)?;
-
-    Ok::<(), diesel::result::Error>(())
-  })?;
-
-  Ok(true)
+  conn
+    .transaction(|conn| {
+      let price_reports_union = Into::<Vec<NewPriceReportDSL>>::into(price_reports_union);
+
+      let price_reports_only: Vec<_> = price_reports_union
+        .clone()
+        .into_iter()
+        .map(Into::<NewPriceReportPublic>::into)
+        .map(|v| v.with_user(user_id))
+        .collect();
+
+      let inserted_reports = diesel::insert_into(price_reports::table)
+        .values(price_reports_only)
+        .get_results::<PriceReport>(conn)?;
+
+      let reports_with_marketplaces: Vec<(PriceReport, i32)> = inserted_reports
+        .into_iter()
+        .zip(price_reports_union)
+        .map(|(report, other)| (report, other.marketplace_id))
+        .collect();
+
+      let price_report_to_marketplaces: Vec<PriceReportToMarketplace> = reports_with_marketplaces
+        .iter()
+        .map(|(report, marketplace_id)| PriceReportToMarketplace {
+          price_report_id: report.id,
+          reported_at: report.reported_at,
+          marketplace_id: *marketplace_id,
+        })
+        .collect();
+
+      diesel::insert_into(price_report_to_marketplaces::table)
+        .values(price_report_to_marketplaces)
+        .execute(conn)?;
+
+      webhooks::enqueue(
+        conn,
+        webhooks::PRICE_REPORT_CREATED,
+        reports_with_marketplaces.iter().map(
+          |(report, marketplace_id)| serde_json::json!({ "price_report": report, "marketplace_id": marketplace_id }),
+        ),
+      )?;
+
+      db_evaluate_price_watches(conn, &reports_with_marketplaces)
+    })
+    .map_err(Into::into)
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   request_body(description = "Price report(s)",
@@ -473,27 +539,100 @@ pub(crate) fn db_add_price_report<T: Into<Vec<NewPriceReportDSL>>>(
   ),
   responses(
     (status = OK, body = bool),
+    (status = BAD_REQUEST, description = "An unknown product, marketplace or currency, or an invalid promotion"),
     (status = 401),
     (status = 500),
   ),
-  security(
-    ("http" = []),
-  )
 )]
 #[post("")]
 pub(crate) async fn post_price_report(
   db: web::Data<Pool>,
+  notifiers: web::Data<Notifiers>,
   body: web::Json<NewPriceReportDSLUnion>,
-  auth: BearerAuth,
+  claims: Authorized<Or<CreateAll, CreatePriceReport>>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::CreateAll, PermissionName::CreatePriceReport])
-    .validate(&claims.permissions)?;
-
   let id = claims.sub;
+  let body = body.into_inner();
+  let reports: &[NewPriceReportDSL] = match &body {
+    NewPriceReportDSLUnion::Single(single) => std::slice::from_ref(single),
+    NewPriceReportDSLUnion::Multiple(multiple) => multiple,
+  };
+  for report in reports {
+    report
+      .price_report
+      .validate_promotion()
+      .map_err(ServiceError::BadRequest)?;
+  }
+
+  let result = web::block(move || db_add_price_report(db, body, id)).await?;
+
+  match result {
+    Ok(deliveries) => {
+      // the inbox was written along with the reports, a slow mail server or webhook shouldn't hold up the reporter
+      actix_rt::spawn(async move { notifiers.notify(&deliveries).await });
+      Ok(HttpResponse::Ok().json(true))
+    }
+    Err(err) => match err.downcast_ref::<DieselError>() {
+      Some(DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+        Err(ServiceError::BadRequest("Unknown product, marketplace or currency".to_string()).into())
+      }
+      _ => {
+        log::error!("{}", err);
+        Ok(Err(ServiceError::InternalServerError)?)
+      }
+    },
+  }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AnonymizePriceReportsRequest {
+  pub user_ids: Vec<i32>,
+}
 
-  let result = web::block(move || db_add_price_report(db, body.into_inner(), id)).await?;
+/// Detaches price reports from the given users and deletes their price watches and notifications, called by the auth
+/// service before it purges their accounts
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body = AnonymizePriceReportsRequest,
+  responses(
+    (status = OK, body = usize, description = "Number of anonymized price reports"),
+    (status = 401),
+    (status = 500),
+  ),
+)]
+#[post("/anonymize")]
+pub(crate) async fn anonymize_price_reports(
+  db: web::Data<Pool>,
+  body: web::Json<AnonymizePriceReportsRequest>,
+  claims: Authorized<Or<DeleteAll, DeleteUser>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let result = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let mut anonymized = 0;
+      for user_id in &body.user_ids {
+        let count = diesel::update(price_reports::table.filter(price_reports::created_by.eq(user_id)))
+          .set(price_reports::created_by.eq(ANONYMOUS_USER_ID))
+          .execute(conn)?;
+        diesel::delete(price_watches::table.filter(price_watches::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(notifications::table.filter(notifications::user_id.eq(user_id))).execute(conn)?;
+
+        auth::audit::record(conn, &metadata)
+          .actor_id(actor_id)
+          .action("anonymize")
+          .resource("price_report")
+          .resource_id(user_id.to_string())
+          .after(serde_json::json!({ "created_by": user_id, "count": count }))
+          .call()?;
+        anonymized += count;
+      }
+      Ok::<_, diesel::result::Error>(anonymized)
+    })
+  })
+  .await?;
 
   match result {
     Ok(res) => Ok(HttpResponse::Ok().json(res)),
@@ -0,0 +1,353 @@
+/*
+  Name: price_watches.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/price_watches`, the caller's watches on a product's price. Watches are evaluated
+  against every new price report by `db_evaluate_price_watches`, and notify through `notifier::Notifiers`.
+*/
+
+use crate::handlers::products::REPORTED_UNIT_PRICE;
+use crate::models::*;
+use crate::notifier::Delivery;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{delete, get, post, web, HttpResponse};
+use auth::errors::ServiceError;
+use auth::Authorized;
+use bigdecimal::BigDecimal;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use itertools::Itertools;
+use serde::Deserialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/price_watches";
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_price_watches)
+        .service(create_price_watch)
+        .service(delete_price_watch),
+    );
+  }
+}
+
+/// Whether the report's marketplace is within the scope of the watch
+fn in_scope(watch: &PriceWatch, marketplace_id: i32, company_id: i32) -> bool {
+  watch.marketplace_id.is_none_or(|id| id == marketplace_id) && watch.company_id.is_none_or(|id| id == company_id)
+}
+
+/// The reason to notify about `price`, `None` if it doesn't reach the watch's thresholds
+fn alert_reason(watch: &PriceWatch, price: &BigDecimal) -> Option<String> {
+  if let Some(target_price) = watch
+    .target_price
+    .as_ref()
+    .filter(|target_price| price <= *target_price)
+  {
+    return Some(format!("at or below your target of {}", target_price));
+  }
+  let (percent_drop, reference_price) = watch.percent_drop.as_ref().zip(watch.reference_price.as_ref())?;
+  let drop = (reference_price - price) * BigDecimal::from(100) / reference_price;
+  (drop >= *percent_drop).then(|| format!("down {}% from {}", drop.round(0), reference_price))
+}
+
+/// Checks the watches on the products of the new reports, given along with the marketplace they were reported at.
+/// Updates the watches and adds their notifications to the inbox, returning them to email and post to webhooks once
+/// the reports are committed.
+pub(crate) fn db_evaluate_price_watches(
+  conn: &mut PgConnection,
+  reports: &[(PriceReport, i32)],
+) -> QueryResult<Vec<Delivery>> {
+  let gtins = reports
+    .iter()
+    .map(|(report, _)| report.gtin.clone())
+    .unique()
+    .collect::<Vec<_>>();
+  let mut watches = price_watches::table
+    .filter(price_watches::gtin.eq_any(&gtins))
+    .order(price_watches::id.asc())
+    .for_update()
+    .load::<PriceWatch>(conn)?;
+  if watches.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let marketplaces = marketplaces::table
+    .filter(marketplaces::id.eq_any(reports.iter().map(|(_, marketplace_id)| *marketplace_id).unique()))
+    .select((marketplaces::id, (marketplaces::company_id, marketplaces::name)))
+    .load::<(i32, (i32, String))>(conn)?
+    .into_iter()
+    .collect::<HashMap<_, _>>();
+  let productnames = products::table
+    .filter(products::gtin.eq_any(&gtins))
+    .select((products::gtin, products::productname))
+    .load::<(String, String)>(conn)?
+    .into_iter()
+    .collect::<HashMap<_, _>>();
+
+  let now = chrono::Utc::now().naive_utc();
+  let mut deliveries = vec![];
+  let mut changed = vec![];
+  for (report, marketplace_id) in reports {
+    let Some((company_id, marketplace_name)) = marketplaces.get(marketplace_id) else {
+      continue;
+    };
+    for watch in watches.iter_mut().filter(|watch| {
+      watch.gtin == report.gtin && watch.currency == report.currency && in_scope(watch, *marketplace_id, *company_id)
+    }) {
+      // a multi-buy is compared by what one unit costs, and a promotion that isn't running or needs a loyalty card by
+      // the regular price, as watches aren't tied to a card
+      let Some(price) = report.current_unit_price(false, now) else {
+        continue;
+      };
+      let reason = alert_reason(watch, &price);
+      let already_notified = watch
+        .last_notified_price
+        .as_ref()
+        .is_some_and(|last_notified_price| price >= *last_notified_price);
+
+      match reason {
+        Some(reason) if !already_notified => {
+          let productname = productnames.get(&report.gtin).unwrap_or(&report.gtin);
+          deliveries.push(Delivery {
+            notification: NewNotification {
+              user_id: watch.user_id,
+              kind: PRICE_ALERT.to_string(),
+              title: format!("Price drop: {}", productname),
+              body: format!(
+                "{} is {} {} at {}, {}",
+                productname, price, report.currency, marketplace_name, reason
+              ),
+              data: serde_json::json!({
+                "price_watch_id": watch.id,
+                "gtin": report.gtin,
+                "price_report_id": report.id,
+                "marketplace_id": marketplace_id,
+                "price": price,
+                "promotion_type": report.promotion_type.filter(|_| report.promotion_applies(false, now)),
+                "currency": report.currency,
+              }),
+            },
+            email: watch.email.clone(),
+            webhook_url: watch.webhook_url.clone(),
+          });
+          watch.reference_price = Some(price.clone());
+          watch.last_notified_price = Some(price);
+          watch.last_notified_at = Some(now);
+        }
+        // the first price seen becomes the reference of a watch created before any report
+        None if watch.reference_price.is_none() => watch.reference_price = Some(price),
+        // re-arm once the price no longer reaches the thresholds
+        None if watch.last_notified_price.is_some() => watch.last_notified_price = None,
+        _ => continue,
+      }
+      changed.push(watch.id);
+    }
+  }
+
+  for watch in watches.iter().filter(|watch| changed.contains(&watch.id)) {
+    diesel::update(price_watches::table.find(watch.id))
+      .set((
+        price_watches::reference_price.eq(&watch.reference_price),
+        price_watches::last_notified_price.eq(&watch.last_notified_price),
+        price_watches::last_notified_at.eq(watch.last_notified_at),
+        price_watches::updated_at.eq(now),
+      ))
+      .execute(conn)?;
+  }
+  crate::notifier::db_add_to_inbox(conn, &deliveries)?;
+  Ok(deliveries)
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Vec<PriceWatch>),
+    (status = 401),
+  ),
+)]
+#[get("")]
+pub(crate) async fn get_price_watches(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let watches = web::block(move || {
+    let mut conn = db.get().unwrap();
+    price_watches::table
+      .filter(price_watches::user_id.eq(user_id))
+      .order(price_watches::id.asc())
+      .load::<PriceWatch>(&mut conn)
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(watches))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreatePriceWatchRequest {
+  #[schema(min_length = 8, max_length = 14)]
+  pub gtin: String,
+  /// Only watch this marketplace
+  pub marketplace_id: Option<i32>,
+  /// Only watch the marketplaces of this company
+  pub company_id: Option<i32>,
+  /// Only reports in this currency are compared, `USD` by default
+  #[schema(min_length = 3, max_length = 3)]
+  pub currency: Option<String>,
+  #[schema(value_type = Option<f64>)]
+  pub target_price: Option<BigDecimal>,
+  /// Percent below the latest price, each notification lowers the reference to the notified price
+  #[schema(value_type = Option<f64>)]
+  pub percent_drop: Option<BigDecimal>,
+  /// Also notify by email, at the caller's address which has to be confirmed
+  #[serde(default)]
+  pub notify_by_email: bool,
+  /// Also notify by a `POST` of the notification as JSON to this url
+  pub webhook_url: Option<String>,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = CreatePriceWatchRequest, content_type = "application/json"),
+  responses(
+    (status = CREATED, body = PriceWatch),
+    (status = BAD_REQUEST, description = "Invalid thresholds or webhook url, email notifications to an unconfirmed address, or an unknown product, marketplace, company or currency"),
+    (status = 401),
+  ),
+)]
+#[post("")]
+pub(crate) async fn create_price_watch(
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+  body: web::Json<CreatePriceWatchRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let body = body.into_inner();
+  if body.target_price.is_none() && body.percent_drop.is_none() {
+    return Err(ServiceError::BadRequest("Either `target_price` or `percent_drop` is required".to_string()).into());
+  }
+  if body.marketplace_id.is_some() && body.company_id.is_some() {
+    return Err(
+      ServiceError::BadRequest("Only one of `marketplace_id` and `company_id` may be set".to_string()).into(),
+    );
+  }
+  if let Some(webhook_url) = &body.webhook_url {
+    crate::webhooks::validate_url(webhook_url)
+      .await
+      .map_err(|e| ServiceError::BadRequest(format!("`webhook_url` {}", e)))?;
+  }
+  // anyone can claim an address they haven't confirmed, mailing it would let them spam it
+  if body.notify_by_email && !claims.email_verified {
+    return Err(
+      ServiceError::BadRequest("Confirm your email address before asking for notifications by email".to_string())
+        .into(),
+    );
+  }
+
+  let new_watch = NewPriceWatch {
+    user_id: claims.sub,
+    gtin: body.gtin,
+    marketplace_id: body.marketplace_id,
+    company_id: body.company_id,
+    currency: body.currency.unwrap_or_else(|| "USD".to_string()),
+    target_price: body.target_price,
+    percent_drop: body.percent_drop,
+    reference_price: None,
+    email: body.notify_by_email.then(|| claims.email.clone()),
+    webhook_url: body.webhook_url,
+  };
+
+  let watch = web::block(move || {
+    let mut conn = db.get().unwrap();
+
+    // the latest price within the watch's scope
+    let mut latest = price_reports::table
+      .inner_join(
+        price_report_to_marketplaces::table.on(
+          price_report_to_marketplaces::price_report_id
+            .eq(price_reports::id)
+            .and(price_report_to_marketplaces::reported_at.eq(price_reports::reported_at)),
+        ),
+      )
+      .inner_join(marketplaces::table.on(marketplaces::id.eq(price_report_to_marketplaces::marketplace_id)))
+      .filter(price_reports::gtin.eq(&new_watch.gtin))
+      .filter(price_reports::currency.eq(&new_watch.currency))
+      .into_boxed();
+    if let Some(marketplace_id) = new_watch.marketplace_id {
+      latest = latest.filter(marketplaces::id.eq(marketplace_id));
+    }
+    if let Some(company_id) = new_watch.company_id {
+      latest = latest.filter(marketplaces::company_id.eq(company_id));
+    }
+    let reference_price = latest
+      .order(price_reports::reported_at.desc())
+      .select(diesel::dsl::sql::<diesel::sql_types::Numeric>(REPORTED_UNIT_PRICE))
+      .first::<BigDecimal>(&mut conn)
+      .optional()?;
+
+    diesel::insert_into(price_watches::table)
+      .values(NewPriceWatch {
+        reference_price,
+        ..new_watch
+      })
+      .get_result::<PriceWatch>(&mut conn)
+      .map_err(|e| match e {
+        DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+          ServiceError::BadRequest("Unknown product, marketplace, company or currency".to_string())
+        }
+        DieselError::DatabaseError(DatabaseErrorKind::CheckViolation, _) => {
+          ServiceError::BadRequest("`target_price` must be positive and `percent_drop` between 0 and 100".to_string())
+        }
+        e => e.into(),
+      })
+  })
+  .await??;
+
+  Ok(HttpResponse::Created().json(watch))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = PriceWatch),
+    (status = 401),
+    (status = NOT_FOUND, description = "Price watch not found"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Price watch id"),
+  ),
+)]
+#[delete("/{id}")]
+pub(crate) async fn delete_price_watch(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let watch = web::block(move || {
+    let mut conn = db.get().unwrap();
+    diesel::delete(
+      price_watches::table
+        .find(*id)
+        .filter(price_watches::user_id.eq(user_id)),
+    )
+    .get_result::<PriceWatch>(&mut conn)
+    .optional()?
+    .ok_or(ServiceError::NotFound(Some("Price watch not found".to_string())))
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(watch))
+}
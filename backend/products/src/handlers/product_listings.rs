@@ -0,0 +1,218 @@
+/*
+  Name: product_listings.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/product_listings`, the pages of products at online marketplaces. Scrapers upsert
+  listings as they check them and look up the ones checked longest ago to revisit next.
+*/
+
+use crate::models::*;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{delete, get, put, web, HttpResponse};
+use auth::audit::{snapshot, RequestMetadata};
+use auth::errors::ServiceError;
+use auth::scopes::*;
+use auth::Authorized;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::upsert::excluded;
+use serde::Deserialize;
+use std::collections::HashMap;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/product_listings";
+
+/// Default of `limit` when listing
+const DEFAULT_LIMIT: i64 = 100;
+
+const MAX_LIMIT: i64 = 1000;
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_product_listings)
+        .service(put_product_listings)
+        .service(delete_product_listing),
+    );
+  }
+}
+
+#[derive(Deserialize)]
+struct ProductListingsParams {
+  marketplace_id: Option<i32>,
+  gtin: Option<String>,
+  checked_before: Option<chrono::NaiveDateTime>,
+  limit: Option<i64>,
+}
+
+/// Listings checked longest ago first
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Vec<ProductListing>),
+  ),
+  params(
+    ("marketplace_id" = Option<i32>, Query, description = "Only listings at this online marketplace"),
+    ("gtin" = Option<String>, Query, description = "Only listings of this product"),
+    ("checked_before" = Option<chrono::NaiveDateTime>, Query, description = "Only listings last checked before this"),
+    ("limit" = Option<i64>, Query, description = "Number of listings, 100 by default and at most 1000"),
+  ),
+)]
+#[get("")]
+pub(crate) async fn get_product_listings(
+  db: web::Data<Pool>,
+  query: web::Query<ProductListingsParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let query = query.into_inner();
+
+  let listings = web::block(move || {
+    let mut listings = product_listings::table.into_boxed();
+    if let Some(marketplace_id) = query.marketplace_id {
+      listings = listings.filter(product_listings::marketplace_id.eq(marketplace_id));
+    }
+    if let Some(gtin) = query.gtin {
+      listings = listings.filter(product_listings::gtin.eq(gtin));
+    }
+    if let Some(checked_before) = query.checked_before {
+      listings = listings.filter(product_listings::last_checked_at.lt(checked_before));
+    }
+    listings
+      .order((
+        product_listings::last_checked_at.asc(),
+        product_listings::marketplace_id.asc(),
+        product_listings::gtin.asc(),
+      ))
+      .limit(query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+      .select(ProductListing::as_select())
+      .load::<ProductListing>(&mut db.get().unwrap())
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(listings))
+}
+
+/// Adds the listings, replacing those of the same product and marketplace
+fn db_upsert_listings(
+  conn: &mut PgConnection,
+  new_listings: Vec<NewProductListing>,
+) -> Result<Vec<ProductListing>, ServiceError> {
+  // Postgres can't update a row twice in one statement, the last listing of a product and marketplace wins
+  let new_listings = new_listings
+    .into_iter()
+    .map(|listing| ((listing.gtin.clone(), listing.marketplace_id), listing))
+    .collect::<HashMap<_, _>>()
+    .into_values()
+    .collect::<Vec<_>>();
+
+  diesel::insert_into(product_listings::table)
+    .values(&new_listings)
+    .on_conflict((product_listings::gtin, product_listings::marketplace_id))
+    .do_update()
+    .set((
+      product_listings::url.eq(excluded(product_listings::url)),
+      product_listings::retailer_item_id.eq(excluded(product_listings::retailer_item_id)),
+      product_listings::in_stock.eq(excluded(product_listings::in_stock)),
+      product_listings::last_checked_at.eq(excluded(product_listings::last_checked_at)),
+      product_listings::updated_at.eq(diesel::dsl::now),
+    ))
+    .returning(ProductListing::as_returning())
+    .get_results::<ProductListing>(conn)
+    .map_err(|err| match err {
+      DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+        ServiceError::BadRequest("Unknown product or online marketplace".to_string())
+      }
+      DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+        ServiceError::Conflict("`retailer_item_id` is already listed for another product".to_string())
+      }
+      err => err.into(),
+    })
+}
+
+/// Adds or updates listings as a scraper checks them, keyed by product and marketplace
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = Vec<NewProductListing>, content_type = "application/json"),
+  responses(
+    (status = OK, body = Vec<ProductListing>),
+    (status = BAD_REQUEST, description = "A url that isn't http(s), or an unknown product or online marketplace"),
+    (status = CONFLICT, description = "A `retailer_item_id` already listed for another product at the marketplace"),
+    (status = 401),
+  ),
+)]
+#[put("")]
+pub(crate) async fn put_product_listings(
+  db: web::Data<Pool>,
+  _claims: Authorized<Or<UpdateAll, UpdateMarketplace>>,
+  body: web::Json<Vec<NewProductListing>>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let new_listings = body.into_inner();
+  if let Some(listing) = new_listings
+    .iter()
+    .find(|listing| !reqwest::Url::parse(&listing.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")))
+  {
+    return Err(ServiceError::BadRequest(format!("`{}` isn't an http(s) url", listing.url)).into());
+  }
+
+  let listings = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| db_upsert_listings(conn, new_listings))
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(listings))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = ProductListing),
+    (status = 401),
+    (status = NOT_FOUND, description = "Listing not found"),
+  ),
+  params(
+    ("marketplace_id" = i32, Path, description = "Id of the online marketplace"),
+    ("gtin" = String, Path, description = "Global Trade Item Number (gtin)"),
+  ),
+)]
+#[delete("/{marketplace_id}/{gtin}")]
+pub(crate) async fn delete_product_listing(
+  path: web::Path<(i32, String)>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<DeleteAll, DeleteMarketplace>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let (marketplace_id, gtin) = path.into_inner();
+  let actor_id = claims.sub;
+
+  let listing = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let listing = diesel::delete(product_listings::table.find((&gtin, marketplace_id)))
+        .returning(ProductListing::as_returning())
+        .get_result::<ProductListing>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound(Some("Listing not found".to_string())))?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("delete")
+        .resource("product_listing")
+        .resource_id(format!("{}/{}", marketplace_id, gtin))
+        .maybe_before(snapshot(&listing))
+        .call()?;
+      Ok::<_, ServiceError>(listing)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(listing))
+}
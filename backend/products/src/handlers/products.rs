@@ -15,36 +15,48 @@
   - 2025-03-30 - @codyduong - add delete/edit
 */
 
+use crate::handlers::exchange_rates::{convert_price, db_find_currency, DEFAULT_CURRENCY};
 use crate::models::*;
 use crate::schema::*;
+use crate::webhooks;
 use crate::Pool;
 use actix_web::delete;
 use actix_web::get;
 use actix_web::post;
+use actix_web::put;
 use actix_web::web;
 use actix_web::web::ServiceConfig;
 use actix_web::HttpResponse;
-use actix_web_httpauth::extractors::bearer::BearerAuth;
 use anyhow::anyhow;
+use auth::audit::{snapshot, RequestMetadata};
 use auth::errors::ServiceError;
-use auth::models::PermissionName;
+use auth::scopes::*;
+use auth::Authorized;
 use common_rs::graphql::GraphConnection;
 use common_rs::graphql::Node;
 use common_rs::graphql::PageInfo;
 use common_rs::graphql::PaginationParams;
+use diesel::define_sql_function;
 use diesel::dsl::insert_into;
+use diesel::expression::{BoxableExpression, SqlLiteral};
+use diesel::pg::Pg;
+use diesel::sql_types::{Bool, Double, Float, Nullable, Numeric, Text};
 use diesel::upsert::excluded;
 use diesel::BoolExpressionMethods;
 use diesel::Connection;
 use diesel::ExpressionMethods;
 use diesel::JoinOnDsl;
+use diesel::NullableExpressionMethods;
+use diesel::OptionalExtension;
+use diesel::PgArrayExpressionMethods;
 use diesel::SelectableHelper;
+use diesel::{PgConnection, QueryResult};
 use diesel::{QueryDsl, RunQueryDsl};
 use itertools::Itertools;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::vec::Vec;
-use validator_rs::ValidatorBuilder;
+use validator_rs::openapi_security;
 
 pub(crate) const V1_PATH: &str = "/api/v1/products";
 
@@ -52,15 +64,28 @@ pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
   |config: &mut ServiceConfig| {
     config.service(
       web::scope(V1_PATH)
+        .service(suggest_products)
         .service(get_product)
         .service(delete_product)
         .service(get_products)
-        .service(post_products),
+        .service(post_products)
+        .service(put_product_categories),
     );
   }
 }
 
-fn db_get_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Result<ProductResponse> {
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetProductParams {
+  currency: Option<String>,
+  loyalty_member: Option<bool>,
+}
+
+fn db_get_product_by_gtin(
+  pool: web::Data<Pool>,
+  gtin: String,
+  currency: String,
+  loyalty_member: bool,
+) -> anyhow::Result<ProductResponse> {
   let mut conn = pool.get()?;
 
   let result = products::table
@@ -68,6 +93,7 @@ fn db_get_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Result
     .inner_join(units::table.on(units::id.eq(products_to_measures::unit_id)))
     .left_join(products_to_images::table.on(products_to_images::gtin.eq(products::gtin)))
     .filter(products::gtin.eq(gtin))
+    .order((products_to_images::is_primary.desc(), products_to_images::id.asc()))
     .select((
       Product::as_select(),
       ProductToMeasure::as_select(),
@@ -76,9 +102,15 @@ fn db_get_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Result
     ))
     .load::<(Product, ProductToMeasure, Unit, Option<ProductToImage>)>(&mut conn)?;
 
-  fold_products_and_measures(result)
-    .first()
-    .cloned()
+  let mut product_responses = fold_products_and_measures(result);
+  attach_categories(&mut conn, &mut product_responses)?;
+  attach_brands(&mut conn, &mut product_responses)?;
+  attach_listings(&mut conn, &mut product_responses)?;
+  attach_current_prices(&mut conn, &mut product_responses, currency, loyalty_member)?;
+
+  product_responses
+    .into_iter()
+    .next()
     .ok_or_else(|| anyhow!("Failed to find product"))
 }
 
@@ -86,10 +118,13 @@ fn db_get_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Result
   context_path = V1_PATH,
   responses(
     (status = OK, body = ProductResponse),
+    (status = BAD_REQUEST, description = "Unknown currency"),
     (status = 401),
   ),
   params(
-    ("gtin" = String, Path, description = "Global Trade Item Number (gtin)")
+    ("gtin" = String, Path, description = "Global Trade Item Number (gtin)"),
+    ("currency" = Option<String>, Query, description = "ISO 4217 code of the current price range, `USD` by default"),
+    ("loyalty_member" = Option<bool>, Query, description = "Count promotions that need a loyalty card, `false` by default"),
   ),
   // security(
   //   ("http" = [])
@@ -99,6 +134,7 @@ fn db_get_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Result
 pub(crate) async fn get_product(
   gtin: web::Path<String>,
   db: web::Data<Pool>,
+  query: web::Query<GetProductParams>,
   // _auth: BearerAuth,
 ) -> Result<HttpResponse, actix_web::Error> {
   // let _claims = ValidatorBuilder::new()
@@ -108,7 +144,15 @@ pub(crate) async fn get_product(
 
   let result = {
     let gtin = gtin.clone();
-    web::block(move || db_get_product_by_gtin(db, gtin)).await
+    web::block(move || {
+      let currency = match &query.currency {
+        Some(currency) => db_find_currency(&mut db.get().unwrap(), currency)?,
+        None => DEFAULT_CURRENCY.to_string(),
+      };
+      let loyalty_member = query.loyalty_member.unwrap_or_default();
+      Ok::<_, ServiceError>(db_get_product_by_gtin(db, gtin, currency, loyalty_member))
+    })
+    .await?
   };
 
   match result {
@@ -127,71 +171,70 @@ pub(crate) async fn get_product(
         Ok(Err(ServiceError::InternalServerError)?)
       }
     },
-    Err(err) => {
-      log::error!("{}", err);
-      Ok(Err(ServiceError::InternalServerError)?)
-    }
+    Err(err) => Err(err.into()),
   }
 }
 
 #[derive(Debug, Deserialize)]
-struct GetProductsParams {
+pub(crate) struct GetProductsParams {
   #[serde(flatten)]
-  pagination_params: PaginationParams<String>,
-  search: Option<String>,
-  hide_unpriced: Option<bool>,
-  minimum_price: Option<bigdecimal::BigDecimal>,
-  maximum_price: Option<bigdecimal::BigDecimal>,
-  company_id: Option<i32>,
+  pub(crate) pagination_params: PaginationParams<String>,
+  pub(crate) search: Option<String>,
+  pub(crate) hide_unpriced: Option<bool>,
+  pub(crate) minimum_price: Option<bigdecimal::BigDecimal>,
+  pub(crate) maximum_price: Option<bigdecimal::BigDecimal>,
+  /// What `minimum_price`, `maximum_price` and the price facets are in, `DEFAULT_CURRENCY` if `None`
+  pub(crate) currency: Option<String>,
+  /// Whether prices that need a loyalty card count, they don't if `None`
+  pub(crate) loyalty_member: Option<bool>,
+  pub(crate) company_id: Option<i32>,
+  pub(crate) category_id: Option<i32>,
+  pub(crate) brand_id: Option<i32>,
+  pub(crate) sort: Option<ProductSort>,
+  /// Defaults to descending for `relevance` when searching and `recently_updated`, ascending otherwise
+  pub(crate) order: Option<SortOrder>,
 }
 
-fn db_get_all_products(
-  pool: web::Data<Pool>,
-  options: GetProductsParams,
-) -> anyhow::Result<GraphConnection<ProductResponse>> {
-  let mut conn = pool.get()?;
+/// Conflicting pagination parameters or a cursor that isn't from a page of the same sort, answered with 400
+#[derive(Debug, derive_more::Display)]
+pub(crate) struct InvalidPagination(&'static str);
 
-  let mut query = products::table.into_boxed();
-  let params = &options.pagination_params;
-  let id_column = products::gtin;
-  let cursor_fn = |x: &ProductResponse| x.product.gtin.to_string();
-
-  // Validate pagination parameters
-  if params.first.is_some() && params.last.is_some() {
-    return Err(anyhow!("Can't have first and last pagination parameters"));
-  }
+impl std::error::Error for InvalidPagination {}
 
-  if params.after.is_some() && params.before.is_some() {
-    return Err(anyhow!("Can't have after and before pagination parameters"));
-  }
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ProductSort {
+  /// By relevance to `search`, by GTIN alone when not searching
+  #[default]
+  Relevance,
+  Name,
+  /// By the lowest current price, products without one last in either order
+  Price,
+  /// By the lowest current price per gram, millilitre or count of the primary measure, products without one last
+  UnitPrice,
+  /// By when the product was last updated
+  RecentlyUpdated,
+}
 
-  let limit = params.first.or(params.last).unwrap_or(20).clamp(1, 100);
-  let is_forward = params.first.is_some();
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SortOrder {
+  Asc,
+  Desc,
+}
 
-  match (is_forward, params.after.clone(), params.before.clone()) {
-    // Forward pagination
-    (true, after, None) => {
-      if let Some(after_id) = after {
-        query = query.filter(id_column.gt(after_id));
-      }
-      query = query.order(id_column.asc()).limit(limit as i64 + 1);
-    }
-    // Backward pagination
-    (false, None, before) => {
-      if let Some(before_id) = before {
-        query = query.filter(id_column.lt(before_id));
-      }
-      query = query.order(id_column.desc()).limit(limit as i64 + 1);
-    }
-    _ => return Err(anyhow!("Failed to resolve pagination")),
-  }
+/// The products matching every filter of `options`, without pagination
+fn filter_products(options: &GetProductsParams) -> products::BoxedQuery<'static, diesel::pg::Pg> {
+  let mut query = products::table.into_boxed();
 
-  // Apply full-text search
-  if let Some(search) = options.search {
+  // Apply full-text search, falling back to fuzzy matching of the product name, see `products_search_rank`
+  if let Some(search) = options.search.clone() {
     query = query.filter(
-      diesel::dsl::sql::<diesel::sql_types::Bool>("search_vector @@ plainto_tsquery('english', ")
+      diesel::dsl::sql::<diesel::sql_types::Bool>("(search_vector @@ products_search_query(")
+        .bind::<diesel::sql_types::Text, _>(search.clone())
+        .sql(") OR ")
         .bind::<diesel::sql_types::Text, _>(search)
-        .sql(")"),
+        .sql(" <% productname)"),
     )
   }
 
@@ -200,24 +243,13 @@ fn db_get_all_products(
     query = query.filter(products::gtin.eq_any(price_reports::table.select(price_reports::gtin)));
   }
 
-  if let Some(min_price) = options.minimum_price {
-    query = query.filter(
-      products::gtin.eq_any(
-        price_reports::table
-          .select(price_reports::gtin)
-          .filter(price_reports::price.ge(min_price)),
-      ),
-    );
+  // Only the latest report of each marketplace counts, a product is as cheap as its lowest current price
+  if let Some(min_price) = options.minimum_price.clone() {
+    query = query.filter(lowest_current_price(options).ge(min_price));
   }
 
-  if let Some(max_price) = options.maximum_price {
-    query = query.filter(
-      products::gtin.eq_any(
-        price_reports::table
-          .select(price_reports::gtin)
-          .filter(price_reports::price.le(max_price)),
-      ),
-    );
+  if let Some(max_price) = options.maximum_price.clone() {
+    query = query.filter(lowest_current_price(options).le(max_price));
   }
 
   if let Some(company_id) = options.company_id {
@@ -239,66 +271,682 @@ fn db_get_all_products(
     );
   }
 
-  let result = query
-    .inner_join(products_to_measures::table.on(products_to_measures::gtin.eq(products::gtin)))
-    .inner_join(units::table.on(units::id.eq(products_to_measures::unit_id)))
-    .left_join(products_to_images::table.on(products_to_images::gtin.eq(products::gtin)))
-    .order((products::gtin.asc(), products_to_images::id.asc()))
-    .select((
-      Product::as_select(),
-      ProductToMeasure::as_select(),
-      Unit::as_select(),
-      Option::<ProductToImage>::as_select(),
+  // Subcategories belong to their ancestors, filtering by "Dairy" includes "Dairy > Milk"
+  if let Some(category_id) = options.category_id {
+    query = query.filter(
+      products::gtin.eq_any(
+        products_to_categories::table
+          .inner_join(categories::table)
+          .filter(categories::path.contains(vec![category_id]))
+          .select(products_to_categories::gtin),
+      ),
+    );
+  }
+
+  if let Some(brand_id) = options.brand_id {
+    query = query.filter(products::brand_id.eq(brand_id));
+  }
+
+  query
+}
+
+/// Fuzzy matches of the product name need at least this trigram word similarity, the `pg_trgm` default of 0.6 misses
+/// most single typos in short words
+const FUZZY_SEARCH_THRESHOLD: f32 = 0.5;
+
+/// Upper bounds of the price buckets in the facets, the last bucket is open ended
+const PRICE_BUCKET_BOUNDS: [i32; 4] = [5, 10, 20, 50];
+
+/// What one unit costs at a reported price, the price of a multi-buy is for `promotion_quantity` units
+pub(crate) const REPORTED_UNIT_PRICE: &str =
+  "trim_scale(round(price_reports.price / COALESCE(price_reports.promotion_quantity, 1), 4))";
+
+// diesel has no ordering for `Numeric`, so its `min` and `max` aggregates can't be used on prices
+pub(crate) fn min_price() -> SqlLiteral<Nullable<Numeric>> {
+  diesel::dsl::sql(&format!("MIN({})", REPORTED_UNIT_PRICE))
+}
+
+pub(crate) fn max_price() -> SqlLiteral<Nullable<Numeric>> {
+  diesel::dsl::sql(&format!("MAX({})", REPORTED_UNIT_PRICE))
+}
+
+define_sql_function! {
+  #[aggregate]
+  #[sql_name = "MIN"]
+  fn min_numeric(expr: Nullable<Numeric>) -> Nullable<Numeric>;
+}
+
+define_sql_function! {
+  #[aggregate]
+  #[sql_name = "MAX"]
+  fn max_numeric(expr: Nullable<Numeric>) -> Nullable<Numeric>;
+}
+
+fn price_currency(options: &GetProductsParams) -> String {
+  options.currency.clone().unwrap_or_else(|| DEFAULT_CURRENCY.to_string())
+}
+
+/// `current_unit_price` of the current price `cp`, the promotional unit price while it runs and applies to the shopper
+/// and the regular price otherwise
+fn current_unit_price(cp: &str, options: &GetProductsParams) -> String {
+  format!(
+    "current_unit_price({}, {})",
+    cp,
+    options.loyalty_member.unwrap_or_default()
+  )
+}
+
+/// Each product's lowest current unit price across marketplaces in the currency of `options`, prices reported in
+/// another currency without an exchange rate are left out
+fn lowest_current_price(
+  options: &GetProductsParams,
+) -> Box<dyn BoxableExpression<products::table, Pg, SqlType = Nullable<Numeric>>> {
+  Box::new(
+    diesel::dsl::sql::<Nullable<Numeric>>(&format!(
+      "(SELECT MIN(convert_price({}, cp.currency, ",
+      current_unit_price("cp", options)
     ))
-    .load::<(Product, ProductToMeasure, Unit, Option<ProductToImage>)>(&mut conn)?;
+    .bind::<Text, _>(price_currency(options))
+    .sql(", cp.reported_at)) FROM current_prices cp WHERE cp.gtin = products.gtin)"),
+  )
+}
+
+/// Amount of each product's primary measure in grams, millilitres or counts, so unit prices of products measured in
+/// ounces and in grams compare
+const PRIMARY_MEASURE_BASE_AMOUNT: &str = "(SELECT NULLIF(m.amount * CASE u.symbol \
+  WHEN 'oz' THEN 28.349523125 WHEN 'fl oz' THEN 29.5735295625 ELSE 1 END, 0) \
+  FROM products_to_measures m JOIN units u ON u.id = m.unit_id \
+  WHERE m.gtin = products.gtin AND m.is_primary_measure LIMIT 1)";
 
-  let mut product_respones = fold_products_and_measures(result);
+fn sort_order(options: &GetProductsParams) -> SortOrder {
+  options.order.unwrap_or(match options.sort.unwrap_or_default() {
+    ProductSort::Relevance if options.search.is_none() => SortOrder::Asc,
+    ProductSort::Relevance | ProductSort::RecentlyUpdated => SortOrder::Desc,
+    ProductSort::Name | ProductSort::Price | ProductSort::UnitPrice => SortOrder::Asc,
+  })
+}
+
+/// Products are ordered by this, then by `text_sort_key` and then by GTIN, `0` for sorts by name
+fn number_sort_key(options: &GetProductsParams) -> Box<dyn BoxableExpression<products::table, Pg, SqlType = Double>> {
+  // missing prices sort past every other in the requested order
+  let missing = match sort_order(options) {
+    SortOrder::Asc => "'Infinity'",
+    SortOrder::Desc => "'-Infinity'",
+  };
+  let lowest_price = |per: &str| {
+    diesel::dsl::sql::<Double>(&format!(
+      "COALESCE(((SELECT MIN(convert_price({}, cp.currency, ",
+      current_unit_price("cp", options)
+    ))
+    .bind::<Text, _>(price_currency(options))
+    .sql(&format!(
+      ", cp.reported_at)) FROM current_prices cp WHERE cp.gtin = products.gtin){})::FLOAT8, {})",
+      per, missing
+    ))
+  };
+  match (options.sort.unwrap_or_default(), options.search.clone()) {
+    (ProductSort::Relevance, Some(search)) => Box::new(
+      diesel::dsl::sql::<Double>("products_search_rank(search_vector, productname, ")
+        .bind::<Text, _>(search)
+        .sql(")::FLOAT8"),
+    ),
+    (ProductSort::Relevance, None) | (ProductSort::Name, _) => Box::new(diesel::dsl::sql::<Double>("0::FLOAT8")),
+    (ProductSort::Price, _) => Box::new(lowest_price("")),
+    (ProductSort::UnitPrice, _) => Box::new(lowest_price(&format!(" / {}", PRIMARY_MEASURE_BASE_AMOUNT))),
+    (ProductSort::RecentlyUpdated, _) => Box::new(diesel::dsl::sql::<Double>(
+      "EXTRACT(EPOCH FROM products.updated_at)::FLOAT8",
+    )),
+  }
+}
 
-  let has_additional = product_respones.len() as i64 > limit.into();
-  if has_additional {
-    product_respones.pop();
+/// The product name in lower case for sorts by name, empty for every other
+fn text_sort_key(options: &GetProductsParams) -> Box<dyn BoxableExpression<products::table, Pg, SqlType = Text>> {
+  match options.sort.unwrap_or_default() {
+    ProductSort::Name => Box::new(diesel::dsl::sql::<Text>("LOWER(products.productname)")),
+    _ => Box::new(diesel::dsl::sql::<Text>("''::TEXT")),
   }
+}
 
-  // Get cursors without cloning items
-  let start_cursor = product_respones.first().map(cursor_fn);
-  let end_cursor = product_respones.last().map(cursor_fn);
+/// Whether products are ordered by more than their GTIN
+fn is_keyed(options: &GetProductsParams) -> bool {
+  options.search.is_some() || options.sort.unwrap_or_default() != ProductSort::Relevance
+}
 
-  // Determine page info
-  let has_next_page = if is_forward {
-    has_additional
-  } else {
-    params.before.is_some()
-  };
+/// Ties of the sort keys are broken by GTIN ascending, products ordered by GTIN alone follow `order`
+fn gtin_ascending(options: &GetProductsParams) -> bool {
+  is_keyed(options) || sort_order(options) == SortOrder::Asc
+}
 
-  let has_previous_page = if is_forward {
-    params.after.is_some()
-  } else {
-    has_additional
+/// The products after the one at `cursor` in the order of `options`, before it when paginating backward
+fn past_cursor(
+  options: &GetProductsParams,
+  (number, text, gtin): (f64, String, String),
+  forward: bool,
+) -> Box<dyn BoxableExpression<products::table, Pg, SqlType = Bool>> {
+  type Condition = Box<dyn BoxableExpression<products::table, Pg, SqlType = Bool>>;
+  let (number_past, text_past): (Condition, Condition) = match (sort_order(options) == SortOrder::Asc) == forward {
+    true => (
+      Box::new(number_sort_key(options).gt(number)),
+      Box::new(text_sort_key(options).gt(text.clone())),
+    ),
+    false => (
+      Box::new(number_sort_key(options).lt(number)),
+      Box::new(text_sort_key(options).lt(text.clone())),
+    ),
   };
+  let gtin_past: Condition = match gtin_ascending(options) == forward {
+    true => Box::new(products::gtin.gt(gtin)),
+    false => Box::new(products::gtin.lt(gtin)),
+  };
+  Box::new(
+    number_past.or(
+      number_sort_key(options)
+        .eq(number)
+        .and(text_past.or(text_sort_key(options).eq(text).and(gtin_past))),
+    ),
+  )
+}
 
-  let edges = product_respones
-    .into_iter()
-    .map(|item| Node {
-      cursor: cursor_fn(&item),
-      node: item,
+/// A backward page is the forward order reversed
+fn order_products(
+  query: products::BoxedQuery<'static, Pg>,
+  options: &GetProductsParams,
+  forward: bool,
+) -> products::BoxedQuery<'static, Pg> {
+  match (
+    (sort_order(options) == SortOrder::Asc) == forward,
+    gtin_ascending(options) == forward,
+  ) {
+    (true, true) => query.order((
+      number_sort_key(options).asc(),
+      text_sort_key(options).asc(),
+      products::gtin.asc(),
+    )),
+    (false, true) => query.order((
+      number_sort_key(options).desc(),
+      text_sort_key(options).desc(),
+      products::gtin.asc(),
+    )),
+    (true, false) => query.order((
+      number_sort_key(options).asc(),
+      text_sort_key(options).asc(),
+      products::gtin.desc(),
+    )),
+    (false, false) => query.order((
+      number_sort_key(options).desc(),
+      text_sort_key(options).desc(),
+      products::gtin.desc(),
+    )),
+  }
+}
+
+/// Relevance of each product to `search` as computed by `products_search_rank`, the same for every product without one
+fn search_rank(search: Option<&str>) -> Box<dyn BoxableExpression<products::table, Pg, SqlType = Float>> {
+  match search {
+    Some(search) => Box::new(
+      diesel::dsl::sql::<Float>("products_search_rank(search_vector, productname, ")
+        .bind::<Text, _>(search.to_string())
+        .sql(")"),
+    ),
+    None => Box::new(diesel::dsl::sql::<Float>("0::REAL")),
+  }
+}
+
+/// Cursors are `<sort key>:<gtin>` unless products are ordered by GTIN alone, so pages stay stable in every order
+fn parse_cursor(cursor: String, options: &GetProductsParams) -> anyhow::Result<(f64, String, String)> {
+  if !is_keyed(options) {
+    return Ok((0.0, String::new(), cursor));
+  }
+  // product names may contain `:`, GTINs never do
+  let invalid = || InvalidPagination("Invalid cursor");
+  let (key, gtin) = cursor.rsplit_once(':').ok_or_else(invalid)?;
+  match options.sort.unwrap_or_default() {
+    ProductSort::Name => Ok((0.0, key.to_string(), gtin.to_string())),
+    _ => Ok((key.parse().map_err(|_| invalid())?, String::new(), gtin.to_string())),
+  }
+}
+
+pub(crate) fn db_get_all_products(
+  pool: web::Data<Pool>,
+  options: GetProductsParams,
+) -> anyhow::Result<ProductConnection> {
+  let mut conn = pool.get()?;
+  conn.transaction(|conn| {
+    diesel::sql_query(format!(
+      "SET LOCAL pg_trgm.word_similarity_threshold = {}",
+      FUZZY_SEARCH_THRESHOLD
+    ))
+    .execute(conn)?;
+
+    let search = options.search.as_deref();
+    let params = &options.pagination_params;
+
+    // Validate pagination parameters
+    if params.first.is_some() && params.last.is_some() {
+      return Err(InvalidPagination("Can't have first and last pagination parameters").into());
+    }
+
+    if params.after.is_some() && params.before.is_some() {
+      return Err(InvalidPagination("Can't have after and before pagination parameters").into());
+    }
+
+    let limit = params.first.or(params.last).unwrap_or(20).clamp(1, 100);
+    let is_forward = params.first.is_some();
+
+    let mut query = filter_products(&options);
+    match (is_forward, params.after.clone(), params.before.clone()) {
+      // Forward pagination
+      (true, after, None) => {
+        if let Some(after) = after {
+          query = query.filter(past_cursor(&options, parse_cursor(after, &options)?, true));
+        }
+        query = order_products(query, &options, true);
+      }
+      // Backward pagination
+      (false, None, before) => {
+        if let Some(before) = before {
+          query = query.filter(past_cursor(&options, parse_cursor(before, &options)?, false));
+        }
+        query = order_products(query, &options, false);
+      }
+      _ => return Err(InvalidPagination("`after` pages with `first` and `before` with `last`").into()),
+    }
+
+    // Paginate the products themselves, their measures and images would otherwise count towards the limit
+    let mut page = query
+      .select((
+        products::gtin,
+        search_rank(search),
+        number_sort_key(&options),
+        text_sort_key(&options),
+      ))
+      .limit(limit as i64 + 1)
+      .load::<(String, f32, f64, String)>(conn)?;
+
+    let has_additional = page.len() as i64 > limit.into();
+    if has_additional {
+      page.pop();
+    }
+    if !is_forward {
+      page.reverse();
+    }
+
+    let gtins = page.iter().map(|(gtin, ..)| gtin.clone()).collect::<Vec<_>>();
+    let sort_keys = page
+      .iter()
+      .map(|(gtin, _, number, text)| match options.sort.unwrap_or_default() {
+        ProductSort::Name => (gtin.clone(), text.clone()),
+        _ => (gtin.clone(), number.to_string()),
+      })
+      .collect::<HashMap<_, _>>();
+    let cursor_fn = |x: &ProductResponse| match is_keyed(&options) {
+      true => format!("{}:{}", sort_keys[&x.product.gtin], x.product.gtin),
+      false => x.product.gtin.to_string(),
+    };
+    let result = products::table
+      .filter(products::gtin.eq_any(&gtins))
+      .inner_join(products_to_measures::table.on(products_to_measures::gtin.eq(products::gtin)))
+      .inner_join(units::table.on(units::id.eq(products_to_measures::unit_id)))
+      .left_join(products_to_images::table.on(products_to_images::gtin.eq(products::gtin)))
+      .order((
+        products::gtin.asc(),
+        products_to_images::is_primary.desc(),
+        products_to_images::id.asc(),
+      ))
+      .select((
+        Product::as_select(),
+        ProductToMeasure::as_select(),
+        Unit::as_select(),
+        Option::<ProductToImage>::as_select(),
+      ))
+      .load::<(Product, ProductToMeasure, Unit, Option<ProductToImage>)>(conn)?;
+
+    let mut headlines = match search {
+      Some(search) => db_get_search_headlines(conn, search, &gtins)?,
+      None => HashMap::new(),
+    };
+    let mut responses_by_gtin = fold_products_and_measures(result)
+      .into_iter()
+      .map(|response| (response.product.gtin.clone(), response))
+      .collect::<HashMap<_, _>>();
+    let mut product_respones = page
+      .into_iter()
+      .filter_map(|(gtin, rank, ..)| {
+        let mut response = responses_by_gtin.remove(&gtin)?;
+        response.search_match = headlines.remove(&gtin).map(|(productname, description)| SearchMatch {
+          rank,
+          productname,
+          description,
+        });
+        Some(response)
+      })
+      .collect::<Vec<_>>();
+    attach_categories(conn, &mut product_respones)?;
+    attach_brands(conn, &mut product_respones)?;
+    attach_listings(conn, &mut product_respones)?;
+    attach_current_prices(
+      conn,
+      &mut product_respones,
+      price_currency(&options),
+      options.loyalty_member.unwrap_or_default(),
+    )?;
+    let facets = ProductFacets {
+      categories: db_get_category_facets(conn, filter_products(&options))?,
+      brands: db_get_brand_facets(conn, filter_products(&options))?,
+      companies: db_get_company_facets(conn, filter_products(&options))?,
+      prices: db_get_price_facets(conn, filter_products(&options), &options)?,
+      price_currency: price_currency(&options),
+    };
+
+    // Get cursors without cloning items
+    let start_cursor = product_respones.first().map(cursor_fn);
+    let end_cursor = product_respones.last().map(cursor_fn);
+
+    // Determine page info
+    let has_next_page = if is_forward {
+      has_additional
+    } else {
+      params.before.is_some()
+    };
+
+    let has_previous_page = if is_forward {
+      params.after.is_some()
+    } else {
+      has_additional
+    };
+
+    let edges = product_respones
+      .into_iter()
+      .map(|item| Node {
+        cursor: cursor_fn(&item),
+        node: item,
+      })
+      .collect();
+
+    Ok(ProductConnection {
+      connection: GraphConnection {
+        edges,
+        page_info: PageInfo {
+          has_next_page,
+          has_prev_page: has_previous_page,
+          start_cursor,
+          end_cursor,
+        },
+      },
+      facets,
     })
-    .collect();
-
-  Ok(GraphConnection {
-    edges,
-    page_info: PageInfo {
-      has_next_page,
-      has_prev_page: has_previous_page,
-      start_cursor,
-      end_cursor,
-    },
   })
 }
 
+/// The product name and a description snippet of each product, with the words matching `search` wrapped in `<b>`
+fn db_get_search_headlines(
+  conn: &mut PgConnection,
+  search: &str,
+  gtins: &[String],
+) -> QueryResult<HashMap<String, (String, Option<String>)>> {
+  Ok(
+    products::table
+      .filter(products::gtin.eq_any(gtins))
+      .select((
+        products::gtin,
+        diesel::dsl::sql::<Text>("COALESCE(ts_headline('english', productname, products_search_query(")
+          .bind::<Text, _>(search.to_string())
+          .sql("), 'HighlightAll=true'), productname)"),
+        diesel::dsl::sql::<Nullable<Text>>("ts_headline('english', description, products_search_query(")
+          .bind::<Text, _>(search.to_string())
+          .sql("))"),
+      ))
+      .load::<(String, String, Option<String>)>(conn)?
+      .into_iter()
+      .map(|(gtin, productname, description)| (gtin, (productname, description)))
+      .collect(),
+  )
+}
+
+/// Product counts of every company with a price report for any of the `filtered` products
+fn db_get_company_facets(
+  conn: &mut PgConnection,
+  filtered: products::BoxedQuery<'static, diesel::pg::Pg>,
+) -> QueryResult<Vec<CompanyFacet>> {
+  Ok(
+    price_reports::table
+      .inner_join(
+        price_report_to_marketplaces::table.on(
+          price_report_to_marketplaces::price_report_id
+            .eq(price_reports::id)
+            .and(price_report_to_marketplaces::reported_at.eq(price_reports::reported_at)),
+        ),
+      )
+      .inner_join(marketplaces::table.on(marketplaces::id.eq(price_report_to_marketplaces::marketplace_id)))
+      .inner_join(companies::table.on(companies::id.eq(marketplaces::company_id)))
+      .filter(price_reports::gtin.eq_any(filtered.select(products::gtin)))
+      .group_by((companies::id, companies::name))
+      .select((
+        companies::id,
+        companies::name,
+        diesel::dsl::count_distinct(price_reports::gtin),
+      ))
+      .order(companies::name.asc())
+      .load::<(i32, String, i64)>(conn)?
+      .into_iter()
+      .map(|(id, name, count)| CompanyFacet { id, name, count })
+      .collect(),
+  )
+}
+
+/// Product counts of the `filtered` products by their lowest current unit price in the currency of `options`, every
+/// bucket is listed even if empty
+fn db_get_price_facets(
+  conn: &mut PgConnection,
+  filtered: products::BoxedQuery<'static, diesel::pg::Pg>,
+  options: &GetProductsParams,
+) -> QueryResult<Vec<PriceBucketFacet>> {
+  let lowest_prices = current_prices::table
+    .filter(current_prices::gtin.eq_any(filtered.select(products::gtin)))
+    .group_by(current_prices::gtin)
+    .select(min_numeric(converted_current_price(
+      price_currency(options),
+      options.loyalty_member.unwrap_or_default(),
+    )))
+    .load::<Option<bigdecimal::BigDecimal>>(conn)?;
+
+  let bounds = PRICE_BUCKET_BOUNDS.map(bigdecimal::BigDecimal::from);
+  let counts = lowest_prices
+    .into_iter()
+    .flatten()
+    .counts_by(|price| bounds.iter().position(|bound| price < *bound).unwrap_or(bounds.len()));
+
+  Ok(
+    (0..=bounds.len())
+      .map(|bucket| PriceBucketFacet {
+        min: bucket
+          .checked_sub(1)
+          .map(|lower| bounds[lower].clone())
+          .unwrap_or_default(),
+        max: bounds.get(bucket).cloned(),
+        count: counts.get(&bucket).copied().unwrap_or_default() as i64,
+      })
+      .collect(),
+  )
+}
+
+/// `current_unit_price` of a row of `current_prices` in `currency`
+fn converted_current_price(
+  currency: String,
+  loyalty_member: bool,
+) -> convert_price<SqlLiteral<Numeric>, current_prices::currency, String, current_prices::reported_at> {
+  convert_price(
+    diesel::dsl::sql(&format!("current_unit_price(current_prices, {})", loyalty_member)),
+    current_prices::currency,
+    currency,
+    current_prices::reported_at,
+  )
+}
+
+/// Sets the range of current unit prices across marketplaces of each product, in `currency`
+fn attach_current_prices(
+  conn: &mut PgConnection,
+  product_responses: &mut [ProductResponse],
+  currency: String,
+  loyalty_member: bool,
+) -> QueryResult<()> {
+  let gtins = product_responses
+    .iter()
+    .map(|response| response.product.gtin.clone())
+    .collect::<Vec<_>>();
+  let mut ranges = current_prices::table
+    .filter(current_prices::gtin.eq_any(&gtins))
+    .group_by(current_prices::gtin)
+    .select((
+      current_prices::gtin,
+      min_numeric(converted_current_price(currency.clone(), loyalty_member)),
+      max_numeric(converted_current_price(currency.clone(), loyalty_member)),
+    ))
+    .load::<(String, Option<bigdecimal::BigDecimal>, Option<bigdecimal::BigDecimal>)>(conn)?
+    .into_iter()
+    .filter_map(|(gtin, min, max)| {
+      Some((
+        gtin,
+        PriceRange {
+          currency: currency.clone(),
+          min: min?,
+          max: max?,
+        },
+      ))
+    })
+    .collect::<HashMap<_, _>>();
+
+  for response in product_responses.iter_mut() {
+    response.current_price = ranges.remove(&response.product.gtin);
+  }
+  Ok(())
+}
+
+/// Product counts of every category containing any of the `filtered` products
+fn db_get_category_facets(
+  conn: &mut PgConnection,
+  filtered: products::BoxedQuery<'static, diesel::pg::Pg>,
+) -> QueryResult<Vec<CategoryFacet>> {
+  let assignments = products_to_categories::table
+    .inner_join(categories::table)
+    .filter(products_to_categories::gtin.eq_any(filtered.select(products::gtin)))
+    .select((products_to_categories::gtin, categories::path))
+    .load::<(String, Vec<i32>)>(conn)?;
+
+  // a product in both "Dairy > Milk" and "Dairy > Yogurt" still counts once towards "Dairy"
+  let counts = assignments
+    .into_iter()
+    .flat_map(|(gtin, path)| path.into_iter().map(move |category_id| (category_id, gtin.clone())))
+    .unique()
+    .counts_by(|(category_id, _)| category_id);
+
+  Ok(
+    categories::table
+      .filter(categories::id.eq_any(counts.keys()))
+      .order(categories::path.asc())
+      .load::<Category>(conn)?
+      .into_iter()
+      .map(|category| CategoryFacet {
+        count: counts.get(&category.id).copied().unwrap_or_default() as i64,
+        id: category.id,
+        parent_id: category.parent_id,
+        name: category.name,
+      })
+      .collect(),
+  )
+}
+
+/// Loads the online marketplace listings of each product
+fn attach_listings(conn: &mut PgConnection, product_responses: &mut [ProductResponse]) -> QueryResult<()> {
+  let gtins = product_responses
+    .iter()
+    .map(|response| response.product.gtin.clone())
+    .collect::<Vec<_>>();
+
+  let mut listings_by_gtin = product_listings::table
+    .filter(product_listings::gtin.eq_any(gtins))
+    .order(product_listings::marketplace_id.asc())
+    .select(ProductListing::as_select())
+    .load::<ProductListing>(conn)?
+    .into_iter()
+    .into_group_map_by(|listing| listing.gtin.clone());
+
+  for response in product_responses {
+    response.listings = listings_by_gtin.remove(&response.product.gtin).unwrap_or_default();
+  }
+  Ok(())
+}
+
+/// Loads the categories of each product, ordered by their position in the tree
+fn attach_categories(conn: &mut PgConnection, product_responses: &mut [ProductResponse]) -> QueryResult<()> {
+  let gtins = product_responses
+    .iter()
+    .map(|response| response.product.gtin.clone())
+    .collect::<Vec<_>>();
+
+  let mut categories_by_gtin = products_to_categories::table
+    .inner_join(categories::table)
+    .filter(products_to_categories::gtin.eq_any(gtins))
+    .order(categories::path.asc())
+    .select((products_to_categories::gtin, Category::as_select()))
+    .load::<(String, Category)>(conn)?
+    .into_iter()
+    .into_group_map();
+
+  for response in product_responses {
+    response.categories = categories_by_gtin.remove(&response.product.gtin).unwrap_or_default();
+  }
+  Ok(())
+}
+
+/// Product counts of every brand owning any of the `filtered` products, products without a brand are left out
+fn db_get_brand_facets(
+  conn: &mut PgConnection,
+  filtered: products::BoxedQuery<'static, diesel::pg::Pg>,
+) -> QueryResult<Vec<BrandFacet>> {
+  Ok(
+    products::table
+      .inner_join(brands::table)
+      .filter(products::gtin.eq_any(filtered.select(products::gtin)))
+      .group_by((brands::id, brands::name))
+      .select((brands::id, brands::name, diesel::dsl::count_star()))
+      .order(brands::name.asc())
+      .load::<(i32, String, i64)>(conn)?
+      .into_iter()
+      .map(|(id, name, count)| BrandFacet { id, name, count })
+      .collect(),
+  )
+}
+
+/// Loads the brand of each product that has one
+fn attach_brands(conn: &mut PgConnection, product_responses: &mut [ProductResponse]) -> QueryResult<()> {
+  let brand_ids = product_responses
+    .iter()
+    .filter_map(|response| response.product.brand_id)
+    .unique()
+    .collect::<Vec<_>>();
+
+  let brands_by_id = brands::table
+    .filter(brands::id.eq_any(brand_ids))
+    .load::<Brand>(conn)?
+    .into_iter()
+    .map(|brand| (brand.id, brand))
+    .collect::<HashMap<_, _>>();
+
+  for response in product_responses {
+    response.brand = response
+      .product
+      .brand_id
+      .and_then(|brand_id| brands_by_id.get(&brand_id).cloned());
+  }
+  Ok(())
+}
+
 #[utoipa::path(
   context_path = V1_PATH,
   responses(
-    (status = OK, body = GraphConnection<ProductResponse>),
+    (status = OK, body = ProductConnection),
+    (status = BAD_REQUEST, description = "Unknown currency, conflicting pagination parameters or an invalid cursor"),
     (status = 401),
   ),
   params(
@@ -306,11 +954,17 @@ fn db_get_all_products(
     ("after" = Option<String>, Query, description = "Cursor for forward pagination"),
     ("last" = Option<i32>, Query, description = "Number of items before cursor"),
     ("before" = Option<String>, Query, description = "Cursor for backward pagination"),
-    ("search" = Option<String>, Query, description = "Full-text search in product name or description"),
+    ("search" = Option<String>, Query, description = "Full-text search in product name or description, most relevant first. Words match as prefixes and misspelled product names match fuzzily"),
     ("hide_unpriced" = Option<bool>, Query, description = "Hide products without price information"),
-    ("minimum_price" = Option<f32>, Query, description = "Minimum product price to include"),
-    ("maximum_price" = Option<f32>, Query, description = "Maximum product price to include"),
+    ("minimum_price" = Option<f32>, Query, description = "Minimum of the lowest current price, the latest reported at any marketplace"),
+    ("maximum_price" = Option<f32>, Query, description = "Maximum of the lowest current price, the latest reported at any marketplace"),
+    ("currency" = Option<String>, Query, description = "ISO 4217 code of the price filters and facets, `USD` by default. Prices reported in other currencies are converted at the exchange rates of the day they were reported"),
+    ("loyalty_member" = Option<bool>, Query, description = "Count promotions that need a loyalty card, `false` by default. Prices filter, sort and facet by the unit price of promotions that are running and the regular price otherwise"),
     ("company_id" = Option<i32>, Query, description = "Filter products by company ID"),
+    ("category_id" = Option<i32>, Query, description = "Filter products by category, including its subcategories"),
+    ("brand_id" = Option<i32>, Query, description = "Filter products by brand"),
+    ("sort" = Option<String>, Query, description = "`relevance` (default), `name`, `price`, `unit_price` or `recently_updated`, prices sort by the lowest current price"),
+    ("order" = Option<String>, Query, description = "`asc` or `desc`, descending by default for `relevance` when searching and `recently_updated`. Unsearched `relevance` orders by GTIN"),
   ),
   // security(
   //   ("http" = [])
@@ -328,15 +982,110 @@ pub(crate) async fn get_products(
   //   ])
   //   .validate(&auth)?;
 
-  let result = web::block(move || db_get_all_products(db, query.into_inner())).await?;
+  let result = web::block(move || {
+    let mut options = query.into_inner();
+    options.currency = options
+      .currency
+      .map(|currency| db_find_currency(&mut db.get().unwrap(), &currency))
+      .transpose()?;
+    Ok::<_, ServiceError>(db_get_all_products(db, options))
+  })
+  .await??;
 
   match result {
     Ok(res) => Ok(HttpResponse::Ok().json(res)),
-    Err(err) => {
-      log::error!("{}", err);
-      Ok(Err(ServiceError::InternalServerError)?)
-    }
+    Err(err) => match err.downcast_ref::<InvalidPagination>() {
+      Some(invalid) => Err(ServiceError::BadRequest(invalid.to_string()).into()),
+      None => {
+        log::error!("{}", err);
+        Ok(Err(ServiceError::InternalServerError)?)
+      }
+    },
+  }
+}
+
+const DEFAULT_SUGGESTIONS: i64 = 8;
+const MAX_SUGGESTIONS: i64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SuggestProductsParams {
+  q: String,
+  limit: Option<i64>,
+}
+
+/// Autocompletes a search by product name, without the measures, images and facets of `get_products`
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Vec<ProductSuggestion>),
+  ),
+  params(
+    ("q" = String, Query, description = "The search as typed so far, the last word may be incomplete"),
+    ("limit" = Option<i64>, Query, description = "Number of suggestions, 8 by default and at most 20"),
+  ),
+)]
+#[get("/suggest")]
+pub(crate) async fn suggest_products(
+  db: web::Data<Pool>,
+  query: web::Query<SuggestProductsParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let SuggestProductsParams { q, limit } = query.into_inner();
+  if q.trim().is_empty() {
+    return Ok(HttpResponse::Ok().json(Vec::<ProductSuggestion>::new()));
   }
+  let limit = limit.unwrap_or(DEFAULT_SUGGESTIONS).clamp(1, MAX_SUGGESTIONS);
+
+  let suggestions = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      diesel::sql_query(format!(
+        "SET LOCAL pg_trgm.word_similarity_threshold = {}",
+        FUZZY_SEARCH_THRESHOLD
+      ))
+      .execute(conn)?;
+
+      let rank = || {
+        diesel::dsl::sql::<Float>("products_suggest_rank(products.productname, ")
+          .bind::<Text, _>(q.clone())
+          .sql(")")
+      };
+      // matches `products_productname_tsv_idx` and `products_productname_trgm_idx`
+      products::table
+        .left_join(brands::table)
+        .filter(
+          diesel::dsl::sql::<diesel::sql_types::Bool>(
+            "(to_tsvector('english', products.productname) @@ products_search_query(",
+          )
+          .bind::<Text, _>(q.clone())
+          .sql(") OR ")
+          .bind::<Text, _>(q.clone())
+          .sql(" <% products.productname)"),
+        )
+        .select((products::gtin, products::productname, brands::name.nullable(), rank()))
+        .order((rank().desc(), products::gtin.asc()))
+        .limit(limit)
+        .load::<(String, String, Option<String>, f32)>(conn)
+    })
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?
+  .into_iter()
+  .map(|(gtin, productname, brand, rank)| ProductSuggestion {
+    gtin,
+    productname,
+    brand,
+    rank,
+  })
+  .collect::<Vec<_>>();
+
+  Ok(
+    HttpResponse::Ok()
+      .insert_header((actix_web::http::header::CACHE_CONTROL, "public, max-age=60"))
+      .json(suggestions),
+  )
 }
 
 fn fold_products_and_measures(
@@ -379,6 +1128,11 @@ fn fold_products_and_measures(
           product,
           measures,
           images,
+          categories: vec![],
+          brand: None,
+          current_price: None,
+          listings: vec![],
+          search_match: None,
         }
       })
     })
@@ -454,13 +1208,37 @@ pub fn db_insert_products(
     let gtins: Vec<String> = new_products.iter().map(|p| p.new_product.gtin.clone()).collect();
     diesel::delete(products_to_measures::table.filter(products_to_measures::gtin.eq_any(gtins.clone())))
       .execute(conn)?;
-    diesel::delete(products_to_images::table.filter(products_to_images::gtin.eq_any(gtins.clone()))).execute(conn)?;
+    // uploaded images are managed through `products_to_images`, an import only replaces the external urls
+    diesel::delete(
+      products_to_images::table
+        .filter(products_to_images::gtin.eq_any(gtins.clone()))
+        .filter(products_to_images::content_hash.is_null()),
+    )
+    .execute(conn)?;
 
     insert_into(products_to_measures::table)
       .values(new_measures_to_products)
       .execute(conn)?;
     insert_into(products_to_images::table).values(images).execute(conn)?;
 
+    for new_product in &new_products {
+      // an omitted brand keeps the one already assigned, or derived when the product was first inserted
+      if let Some(brand_id) = new_product.new_product.brand_id {
+        diesel::update(products::table.find(&new_product.new_product.gtin))
+          .set(products::brand_id.eq(brand_id))
+          .execute(conn)?;
+      }
+      if let Some(category_ids) = &new_product.categories {
+        replace_categories(conn, &new_product.new_product.gtin, category_ids)?;
+      }
+    }
+
+    let upserted = products::table
+      .filter(products::gtin.eq_any(gtins))
+      .select(Product::as_select())
+      .load::<Product>(conn)?;
+    webhooks::enqueue(conn, webhooks::PRODUCT_UPSERTED, upserted)?;
+
     diesel::result::QueryResult::Ok(())
   }) {
     Ok(_) => (),
@@ -472,6 +1250,7 @@ pub fn db_insert_products(
   Ok(true)
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   request_body(description = "A product or products",
@@ -485,26 +1264,47 @@ pub fn db_insert_products(
     (status = 401),
     (status = 500),
   ),
-  security(
-    ("http" = []),
-  )
 )]
 #[post("")]
 pub(crate) async fn post_products(
   pool: web::Data<Pool>,
   new_product_union: web::Json<NewProductPostUnion>,
-  auth: BearerAuth,
+  claims: Authorized<Or<CreateAll, CreateProduct>>,
+  metadata: RequestMetadata,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::CreateAll, PermissionName::CreateProduct])
-    .validate(&claims.permissions)?;
-
   let new_products: Vec<NewProductPost> = new_product_union.into_inner().into();
+  let actor_id = claims.sub;
 
   let result = web::block(move || {
     let mut conn = pool.get().unwrap();
-    db_insert_products(new_products, &mut conn)
+    conn.transaction(|conn| {
+      let gtins: Vec<String> = new_products.iter().map(|p| p.new_product.gtin.clone()).collect();
+      let mut before = products::table
+        .filter(products::gtin.eq_any(&gtins))
+        .select(Product::as_select())
+        .load::<Product>(conn)?
+        .into_iter()
+        .map(|product| (product.gtin.clone(), product))
+        .collect::<HashMap<_, _>>();
+
+      db_insert_products(new_products, conn)?;
+
+      let after = products::table
+        .filter(products::gtin.eq_any(&gtins))
+        .select(Product::as_select())
+        .load::<Product>(conn)?;
+      for product in after {
+        auth::audit::record(conn, &metadata)
+          .actor_id(actor_id)
+          .action("import")
+          .resource("product")
+          .resource_id(product.gtin.clone())
+          .maybe_before(before.remove(&product.gtin).as_ref().and_then(snapshot))
+          .maybe_after(snapshot(&product))
+          .call()?;
+      }
+      Ok::<_, anyhow::Error>(true)
+    })
   })
   .await;
 
@@ -521,7 +1321,12 @@ pub(crate) async fn post_products(
   }
 }
 
-fn db_delete_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Result<ProductResponse> {
+fn db_delete_product_by_gtin(
+  pool: web::Data<Pool>,
+  gtin: String,
+  actor_id: i32,
+  metadata: &RequestMetadata,
+) -> anyhow::Result<ProductResponse> {
   let mut conn = pool.get()?;
 
   let result = products::table
@@ -537,16 +1342,30 @@ fn db_delete_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Res
     ))
     .load::<(Product, ProductToMeasure, Unit, Option<ProductToImage>)>(&mut conn)?;
 
-  let product_response = fold_products_and_measures(result)
-    .first()
-    .cloned()
+  let mut product_responses = fold_products_and_measures(result);
+  attach_categories(&mut conn, &mut product_responses)?;
+  attach_brands(&mut conn, &mut product_responses)?;
+  attach_listings(&mut conn, &mut product_responses)?;
+  let product_response = product_responses
+    .into_iter()
+    .next()
     .ok_or_else(|| anyhow!("Failed to find product"))?;
 
-  diesel::delete(products::table.filter(products::gtin.eq(&gtin))).execute(&mut conn)?;
+  conn.transaction(|conn| {
+    diesel::delete(products::table.filter(products::gtin.eq(&gtin))).execute(conn)?;
+    auth::audit::record(conn, metadata)
+      .actor_id(actor_id)
+      .action("delete")
+      .resource("product")
+      .resource_id(gtin.clone())
+      .maybe_before(snapshot(&product_response))
+      .call()
+  })?;
 
   Ok(product_response)
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   responses(
@@ -556,24 +1375,18 @@ fn db_delete_product_by_gtin(pool: web::Data<Pool>, gtin: String) -> anyhow::Res
   params(
     ("gtin" = String, Path, description = "Global Trade Item Number (gtin)")
   ),
-  security(
-    ("http" = [])
-  )
 )]
 #[delete("/{gtin}")]
 pub(crate) async fn delete_product(
   gtin: web::Path<String>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<Or<DeleteAll, DeleteProduct>>,
+  metadata: RequestMetadata,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::DeleteAll, PermissionName::DeleteProduct])
-    .validate(&claims.permissions)?;
-
+  let actor_id = claims.sub;
   let result = {
     let gtin = gtin.clone();
-    web::block(move || db_delete_product_by_gtin(db, gtin)).await
+    web::block(move || db_delete_product_by_gtin(db, gtin, actor_id, &metadata)).await
   };
 
   match result {
@@ -598,3 +1411,93 @@ pub(crate) async fn delete_product(
     }
   }
 }
+
+/// Replaces every category assignment of the product
+fn replace_categories(conn: &mut PgConnection, gtin: &str, category_ids: &[i32]) -> QueryResult<()> {
+  diesel::delete(products_to_categories::table.filter(products_to_categories::gtin.eq(gtin))).execute(conn)?;
+  insert_into(products_to_categories::table)
+    .values(
+      category_ids
+        .iter()
+        .unique()
+        .map(|category_id| ProductToCategory {
+          gtin: gtin.to_string(),
+          category_id: *category_id,
+        })
+        .collect::<Vec<_>>(),
+    )
+    .execute(conn)?;
+  Ok(())
+}
+
+/// Replaces the categories of the product, assigning a subcategory implies its ancestors so they need not be listed
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = Vec<i32>, description = "Category ids", content_type = "application/json"),
+  responses(
+    (status = OK, body = Vec<Category>),
+    (status = BAD_REQUEST, description = "Unknown category id"),
+    (status = 401),
+    (status = NOT_FOUND, description = "Product not found"),
+  ),
+  params(
+    ("gtin" = String, Path, description = "Global Trade Item Number (gtin)")
+  ),
+)]
+#[put("/{gtin}/categories")]
+pub(crate) async fn put_product_categories(
+  gtin: web::Path<String>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<UpdateAll, UpdateProduct>>,
+  metadata: RequestMetadata,
+  category_ids: web::Json<Vec<i32>>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let gtin = gtin.into_inner();
+  let actor_id = claims.sub;
+
+  let categories = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      products::table
+        .find(&gtin)
+        .select(products::gtin)
+        .first::<String>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound(Some("Product not found".to_string())))?;
+
+      let load_categories = |conn: &mut PgConnection| {
+        products_to_categories::table
+          .inner_join(categories::table)
+          .filter(products_to_categories::gtin.eq(&gtin))
+          .order(categories::path.asc())
+          .select(Category::as_select())
+          .load::<Category>(conn)
+      };
+      let before = load_categories(conn)?;
+
+      replace_categories(conn, &gtin, &category_ids).map_err(|e| match e {
+        diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::ForeignKeyViolation, _) => {
+          ServiceError::BadRequest("Unknown category id".to_string())
+        }
+        e => e.into(),
+      })?;
+      let after = load_categories(conn)?;
+
+      let category_ids =
+        |categories: &[Category]| serde_json::json!(categories.iter().map(|c| c.id).collect::<Vec<_>>());
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("update")
+        .resource("product_categories")
+        .resource_id(gtin.clone())
+        .before(category_ids(&before))
+        .after(category_ids(&after))
+        .call()?;
+      Ok::<_, ServiceError>(after)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(categories))
+}
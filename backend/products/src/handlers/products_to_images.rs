@@ -9,29 +9,52 @@
   Revision History:
 */
 
+use crate::blob_store::{self, BlobStore};
 use crate::models::*;
 use crate::schema::*;
 use crate::Pool;
-use actix_web::get;
+use actix_multipart::{Field, Multipart};
+use actix_web::http::header;
 use actix_web::web;
-use actix_web::web::ServiceConfig;
+use actix_web::web::{Bytes, BytesMut, ServiceConfig};
 use actix_web::HttpResponse;
-use actix_web_httpauth::extractors::bearer::BearerAuth;
+use actix_web::{delete, get, post, put};
 use anyhow::anyhow;
+use auth::audit::{snapshot, RequestMetadata};
 use auth::errors::ServiceError;
-use auth::models::PermissionName;
+use auth::scopes::*;
+use auth::Authorized;
 use diesel::dsl::insert_into;
-use diesel::Connection;
-use diesel::ExpressionMethods;
-use diesel::{QueryDsl, RunQueryDsl};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use futures_util::TryStreamExt;
+use image::ImageFormat;
+use sha2::{Digest, Sha256};
 use std::vec::Vec;
-use validator_rs::ValidatorBuilder;
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
 
 pub(crate) const V1_PATH: &str = "/api/v1/product_to_image";
 
+/// Default of `IMAGE_MAX_BYTES`
+const DEFAULT_IMAGE_MAX_BYTES: usize = 5 * 1024 * 1024;
+
+/// Thumbnails fit within a square of this many pixels
+const THUMBNAIL_SIZE: u32 = 256;
+/// Largest width or height decoded, a few KiB of compressed image can otherwise claim gigabytes of pixels
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+
 pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
   |config: &mut ServiceConfig| {
-    config.service(web::scope(V1_PATH).service(get_image));
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_blob)
+        .service(get_image)
+        .service(post_image)
+        .service(upload_image)
+        .service(set_primary_image)
+        .service(delete_image),
+    );
   }
 }
 
@@ -41,15 +64,17 @@ fn db_get_product_to_image_by_gtin(
 ) -> anyhow::Result<Option<ProductToImageResponse>> {
   let mut conn = pool.get()?;
 
-  let binding = products_to_images::table
+  let result = products_to_images::table
     .filter(products_to_images::gtin.eq(gtin))
-    .load::<ProductToImage>(&mut conn)?;
+    .order((products_to_images::is_primary.desc(), products_to_images::id.asc()))
+    .first::<ProductToImage>(&mut conn)
+    .optional()?;
 
-  let result = binding.first();
-
-  Ok(result.cloned())
+  Ok(result)
 }
 
+/// The primary image of the product, or its oldest image if none is marked primary
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   responses(
@@ -59,21 +84,13 @@ fn db_get_product_to_image_by_gtin(
   params(
     ("id" = String, Path)
   ),
-  security(
-    ("http" = [])
-  )
 )]
 #[get("/{id}")]
 pub(crate) async fn get_image(
   gtin: web::Path<String>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  _claims: Authorized<Or<ReadAll, ReadProduct>>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::ReadAll, PermissionName::ReadProduct])
-    .validate(&claims.permissions)?;
-
   let result = {
     let gtin = gtin.clone();
     web::block(move || db_get_product_to_image_by_gtin(db, gtin)).await
@@ -111,6 +128,7 @@ fn db_insert_image(new_images: Vec<NewProductToImage>, pool: web::Data<Pool>) ->
   Ok(true)
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   request_body(description = "An image url",
@@ -124,21 +142,13 @@ fn db_insert_image(new_images: Vec<NewProductToImage>, pool: web::Data<Pool>) ->
     (status = 401),
     (status = 500),
   ),
-  security(
-    ("http" = [])
-  )
 )]
-#[get("")]
+#[post("")]
 pub(crate) async fn post_image(
   db: web::Data<Pool>,
   new_images_union: web::Json<NewProductToImageUnion>,
-  auth: BearerAuth,
+  _claims: Authorized<Or<CreateAll, CreateProduct>>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
-  ValidatorBuilder::new()
-    .with_or(vec![PermissionName::ReadAll, PermissionName::ReadProduct])
-    .validate(&claims.permissions)?;
-
   let new_images: Vec<NewProductToImage> = new_images_union.into_inner().into();
 
   let result = web::block(move || db_insert_image(new_images, db)).await;
@@ -155,3 +165,487 @@ pub(crate) async fn post_image(
     }
   }
 }
+
+/// The accepted upload formats by content type, and the extension they are stored with
+fn image_format(content_type: &str) -> Option<(ImageFormat, &'static str)> {
+  match content_type {
+    "image/jpeg" => Some((ImageFormat::Jpeg, "jpg")),
+    "image/png" => Some((ImageFormat::Png, "png")),
+    "image/webp" => Some((ImageFormat::WebP, "webp")),
+    _ => None,
+  }
+}
+
+fn image_key(content_hash: &str, extension: &str) -> String {
+  format!("images/{}.{}", content_hash, extension)
+}
+
+fn thumbnail_key(content_hash: &str) -> String {
+  format!("thumbnails/{}.jpg", content_hash)
+}
+
+fn image_max_bytes() -> usize {
+  std::env::var("IMAGE_MAX_BYTES")
+    .ok()
+    .and_then(|max| max.parse().ok())
+    .unwrap_or(DEFAULT_IMAGE_MAX_BYTES)
+}
+
+/// Reads the whole field, rejecting it as soon as it exceeds `limit` rather than buffering the rest
+async fn read_field(field: &mut Field, limit: usize) -> Result<Bytes, actix_web::Error> {
+  let mut bytes = BytesMut::new();
+  while let Some(chunk) = field.try_next().await? {
+    if bytes.len() + chunk.len() > limit {
+      return Err(ServiceError::PayloadTooLarge(format!("Field must be at most {} bytes", limit)).into());
+    }
+    bytes.extend_from_slice(&chunk);
+  }
+  Ok(bytes.freeze())
+}
+
+/// Downscales the image to fit [`THUMBNAIL_SIZE`], encoded as JPEG
+fn make_thumbnail(bytes: &[u8], format: ImageFormat) -> image::ImageResult<Vec<u8>> {
+  let mut limits = image::Limits::default();
+  limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+  limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+  let mut reader = image::ImageReader::with_format(std::io::Cursor::new(bytes), format);
+  reader.limits(limits);
+  let image = reader.decode()?;
+  let thumbnail = image::DynamicImage::from(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8());
+
+  let mut out = std::io::Cursor::new(Vec::new());
+  thumbnail.write_to(&mut out, ImageFormat::Jpeg)?;
+  Ok(out.into_inner())
+}
+
+/// A session advisory lock on a content hash. Blobs are shared between products and written or deleted outside of any
+/// transaction, holding this while deciding whether to put or delete them keeps an upload and a delete of the same
+/// image from interleaving.
+struct BlobLock {
+  conn: Option<PooledConnection<ConnectionManager<PgConnection>>>,
+}
+
+impl BlobLock {
+  async fn acquire(db: web::Data<Pool>, content_hash: String) -> Result<Self, ServiceError> {
+    let conn = web::block(move || {
+      let mut conn = db.get().map_err(|e| {
+        log::error!("Error: {}", e);
+        ServiceError::InternalServerError
+      })?;
+      diesel::sql_query("SELECT pg_advisory_lock(hashtext($1))")
+        .bind::<diesel::sql_types::Text, _>(content_hash)
+        .execute(&mut conn)?;
+      Ok::<_, ServiceError>(conn)
+    })
+    .await
+    .map_err(|e| {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    })??;
+    Ok(BlobLock { conn: Some(conn) })
+  }
+}
+
+impl Drop for BlobLock {
+  // also runs when the request is cancelled, the connection goes back to the pool so the lock must not outlive it
+  fn drop(&mut self) {
+    if let Some(mut conn) = self.conn.take() {
+      actix_rt::task::spawn_blocking(move || {
+        if let Err(e) = diesel::sql_query("SELECT pg_advisory_unlock_all()").execute(&mut conn) {
+          log::error!("Failed to release blob lock: {}", e);
+        }
+      });
+    }
+  }
+}
+
+/// Makes `id` the only primary image of the product
+fn set_primary(conn: &mut PgConnection, gtin: &str, id: i32) -> QueryResult<ProductToImage> {
+  diesel::update(
+    products_to_images::table
+      .filter(products_to_images::gtin.eq(gtin))
+      .filter(products_to_images::is_primary.eq(true))
+      .filter(products_to_images::id.ne(id)),
+  )
+  .set(products_to_images::is_primary.eq(false))
+  .execute(conn)?;
+
+  diesel::update(products_to_images::table.find(id))
+    .set((
+      products_to_images::is_primary.eq(true),
+      products_to_images::updated_at.eq(diesel::dsl::now),
+    ))
+    .get_result::<ProductToImage>(conn)
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct ImageUpload {
+  /// JPEG, PNG or WebP, at most `IMAGE_MAX_BYTES` (default 5 MiB)
+  #[schema(value_type = String, format = Binary)]
+  image: Vec<u8>,
+  /// Make this the product's primary image, the first image of a product always is
+  primary: Option<bool>,
+}
+
+/// Uploads an image into the blob store. Uploading the same image twice returns the existing one.
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = ImageUpload, content_type = "multipart/form-data"),
+  responses(
+    (status = CREATED, body = ProductToImageResponse),
+    (status = OK, body = ProductToImageResponse, description = "The product already has this image"),
+    (status = BAD_REQUEST, description = "Missing or undecodable image"),
+    (status = 401),
+    (status = NOT_FOUND, description = "Product not found"),
+    (status = PAYLOAD_TOO_LARGE),
+    (status = UNSUPPORTED_MEDIA_TYPE, description = "Not a JPEG, PNG or WebP image"),
+  ),
+  params(
+    ("gtin" = String, Path, description = "Global Trade Item Number (gtin)")
+  ),
+)]
+#[post("/{gtin}/upload")]
+pub(crate) async fn upload_image(
+  gtin: web::Path<String>,
+  db: web::Data<Pool>,
+  store: web::Data<dyn BlobStore>,
+  claims: Authorized<Or<CreateAll, CreateProduct>>,
+  metadata: RequestMetadata,
+  mut payload: Multipart,
+) -> Result<HttpResponse, actix_web::Error> {
+  let gtin = gtin.into_inner();
+  let actor_id = claims.sub;
+
+  let mut upload = None;
+  let mut primary = false;
+  while let Some(mut field) = payload.try_next().await? {
+    match field.name() {
+      Some("image") => {
+        let content_type = field
+          .content_type()
+          .map(|mime| mime.essence_str().to_string())
+          .unwrap_or_default();
+        let (format, extension) = image_format(&content_type).ok_or(ServiceError::UnsupportedMediaType(format!(
+          "Unsupported content type `{}`, expected image/jpeg, image/png or image/webp",
+          content_type
+        )))?;
+        let bytes = read_field(&mut field, image_max_bytes()).await?;
+        upload = Some((content_type, format, extension, bytes));
+      }
+      Some("primary") => {
+        let value = read_field(&mut field, 16).await?;
+        primary = matches!(std::str::from_utf8(&value).map(str::trim), Ok("true" | "1" | "on"));
+      }
+      _ => while field.try_next().await?.is_some() {},
+    }
+  }
+  let (content_type, format, extension, bytes) =
+    upload.ok_or(ServiceError::BadRequest("Missing `image` field".to_string()))?;
+
+  // the declared content type is chosen by the client, trust only what the bytes say
+  if image::guess_format(&bytes).ok() != Some(format) {
+    return Err(ServiceError::UnsupportedMediaType(format!("Content is not a valid {} image", content_type)).into());
+  }
+  let content_hash = Sha256::digest(&bytes)
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect::<String>();
+
+  // held until the row is inserted, so a concurrent delete can't remove the blobs this upload relies on
+  let _lock = BlobLock::acquire(db.clone(), content_hash.clone()).await?;
+  let (existing, stored) = {
+    let db = db.clone();
+    let gtin = gtin.clone();
+    let content_hash = content_hash.clone();
+    web::block(move || {
+      let mut conn = db.get().unwrap();
+      products::table
+        .find(&gtin)
+        .select(products::gtin)
+        .first::<String>(&mut conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound(Some("Product not found".to_string())))?;
+
+      let existing = products_to_images::table
+        .filter(products_to_images::gtin.eq(&gtin))
+        .filter(products_to_images::content_hash.eq(&content_hash))
+        .first::<ProductToImage>(&mut conn)
+        .optional()?;
+      let stored = diesel::select(diesel::dsl::exists(
+        products_to_images::table.filter(products_to_images::content_hash.eq(&content_hash)),
+      ))
+      .get_result::<bool>(&mut conn)?;
+      Ok::<_, ServiceError>((existing, stored))
+    })
+    .await??
+  };
+  if let Some(existing) = existing {
+    return Ok(HttpResponse::Ok().json(existing));
+  }
+
+  // blobs are content addressed, another product may already have uploaded the same image
+  if !stored {
+    let thumbnail = {
+      let bytes = bytes.clone();
+      web::block(move || make_thumbnail(&bytes, format))
+        .await?
+        .map_err(|e| ServiceError::BadRequest(format!("Invalid image: {}", e)))?
+    };
+
+    let put = async {
+      store
+        .put(&image_key(&content_hash, extension), &content_type, bytes)
+        .await?;
+      store
+        .put(&thumbnail_key(&content_hash), "image/jpeg", Bytes::from(thumbnail))
+        .await
+    };
+    put.await.map_err(|e| {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    })?;
+  }
+
+  let image_url = blob_store::public_url(&image_key(&content_hash, extension));
+  let thumbnail_url = blob_store::public_url(&thumbnail_key(&content_hash));
+  let image = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let has_primary = diesel::select(diesel::dsl::exists(
+        products_to_images::table
+          .filter(products_to_images::gtin.eq(&gtin))
+          .filter(products_to_images::is_primary.eq(true)),
+      ))
+      .get_result::<bool>(conn)?;
+
+      let image = insert_into(products_to_images::table)
+        .values(NewUploadedProductToImage {
+          gtin: &gtin,
+          image_url: &image_url,
+          content_hash: &content_hash,
+          content_type: &content_type,
+          thumbnail_url: &thumbnail_url,
+          is_primary: false,
+        })
+        .get_result::<ProductToImage>(conn)
+        .map_err(|e| match e {
+          diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => {
+            ServiceError::Conflict("The product already has this image".to_string())
+          }
+          e => e.into(),
+        })?;
+      let image = if primary || !has_primary {
+        set_primary(conn, &gtin, image.id)?
+      } else {
+        image
+      };
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("create")
+        .resource("product_image")
+        .resource_id(image.id.to_string())
+        .maybe_after(snapshot(&image))
+        .call()?;
+      Ok::<_, ServiceError>(image)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Created().json(image))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = ProductToImageResponse),
+    (status = 401),
+    (status = NOT_FOUND, description = "Image not found"),
+  ),
+  params(
+    ("gtin" = String, Path, description = "Global Trade Item Number (gtin)"),
+    ("id" = i32, Path, description = "Image id"),
+  ),
+)]
+#[put("/{gtin}/{id}/primary")]
+pub(crate) async fn set_primary_image(
+  path: web::Path<(String, i32)>,
+  db: web::Data<Pool>,
+  claims: Authorized<Or<UpdateAll, UpdateProduct>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let (gtin, id) = path.into_inner();
+  let actor_id = claims.sub;
+
+  let image = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let before = products_to_images::table
+        .find(id)
+        .filter(products_to_images::gtin.eq(&gtin))
+        .for_update()
+        .first::<ProductToImage>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound(Some("Image not found".to_string())))?;
+
+      let image = set_primary(conn, &gtin, id)?;
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("update")
+        .resource("product_image")
+        .resource_id(id.to_string())
+        .maybe_before(snapshot(&before))
+        .maybe_after(snapshot(&image))
+        .call()?;
+      Ok::<_, ServiceError>(image)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(image))
+}
+
+/// Removes the image, its blobs are deleted once no product references them anymore. If it was the primary image the
+/// oldest remaining image takes its place.
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = ProductToImageResponse),
+    (status = 401),
+    (status = NOT_FOUND, description = "Image not found"),
+  ),
+  params(
+    ("gtin" = String, Path, description = "Global Trade Item Number (gtin)"),
+    ("id" = i32, Path, description = "Image id"),
+  ),
+)]
+#[delete("/{gtin}/{id}")]
+pub(crate) async fn delete_image(
+  path: web::Path<(String, i32)>,
+  db: web::Data<Pool>,
+  store: web::Data<dyn BlobStore>,
+  claims: Authorized<Or<DeleteAll, DeleteProduct>>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let (gtin, id) = path.into_inner();
+  let actor_id = claims.sub;
+
+  let image = {
+    let db = db.clone();
+    web::block(move || {
+      let mut conn = db.get().unwrap();
+      conn.transaction(|conn| {
+        let image = diesel::delete(
+          products_to_images::table
+            .find(id)
+            .filter(products_to_images::gtin.eq(&gtin)),
+        )
+        .get_result::<ProductToImage>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound(Some("Image not found".to_string())))?;
+
+        if image.is_primary {
+          let next = products_to_images::table
+            .filter(products_to_images::gtin.eq(&gtin))
+            .order(products_to_images::id.asc())
+            .select(products_to_images::id)
+            .first::<i32>(conn)
+            .optional()?;
+          if let Some(next) = next {
+            set_primary(conn, &gtin, next)?;
+          }
+        }
+
+        auth::audit::record(conn, &metadata)
+          .actor_id(actor_id)
+          .action("delete")
+          .resource("product_image")
+          .resource_id(id.to_string())
+          .maybe_before(snapshot(&image))
+          .call()?;
+        Ok::<_, ServiceError>(image)
+      })
+    })
+    .await??
+  };
+
+  if let Some(content_hash) = &image.content_hash {
+    // checked under the lock, an upload of the same image either sees no reference and puts the blobs again after us,
+    // or has already inserted its row
+    let _lock = BlobLock::acquire(db.clone(), content_hash.clone()).await?;
+    let referenced = {
+      let content_hash = content_hash.clone();
+      web::block(move || {
+        diesel::select(diesel::dsl::exists(
+          products_to_images::table.filter(products_to_images::content_hash.eq(content_hash)),
+        ))
+        .get_result::<bool>(&mut db.get().unwrap())
+        .map_err(ServiceError::from)
+      })
+      .await??
+    };
+    if referenced {
+      return Ok(HttpResponse::Ok().json(image));
+    }
+
+    let extension = image
+      .content_type
+      .as_deref()
+      .and_then(image_format)
+      .map(|(_, extension)| extension);
+    // the row is gone either way, a blob left behind is only wasted space
+    for key in extension
+      .map(|extension| image_key(content_hash, extension))
+      .into_iter()
+      .chain([thumbnail_key(content_hash)])
+    {
+      if let Err(e) = store.delete(&key).await {
+        log::warn!("Failed to delete blob `{}`: {}", key, e);
+      }
+    }
+  }
+
+  Ok(HttpResponse::Ok().json(image))
+}
+
+/// Serves uploaded images and thumbnails, public so they can be used in `<img>` tags
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, content_type = "image/*", body = Vec<u8>),
+    (status = NOT_FOUND),
+  ),
+  params(
+    ("key" = String, Path, description = "Blob key, e.g. `images/<sha256>.png`"),
+  ),
+)]
+#[get("/blobs/{key:.*}")]
+pub(crate) async fn get_blob(
+  key: web::Path<String>,
+  store: web::Data<dyn BlobStore>,
+) -> Result<HttpResponse, actix_web::Error> {
+  if blob_store::validate_key(&key).is_err() {
+    return Err(ServiceError::NotFound(None).into());
+  }
+
+  let (bytes, content_type) = store
+    .get(&key)
+    .await
+    .map_err(|e| {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    })?
+    .ok_or(ServiceError::NotFound(None))?;
+
+  Ok(
+    HttpResponse::Ok()
+      .content_type(content_type)
+      // keys are content hashes, a blob never changes
+      .append_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+      .body(bytes),
+  )
+}
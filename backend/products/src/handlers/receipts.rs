@@ -0,0 +1,553 @@
+/*
+  Name: receipts.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/receipts`. A receipt, either structured or the plain text of an OCR client, is
+  matched to products and a marketplace as a draft, and the lines the reporter confirmed are then committed as price
+  reports in one go.
+*/
+
+use crate::handlers::price_reports::db_add_price_report;
+use crate::models::*;
+use crate::notifier::Notifiers;
+use crate::schema::*;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{post, web, HttpResponse};
+use auth::errors::ServiceError;
+use auth::scopes::*;
+use auth::Authorized;
+use bigdecimal::{BigDecimal, Zero};
+use diesel::expression::BoxableExpression;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::{Bool, Float, Text};
+use serde::Deserialize;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/receipts";
+
+/// Receipts abbreviate product names heavily, so candidates are looser than the fuzzy matches of product search
+const CANDIDATE_THRESHOLD: f32 = 0.3;
+/// Matches at least this similar are confirmed in the draft without review
+const CONFIRM_THRESHOLD: f32 = 0.6;
+const MAX_CANDIDATES: i64 = 3;
+const MAX_LINES: usize = 200;
+/// Lines of a plain text receipt mentioning these are about the payment rather than an item
+const NON_ITEM_WORDS: [&str; 14] = [
+  "TOTAL",
+  "SUBTOTAL",
+  "TAX",
+  "CHANGE",
+  "CASH",
+  "VISA",
+  "MASTERCARD",
+  "AMEX",
+  "DEBIT",
+  "CREDIT",
+  "BALANCE",
+  "TEND",
+  "SAVINGS",
+  "COUPON",
+];
+const DATE_FORMATS: [&str; 3] = ["%m/%d/%Y", "%m/%d/%y", "%Y-%m-%d"];
+
+define_sql_function!(fn ltrim(string: Text, characters: Text) -> Text);
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(web::scope(V1_PATH).service(draft_receipt).service(commit_receipt));
+  }
+}
+
+#[derive(Deserialize, ToSchema, Clone)]
+pub struct ReceiptLine {
+  pub upc: Option<String>,
+  pub description: Option<String>,
+  /// The line total
+  #[schema(value_type = f64)]
+  pub price: BigDecimal,
+  pub quantity: Option<i32>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct StructuredReceipt {
+  pub store: Option<String>,
+  /// Skips matching the store
+  pub marketplace_id: Option<i32>,
+  pub date: Option<chrono::NaiveDate>,
+  pub currency: Option<String>,
+  pub lines: Vec<ReceiptLine>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TextReceipt {
+  /// One item per line, ending in its price and optionally containing its UPC, e.g. `LUNCHBLS PIZZA 004470036114 3.12 F`
+  pub text: String,
+  pub marketplace_id: Option<i32>,
+  pub currency: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum ReceiptRequest {
+  Structured(StructuredReceipt),
+  Text(TextReceipt),
+}
+
+struct ParsedReceipt {
+  store: Option<String>,
+  date: Option<chrono::NaiveDate>,
+  lines: Vec<ReceiptLine>,
+  skipped: Vec<String>,
+}
+
+fn parse_price(token: &str) -> Option<BigDecimal> {
+  let token = token.trim_start_matches('$');
+  let (_, cents) = token.split_once('.')?;
+  if cents.len() != 2 {
+    return None;
+  }
+  BigDecimal::from_str(token).ok()
+}
+
+fn parse_date(line: &str) -> Option<chrono::NaiveDate> {
+  line.split_whitespace().find_map(|token| {
+    DATE_FORMATS
+      .iter()
+      .find_map(|format| chrono::NaiveDate::parse_from_str(token, format).ok())
+  })
+}
+
+/// Reads items from the plain text of a receipt. The first line without a price is taken as the store.
+fn parse_receipt_text(text: &str) -> ParsedReceipt {
+  let mut receipt = ParsedReceipt {
+    store: None,
+    date: None,
+    lines: Vec::new(),
+    skipped: Vec::new(),
+  };
+
+  for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+    if receipt.date.is_none() {
+      receipt.date = parse_date(line);
+    }
+
+    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+    // tax flags such as `F` or `N` follow the price
+    if tokens.len() > 1
+      && tokens
+        .last()
+        .is_some_and(|t| t.len() == 1 && t.chars().all(char::is_alphabetic))
+    {
+      tokens.pop();
+    }
+    let price = tokens.last().and_then(|token| parse_price(token));
+    let is_item = price.as_ref().is_some_and(|price| price > &BigDecimal::zero())
+      && !tokens
+        .iter()
+        .any(|token| NON_ITEM_WORDS.contains(&token.to_uppercase().as_str()));
+
+    match price {
+      Some(price) if is_item => {
+        tokens.pop();
+        let upc = tokens
+          .iter()
+          .position(|t| (8..=14).contains(&t.len()) && t.chars().all(|c| c.is_ascii_digit()))
+          .map(|i| tokens.remove(i).to_string());
+        let description = Some(tokens.join(" ")).filter(|d| !d.is_empty());
+        receipt.lines.push(ReceiptLine {
+          upc,
+          description,
+          price,
+          quantity: None,
+        });
+      }
+      None if receipt.store.is_none() && receipt.lines.is_empty() && line.chars().any(char::is_alphabetic) => {
+        receipt.store = Some(line.to_string());
+      }
+      _ => receipt.skipped.push(line.to_string()),
+    }
+  }
+  receipt
+}
+
+/// Receipts often leave out the check digit, so both the UPC as printed and with its GS1 check digit are tried
+fn upc_candidates(upc: &str) -> Vec<String> {
+  let digits: String = upc.chars().filter(char::is_ascii_digit).collect();
+  let sum: u32 = digits
+    .chars()
+    .rev()
+    .enumerate()
+    .map(|(i, c)| c.to_digit(10).unwrap() * if i % 2 == 0 { 3 } else { 1 })
+    .sum();
+  let with_check_digit = format!("{}{}", digits, (10 - sum % 10) % 10);
+  [digits, with_check_digit]
+    .into_iter()
+    .map(|gtin| gtin.trim_start_matches('0').to_string())
+    .collect()
+}
+
+fn similarity_to(description: &str) -> Box<dyn BoxableExpression<products::table, Pg, SqlType = Float>> {
+  Box::new(
+    diesel::dsl::sql::<Float>("word_similarity(")
+      .bind::<Text, _>(description.to_string())
+      .sql(", productname)"),
+  )
+}
+
+fn db_match_line(conn: &mut PgConnection, line: &ReceiptLine) -> QueryResult<Vec<ProductMatch>> {
+  if let Some(upc) = &line.upc {
+    let by_upc = products::table
+      .filter(ltrim(products::gtin, "0").eq_any(upc_candidates(upc)))
+      .select((products::gtin, products::productname))
+      .first::<(String, String)>(conn)
+      .optional()?;
+    if let Some((gtin, productname)) = by_upc {
+      return Ok(vec![ProductMatch {
+        gtin,
+        productname,
+        confidence: 1.0,
+      }]);
+    }
+  }
+
+  let Some(description) = &line.description else {
+    return Ok(Vec::new());
+  };
+  Ok(
+    products::table
+      .filter(
+        diesel::dsl::sql::<Bool>("")
+          .bind::<Text, _>(description)
+          .sql(" <% productname"),
+      )
+      .select((products::gtin, products::productname, similarity_to(description)))
+      .order(similarity_to(description).desc())
+      .limit(MAX_CANDIDATES)
+      .load::<(String, String, f32)>(conn)?
+      .into_iter()
+      .map(|(gtin, productname, confidence)| ProductMatch {
+        gtin,
+        productname,
+        confidence,
+      })
+      .collect(),
+  )
+}
+
+fn db_match_marketplace(
+  conn: &mut PgConnection,
+  marketplace_id: Option<i32>,
+  store: Option<&str>,
+) -> QueryResult<Option<MarketplaceMatch>> {
+  let query = marketplaces::table
+    .inner_join(companies::table)
+    .filter(marketplaces::deleted.eq(false))
+    .into_boxed();
+
+  let found = match (marketplace_id, store) {
+    (Some(marketplace_id), _) => query
+      .filter(marketplaces::id.eq(marketplace_id))
+      .select((
+        marketplaces::id,
+        marketplaces::name,
+        companies::name,
+        diesel::dsl::sql::<Float>("1"),
+      ))
+      .first::<(i32, String, String, f32)>(conn)
+      .optional()?,
+    (None, Some(store)) => {
+      let similarity = || {
+        diesel::dsl::sql::<Float>("word_similarity(")
+          .bind::<Text, _>(store.to_string())
+          .sql(", companies.name || ' ' || marketplaces.name)")
+      };
+      query
+        .filter(similarity().ge(CANDIDATE_THRESHOLD))
+        .select((marketplaces::id, marketplaces::name, companies::name, similarity()))
+        .order(similarity().desc())
+        .first::<(i32, String, String, f32)>(conn)
+        .optional()?
+    }
+    (None, None) => None,
+  };
+
+  Ok(found.map(|(id, name, company, confidence)| MarketplaceMatch {
+    id,
+    name,
+    company,
+    confidence,
+  }))
+}
+
+/// The receipt's lines, marketplace and currency, reading them from the text of a text receipt
+fn parse_request(request: ReceiptRequest) -> (ParsedReceipt, Option<i32>, Option<String>) {
+  match request {
+    ReceiptRequest::Structured(receipt) => (
+      ParsedReceipt {
+        store: receipt.store,
+        date: receipt.date,
+        lines: receipt.lines,
+        skipped: Vec::new(),
+      },
+      receipt.marketplace_id,
+      receipt.currency,
+    ),
+    ReceiptRequest::Text(receipt) => (
+      parse_receipt_text(&receipt.text),
+      receipt.marketplace_id,
+      receipt.currency,
+    ),
+  }
+}
+
+fn db_draft_receipt(
+  conn: &mut PgConnection,
+  receipt: ParsedReceipt,
+  marketplace_id: Option<i32>,
+  currency: Option<String>,
+) -> QueryResult<ReceiptDraft> {
+  conn.transaction(|conn| {
+    diesel::sql_query(format!(
+      "SET LOCAL pg_trgm.word_similarity_threshold = {}",
+      CANDIDATE_THRESHOLD
+    ))
+    .execute(conn)?;
+
+    let lines = receipt
+      .lines
+      .iter()
+      .enumerate()
+      .map(|(index, line)| {
+        let candidates = db_match_line(conn, line)?;
+        let product = candidates.first().cloned();
+        let quantity = line.quantity.filter(|quantity| *quantity > 0).unwrap_or(1);
+        Ok(ReceiptLineDraft {
+          index,
+          description: line.description.clone(),
+          upc: line.upc.clone(),
+          price: (&line.price / BigDecimal::from(quantity)).round(2),
+          quantity,
+          confirmed: product
+            .as_ref()
+            .is_some_and(|product| product.confidence >= CONFIRM_THRESHOLD),
+          product,
+          candidates,
+        })
+      })
+      .collect::<QueryResult<Vec<_>>>()?;
+
+    Ok(ReceiptDraft {
+      marketplace: db_match_marketplace(conn, marketplace_id, receipt.store.as_deref())?,
+      store: receipt.store,
+      reported_at: receipt.date.and_then(|date| date.and_hms_opt(0, 0, 0)),
+      currency: currency.unwrap_or_else(|| "USD".to_string()),
+      lines,
+      skipped: receipt.skipped,
+    })
+  })
+}
+
+/// Matches the lines of a receipt to products and the store to a marketplace without reporting anything
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(description = "A structured receipt or the plain text of one",
+    content(
+      (StructuredReceipt),
+      (TextReceipt),
+    ),
+  ),
+  responses(
+    (status = OK, body = ReceiptDraft),
+    (status = BAD_REQUEST, description = "The receipt has no items or too many"),
+    (status = 401),
+  ),
+)]
+#[post("/draft")]
+pub(crate) async fn draft_receipt(
+  db: web::Data<Pool>,
+  _claims: Authorized<Or<CreateAll, CreatePriceReport>>,
+  body: web::Json<ReceiptRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  // every line costs a similarity query, so reject oversized receipts before matching any
+  let (receipt, marketplace_id, currency) = parse_request(body.into_inner());
+  if receipt.lines.is_empty() || receipt.lines.len() > MAX_LINES {
+    return Err(ServiceError::BadRequest(format!("A receipt must have 1 to {} items", MAX_LINES)).into());
+  }
+
+  let draft = web::block(move || db_draft_receipt(&mut db.get().unwrap(), receipt, marketplace_id, currency))
+    .await?
+    .map_err(|e| {
+      log::error!("Error: {}", e);
+      ServiceError::InternalServerError
+    })?;
+
+  Ok(HttpResponse::Ok().json(draft))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ConfirmedReceiptLine {
+  pub gtin: String,
+  /// Per unit
+  #[schema(value_type = f64)]
+  pub price: BigDecimal,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CommitReceiptRequest {
+  pub marketplace_id: i32,
+  /// Defaults to now
+  pub reported_at: Option<chrono::NaiveDateTime>,
+  pub currency: Option<String>,
+  pub lines: Vec<ConfirmedReceiptLine>,
+}
+
+/// Reports the price of every confirmed line of a draft, either all of them are reported or none are
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = CommitReceiptRequest, content_type = "application/json"),
+  responses(
+    (status = OK, body = usize, description = "Number of price reports created"),
+    (status = BAD_REQUEST, description = "No lines, a price that isn't positive, or an unknown product, marketplace or currency"),
+    (status = 401),
+  ),
+)]
+#[post("/commit")]
+pub(crate) async fn commit_receipt(
+  db: web::Data<Pool>,
+  notifiers: web::Data<Notifiers>,
+  claims: Authorized<Or<CreateAll, CreatePriceReport>>,
+  body: web::Json<CommitReceiptRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let body = body.into_inner();
+  if body.lines.is_empty() || body.lines.len() > MAX_LINES {
+    return Err(ServiceError::BadRequest(format!("A receipt must have 1 to {} lines", MAX_LINES)).into());
+  }
+  if body.lines.iter().any(|line| line.price <= BigDecimal::zero()) {
+    return Err(ServiceError::BadRequest("Prices must be positive".to_string()).into());
+  }
+
+  let currency = body.currency.unwrap_or_else(|| "USD".to_string());
+  let price_reports: Vec<NewPriceReportDSL> = body
+    .lines
+    .into_iter()
+    .map(|line| NewPriceReportDSL {
+      price_report: NewPriceReportPublic {
+        reported_at: body.reported_at,
+        gtin: line.gtin,
+        price: line.price,
+        currency: currency.clone(),
+        ..Default::default()
+      },
+      marketplace_id: body.marketplace_id,
+    })
+    .collect();
+  let count = price_reports.len();
+
+  let user_id = claims.sub;
+  let result = web::block(move || db_add_price_report(db, price_reports, user_id)).await?;
+
+  match result {
+    Ok(deliveries) => {
+      actix_rt::spawn(async move { notifiers.notify(&deliveries).await });
+      Ok(HttpResponse::Ok().json(count))
+    }
+    Err(err) => match err.downcast_ref::<DieselError>() {
+      Some(DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+        Err(ServiceError::BadRequest("Unknown product, marketplace or currency".to_string()).into())
+      }
+      _ => {
+        log::error!("{}", err);
+        Err(ServiceError::InternalServerError.into())
+      }
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_price() {
+    assert_eq!(parse_price("3.12"), Some(BigDecimal::from_str("3.12").unwrap()));
+    assert_eq!(parse_price("$10.00"), Some(BigDecimal::from_str("10.00").unwrap()));
+    assert_eq!(parse_price("-1.50"), Some(BigDecimal::from_str("-1.50").unwrap()));
+    // quantities, weights and codes aren't prices
+    assert_eq!(parse_price("3"), None);
+    assert_eq!(parse_price("1.5"), None);
+    assert_eq!(parse_price("0.125"), None);
+    assert_eq!(parse_price("004470036114"), None);
+    assert_eq!(parse_price("AB.CD"), None);
+  }
+
+  #[test]
+  fn test_parse_receipt_text() {
+    let receipt = parse_receipt_text(
+      "WALMART SUPERCENTER
+      03/14/2025 10:42
+      LUNCHBLS PIZZA 004470036114 3.12 F
+      BANANAS 1.24 N
+      MILK GAL 3.48
+      COUPON 1.00
+      SUBTOTAL 7.84
+      TAX 0.35
+      TOTAL 8.19
+      VISA TEND 8.19",
+    );
+
+    assert_eq!(receipt.store.as_deref(), Some("WALMART SUPERCENTER"));
+    assert_eq!(receipt.date, chrono::NaiveDate::from_ymd_opt(2025, 3, 14));
+
+    let lines = receipt
+      .lines
+      .iter()
+      .map(|line| (line.description.as_deref(), line.upc.as_deref(), line.price.to_string()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      lines,
+      vec![
+        (Some("LUNCHBLS PIZZA"), Some("004470036114"), "3.12".to_string()),
+        (Some("BANANAS"), None, "1.24".to_string()),
+        (Some("MILK GAL"), None, "3.48".to_string()),
+      ]
+    );
+    assert_eq!(
+      receipt.skipped,
+      vec![
+        "03/14/2025 10:42",
+        "COUPON 1.00",
+        "SUBTOTAL 7.84",
+        "TAX 0.35",
+        "TOTAL 8.19",
+        "VISA TEND 8.19"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_receipt_text_tax_flag() {
+    // a lone letter is only a tax flag after a price, not an item on its own
+    let receipt = parse_receipt_text("A\nX 2.00 T");
+    assert_eq!(receipt.store.as_deref(), Some("A"));
+    assert_eq!(receipt.lines.len(), 1);
+    assert_eq!(receipt.lines[0].description.as_deref(), Some("X"));
+    assert_eq!(receipt.lines[0].price.to_string(), "2.00");
+  }
+
+  #[test]
+  fn test_upc_candidates() {
+    // 036000291452 is a valid UPC-A, printed without its check digit
+    assert_eq!(upc_candidates("03600029145"), vec!["3600029145", "36000291452"]);
+    assert_eq!(upc_candidates("004470036114"), vec!["4470036114", "44700361146"]);
+    // a sum that is already a multiple of 10 takes 0, not 10
+    assert_eq!(upc_candidates("10000000009"), vec!["10000000009", "100000000090"]);
+    assert_eq!(upc_candidates("0980012401"), vec!["980012401", "9800124015"]);
+  }
+}
@@ -12,36 +12,55 @@
 */
 
 use crate::models::*;
+use crate::realtime::{self, ReceivedEvent, ShoppingListEvents};
 use crate::schema::*;
+use crate::webhooks;
 use crate::Pool;
 use actix_web::delete;
 use actix_web::get;
+use actix_web::http::header;
 use actix_web::patch;
 use actix_web::post;
 use actix_web::web;
+use actix_web::web::Bytes;
 use actix_web::web::ServiceConfig;
 use actix_web::HttpResponse;
-use actix_web_httpauth::extractors::bearer::BearerAuth;
+use auth::directory::UserDirectory;
 use auth::errors::ServiceError;
+use auth::Authorized;
 use diesel::dsl::insert_into;
 use diesel::Connection;
 use diesel::ExpressionMethods;
 use diesel::OptionalExtension;
+use diesel::PgSortExpressionMethods;
 use diesel::{QueryDsl, RunQueryDsl};
+use futures_util::StreamExt;
 use serde::Deserialize;
 use serde::Serialize;
 use std::vec::Vec;
 use utoipa::ToSchema;
+use validator_rs::openapi_security;
 
 pub(crate) const V1_PATH: &str = "/api/v1/shopping_lists";
 
 pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
   |config: &mut ServiceConfig| {
+    // invitation routes are registered first so `/invitations` isn't captured by `/{id}`
     config.service(
       web::scope(V1_PATH)
+        .service(get_my_invitations)
+        .service(accept_invitation)
+        .service(decline_invitation)
+        .service(create_invitation)
+        .service(get_invitations)
+        .service(delete_invitation)
+        .service(patch_member)
+        .service(delete_member)
         .service(create_shopping_list)
         .service(patch_shopping_list)
         .service(delete_shopping_list)
+        .service(create_events_ticket)
+        .service(get_shopping_list_events)
         .service(get_shopping_list)
         .service(get_shopping_lists),
     );
@@ -50,17 +69,76 @@ pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
 
 #[derive(Deserialize, ToSchema)]
 pub struct NewShoppingListRequest {
-  pub user_ids: Option<Vec<i32>>,
+  pub invitations: Option<Vec<NewInvitationRequest>>,
   pub items: Vec<ShoppingListItemRequest>,
   pub name: Option<String>,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct NewInvitationRequest {
+  /// Email or username of the user to invite, they can respond once they confirmed their email. A username is
+  /// resolved to its user's confirmed email when inviting.
+  pub invitee: String,
+  pub role: ShoppingListRole,
+}
+
+impl NewInvitationRequest {
+  /// The email the invitation is addressed to, see [`invitee_emails`]
+  async fn resolve(&self, directory: Option<&UserDirectory>) -> Result<String, ServiceError> {
+    let invitee = self.invitee.trim();
+    if invitee.parse::<lettre::Address>().is_ok() {
+      return Ok(invitee.to_lowercase());
+    }
+    let email = match directory {
+      Some(directory) => directory.confirmed_email(invitee.to_string()).await?,
+      None => None,
+    };
+    email.ok_or_else(|| {
+      ServiceError::BadRequest(format!(
+        "`{}` isn't an email address or the username of a user with a confirmed email",
+        self.invitee
+      ))
+    })
+  }
+}
+
 #[derive(Deserialize, ToSchema)]
 pub struct ShoppingListItemRequest {
-  pub gtin: String,
+  pub gtin: Option<String>,
+  /// Name of a free-form item, required when `gtin` is not set
+  pub name: Option<String>,
   #[schema(value_type = f64)]
   pub amount: bigdecimal::BigDecimal,
   pub unit_id: Option<i32>,
+  pub note: Option<String>,
+  pub aisle: Option<String>,
+  pub position: Option<i32>,
+}
+
+impl ShoppingListItemRequest {
+  fn validate(&self) -> Result<(), ServiceError> {
+    match (&self.gtin, &self.name) {
+      (None, None) => Err(ServiceError::BadRequest(
+        "Items require either a `gtin` or a `name`".to_string(),
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  fn to_new_item(&self, shopping_list_id: i32) -> NewShoppingListItem {
+    NewShoppingListItem {
+      shopping_list_id,
+      gtin: self.gtin.clone(),
+      amount: self.amount.clone(),
+      unit_id: self.unit_id,
+      created_at: None,
+      updated_at: None,
+      name: self.name.clone(),
+      note: self.note.clone(),
+      aisle: self.aisle.clone(),
+      position: self.position,
+    }
+  }
 }
 
 #[derive(Serialize, ToSchema)]
@@ -82,13 +160,67 @@ fn get_full_shopping_list(
     .filter(shopping_list_to_user::shopping_list_id.eq(shopping_list_id))
     .load::<ShoppingListToUser>(conn)?;
 
+  // Grouped by aisle so the list can be walked through in store order
   let items = shopping_list_items::table
     .filter(shopping_list_items::shopping_list_id.eq(shopping_list_id))
+    .order((
+      shopping_list_items::aisle.asc().nulls_last(),
+      shopping_list_items::position.asc(),
+      shopping_list_items::id.asc(),
+    ))
     .load::<ShoppingListItem>(conn)?;
 
   Ok(ShoppingListResponse { list, users, items })
 }
 
+/// Returns the caller's role on the shopping list, non-members are forbidden
+fn get_member_role(
+  conn: &mut diesel::PgConnection,
+  shopping_list_id: i32,
+  user_id: i32,
+) -> Result<ShoppingListRole, ServiceError> {
+  shopping_list_to_user::table
+    .filter(shopping_list_to_user::shopping_list_id.eq(shopping_list_id))
+    .filter(shopping_list_to_user::user_id.eq(user_id))
+    .select(shopping_list_to_user::role)
+    .first::<ShoppingListRole>(conn)
+    .optional()
+    .map_err(|e| {
+      log::error!("Error checking access: {}", e);
+      ServiceError::InternalServerError
+    })?
+    .ok_or(ServiceError::Forbidden)
+}
+
+/// Emits `shopping_list.updated`, `action` is one of `created`, `updated` or `deleted`. Lists are private to their
+/// members, so partners only learn which list changed.
+fn enqueue_shopping_list_updated(
+  conn: &mut diesel::PgConnection,
+  action: &str,
+  shopping_list_id: i32,
+) -> Result<(), diesel::result::Error> {
+  webhooks::enqueue(
+    conn,
+    webhooks::SHOPPING_LIST_UPDATED,
+    [serde_json::json!({ "action": action, "shopping_list_id": shopping_list_id })],
+  )
+}
+
+fn new_invitation(
+  shopping_list_id: i32,
+  invited_by: i32,
+  invitee: String,
+  role: ShoppingListRole,
+) -> NewShoppingListInvitation {
+  NewShoppingListInvitation {
+    shopping_list_id,
+    invitee,
+    role,
+    invited_by,
+  }
+}
+
+#[openapi_security]
 #[utoipa::path(
     context_path = V1_PATH,
     request_body = NewShoppingListRequest,
@@ -102,15 +234,22 @@ fn get_full_shopping_list(
 pub async fn create_shopping_list(
   data: web::Json<NewShoppingListRequest>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  directory: Option<web::Data<UserDirectory>>,
+  claims: Authorized<()>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
   let user_id = claims.sub;
 
-  // Ensure the creator is included in the user_ids
-  let mut user_ids = data.user_ids.clone().unwrap_or_default();
-  if !user_ids.contains(&user_id) {
-    user_ids.push(user_id);
+  for item in &data.items {
+    item.validate()?;
+  }
+  let mut invitations = Vec::new();
+  for invitation in data.invitations.iter().flatten() {
+    invitations.push((
+      invitation
+        .resolve(directory.as_ref().map(|directory| directory.get_ref()))
+        .await?,
+      invitation.role,
+    ));
   }
 
   let mut conn = db.get().map_err(|e| {
@@ -128,36 +267,37 @@ pub async fn create_shopping_list(
         .values(&new_shopping_list)
         .get_result(conn)?;
 
-      // Bulk insert user associations
-      let user_associations: Vec<NewShoppingListToUser> = user_ids
-        .iter()
-        .map(|user_id| NewShoppingListToUser {
+      // The creator owns the list, everyone else has to accept an invitation
+      insert_into(shopping_list_to_user::table)
+        .values(NewShoppingListToUser {
           shopping_list_id: shopping_list.id,
-          user_id: *user_id,
+          user_id,
+          role: ShoppingListRole::Owner,
         })
+        .execute(conn)?;
+
+      let invitations: Vec<NewShoppingListInvitation> = invitations
+        .into_iter()
+        .map(|(invitee, role)| new_invitation(shopping_list.id, user_id, invitee, role))
         .collect();
 
-      insert_into(shopping_list_to_user::table)
-        .values(&user_associations)
+      insert_into(shopping_list_invitations::table)
+        .values(&invitations)
+        .on_conflict_do_nothing()
         .execute(conn)?;
 
       // Bulk insert items
       let items: Vec<NewShoppingListItem> = data
         .items
         .iter()
-        .map(|item| NewShoppingListItem {
-          shopping_list_id: shopping_list.id,
-          gtin: item.gtin.clone(),
-          amount: item.amount.clone(),
-          unit_id: item.unit_id,
-          created_at: None,
-          updated_at: None,
-        })
+        .map(|item| item.to_new_item(shopping_list.id))
         .collect();
 
       insert_into(shopping_list_items::table).values(&items).execute(conn)?;
 
-      get_full_shopping_list(conn, shopping_list.id)
+      let response = get_full_shopping_list(conn, shopping_list.id)?;
+      enqueue_shopping_list_updated(conn, "created", response.list.id)?;
+      Ok::<_, diesel::result::Error>(response)
     })
     .map_err(|e| {
       log::error!("Transaction error: {}", e);
@@ -167,13 +307,96 @@ pub async fn create_shopping_list(
   Ok(HttpResponse::Created().json(response))
 }
 
+/// Identifies an existing item by `id`, or by `gtin` for catalog items
+#[derive(Deserialize, ToSchema)]
+pub struct ItemSelector {
+  pub id: Option<i32>,
+  pub gtin: Option<String>,
+}
+
+impl ItemSelector {
+  fn validate(&self) -> Result<(), ServiceError> {
+    match (&self.id, &self.gtin) {
+      (None, None) => Err(ServiceError::BadRequest(
+        "Item actions require either an `id` or a `gtin`".to_string(),
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  fn find(&self, conn: &mut diesel::PgConnection, shopping_list_id: i32) -> Result<Option<i32>, diesel::result::Error> {
+    let mut query = shopping_list_items::table
+      .filter(shopping_list_items::shopping_list_id.eq(shopping_list_id))
+      .select(shopping_list_items::id)
+      .into_boxed();
+    if let Some(id) = self.id {
+      query = query.filter(shopping_list_items::id.eq(id));
+    }
+    if let Some(gtin) = &self.gtin {
+      query = query.filter(shopping_list_items::gtin.eq(gtin));
+    }
+    query.first::<i32>(conn).optional()
+  }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ItemUpdateRequest {
+  #[serde(flatten)]
+  pub item: ItemSelector,
+  #[schema(value_type = Option<f64>)]
+  pub amount: Option<bigdecimal::BigDecimal>,
+  #[serde(default, with = "::serde_with::rust::double_option")]
+  #[schema(value_type = Option<i32>)]
+  pub unit_id: Option<Option<i32>>,
+  pub name: Option<String>,
+  #[serde(default, with = "::serde_with::rust::double_option")]
+  #[schema(value_type = Option<String>)]
+  pub note: Option<Option<String>>,
+  /// Marks the item as purchased by the requesting user, or clears it when `false`
+  pub checked: Option<bool>,
+  #[serde(default, with = "::serde_with::rust::double_option")]
+  #[schema(value_type = Option<String>)]
+  pub aisle: Option<Option<String>>,
+  pub position: Option<i32>,
+}
+
+impl ItemUpdateRequest {
+  fn to_changeset(&self, user_id: i32, now: chrono::NaiveDateTime) -> ShoppingListItemChangeset {
+    ShoppingListItemChangeset {
+      amount: self.amount.clone(),
+      unit_id: self.unit_id,
+      name: self.name.clone().map(Some),
+      note: self.note.clone(),
+      checked: self.checked,
+      checked_by: self.checked.map(|checked| checked.then_some(user_id)),
+      checked_at: self.checked.map(|checked| checked.then_some(now)),
+      aisle: self.aisle.clone(),
+      position: self.position,
+      updated_at: Some(now),
+    }
+  }
+}
+
 #[derive(Deserialize, ToSchema)]
 #[serde(tag = "action")]
 pub enum ItemPatchAction {
+  /// Adds an item, catalog items already on the list have their amount and unit replaced
   #[serde(rename = "add")]
   Add(ShoppingListItemRequest),
+  #[serde(rename = "update")]
+  Update(ItemUpdateRequest),
   #[serde(rename = "remove")]
-  Remove { gtin: String },
+  Remove(ItemSelector),
+}
+
+impl ItemPatchAction {
+  fn validate(&self) -> Result<(), ServiceError> {
+    match self {
+      ItemPatchAction::Add(item) => item.validate(),
+      ItemPatchAction::Update(update) => update.item.validate(),
+      ItemPatchAction::Remove(item) => item.validate(),
+    }
+  }
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -181,6 +404,7 @@ pub struct PatchShoppingListRequest {
   pub items: Option<Vec<ItemPatchAction>>,
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   params(
@@ -190,7 +414,7 @@ pub struct PatchShoppingListRequest {
   responses(
       (status = 200, description = "Shopping list updated", body = ShoppingListResponse),
       (status = 400, description = "Bad request"),
-      (status = 403, description = "Forbidden - not owner of shopping list"),
+      (status = 403, description = "Forbidden - not an owner or editor of shopping list"),
       (status = 404, description = "Shopping list not found"),
       (status = 500, description = "Internal server error"),
   ),
@@ -200,9 +424,8 @@ pub async fn patch_shopping_list(
   id: web::Path<i32>,
   data: web::Json<PatchShoppingListRequest>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<()>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
   let user_id = claims.sub;
   let shopping_list_id = id.into_inner();
 
@@ -211,39 +434,39 @@ pub async fn patch_shopping_list(
     ServiceError::InternalServerError
   })?;
 
-  // Verify user owns the shopping list
-  let exists = shopping_list_to_user::table
-    .filter(shopping_list_to_user::shopping_list_id.eq(shopping_list_id))
-    .filter(shopping_list_to_user::user_id.eq(user_id))
-    .first::<ShoppingListToUser>(&mut conn)
-    .optional()
-    .map_err(|e| {
-      log::error!("Error checking ownership: {}", e);
-      ServiceError::InternalServerError
-    })?;
-
-  if exists.is_none() {
+  // Viewers can only read the shopping list
+  if !get_member_role(&mut conn, shopping_list_id, user_id)?.can_edit() {
     return Err(ServiceError::Forbidden.into());
   }
 
+  for action in data.items.iter().flatten() {
+    action.validate()?;
+  }
+
   let response = conn
     .transaction(|conn| {
       if let Some(item_actions) = &data.items {
         let now = chrono::Utc::now().naive_utc();
+        let notify_item = |conn: &mut diesel::PgConnection, kind, item_id, item| {
+          realtime::notify_shopping_list(
+            conn,
+            ShoppingListEvent {
+              shopping_list_id,
+              kind,
+              user_id,
+              item_id: Some(item_id),
+              item,
+              member_id: None,
+            },
+          )
+        };
 
         for action in item_actions {
           match action {
-            ItemPatchAction::Add(item) => {
+            ItemPatchAction::Add(item) if item.gtin.is_some() => {
               // Upsert item - update if exists, insert if new
-              diesel::insert_into(shopping_list_items::table)
-                .values(NewShoppingListItem {
-                  shopping_list_id,
-                  gtin: item.gtin.clone(),
-                  amount: item.amount.clone(),
-                  unit_id: item.unit_id,
-                  created_at: None,
-                  updated_at: None,
-                })
+              let item = diesel::insert_into(shopping_list_items::table)
+                .values(item.to_new_item(shopping_list_id))
                 .on_conflict((shopping_list_items::shopping_list_id, shopping_list_items::gtin))
                 .do_update()
                 .set((
@@ -251,15 +474,29 @@ pub async fn patch_shopping_list(
                   shopping_list_items::unit_id.eq(item.unit_id),
                   shopping_list_items::updated_at.eq(now),
                 ))
-                .execute(conn)?;
+                .get_result::<ShoppingListItem>(conn)?;
+              notify_item(conn, ShoppingListEventKind::ItemAdded, item.id, Some(item))?;
+            }
+            ItemPatchAction::Add(item) => {
+              // Free-form items can't be matched, so each add is a new item
+              let item = diesel::insert_into(shopping_list_items::table)
+                .values(item.to_new_item(shopping_list_id))
+                .get_result::<ShoppingListItem>(conn)?;
+              notify_item(conn, ShoppingListEventKind::ItemAdded, item.id, Some(item))?;
             }
-            ItemPatchAction::Remove { gtin } => {
-              diesel::delete(
-                shopping_list_items::table
-                  .filter(shopping_list_items::shopping_list_id.eq(shopping_list_id))
-                  .filter(shopping_list_items::gtin.eq(gtin)),
-              )
-              .execute(conn)?;
+            ItemPatchAction::Update(update) => {
+              if let Some(item_id) = update.item.find(conn, shopping_list_id)? {
+                let item = diesel::update(shopping_list_items::table.find(item_id))
+                  .set(update.to_changeset(user_id, now))
+                  .get_result::<ShoppingListItem>(conn)?;
+                notify_item(conn, ShoppingListEventKind::ItemUpdated, item_id, Some(item))?;
+              }
+            }
+            ItemPatchAction::Remove(item) => {
+              if let Some(item_id) = item.find(conn, shopping_list_id)? {
+                diesel::delete(shopping_list_items::table.find(item_id)).execute(conn)?;
+                notify_item(conn, ShoppingListEventKind::ItemRemoved, item_id, None)?;
+              }
             }
           }
         }
@@ -270,7 +507,9 @@ pub async fn patch_shopping_list(
         .set(shopping_list::updated_at.eq(chrono::Utc::now().naive_utc()))
         .execute(conn)?;
 
-      get_full_shopping_list(conn, shopping_list_id)
+      let response = get_full_shopping_list(conn, shopping_list_id)?;
+      enqueue_shopping_list_updated(conn, "updated", shopping_list_id)?;
+      Ok::<_, diesel::result::Error>(response)
     })
     .map_err(|e| {
       log::error!("Transaction error: {}", e);
@@ -280,6 +519,7 @@ pub async fn patch_shopping_list(
   Ok(HttpResponse::Ok().json(response))
 }
 
+#[openapi_security]
 #[utoipa::path(
     context_path = V1_PATH,
     params(
@@ -296,9 +536,8 @@ pub async fn patch_shopping_list(
 pub async fn delete_shopping_list(
   id: web::Path<i32>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<()>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
   let user_id = claims.sub;
   let shopping_list_id = id.into_inner();
 
@@ -307,7 +546,10 @@ pub async fn delete_shopping_list(
     ServiceError::InternalServerError
   })?;
 
-  // First get the full shopping list before deletion
+  // Verify user owns the shopping list, before looking it up so non-members can't tell which lists exist
+  require_owner(&mut conn, shopping_list_id, user_id)?;
+
+  // Get the full shopping list before deletion
   let response = get_full_shopping_list(&mut conn, shopping_list_id).map_err(|e| match e {
     diesel::result::Error::NotFound => ServiceError::NotFound(Some("Shopping list not found".to_string())),
     _ => {
@@ -316,21 +558,6 @@ pub async fn delete_shopping_list(
     }
   })?;
 
-  // Verify user owns the shopping list
-  let exists = shopping_list_to_user::table
-    .filter(shopping_list_to_user::shopping_list_id.eq(shopping_list_id))
-    .filter(shopping_list_to_user::user_id.eq(user_id))
-    .first::<ShoppingListToUser>(&mut conn)
-    .optional()
-    .map_err(|e| {
-      log::error!("Error checking ownership: {}", e);
-      ServiceError::InternalServerError
-    })?;
-
-  if exists.is_none() {
-    return Err(ServiceError::Forbidden.into());
-  }
-
   conn
     .transaction(|conn| {
       // Delete all associated records first
@@ -343,7 +570,18 @@ pub async fn delete_shopping_list(
       // Finally delete the shopping list itself
       diesel::delete(shopping_list::table.find(shopping_list_id)).execute(conn)?;
 
-      Ok::<_, diesel::result::Error>(())
+      realtime::notify_shopping_list(
+        conn,
+        ShoppingListEvent {
+          shopping_list_id,
+          kind: ShoppingListEventKind::ListDeleted,
+          user_id,
+          item_id: None,
+          item: None,
+          member_id: None,
+        },
+      )?;
+      enqueue_shopping_list_updated(conn, "deleted", shopping_list_id)
     })
     .map_err(|e| {
       log::error!("Transaction error: {}", e);
@@ -353,6 +591,7 @@ pub async fn delete_shopping_list(
   Ok(HttpResponse::Ok().json(response))
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   params(
@@ -360,7 +599,7 @@ pub async fn delete_shopping_list(
   ),
   responses(
       (status = 200, description = "Shopping list details", body = ShoppingListResponse),
-      (status = 403, description = "Forbidden - not owner of shopping list"),
+      (status = 403, description = "Forbidden - not a member of shopping list"),
       (status = 404, description = "Shopping list not found"),
       (status = 500, description = "Internal server error"),
   ),
@@ -369,9 +608,8 @@ pub async fn delete_shopping_list(
 pub async fn get_shopping_list(
   id: web::Path<i32>,
   db: web::Data<Pool>,
-  auth: BearerAuth,
+  claims: Authorized<()>,
 ) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
   let user_id = claims.sub;
   let shopping_list_id = id.into_inner();
 
@@ -380,20 +618,8 @@ pub async fn get_shopping_list(
     ServiceError::InternalServerError
   })?;
 
-  // Verify user has access to the shopping list
-  let exists = shopping_list_to_user::table
-    .filter(shopping_list_to_user::shopping_list_id.eq(shopping_list_id))
-    .filter(shopping_list_to_user::user_id.eq(user_id))
-    .first::<ShoppingListToUser>(&mut conn)
-    .optional()
-    .map_err(|e| {
-      log::error!("Error checking access: {}", e);
-      ServiceError::InternalServerError
-    })?;
-
-  if exists.is_none() {
-    return Err(ServiceError::Forbidden.into());
-  }
+  // Verify user has access to the shopping list, any role may read it
+  get_member_role(&mut conn, shopping_list_id, user_id)?;
 
   let response = get_full_shopping_list(&mut conn, shopping_list_id).map_err(|e| match e {
     diesel::result::Error::NotFound => ServiceError::NotFound(Some("Shopping list not found".to_string())),
@@ -406,11 +632,195 @@ pub async fn get_shopping_list(
   Ok(HttpResponse::Ok().json(response))
 }
 
+/// A comment line keeping idle connections from being closed by proxies
+const KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// How long an events ticket can be used to connect, the stream itself stays open past it
+const EVENTS_TICKET_SECONDS: i64 = 60;
+
+/// Distinguishes tickets from access tokens signed with the same key, `auth::decode_jwt` rejects any audience
+const EVENTS_TICKET_AUDIENCE: &str = "shopping_list_events";
+
+#[derive(Serialize, Deserialize)]
+struct EventsTicketClaims {
+  aud: String,
+  exp: usize,
+  sub: i32,
+  shopping_list_id: i32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EventsTicketResponse {
+  /// Pass as the `ticket` query parameter of the events stream
+  pub ticket: String,
+  /// Seconds the ticket can be used to connect
+  pub expires_in: i64,
+}
+
+fn ticket_key() -> String {
+  std::env::var("SECRET_KEY").expect("SECRET_KEY must be set")
+}
+
+/// The member the ticket was issued to, if it's valid for this shopping list
+fn verify_events_ticket(ticket: &str, shopping_list_id: i32) -> Result<i32, ServiceError> {
+  let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
+  validation.set_audience(&[EVENTS_TICKET_AUDIENCE]);
+  let claims = jsonwebtoken::decode::<EventsTicketClaims>(
+    ticket,
+    &jsonwebtoken::DecodingKey::from_secret(ticket_key().as_ref()),
+    &validation,
+  )
+  .map_err(|_| ServiceError::Unauthorized)?
+  .claims;
+  if claims.shopping_list_id != shopping_list_id {
+    return Err(ServiceError::Unauthorized);
+  }
+  Ok(claims.sub)
+}
+
+/// A short lived ticket to connect to the shopping list's events with, for clients that can't set the
+/// `Authorization` header such as the browser's `EventSource`
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("id", description = "Shopping list ID"),
+  ),
+  responses(
+      (status = 200, body = EventsTicketResponse),
+      (status = 403, description = "Forbidden - not a member of shopping list"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[post("/{id}/events/ticket")]
+pub async fn create_events_ticket(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let shopping_list_id = id.into_inner();
+
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+  get_member_role(&mut conn, shopping_list_id, user_id)?;
+
+  let exp = chrono::Utc::now() + chrono::Duration::seconds(EVENTS_TICKET_SECONDS);
+  let ticket = jsonwebtoken::encode(
+    &jsonwebtoken::Header::default(),
+    &EventsTicketClaims {
+      aud: EVENTS_TICKET_AUDIENCE.to_string(),
+      exp: exp.timestamp() as usize,
+      sub: user_id,
+      shopping_list_id,
+    },
+    &jsonwebtoken::EncodingKey::from_secret(ticket_key().as_ref()),
+  )
+  .map_err(|e| {
+    log::error!("Error signing events ticket: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(EventsTicketResponse {
+    ticket,
+    expires_in: EVENTS_TICKET_SECONDS,
+  }))
+}
+
+#[derive(Deserialize)]
+pub struct EventsParams {
+  ticket: Option<String>,
+}
+
+fn sse_frame(event: &ReceivedEvent) -> Bytes {
+  let kind = serde_json::to_value(event.kind).unwrap_or_default();
+  Bytes::from(format!(
+    "event: {}\ndata: {}\n\n",
+    kind.as_str().unwrap_or_default(),
+    event.payload
+  ))
+}
+
+/// Server-Sent Events of the changes members make to the shopping list, including the caller's own so their other
+/// devices stay in sync, skip events whose `user_id` is the caller to ignore them. The event name is the `kind` of
+/// the `ShoppingListEvent` sent as data. Events are only sent while connected, so the list should be fetched again
+/// after connecting. The stream ends when the list is deleted or the caller leaves it.
+///
+/// Authenticate with the bearer token as usual, or with a `ticket` from `POST /{id}/events/ticket` where the client
+/// can't set headers, as with the browser's `EventSource`.
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("id", description = "Shopping list ID"),
+      ("ticket" = Option<String>, Query, description = "Ticket from `POST /{id}/events/ticket`, instead of the bearer token"),
+  ),
+  responses(
+      (status = 200, description = "Stream of shopping list events", body = ShoppingListEvent, content_type = "text/event-stream"),
+      (status = 401, description = "Missing or invalid token or ticket"),
+      (status = 403, description = "Forbidden - not a member of shopping list"),
+      (status = 500, description = "Internal server error"),
+  ),
+  security(("http" = []), ()),
+)]
+#[get("/{id}/events")]
+pub async fn get_shopping_list_events(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  events: web::Data<ShoppingListEvents>,
+  query: web::Query<EventsParams>,
+  claims: Option<Authorized<()>>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let shopping_list_id = id.into_inner();
+  let user_id = match (&query.ticket, claims) {
+    (Some(ticket), _) => verify_events_ticket(ticket, shopping_list_id)?,
+    (None, Some(claims)) => claims.sub,
+    (None, None) => return Err(ServiceError::Unauthorized.into()),
+  };
+
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  // any role may follow the shopping list
+  get_member_role(&mut conn, shopping_list_id, user_id)?;
+  drop(conn);
+
+  // the subscription is dropped along with the stream when the client disconnects
+  let subscription = events.subscribe(shopping_list_id);
+  let stream = futures_util::stream::unfold(Some(subscription), move |subscription| async move {
+    let mut subscription = subscription?;
+    match actix_rt::time::timeout(KEEPALIVE_INTERVAL, subscription.recv()).await {
+      Err(_) => Some((Ok(Bytes::from_static(b": keepalive\n\n")), Some(subscription))),
+      Ok(Ok(event)) => {
+        let last = event.kind == ShoppingListEventKind::ListDeleted
+          || (event.kind == ShoppingListEventKind::MemberRemoved && event.member_id == Some(user_id));
+        Some((Ok(sse_frame(&event)), (!last).then_some(subscription)))
+      }
+      // fell behind or the listener reconnected, the client has to reconnect and refetch
+      Ok(Err(_)) => None,
+    }
+  });
+  let stream = futures_util::stream::once(async { Ok::<_, actix_web::Error>(Bytes::from_static(b": connected\n\n")) })
+    .chain(stream);
+
+  Ok(
+    HttpResponse::Ok()
+      .content_type("text/event-stream")
+      .insert_header((header::CACHE_CONTROL, "no-cache"))
+      .insert_header(("X-Accel-Buffering", "no"))
+      .streaming(stream),
+  )
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct ShoppingListsResponse {
   pub lists: Vec<ShoppingListResponse>,
 }
 
+#[openapi_security]
 #[utoipa::path(
   context_path = V1_PATH,
   responses(
@@ -419,8 +829,7 @@ pub struct ShoppingListsResponse {
   ),
 )]
 #[get("/")]
-pub async fn get_shopping_lists(db: web::Data<Pool>, auth: BearerAuth) -> Result<HttpResponse, actix_web::Error> {
-  let claims = auth::get_claims(&auth)?;
+pub async fn get_shopping_lists(db: web::Data<Pool>, claims: Authorized<()>) -> Result<HttpResponse, actix_web::Error> {
   let user_id = claims.sub;
 
   let mut conn = db.get().map_err(|e| {
@@ -457,3 +866,450 @@ pub async fn get_shopping_lists(db: web::Data<Pool>, auth: BearerAuth) -> Result
 
   Ok(HttpResponse::Ok().json(ShoppingListsResponse { lists }))
 }
+
+fn require_owner(conn: &mut diesel::PgConnection, shopping_list_id: i32, user_id: i32) -> Result<(), ServiceError> {
+  match get_member_role(conn, shopping_list_id, user_id)? {
+    ShoppingListRole::Owner => Ok(()),
+    _ => Err(ServiceError::Forbidden),
+  }
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("id", description = "Shopping list ID"),
+  ),
+  request_body = NewInvitationRequest,
+  responses(
+      (status = 201, description = "Invitation created", body = ShoppingListInvitation),
+      (status = 400, description = "Invitee isn't an email address or a username with a confirmed email"),
+      (status = 403, description = "Forbidden - not owner of shopping list"),
+      (status = 409, description = "Invitation already pending"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[post("/{id}/invitations")]
+pub async fn create_invitation(
+  id: web::Path<i32>,
+  data: web::Json<NewInvitationRequest>,
+  db: web::Data<Pool>,
+  directory: Option<web::Data<UserDirectory>>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let shopping_list_id = id.into_inner();
+  let invitee = data
+    .resolve(directory.as_ref().map(|directory| directory.get_ref()))
+    .await?;
+
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  require_owner(&mut conn, shopping_list_id, user_id)?;
+
+  let invitation = insert_into(shopping_list_invitations::table)
+    .values(new_invitation(shopping_list_id, user_id, invitee, data.role))
+    .get_result::<ShoppingListInvitation>(&mut conn)
+    .map_err(|e| match e {
+      diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => {
+        ServiceError::Conflict("Invitation already pending".to_string())
+      }
+      _ => {
+        log::error!("Error creating invitation: {}", e);
+        ServiceError::InternalServerError
+      }
+    })?;
+
+  Ok(HttpResponse::Created().json(invitation))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ShoppingListInvitationsResponse {
+  pub invitations: Vec<ShoppingListInvitation>,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("id", description = "Shopping list ID"),
+  ),
+  responses(
+      (status = 200, description = "Invitations sent for the shopping list", body = ShoppingListInvitationsResponse),
+      (status = 403, description = "Forbidden - not owner of shopping list"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[get("/{id}/invitations")]
+pub async fn get_invitations(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let shopping_list_id = id.into_inner();
+
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  require_owner(&mut conn, shopping_list_id, user_id)?;
+
+  let invitations = shopping_list_invitations::table
+    .filter(shopping_list_invitations::shopping_list_id.eq(shopping_list_id))
+    .order(shopping_list_invitations::created_at.desc())
+    .load::<ShoppingListInvitation>(&mut conn)
+    .map_err(|e| {
+      log::error!("Error fetching invitations: {}", e);
+      ServiceError::InternalServerError
+    })?;
+
+  Ok(HttpResponse::Ok().json(ShoppingListInvitationsResponse { invitations }))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("id", description = "Shopping list ID"),
+      ("invitation_id", description = "Invitation ID"),
+  ),
+  responses(
+      (status = 204, description = "Invitation revoked"),
+      (status = 403, description = "Forbidden - not owner of shopping list"),
+      (status = 404, description = "Pending invitation not found"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[delete("/{id}/invitations/{invitation_id}")]
+pub async fn delete_invitation(
+  path: web::Path<(i32, i32)>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let (shopping_list_id, invitation_id) = path.into_inner();
+
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  require_owner(&mut conn, shopping_list_id, user_id)?;
+
+  let deleted = diesel::delete(
+    shopping_list_invitations::table
+      .filter(shopping_list_invitations::id.eq(invitation_id))
+      .filter(shopping_list_invitations::shopping_list_id.eq(shopping_list_id))
+      .filter(shopping_list_invitations::status.eq(InvitationStatus::Pending)),
+  )
+  .execute(&mut conn)
+  .map_err(|e| {
+    log::error!("Error revoking invitation: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  if deleted == 0 {
+    return Err(ServiceError::NotFound(Some("Invitation not found".to_string())).into());
+  }
+
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Invitations are addressed by email, which only identifies the caller once they confirmed it. Usernames and
+/// unconfirmed addresses can be taken by anyone.
+fn invitee_emails(claims: &auth::Claims) -> Vec<String> {
+  claims
+    .email_verified
+    .then(|| claims.email.trim().to_lowercase())
+    .into_iter()
+    .collect()
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+      (status = 200, description = "Pending invitations to the user's confirmed email", body = ShoppingListInvitationsResponse),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[get("/invitations")]
+pub async fn get_my_invitations(db: web::Data<Pool>, claims: Authorized<()>) -> Result<HttpResponse, actix_web::Error> {
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  let invitations = shopping_list_invitations::table
+    .filter(shopping_list_invitations::invitee.eq_any(invitee_emails(&claims)))
+    .filter(shopping_list_invitations::status.eq(InvitationStatus::Pending))
+    .order(shopping_list_invitations::created_at.desc())
+    .load::<ShoppingListInvitation>(&mut conn)
+    .map_err(|e| {
+      log::error!("Error fetching invitations: {}", e);
+      ServiceError::InternalServerError
+    })?;
+
+  Ok(HttpResponse::Ok().json(ShoppingListInvitationsResponse { invitations }))
+}
+
+fn respond_to_invitation(
+  db: web::Data<Pool>,
+  claims: &auth::Claims,
+  invitation_id: i32,
+  status: InvitationStatus,
+) -> Result<ShoppingListInvitation, ServiceError> {
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  conn.transaction(|conn| {
+    let invitation = shopping_list_invitations::table
+      .find(invitation_id)
+      .filter(shopping_list_invitations::invitee.eq_any(invitee_emails(claims)))
+      .filter(shopping_list_invitations::status.eq(InvitationStatus::Pending))
+      .for_update()
+      .first::<ShoppingListInvitation>(conn)
+      .optional()?
+      .ok_or_else(|| ServiceError::NotFound(Some("Invitation not found".to_string())))?;
+
+    if status == InvitationStatus::Accepted {
+      // Accepting an invitation to a list the user is already on keeps their current role
+      let added = insert_into(shopping_list_to_user::table)
+        .values(NewShoppingListToUser {
+          shopping_list_id: invitation.shopping_list_id,
+          user_id: claims.sub,
+          role: invitation.role,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+      if added > 0 {
+        realtime::notify_shopping_list(
+          conn,
+          ShoppingListEvent {
+            shopping_list_id: invitation.shopping_list_id,
+            kind: ShoppingListEventKind::MemberAdded,
+            user_id: claims.sub,
+            item_id: None,
+            item: None,
+            member_id: Some(claims.sub),
+          },
+        )?;
+        enqueue_shopping_list_updated(conn, "updated", invitation.shopping_list_id)?;
+      }
+    }
+
+    Ok(
+      diesel::update(shopping_list_invitations::table.find(invitation.id))
+        .set((
+          shopping_list_invitations::status.eq(status),
+          shopping_list_invitations::updated_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .get_result::<ShoppingListInvitation>(conn)?,
+    )
+  })
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("invitation_id", description = "Invitation ID"),
+  ),
+  responses(
+      (status = 200, description = "Invitation accepted", body = ShoppingListInvitation),
+      (status = 404, description = "Pending invitation not found"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[post("/invitations/{invitation_id}/accept")]
+pub async fn accept_invitation(
+  invitation_id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let invitation = respond_to_invitation(db, &claims, invitation_id.into_inner(), InvitationStatus::Accepted)?;
+  Ok(HttpResponse::Ok().json(invitation))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("invitation_id", description = "Invitation ID"),
+  ),
+  responses(
+      (status = 200, description = "Invitation declined", body = ShoppingListInvitation),
+      (status = 404, description = "Pending invitation not found"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[post("/invitations/{invitation_id}/decline")]
+pub async fn decline_invitation(
+  invitation_id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let invitation = respond_to_invitation(db, &claims, invitation_id.into_inner(), InvitationStatus::Declined)?;
+  Ok(HttpResponse::Ok().json(invitation))
+}
+
+/// Errors if the change would leave the shopping list without an owner
+fn ensure_other_owner(
+  conn: &mut diesel::PgConnection,
+  shopping_list_id: i32,
+  member_id: i32,
+) -> Result<(), ServiceError> {
+  let other_owners: i64 = shopping_list_to_user::table
+    .filter(shopping_list_to_user::shopping_list_id.eq(shopping_list_id))
+    .filter(shopping_list_to_user::user_id.ne(member_id))
+    .filter(shopping_list_to_user::role.eq(ShoppingListRole::Owner))
+    .count()
+    .get_result(conn)?;
+
+  if other_owners == 0 {
+    return Err(ServiceError::Conflict(
+      "Shopping list must have at least one owner".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PatchMemberRequest {
+  pub role: ShoppingListRole,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("id", description = "Shopping list ID"),
+      ("user_id", description = "Member user ID"),
+  ),
+  request_body = PatchMemberRequest,
+  responses(
+      (status = 200, description = "Member role updated", body = ShoppingListResponse),
+      (status = 403, description = "Forbidden - not owner of shopping list"),
+      (status = 404, description = "Member not found"),
+      (status = 409, description = "Shopping list must have at least one owner"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[patch("/{id}/members/{user_id}")]
+pub async fn patch_member(
+  path: web::Path<(i32, i32)>,
+  data: web::Json<PatchMemberRequest>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let (shopping_list_id, member_id) = path.into_inner();
+
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  require_owner(&mut conn, shopping_list_id, user_id)?;
+
+  let response = conn.transaction(|conn| {
+    if data.role != ShoppingListRole::Owner {
+      ensure_other_owner(conn, shopping_list_id, member_id)?;
+    }
+
+    let updated = diesel::update(shopping_list_to_user::table.find((shopping_list_id, member_id)))
+      .set(shopping_list_to_user::role.eq(data.role))
+      .execute(conn)?;
+
+    if updated == 0 {
+      return Err(ServiceError::NotFound(Some("Member not found".to_string())));
+    }
+
+    realtime::notify_shopping_list(
+      conn,
+      ShoppingListEvent {
+        shopping_list_id,
+        kind: ShoppingListEventKind::MemberUpdated,
+        user_id,
+        item_id: None,
+        item: None,
+        member_id: Some(member_id),
+      },
+    )?;
+    let response = get_full_shopping_list(conn, shopping_list_id)?;
+    enqueue_shopping_list_updated(conn, "updated", shopping_list_id)?;
+    Ok(response)
+  })?;
+
+  Ok(HttpResponse::Ok().json(response))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  params(
+      ("id", description = "Shopping list ID"),
+      ("user_id", description = "Member user ID"),
+  ),
+  responses(
+      (status = 204, description = "Member removed"),
+      (status = 403, description = "Forbidden - not owner of shopping list or the member themselves"),
+      (status = 404, description = "Member not found"),
+      (status = 409, description = "Shopping list must have at least one owner"),
+      (status = 500, description = "Internal server error"),
+  ),
+)]
+#[delete("/{id}/members/{user_id}")]
+pub async fn delete_member(
+  path: web::Path<(i32, i32)>,
+  db: web::Data<Pool>,
+  claims: Authorized<()>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let user_id = claims.sub;
+  let (shopping_list_id, member_id) = path.into_inner();
+
+  let mut conn = db.get().map_err(|e| {
+    log::error!("Error getting DB connection: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  // Members may always leave, only owners can remove someone else
+  if member_id != user_id {
+    require_owner(&mut conn, shopping_list_id, user_id)?;
+  }
+
+  conn.transaction(|conn| {
+    ensure_other_owner(conn, shopping_list_id, member_id)?;
+
+    let deleted = diesel::delete(shopping_list_to_user::table.find((shopping_list_id, member_id))).execute(conn)?;
+
+    if deleted == 0 {
+      return Err(ServiceError::NotFound(Some("Member not found".to_string())));
+    }
+
+    // ends the member's own event stream, along with telling everyone else
+    realtime::notify_shopping_list(
+      conn,
+      ShoppingListEvent {
+        shopping_list_id,
+        kind: ShoppingListEventKind::MemberRemoved,
+        user_id,
+        item_id: None,
+        item: None,
+        member_id: Some(member_id),
+      },
+    )?;
+    enqueue_shopping_list_updated(conn, "updated", shopping_list_id)?;
+    Ok(())
+  })?;
+
+  Ok(HttpResponse::NoContent().finish())
+}
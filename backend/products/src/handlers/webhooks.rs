@@ -0,0 +1,412 @@
+/*
+  Name: webhooks.rs
+
+  Description:
+  The endpoint handlers for `/api/v1/webhooks`, subscriptions of partners to the events in `webhooks::EVENT_TYPES`
+  and the deliveries that ran out of attempts
+*/
+
+use crate::models::*;
+use crate::schema::*;
+use crate::webhooks::EVENT_TYPES;
+use crate::Pool;
+use actix_web::web::ServiceConfig;
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use auth::audit::{snapshot, RequestMetadata};
+use auth::errors::ServiceError;
+use auth::scopes::*;
+use auth::Authorized;
+use common_rs::graphql::{GraphConnection, PaginationParams};
+use common_rs::paginate;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator_rs::openapi_security;
+
+pub(crate) const V1_PATH: &str = "/api/v1/webhooks";
+
+const MIN_SECRET_LENGTH: usize = 16;
+
+pub fn configure() -> impl FnOnce(&mut ServiceConfig) {
+  |config: &mut ServiceConfig| {
+    config.service(
+      web::scope(V1_PATH)
+        .service(get_webhooks)
+        .service(create_webhook)
+        .service(update_webhook)
+        .service(delete_webhook)
+        .service(get_failed_deliveries)
+        .service(retry_delivery),
+    );
+  }
+}
+
+async fn validate_url(url: &str) -> Result<(), ServiceError> {
+  crate::webhooks::validate_url(url)
+    .await
+    .map(|_| ())
+    .map_err(|e| ServiceError::BadRequest(format!("`url` {}", e)))
+}
+
+fn validate_secret(secret: &str) -> Result<(), ServiceError> {
+  if secret.len() < MIN_SECRET_LENGTH {
+    return Err(ServiceError::BadRequest(format!(
+      "`secret` must be at least {} characters",
+      MIN_SECRET_LENGTH
+    )));
+  }
+  Ok(())
+}
+
+fn validate_event_types(event_types: &[String]) -> Result<(), ServiceError> {
+  if event_types.is_empty() {
+    return Err(ServiceError::BadRequest("`event_types` must not be empty".to_string()));
+  }
+  if let Some(unknown) = event_types.iter().find(|t| !EVENT_TYPES.contains(&t.as_str())) {
+    return Err(ServiceError::BadRequest(format!(
+      "Unknown event type `{}`, expected one of {}",
+      unknown,
+      EVENT_TYPES.join(", ")
+    )));
+  }
+  Ok(())
+}
+
+fn get_by_id(conn: &mut PgConnection, id: i32) -> Result<WebhookSubscription, ServiceError> {
+  webhook_subscriptions::table
+    .find(id)
+    .first::<WebhookSubscription>(conn)
+    .optional()?
+    .ok_or(ServiceError::NotFound(Some("Webhook not found".to_string())))
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = Vec<WebhookSubscription>),
+    (status = 401),
+  ),
+)]
+#[get("")]
+pub(crate) async fn get_webhooks(
+  db: web::Data<Pool>,
+  _claims: Authorized<ReadAll>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let subscriptions = web::block(move || {
+    webhook_subscriptions::table
+      .order(webhook_subscriptions::id.asc())
+      .load::<WebhookSubscription>(&mut db.get().unwrap())
+  })
+  .await?
+  .map_err(|e| {
+    log::error!("Error: {}", e);
+    ServiceError::InternalServerError
+  })?;
+
+  Ok(HttpResponse::Ok().json(subscriptions))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateWebhookRequest {
+  /// Receives a `POST` of every subscribed event
+  pub url: String,
+  /// Key of the HMAC-SHA256 signature in `X-GroceryWise-Signature`, computed over `<X-GroceryWise-Timestamp>.<body>`
+  pub secret: String,
+  /// Any of `product.upserted`, `price_report.created` and `shopping_list.updated`
+  pub event_types: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedWebhookResponse {
+  #[serde(flatten)]
+  pub subscription: WebhookSubscription,
+  pub secret: String,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = CreateWebhookRequest, content_type = "application/json"),
+  responses(
+    (status = CREATED, body = CreatedWebhookResponse),
+    (status = BAD_REQUEST, description = "Invalid url, secret or event types"),
+    (status = 401),
+  ),
+)]
+#[post("")]
+pub(crate) async fn create_webhook(
+  db: web::Data<Pool>,
+  claims: Authorized<CreateAll>,
+  metadata: RequestMetadata,
+  body: web::Json<CreateWebhookRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let body = body.into_inner();
+  validate_url(&body.url).await?;
+  validate_secret(&body.secret)?;
+  validate_event_types(&body.event_types)?;
+
+  let subscription = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let subscription = diesel::insert_into(webhook_subscriptions::table)
+        .values(NewWebhookSubscription {
+          url: &body.url,
+          secret: &body.secret,
+          event_types: &body.event_types,
+          created_by: actor_id,
+        })
+        .get_result::<WebhookSubscription>(conn)?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("create")
+        .resource("webhook")
+        .resource_id(subscription.id.to_string())
+        .maybe_after(snapshot(&subscription))
+        .call()?;
+      Ok::<_, ServiceError>(subscription)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Created().json(CreatedWebhookResponse {
+    secret: subscription.secret.clone(),
+    subscription,
+  }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateWebhookRequest {
+  pub url: Option<String>,
+  /// Rotates the secret, deliveries still queued are signed with the new one
+  pub secret: Option<String>,
+  pub event_types: Option<Vec<String>>,
+  pub active: Option<bool>,
+}
+
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  request_body(content = UpdateWebhookRequest, content_type = "application/json"),
+  responses(
+    (status = OK, body = WebhookSubscription),
+    (status = BAD_REQUEST, description = "Invalid url, secret or event types"),
+    (status = 401),
+    (status = NOT_FOUND, description = "Webhook not found"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Webhook id"),
+  ),
+)]
+#[patch("/{id}")]
+pub(crate) async fn update_webhook(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<UpdateAll>,
+  metadata: RequestMetadata,
+  body: web::Json<UpdateWebhookRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+  let body = body.into_inner();
+  if let Some(url) = &body.url {
+    validate_url(url).await?;
+  }
+  if let Some(secret) = &body.secret {
+    validate_secret(secret)?;
+  }
+  if let Some(event_types) = &body.event_types {
+    validate_event_types(event_types)?;
+  }
+
+  let subscription = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let before = get_by_id(conn, *id)?;
+      let subscription = diesel::update(webhook_subscriptions::table.find(before.id))
+        .set(WebhookSubscriptionChangeset {
+          url: body.url,
+          secret: body.secret,
+          event_types: body.event_types,
+          active: body.active,
+          updated_at: Some(chrono::Utc::now().naive_utc()),
+        })
+        .get_result::<WebhookSubscription>(conn)?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("update")
+        .resource("webhook")
+        .resource_id(subscription.id.to_string())
+        .maybe_before(snapshot(&before))
+        .maybe_after(snapshot(&subscription))
+        .call()?;
+      Ok::<_, ServiceError>(subscription)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(subscription))
+}
+
+/// Deletes a webhook along with its queued and failed deliveries
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = WebhookSubscription),
+    (status = 401),
+    (status = NOT_FOUND, description = "Webhook not found"),
+  ),
+  params(
+    ("id" = i32, Path, description = "Webhook id"),
+  ),
+)]
+#[delete("/{id}")]
+pub(crate) async fn delete_webhook(
+  id: web::Path<i32>,
+  db: web::Data<Pool>,
+  claims: Authorized<DeleteAll>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+
+  let subscription = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let subscription = diesel::delete(webhook_subscriptions::table.find(get_by_id(conn, *id)?.id))
+        .get_result::<WebhookSubscription>(conn)?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("delete")
+        .resource("webhook")
+        .resource_id(subscription.id.to_string())
+        .maybe_before(snapshot(&subscription))
+        .call()?;
+      Ok::<_, ServiceError>(subscription)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(subscription))
+}
+
+#[derive(Deserialize)]
+struct FailedDeliveriesParams {
+  #[serde(flatten)]
+  pagination_params: PaginationParams<i64>,
+  subscription_id: Option<i32>,
+}
+
+fn db_get_failed_deliveries(
+  conn: &mut PgConnection,
+  params: FailedDeliveriesParams,
+) -> Result<GraphConnection<WebhookDeliveryResponse>, diesel::result::Error> {
+  let query = webhook_deliveries::table
+    .inner_join(webhook_events::table)
+    .filter(webhook_deliveries::failed_at.is_not_null())
+    .filter(
+      webhook_deliveries::subscription_id
+        .nullable()
+        .eq(params.subscription_id)
+        .or(params.subscription_id.is_none().into_sql::<diesel::sql_types::Bool>()),
+    )
+    .select(WebhookDeliveryResponse::as_select());
+
+  paginate!(
+    query,
+    params.pagination_params,
+    conn,
+    webhook_deliveries::id,
+    |response: &WebhookDeliveryResponse| response.delivery.id.to_string(),
+    WebhookDeliveryResponse
+  )
+}
+
+/// The dead-letter view, deliveries that failed every attempt. Newest first unless paginating forward with `first`
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = GraphConnection<WebhookDeliveryResponse>),
+    (status = 401),
+  ),
+  params(
+    ("first" = Option<i32>, Query, description = "Number of items after cursor"),
+    ("after" = Option<i64>, Query, description = "Cursor for forward pagination"),
+    ("last" = Option<i32>, Query, description = "Number of items before cursor"),
+    ("before" = Option<i64>, Query, description = "Cursor for backward pagination"),
+    ("subscription_id" = Option<i32>, Query, description = "Only deliveries to this webhook"),
+  ),
+)]
+#[get("/deliveries/failed")]
+pub(crate) async fn get_failed_deliveries(
+  db: web::Data<Pool>,
+  _claims: Authorized<ReadAll>,
+  query: web::Query<FailedDeliveriesParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let result = web::block(move || db_get_failed_deliveries(&mut db.get().unwrap(), query.into_inner())).await?;
+
+  match result {
+    Ok(res) => Ok(HttpResponse::Ok().json(res)),
+    Err(err) => {
+      log::error!("{}", err);
+      Ok(Err(ServiceError::InternalServerError)?)
+    }
+  }
+}
+
+/// Queues a failed delivery again with a fresh set of attempts
+#[openapi_security]
+#[utoipa::path(
+  context_path = V1_PATH,
+  responses(
+    (status = OK, body = WebhookDelivery),
+    (status = 401),
+    (status = NOT_FOUND, description = "Failed delivery not found"),
+  ),
+  params(
+    ("id" = i64, Path, description = "Delivery id"),
+  ),
+)]
+#[post("/deliveries/{id}/retry")]
+pub(crate) async fn retry_delivery(
+  id: web::Path<i64>,
+  db: web::Data<Pool>,
+  claims: Authorized<UpdateAll>,
+  metadata: RequestMetadata,
+) -> Result<HttpResponse, actix_web::Error> {
+  let actor_id = claims.sub;
+
+  let delivery = web::block(move || {
+    let mut conn = db.get().unwrap();
+    conn.transaction(|conn| {
+      let delivery = diesel::update(
+        webhook_deliveries::table
+          .find(*id)
+          .filter(webhook_deliveries::failed_at.is_not_null()),
+      )
+      .set((
+        webhook_deliveries::attempts.eq(0),
+        webhook_deliveries::next_attempt_at.eq(diesel::dsl::now),
+        webhook_deliveries::failed_at.eq(None::<chrono::NaiveDateTime>),
+      ))
+      .get_result::<WebhookDelivery>(conn)
+      .optional()?
+      .ok_or(ServiceError::NotFound(Some("Failed delivery not found".to_string())))?;
+
+      auth::audit::record(conn, &metadata)
+        .actor_id(actor_id)
+        .action("retry")
+        .resource("webhook_delivery")
+        .resource_id(delivery.id.to_string())
+        .call()?;
+      Ok::<_, ServiceError>(delivery)
+    })
+  })
+  .await??;
+
+  Ok(HttpResponse::Ok().json(delivery))
+}
@@ -12,7 +12,11 @@
   - 2025-02-16 - Cody Duong - add comments
 */
 
+pub mod blob_store;
 pub mod handlers;
 pub mod models;
+pub mod notifier;
+pub mod realtime;
 pub mod schema;
+pub mod webhooks;
 pub type Pool = diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>;
@@ -60,41 +60,116 @@ async fn main() -> std::io::Result<()> {
     "8082".to_string()
   });
 
-  let manager = ConnectionManager::<PgConnection>::new(database_url);
+  let manager = ConnectionManager::<PgConnection>::new(database_url.clone());
   let pool = r2d2::Pool::builder().build(manager).expect("Failed to create pool.");
 
   let mut conn = pool.get().unwrap();
 
-  conn.revert_last_migration(MIGRATIONS).unwrap();
   conn.run_pending_migrations(MIGRATIONS).unwrap();
+  conn.run_pending_migrations(auth::audit::MIGRATIONS).unwrap();
 
   seed::run(pool.clone());
 
+  if let Ok(path) = std::env::var("EXCHANGE_RATES_FILE") {
+    let count = handlers::exchange_rates::load_file(&pool, &path).expect("Failed to load exchange rates.");
+    log::info!("Loaded {} exchange rates from {}", count, path);
+  }
+
+  // API keys are stored by the auth service and verified straight against its database, which couples this service to
+  // the auth schema (see auth::api_key), without access to it only JWTs are accepted. Usernames in shopping list
+  // invitations are resolved the same way (see auth::directory), otherwise only emails can be invited.
+  let auth_pool = std::env::var("AUTH_DATABASE_URL").ok().map(|auth_database_url| {
+    let manager = ConnectionManager::<PgConnection>::new(auth_database_url);
+    r2d2::Pool::builder()
+      .build(manager)
+      .expect("Failed to create auth pool.")
+  });
+  if auth_pool.is_none() {
+    log::warn!("AUTH_DATABASE_URL is not set, API keys will be rejected and invitations need an email");
+  }
+  let api_key_verifier = auth_pool.clone().map(auth::api_key::ApiKeyVerifier::new);
+  let user_directory = auth_pool.map(auth::directory::UserDirectory::new);
+
+  let blob_store = blob_store::from_env().expect("Failed to create blob store.");
+  let notifiers = notifier::Notifiers::from_env().expect("Failed to create notifiers.");
+  let webhook_dispatcher = webhooks::Dispatcher::new(pool.clone()).expect("Failed to create webhook dispatcher.");
+  actix_rt::spawn(webhook_dispatcher.run());
+  let shopping_list_events = realtime::ShoppingListEvents::new();
+  actix_rt::spawn(realtime::listen(database_url, shopping_list_events.clone()));
+
   #[derive(OpenApi)]
   #[openapi(
     modifiers(&SecurityAddon),
     paths(
+      auth::audit::get_audit_log,
+      handlers::brands::get_brands,
+      handlers::brands::get_brand,
+      handlers::brands::get_brand_products,
+      handlers::brands::create_brand,
+      handlers::brands::update_brand,
+      handlers::brands::delete_brand,
+      handlers::categories::get_categories,
+      handlers::categories::get_category,
+      handlers::categories::create_category,
+      handlers::categories::update_category,
+      handlers::categories::delete_category,
       handlers::companies::get_company,
       handlers::companies::get_companies,
+      handlers::exchange_rates::get_exchange_rates,
+      handlers::exchange_rates::put_exchange_rates,
       handlers::marketplaces::get_marketplace,
       handlers::marketplaces::get_marketplaces,
       handlers::marketplaces::post_marketplace,
+      handlers::notifications::get_notifications,
+      handlers::notifications::mark_all_read,
+      handlers::notifications::mark_read,
       handlers::price_reports::get_price_reports_for_gtin,
       handlers::price_reports::get_price_reports_for_gtins,
       handlers::price_reports::post_price_report,
+      handlers::price_reports::anonymize_price_reports,
+      handlers::price_watches::get_price_watches,
+      handlers::price_watches::create_price_watch,
+      handlers::price_watches::delete_price_watch,
+      handlers::product_listings::get_product_listings,
+      handlers::product_listings::put_product_listings,
+      handlers::product_listings::delete_product_listing,
       handlers::products_to_images::get_image,
       handlers::products_to_images::post_image,
+      handlers::products_to_images::upload_image,
+      handlers::products_to_images::set_primary_image,
+      handlers::products_to_images::delete_image,
+      handlers::products_to_images::get_blob,
       handlers::products::get_product,
       handlers::products::delete_product,
       handlers::products::get_products,
+      handlers::products::suggest_products,
       handlers::products::post_products,
+      handlers::products::put_product_categories,
+      handlers::receipts::draft_receipt,
+      handlers::receipts::commit_receipt,
       handlers::shopping_lists::create_shopping_list,
       handlers::shopping_lists::patch_shopping_list,
       handlers::shopping_lists::delete_shopping_list,
       handlers::shopping_lists::get_shopping_list,
+      handlers::shopping_lists::create_events_ticket,
+      handlers::shopping_lists::get_shopping_list_events,
       handlers::shopping_lists::get_shopping_lists,
+      handlers::shopping_lists::create_invitation,
+      handlers::shopping_lists::get_invitations,
+      handlers::shopping_lists::delete_invitation,
+      handlers::shopping_lists::get_my_invitations,
+      handlers::shopping_lists::accept_invitation,
+      handlers::shopping_lists::decline_invitation,
+      handlers::shopping_lists::patch_member,
+      handlers::shopping_lists::delete_member,
       handlers::units::get_unit,
       handlers::units::get_units,
+      handlers::webhooks::get_webhooks,
+      handlers::webhooks::create_webhook,
+      handlers::webhooks::update_webhook,
+      handlers::webhooks::delete_webhook,
+      handlers::webhooks::get_failed_deliveries,
+      handlers::webhooks::retry_delivery,
     )
   )]
   struct ApiDoc;
@@ -122,7 +197,7 @@ async fn main() -> std::io::Result<()> {
   HttpServer::new(move || {
     let cors = Cors::default()
       .allowed_origin_fn(|origin, _req_head| ALLOWED_ORIGINS.iter().any(|&i| i == origin))
-      .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+      .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
       .allowed_headers(vec!["Content-Type", "Authorization", "b3", "traceparent"])
       .max_age(3600);
 
@@ -130,6 +205,17 @@ async fn main() -> std::io::Result<()> {
       .wrap(Logger::default())
       .wrap(cors)
       .app_data(Data::new(pool.clone()))
+      .app_data(Data::from(blob_store.clone()))
+      .app_data(Data::new(notifiers.clone()))
+      .app_data(Data::new(shopping_list_events.clone()))
+      .configure(|config| {
+        if let Some(api_key_verifier) = &api_key_verifier {
+          config.app_data(Data::new(api_key_verifier.clone()));
+        }
+        if let Some(user_directory) = &user_directory {
+          config.app_data(Data::new(user_directory.clone()));
+        }
+      })
       .configure(handlers::shopping_lists::configure())
       .configure(handlers::marketplaces::configure())
       .configure(handlers::products_to_images::configure())
@@ -137,6 +223,15 @@ async fn main() -> std::io::Result<()> {
       .configure(handlers::units::configure())
       .configure(handlers::price_reports::configure())
       .configure(handlers::companies::configure())
+      .configure(auth::audit::configure())
+      .configure(handlers::brands::configure())
+      .configure(handlers::categories::configure())
+      .configure(handlers::notifications::configure())
+      .configure(handlers::price_watches::configure())
+      .configure(handlers::webhooks::configure())
+      .configure(handlers::receipts::configure())
+      .configure(handlers::exchange_rates::configure())
+      .configure(handlers::product_listings::configure())
       .service(
         SwaggerUi::new("/swagger-ui/{_:.*}").urls(vec![(Url::new("api", "/api-docs/openapi.json"), ApiDoc::openapi())]),
       )
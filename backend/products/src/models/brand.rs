@@ -0,0 +1,85 @@
+/*
+  Name: brand.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - `products.brand_id` is derived from `brand_gs1_prefixes` by a database trigger whenever it is left `NULL`
+*/
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Deserialize, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::brands)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Brand {
+  pub id: i32,
+  pub name: String,
+  /// The company owning the brand, e.g. Ferrero for Nutella
+  pub manufacturer: Option<String>,
+  pub created_at: chrono::NaiveDateTime,
+  pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::brands)]
+pub struct NewBrand<'a> {
+  pub name: &'a str,
+  pub manufacturer: Option<&'a str>,
+}
+
+/// Partial update of a brand, `None` leaves the column untouched
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = crate::schema::brands)]
+pub struct BrandChangeset {
+  pub name: Option<String>,
+  pub manufacturer: Option<Option<String>>,
+  pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::brand_gs1_prefixes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BrandGs1Prefix {
+  pub prefix: String,
+  pub brand_id: i32,
+}
+
+/// Lowest and highest reported price in one currency
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct PriceRange {
+  pub currency: String,
+  #[schema(value_type = f64)]
+  pub min: bigdecimal::BigDecimal,
+  #[schema(value_type = f64)]
+  pub max: bigdecimal::BigDecimal,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BrandResponse {
+  #[serde(flatten)]
+  pub brand: Brand,
+  /// GS1 company prefixes as the leading digits of a GTIN-13, products with a matching GTIN belong to the brand
+  pub gs1_prefixes: Vec<String>,
+  pub product_count: i64,
+  /// Across every reported price of the brand's products
+  pub price_ranges: Vec<PriceRange>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BrandProductResponse {
+  #[serde(flatten)]
+  pub product: super::ProductResponse,
+  pub price_ranges: Vec<PriceRange>,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct BrandFacet {
+  pub id: i32,
+  pub name: String,
+  pub count: i64,
+}
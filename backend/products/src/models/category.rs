@@ -0,0 +1,69 @@
+/*
+  Name: category.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - `categories.path` is maintained by database triggers, never write it directly
+*/
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Deserialize, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::categories)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Category {
+  pub id: i32,
+  pub parent_id: Option<i32>,
+  pub name: String,
+  /// Ids from the top level category down to and including this one
+  pub path: Vec<i32>,
+  pub created_at: chrono::NaiveDateTime,
+  pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CategoryResponse {
+  #[serde(flatten)]
+  pub category: Category,
+  /// From the top level category down to the parent
+  pub ancestors: Vec<Category>,
+  pub children: Vec<Category>,
+}
+
+#[derive(Deserialize, Insertable, ToSchema, Debug)]
+#[diesel(table_name = crate::schema::categories)]
+pub struct NewCategory {
+  pub parent_id: Option<i32>,
+  pub name: String,
+}
+
+/// Partial update of a category, `None` leaves the column untouched
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = crate::schema::categories)]
+pub struct CategoryChangeset {
+  pub parent_id: Option<Option<i32>>,
+  pub name: Option<String>,
+  pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::products_to_categories)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ProductToCategory {
+  pub gtin: String,
+  pub category_id: i32,
+}
+
+/// Number of products in the category or any of its descendants
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct CategoryFacet {
+  pub id: i32,
+  pub parent_id: Option<i32>,
+  pub name: String,
+  pub count: i64,
+}
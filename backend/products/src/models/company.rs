@@ -11,3 +11,10 @@ pub struct Company {
 }
 
 pub type CompanyResponse = Company;
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct CompanyFacet {
+  pub id: i32,
+  pub name: String,
+  pub count: i64,
+}
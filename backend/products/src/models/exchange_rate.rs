@@ -0,0 +1,35 @@
+/*
+  Name: exchange_rate.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Prices are converted in Postgres with `convert_price`, see `handlers::exchange_rates`
+*/
+
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::exchange_rates)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ExchangeRate {
+  #[schema(min_length = 3, max_length = 3)]
+  pub currency: String,
+  pub date: chrono::NaiveDate,
+  /// Units of `currency` per US dollar
+  #[schema(value_type = f64)]
+  pub rate: bigdecimal::BigDecimal,
+}
+
+/// A price converted at the exchange rates of the day it was reported, rounded to the currency's minor unit
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct ConvertedPrice {
+  #[schema(value_type = f64)]
+  pub price: bigdecimal::BigDecimal,
+  #[schema(min_length = 3, max_length = 3)]
+  pub currency: String,
+}
@@ -19,10 +19,18 @@
     glob style here
 */
 
+mod brand;
+pub use brand::*;
+mod category;
+pub use category::*;
 mod company;
 pub use company::*;
+mod exchange_rate;
+pub use exchange_rate::*;
 mod marketplace;
 pub use marketplace::*;
+mod notification;
+pub use notification::*;
 mod online_marketplace;
 pub use online_marketplace::*;
 mod physical_marketplace;
@@ -31,13 +39,21 @@ mod price_report_to_marketplaces;
 pub use price_report_to_marketplaces::*;
 mod price_report;
 pub use price_report::*;
+mod price_watch;
+pub use price_watch::*;
+mod product_listing;
+pub use product_listing::*;
 mod product_to_image;
 pub use product_to_image::*;
 mod product_to_measure;
 pub use product_to_measure::*;
 mod product;
 pub use product::*;
+mod receipt;
+pub use receipt::*;
 mod unit;
 pub use unit::*;
 mod shopping_list;
 pub use shopping_list::*;
+mod webhook;
+pub use webhook::*;
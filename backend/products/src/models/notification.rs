@@ -0,0 +1,42 @@
+/*
+  Name: notification.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Notifications are written to the inbox by `notifier::db_add_to_inbox`
+*/
+
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// `kind` of the notifications sent for a `PriceWatch`
+pub const PRICE_ALERT: &str = "price_alert";
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::notifications)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Notification {
+  pub id: i64,
+  pub user_id: i32,
+  pub kind: String,
+  pub title: String,
+  pub body: String,
+  /// Depends on `kind`, for price alerts the watch, product and the report that triggered it
+  pub data: serde_json::Value,
+  pub read_at: Option<chrono::NaiveDateTime>,
+  pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone, Serialize)]
+#[diesel(table_name = crate::schema::notifications)]
+pub struct NewNotification {
+  pub user_id: i32,
+  pub kind: String,
+  pub title: String,
+  pub body: String,
+  pub data: serde_json::Value,
+}
@@ -1,8 +1,53 @@
+use bigdecimal::BigDecimal;
 use common_rs::to_rfc3339;
-use diesel::prelude::*;
+use diesel::{
+  deserialize::{self, FromSql, FromSqlRow},
+  expression::AsExpression,
+  pg::{Pg, PgValue},
+  prelude::*,
+  serialize::{self, IsNull, Output, ToSql},
+};
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 use utoipa::ToSchema;
 
+/// `created_by` of seeded reports, and of reports left behind by purged accounts
+pub const ANONYMOUS_USER_ID: i32 = 0;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, FromSqlRow, AsExpression, Clone, Copy, ToSchema)]
+#[diesel(sql_type = diesel::sql_types::Text)]
+#[serde(rename_all = "snake_case")]
+pub enum PromotionType {
+  /// A temporary reduction
+  Sale,
+  /// `price` buys `promotion_quantity` units, such as 2 for $5
+  MultiBuy,
+  /// A reduction to clear remaining stock
+  Clearance,
+}
+
+impl ToSql<diesel::sql_types::Text, Pg> for PromotionType {
+  fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+    match *self {
+      PromotionType::Sale => out.write_all(b"sale")?,
+      PromotionType::MultiBuy => out.write_all(b"multi_buy")?,
+      PromotionType::Clearance => out.write_all(b"clearance")?,
+    }
+    Ok(IsNull::No)
+  }
+}
+
+impl FromSql<diesel::sql_types::Text, Pg> for PromotionType {
+  fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+    match bytes.as_bytes() {
+      b"sale" => Ok(PromotionType::Sale),
+      b"multi_buy" => Ok(PromotionType::MultiBuy),
+      b"clearance" => Ok(PromotionType::Clearance),
+      _ => Err("Unrecognized enum variant".into()),
+    }
+  }
+}
+
 #[derive(Queryable, Selectable, Debug, ToSchema, Serialize, Clone)]
 #[diesel(table_name = crate::schema::price_reports)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -21,6 +66,52 @@ pub struct PriceReport {
   pub price: bigdecimal::BigDecimal,
   #[schema(min_length = 3, max_length = 3)]
   pub currency: String,
+  pub promotion_type: Option<PromotionType>,
+  /// The price without the promotion
+  #[schema(value_type = Option<f64>)]
+  pub regular_price: Option<BigDecimal>,
+  /// How many units `price` is for, one if `None`
+  pub promotion_quantity: Option<i32>,
+  /// Whether `price` is only for loyalty card holders
+  pub loyalty_required: bool,
+  #[serde(with = "to_rfc3339::option")]
+  pub valid_from: Option<chrono::NaiveDateTime>,
+  #[serde(with = "to_rfc3339::option")]
+  pub valid_until: Option<chrono::NaiveDateTime>,
+}
+
+impl PriceReport {
+  /// What one unit costs at the reported price
+  pub fn unit_price(&self) -> BigDecimal {
+    unit_price(&self.price, self.promotion_quantity)
+  }
+
+  /// Whether `price` is what the shopper pays at `now`, it isn't outside the promotion's validity or without the
+  /// loyalty card it needs
+  pub fn promotion_applies(&self, loyalty_member: bool, now: chrono::NaiveDateTime) -> bool {
+    self.valid_from.is_none_or(|valid_from| now >= valid_from)
+      && self.valid_until.is_none_or(|valid_until| now < valid_until)
+      && (loyalty_member || !self.loyalty_required)
+  }
+
+  /// What one unit costs the shopper at `now`, the same as `current_unit_price` in SQL. `None` when the promotion
+  /// doesn't apply and no regular price was reported.
+  pub fn current_unit_price(&self, loyalty_member: bool, now: chrono::NaiveDateTime) -> Option<BigDecimal> {
+    if self.promotion_applies(loyalty_member, now) {
+      Some(self.unit_price())
+    } else {
+      self
+        .regular_price
+        .as_ref()
+        .map(|regular_price| regular_price.round(4).normalized())
+    }
+  }
+}
+
+fn unit_price(price: &BigDecimal, promotion_quantity: Option<i32>) -> BigDecimal {
+  (price / BigDecimal::from(promotion_quantity.unwrap_or(1)))
+    .round(4)
+    .normalized()
 }
 
 #[derive(Serialize, ToSchema)]
@@ -29,9 +120,12 @@ pub struct PriceResponse {
   pub price_report: PriceReport,
   pub company: super::Company,
   pub marketplace: super::MarketplaceResponse,
+  /// The price in the currency asked for with `currency`, `None` if none was or no exchange rate is known
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub converted: Option<super::ConvertedPrice>,
 }
 
-#[derive(Deserialize, Insertable, ToSchema, Clone, Debug)]
+#[derive(Deserialize, Insertable, ToSchema, Clone, Debug, Default)]
 #[diesel(table_name = crate::schema::price_reports)]
 pub struct NewPriceReport {
   pub id: Option<i64>,
@@ -43,9 +137,26 @@ pub struct NewPriceReport {
   pub price: bigdecimal::BigDecimal,
   #[schema(min_length = 3, max_length = 3)]
   pub currency: String,
+  #[serde(default)]
+  pub promotion_type: Option<PromotionType>,
+  /// The price without the promotion, paid outside its validity or without a loyalty card. Required when
+  /// `loyalty_required`, `valid_from` or `valid_until` are set.
+  #[serde(default)]
+  #[schema(value_type = Option<f64>)]
+  pub regular_price: Option<BigDecimal>,
+  /// How many units `price` is for, such as 2 for "2 for $5"
+  #[serde(default)]
+  pub promotion_quantity: Option<i32>,
+  /// Whether `price` is only for loyalty card holders
+  #[serde(default)]
+  pub loyalty_required: bool,
+  #[serde(default)]
+  pub valid_from: Option<chrono::NaiveDateTime>,
+  #[serde(default)]
+  pub valid_until: Option<chrono::NaiveDateTime>,
 }
 
-#[derive(Deserialize, Insertable, ToSchema, Clone, Debug)]
+#[derive(Deserialize, Insertable, ToSchema, Clone, Debug, Default)]
 #[diesel(table_name = crate::schema::price_reports)]
 pub struct NewPriceReportPublic {
   pub reported_at: Option<chrono::NaiveDateTime>,
@@ -55,6 +166,23 @@ pub struct NewPriceReportPublic {
   pub price: bigdecimal::BigDecimal,
   #[schema(min_length = 3, max_length = 3)]
   pub currency: String,
+  #[serde(default)]
+  pub promotion_type: Option<PromotionType>,
+  /// The price without the promotion, paid outside its validity or without a loyalty card. Required when
+  /// `loyalty_required`, `valid_from` or `valid_until` are set.
+  #[serde(default)]
+  #[schema(value_type = Option<f64>)]
+  pub regular_price: Option<BigDecimal>,
+  /// How many units `price` is for, such as 2 for "2 for $5"
+  #[serde(default)]
+  pub promotion_quantity: Option<i32>,
+  /// Whether `price` is only for loyalty card holders
+  #[serde(default)]
+  pub loyalty_required: bool,
+  #[serde(default)]
+  pub valid_from: Option<chrono::NaiveDateTime>,
+  #[serde(default)]
+  pub valid_until: Option<chrono::NaiveDateTime>,
 }
 
 impl NewPriceReportPublic {
@@ -66,8 +194,53 @@ impl NewPriceReportPublic {
       gtin: self.gtin,
       price: self.price,
       currency: self.currency,
+      promotion_type: self.promotion_type,
+      regular_price: self.regular_price,
+      promotion_quantity: self.promotion_quantity,
+      loyalty_required: self.loyalty_required,
+      valid_from: self.valid_from,
+      valid_until: self.valid_until,
     }
   }
+
+  /// Why the promotion fields don't describe a promotion, if they don't
+  pub fn validate_promotion(&self) -> Result<(), String> {
+    if self
+      .regular_price
+      .as_ref()
+      .is_some_and(|regular_price| *regular_price <= BigDecimal::from(0))
+    {
+      return Err("`regular_price` must be positive".to_string());
+    }
+    match (self.promotion_type, self.promotion_quantity) {
+      (Some(PromotionType::MultiBuy), Some(quantity)) if quantity >= 2 => {}
+      (Some(PromotionType::MultiBuy), _) => {
+        return Err("A `multi_buy` needs a `promotion_quantity` of at least 2".to_string())
+      }
+      (_, Some(quantity)) if quantity != 1 => return Err("Only a `multi_buy` has a `promotion_quantity`".to_string()),
+      _ => {}
+    }
+    // shoppers the promoted price doesn't apply to pay the regular price, which has to be known
+    let conditional = self.loyalty_required || self.valid_from.is_some() || self.valid_until.is_some();
+    if conditional && self.regular_price.is_none() {
+      return Err("A promotion for loyalty card holders or with a validity window needs a `regular_price`".to_string());
+    }
+    if let Some(regular_price) = self
+      .regular_price
+      .as_ref()
+      .filter(|_| conditional || self.promotion_type.is_some())
+    {
+      if *regular_price < unit_price(&self.price, self.promotion_quantity) {
+        return Err("`regular_price` is below the promotional unit price".to_string());
+      }
+    }
+    if let (Some(valid_from), Some(valid_until)) = (self.valid_from, self.valid_until) {
+      if valid_until <= valid_from {
+        return Err("`valid_until` must be after `valid_from`".to_string());
+      }
+    }
+    Ok(())
+  }
 }
 
 impl From<NewPriceReportDSL> for NewPriceReportPublic {
@@ -98,3 +271,100 @@ impl From<NewPriceReportDSLUnion> for Vec<NewPriceReportDSL> {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::str::FromStr;
+
+  fn report() -> NewPriceReportPublic {
+    NewPriceReportPublic {
+      gtin: "012345678905".to_string(),
+      price: BigDecimal::from_str("2.50").unwrap(),
+      currency: "USD".to_string(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_conditional_promotion_needs_regular_price() {
+    let loyalty = NewPriceReportPublic {
+      loyalty_required: true,
+      ..report()
+    };
+    assert!(loyalty.validate_promotion().is_err());
+    assert!(NewPriceReportPublic {
+      regular_price: Some(BigDecimal::from(3)),
+      ..loyalty
+    }
+    .validate_promotion()
+    .is_ok());
+
+    let window = NewPriceReportPublic {
+      promotion_type: Some(PromotionType::Sale),
+      valid_until: Some(chrono::NaiveDate::from_ymd_opt(2025, 6, 1).unwrap().into()),
+      ..report()
+    };
+    assert!(window.validate_promotion().is_err());
+    assert!(NewPriceReportPublic {
+      regular_price: Some(BigDecimal::from(2)),
+      ..window.clone()
+    }
+    .validate_promotion()
+    .is_err());
+    assert!(NewPriceReportPublic {
+      regular_price: Some(BigDecimal::from(3)),
+      ..window
+    }
+    .validate_promotion()
+    .is_ok());
+  }
+
+  #[test]
+  fn test_current_unit_price() {
+    let at = |day| chrono::NaiveDate::from_ymd_opt(2025, 6, day).unwrap().into();
+    let now = chrono::NaiveDateTime::default();
+    let report = PriceReport {
+      id: 1,
+      reported_at: now,
+      created_at: now,
+      updated_at: now,
+      created_by: 1,
+      gtin: "012345678905".to_string(),
+      price: BigDecimal::from(5),
+      currency: "USD".to_string(),
+      promotion_type: Some(PromotionType::MultiBuy),
+      regular_price: Some(BigDecimal::from_str("3.00").unwrap()),
+      promotion_quantity: Some(2),
+      loyalty_required: true,
+      valid_from: Some(at(1)),
+      valid_until: Some(at(8)),
+    };
+
+    assert_eq!(
+      report.current_unit_price(true, at(3)),
+      Some(BigDecimal::from_str("2.5").unwrap())
+    );
+    assert_eq!(report.current_unit_price(false, at(3)), Some(BigDecimal::from(3)));
+    assert_eq!(report.current_unit_price(true, at(8)), Some(BigDecimal::from(3)));
+    assert_eq!(
+      PriceReport {
+        regular_price: None,
+        ..report
+      }
+      .current_unit_price(true, at(9)),
+      None
+    );
+  }
+
+  #[test]
+  fn test_unconditional_promotion() {
+    assert!(report().validate_promotion().is_ok());
+    assert!(NewPriceReportPublic {
+      promotion_type: Some(PromotionType::Clearance),
+      ..report()
+    }
+    .validate_promotion()
+    .is_ok());
+  }
+}
@@ -0,0 +1,60 @@
+/*
+  Name: price_watch.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Watches are evaluated by `handlers::price_reports::db_add_price_report` as reports come in
+*/
+
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Queryable, Selectable, Identifiable, AsChangeset, Debug, Clone, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::price_watches)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
+pub struct PriceWatch {
+  pub id: i32,
+  pub user_id: i32,
+  pub gtin: String,
+  pub marketplace_id: Option<i32>,
+  pub company_id: Option<i32>,
+  pub currency: String,
+  /// Notify once the price is at or below this
+  #[schema(value_type = Option<f64>)]
+  pub target_price: Option<bigdecimal::BigDecimal>,
+  /// Notify once the price is this many percent below `reference_price`
+  #[schema(value_type = Option<f64>)]
+  pub percent_drop: Option<bigdecimal::BigDecimal>,
+  /// The latest price when the watch was created, and then each notified price
+  #[schema(value_type = Option<f64>)]
+  pub reference_price: Option<bigdecimal::BigDecimal>,
+  #[schema(value_type = Option<f64>)]
+  pub last_notified_price: Option<bigdecimal::BigDecimal>,
+  pub last_notified_at: Option<chrono::NaiveDateTime>,
+  /// Also notified by email at this address
+  pub email: Option<String>,
+  /// Also notified by a `POST` of the notification to this url
+  pub webhook_url: Option<String>,
+  pub created_at: chrono::NaiveDateTime,
+  pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::price_watches)]
+pub struct NewPriceWatch {
+  pub user_id: i32,
+  pub gtin: String,
+  pub marketplace_id: Option<i32>,
+  pub company_id: Option<i32>,
+  pub currency: String,
+  pub target_price: Option<bigdecimal::BigDecimal>,
+  pub percent_drop: Option<bigdecimal::BigDecimal>,
+  pub reference_price: Option<bigdecimal::BigDecimal>,
+  pub email: Option<String>,
+  pub webhook_url: Option<String>,
+}
@@ -36,6 +36,7 @@ pub struct Product {
   pub productname: String,
   pub sellsinraw: bool,
   pub description: Option<String>,
+  pub brand_id: Option<i32>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema, Clone)]
@@ -44,6 +45,29 @@ pub struct ProductResponse {
   pub product: Product,
   pub measures: Vec<super::ProductToMeasureResponse>,
   pub images: Vec<super::ProductToImageResponse>,
+  #[serde(default)]
+  pub categories: Vec<super::Category>,
+  #[serde(default)]
+  pub brand: Option<super::Brand>,
+  /// Lowest and highest of the latest price at each marketplace
+  #[serde(default)]
+  pub current_price: Option<super::PriceRange>,
+  /// Pages of the product at online marketplaces
+  #[serde(default)]
+  pub listings: Vec<super::ProductListing>,
+  /// Only set when searching
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub search_match: Option<SearchMatch>,
+}
+
+/// How a product matched the search, the name and description have the matching words wrapped in `<b>`
+#[derive(Deserialize, Serialize, ToSchema, Clone)]
+pub struct SearchMatch {
+  /// Full-text matches rank from 1 to 2, fuzzy matches of the product name below 1
+  pub rank: f32,
+  pub productname: String,
+  /// A snippet of the description around the matching words
+  pub description: Option<String>,
 }
 
 #[derive(Deserialize, Insertable, ToSchema, Clone, Debug)]
@@ -54,6 +78,9 @@ pub struct NewProduct {
   pub sku: Option<String>,
   pub productname: String,
   pub description: Option<String>,
+  /// Derived from the GTIN's GS1 company prefix if omitted
+  #[serde(default)]
+  pub brand_id: Option<i32>,
 }
 
 #[derive(Deserialize, ToSchema, Clone)]
@@ -62,20 +89,62 @@ pub struct NewProductPost {
   pub new_product: NewProduct,
   pub measures: super::NewProductToMeasurePartialUnion,
   pub images: Option<super::NewProductToImagePartialUnion>,
+  /// Replaces the product's category ids, `None` keeps the current ones
+  #[serde(default)]
+  pub categories: Option<Vec<i32>>,
 }
 
 #[derive(Deserialize, ToSchema)]
 #[serde(untagged)]
 pub enum NewProductPostUnion {
-  Single(NewProductPost),
+  Single(Box<NewProductPost>),
   Multiple(Vec<NewProductPost>),
 }
 
 impl From<NewProductPostUnion> for Vec<NewProductPost> {
   fn from(r: NewProductPostUnion) -> Self {
     match r {
-      NewProductPostUnion::Single(new_product) => vec![new_product],
+      NewProductPostUnion::Single(new_product) => vec![*new_product],
       NewProductPostUnion::Multiple(new_products) => new_products,
     }
   }
 }
+
+#[derive(Serialize, ToSchema)]
+pub struct ProductConnection {
+  #[serde(flatten)]
+  pub connection: common_rs::graphql::Connection<ProductResponse>,
+  pub facets: ProductFacets,
+}
+
+/// Counts across every page of the filtered products, not just the returned one
+#[derive(Serialize, ToSchema)]
+pub struct ProductFacets {
+  /// Categories with at least one product, a product counts towards each ancestor of its categories
+  pub categories: Vec<super::CategoryFacet>,
+  pub brands: Vec<super::BrandFacet>,
+  /// Companies with a price report for the product
+  pub companies: Vec<super::CompanyFacet>,
+  /// Buckets by the lowest price reported for the product, in `price_currency`
+  pub prices: Vec<PriceBucketFacet>,
+  pub price_currency: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ProductSuggestion {
+  pub gtin: String,
+  pub productname: String,
+  /// Name of the product's brand
+  pub brand: Option<String>,
+  pub rank: f32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PriceBucketFacet {
+  #[schema(value_type = f64)]
+  pub min: bigdecimal::BigDecimal,
+  /// Exclusive, `None` for the highest bucket
+  #[schema(value_type = Option<f64>)]
+  pub max: Option<bigdecimal::BigDecimal>,
+  pub count: i64,
+}
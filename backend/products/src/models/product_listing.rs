@@ -0,0 +1,46 @@
+/*
+  Name: product_listing.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - Listings are only kept for online marketplaces, `marketplace_id` is the id of an `OnlineMarketplace`
+*/
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Queryable, Selectable, Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[diesel(table_name = crate::schema::product_listings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ProductListing {
+  #[schema(min_length = 8, max_length = 14)]
+  pub gtin: String,
+  pub marketplace_id: i32,
+  /// The product's page at the marketplace
+  pub url: String,
+  /// The retailer's own id of the item, such as a Walmart item id or a Target TCIN
+  pub retailer_item_id: Option<String>,
+  pub in_stock: bool,
+  /// When a scraper last looked at the page
+  pub last_checked_at: chrono::NaiveDateTime,
+  pub created_at: chrono::NaiveDateTime,
+  pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, Insertable, AsChangeset, ToSchema, Clone, Debug)]
+#[diesel(table_name = crate::schema::product_listings)]
+pub struct NewProductListing {
+  #[schema(min_length = 8, max_length = 14)]
+  pub gtin: String,
+  /// Id of an online marketplace
+  pub marketplace_id: i32,
+  pub url: String,
+  pub retailer_item_id: Option<String>,
+  pub in_stock: bool,
+  /// Defaults to now
+  pub last_checked_at: Option<chrono::NaiveDateTime>,
+}
@@ -24,10 +24,27 @@ pub struct ProductToImage {
   pub image_url: String,
   pub created_at: chrono::NaiveDateTime,
   pub updated_at: chrono::NaiveDateTime,
+  /// SHA-256 of an uploaded image, `None` for external urls
+  pub content_hash: Option<String>,
+  pub content_type: Option<String>,
+  pub thumbnail_url: Option<String>,
+  pub is_primary: bool,
 }
 
 pub type ProductToImageResponse = ProductToImage;
 
+/// An image uploaded to the blob store, see `handlers::products_to_images::upload_image`
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::products_to_images)]
+pub struct NewUploadedProductToImage<'a> {
+  pub gtin: &'a str,
+  pub image_url: &'a str,
+  pub content_hash: &'a str,
+  pub content_type: &'a str,
+  pub thumbnail_url: &'a str,
+  pub is_primary: bool,
+}
+
 #[derive(Deserialize, ToSchema, Clone, Insertable, Debug)]
 #[diesel(table_name = crate::schema::products_to_images)]
 pub struct NewProductToImage {
@@ -0,0 +1,56 @@
+/*
+  Name: receipt.rs
+
+  Description:
+  Drafts of price reports read from a receipt, returned by `/api/v1/receipts/draft` for the reporter to review before
+  they are committed through `/api/v1/receipts/commit`. Drafts aren't stored.
+*/
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct ProductMatch {
+  pub gtin: String,
+  pub productname: String,
+  /// 1 for a UPC match, otherwise the trigram word similarity of the line's description to the product name
+  pub confidence: f32,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct MarketplaceMatch {
+  pub id: i32,
+  pub name: String,
+  pub company: String,
+  /// 1 when the marketplace was given, otherwise the trigram word similarity of the store to its name
+  pub confidence: f32,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct ReceiptLineDraft {
+  /// Position of the line among the receipt's items
+  pub index: usize,
+  pub description: Option<String>,
+  pub upc: Option<String>,
+  /// Per unit, the line total divided by `quantity`
+  #[schema(value_type = f64)]
+  pub price: bigdecimal::BigDecimal,
+  pub quantity: i32,
+  /// The best match, also the first of `candidates`
+  pub product: Option<ProductMatch>,
+  pub candidates: Vec<ProductMatch>,
+  /// Whether the match is confident enough to commit without review
+  pub confirmed: bool,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct ReceiptDraft {
+  pub store: Option<String>,
+  pub marketplace: Option<MarketplaceMatch>,
+  /// The receipt's date at midnight, `None` when it couldn't be read
+  pub reported_at: Option<chrono::NaiveDateTime>,
+  pub currency: String,
+  pub lines: Vec<ReceiptLineDraft>,
+  /// Lines of a plain text receipt that weren't read as an item, such as totals and payments
+  pub skipped: Vec<String>,
+}
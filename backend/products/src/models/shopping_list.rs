@@ -13,10 +13,85 @@
 
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
-use diesel::prelude::*;
-use serde::Serialize;
+use diesel::{
+  deserialize::{self, FromSql, FromSqlRow},
+  expression::AsExpression,
+  pg::{Pg, PgValue},
+  prelude::*,
+  serialize::{self, IsNull, Output, ToSql},
+};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
 use utoipa::ToSchema;
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, FromSqlRow, AsExpression, Clone, Copy, ToSchema)]
+#[diesel(sql_type = diesel::sql_types::Text)]
+#[serde(rename_all = "lowercase")]
+pub enum ShoppingListRole {
+  Owner,
+  Editor,
+  Viewer,
+}
+
+impl ShoppingListRole {
+  pub fn can_edit(&self) -> bool {
+    matches!(self, ShoppingListRole::Owner | ShoppingListRole::Editor)
+  }
+}
+
+impl ToSql<diesel::sql_types::Text, Pg> for ShoppingListRole {
+  fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+    match *self {
+      ShoppingListRole::Owner => out.write_all(b"owner")?,
+      ShoppingListRole::Editor => out.write_all(b"editor")?,
+      ShoppingListRole::Viewer => out.write_all(b"viewer")?,
+    }
+    Ok(IsNull::No)
+  }
+}
+
+impl FromSql<diesel::sql_types::Text, Pg> for ShoppingListRole {
+  fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+    match bytes.as_bytes() {
+      b"owner" => Ok(ShoppingListRole::Owner),
+      b"editor" => Ok(ShoppingListRole::Editor),
+      b"viewer" => Ok(ShoppingListRole::Viewer),
+      _ => Err("Unrecognized enum variant".into()),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, FromSqlRow, AsExpression, Clone, Copy, ToSchema)]
+#[diesel(sql_type = diesel::sql_types::Text)]
+#[serde(rename_all = "lowercase")]
+pub enum InvitationStatus {
+  Pending,
+  Accepted,
+  Declined,
+}
+
+impl ToSql<diesel::sql_types::Text, Pg> for InvitationStatus {
+  fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+    match *self {
+      InvitationStatus::Pending => out.write_all(b"pending")?,
+      InvitationStatus::Accepted => out.write_all(b"accepted")?,
+      InvitationStatus::Declined => out.write_all(b"declined")?,
+    }
+    Ok(IsNull::No)
+  }
+}
+
+impl FromSql<diesel::sql_types::Text, Pg> for InvitationStatus {
+  fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+    match bytes.as_bytes() {
+      b"pending" => Ok(InvitationStatus::Pending),
+      b"accepted" => Ok(InvitationStatus::Accepted),
+      b"declined" => Ok(InvitationStatus::Declined),
+      _ => Err("Unrecognized enum variant".into()),
+    }
+  }
+}
+
 #[derive(Queryable, Identifiable, Selectable, AsChangeset, ToSchema, Serialize)]
 #[diesel(table_name = crate::schema::shopping_list)]
 pub struct ShoppingList {
@@ -29,15 +104,23 @@ pub struct ShoppingList {
 #[derive(Queryable, Identifiable, Selectable, AsChangeset, Associations, ToSchema, Serialize)]
 #[diesel(table_name = crate::schema::shopping_list_items)]
 #[diesel(belongs_to(ShoppingList, foreign_key = shopping_list_id))]
-#[diesel(primary_key(shopping_list_id, gtin))]
 pub struct ShoppingListItem {
   pub shopping_list_id: i32,
-  pub gtin: String,
+  /// Not set for free-form items that aren't in the catalog
+  pub gtin: Option<String>,
   #[schema(value_type = f64)]
   pub amount: BigDecimal,
   pub unit_id: Option<i32>,
   pub created_at: NaiveDateTime,
   pub updated_at: NaiveDateTime,
+  pub id: i32,
+  pub name: Option<String>,
+  pub note: Option<String>,
+  pub checked: bool,
+  pub checked_by: Option<i32>,
+  pub checked_at: Option<NaiveDateTime>,
+  pub aisle: Option<String>,
+  pub position: i32,
 }
 
 #[derive(Queryable, Identifiable, Selectable, Associations, ToSchema, Serialize)]
@@ -47,6 +130,7 @@ pub struct ShoppingListItem {
 pub struct ShoppingListToUser {
   pub shopping_list_id: i32,
   pub user_id: i32,
+  pub role: ShoppingListRole,
 }
 
 #[derive(Insertable)]
@@ -59,11 +143,31 @@ pub struct NewShoppingList {
 #[diesel(table_name = crate::schema::shopping_list_items)]
 pub struct NewShoppingListItem {
   pub shopping_list_id: i32,
-  pub gtin: String,
+  pub gtin: Option<String>,
   pub amount: BigDecimal,
   pub unit_id: Option<i32>,
   pub created_at: Option<NaiveDateTime>,
   pub updated_at: Option<NaiveDateTime>,
+  pub name: Option<String>,
+  pub note: Option<String>,
+  pub aisle: Option<String>,
+  pub position: Option<i32>,
+}
+
+/// Partial update of a shopping list item, `None` leaves the column untouched
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = crate::schema::shopping_list_items)]
+pub struct ShoppingListItemChangeset {
+  pub amount: Option<BigDecimal>,
+  pub unit_id: Option<Option<i32>>,
+  pub name: Option<Option<String>>,
+  pub note: Option<Option<String>>,
+  pub checked: Option<bool>,
+  pub checked_by: Option<Option<i32>>,
+  pub checked_at: Option<Option<NaiveDateTime>>,
+  pub aisle: Option<Option<String>>,
+  pub position: Option<i32>,
+  pub updated_at: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -71,4 +175,60 @@ pub struct NewShoppingListItem {
 pub struct NewShoppingListToUser {
   pub shopping_list_id: i32,
   pub user_id: i32,
+  pub role: ShoppingListRole,
+}
+
+#[derive(Queryable, Identifiable, Selectable, Associations, ToSchema, Serialize)]
+#[diesel(table_name = crate::schema::shopping_list_invitations)]
+#[diesel(belongs_to(ShoppingList, foreign_key = shopping_list_id))]
+pub struct ShoppingListInvitation {
+  pub id: i32,
+  pub shopping_list_id: i32,
+  pub invitee: String,
+  pub role: ShoppingListRole,
+  pub status: InvitationStatus,
+  pub invited_by: i32,
+  pub created_at: NaiveDateTime,
+  pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::shopping_list_invitations)]
+pub struct NewShoppingListInvitation {
+  pub shopping_list_id: i32,
+  pub invitee: String,
+  pub role: ShoppingListRole,
+  pub invited_by: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ShoppingListEventKind {
+  ItemAdded,
+  /// Includes checking and unchecking an item
+  ItemUpdated,
+  ItemRemoved,
+  /// Someone accepted an invitation and joined the list
+  MemberAdded,
+  /// A member's role changed
+  MemberUpdated,
+  MemberRemoved,
+  ListDeleted,
+}
+
+/// Pushed to the members of a shopping list through `/api/v1/shopping_lists/{id}/events`
+#[derive(Serialize, ToSchema)]
+pub struct ShoppingListEvent {
+  pub shopping_list_id: i32,
+  pub kind: ShoppingListEventKind,
+  /// The member who made the change
+  pub user_id: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub item_id: Option<i32>,
+  /// The item after the change, left out for removed items and items too large to send, which have to be refetched
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub item: Option<ShoppingListItem>,
+  /// The member who joined, whose role changed, or who left or was removed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub member_id: Option<i32>,
 }
@@ -0,0 +1,105 @@
+/*
+  Name: webhook.rs
+
+  Description:
+  Structural typing of database schema into Rust, leveraging Diesel proc-macros
+  and generated types to ensure schemas are always matching
+
+  Preconditions:
+  - `webhook_events` and `webhook_deliveries` are written by `webhooks::enqueue` and drained by `webhooks::Dispatcher`
+*/
+
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::webhook_subscriptions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookSubscription {
+  pub id: i32,
+  pub url: String,
+  /// Only ever sent back when the subscription is created
+  #[serde(skip_serializing)]
+  pub secret: String,
+  pub event_types: Vec<String>,
+  /// Inactive subscriptions don't receive new events, deliveries already queued are still sent
+  pub active: bool,
+  pub created_by: i32,
+  pub created_at: chrono::NaiveDateTime,
+  pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::webhook_subscriptions)]
+pub struct NewWebhookSubscription<'a> {
+  pub url: &'a str,
+  pub secret: &'a str,
+  pub event_types: &'a [String],
+  pub created_by: i32,
+}
+
+/// Partial update of a subscription, `None` leaves the column untouched
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = crate::schema::webhook_subscriptions)]
+pub struct WebhookSubscriptionChangeset {
+  pub url: Option<String>,
+  pub secret: Option<String>,
+  pub event_types: Option<Vec<String>>,
+  pub active: Option<bool>,
+  pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::webhook_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookEvent {
+  pub id: i64,
+  pub event_type: String,
+  pub payload: serde_json::Value,
+  pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::webhook_events)]
+pub struct NewWebhookEvent<'a> {
+  pub event_type: &'a str,
+  pub payload: serde_json::Value,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookDelivery {
+  pub id: i64,
+  pub event_id: i64,
+  pub subscription_id: i32,
+  pub attempts: i32,
+  pub next_attempt_at: chrono::NaiveDateTime,
+  pub last_attempt_at: Option<chrono::NaiveDateTime>,
+  /// HTTP status of the last attempt, `None` when the endpoint couldn't be reached
+  pub last_status: Option<i32>,
+  pub last_error: Option<String>,
+  pub delivered_at: Option<chrono::NaiveDateTime>,
+  /// Set once every attempt failed, the delivery stays in the dead-letter view until it is retried
+  pub failed_at: Option<chrono::NaiveDateTime>,
+  pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+pub struct NewWebhookDelivery {
+  pub event_id: i64,
+  pub subscription_id: i32,
+}
+
+#[derive(Queryable, Selectable, Serialize, ToSchema, Debug, Clone)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookDeliveryResponse {
+  #[serde(flatten)]
+  #[diesel(embed)]
+  pub delivery: WebhookDelivery,
+  #[diesel(embed)]
+  pub event: WebhookEvent,
+}
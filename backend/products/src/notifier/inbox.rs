@@ -0,0 +1,24 @@
+/*
+  Name: inbox.rs
+
+  Description:
+  The in-app inbox, the `notifications` table read through `/api/v1/notifications`. Unlike the other channels it's
+  written inside the transaction that raised the notifications, so a committed change never loses its notifications.
+*/
+
+use super::Delivery;
+use crate::schema::notifications;
+use diesel::prelude::*;
+
+/// Adds the deliveries' notifications to their recipients' inboxes
+pub fn db_add_to_inbox(conn: &mut PgConnection, deliveries: &[Delivery]) -> QueryResult<()> {
+  diesel::insert_into(notifications::table)
+    .values(
+      deliveries
+        .iter()
+        .map(|delivery| delivery.notification.clone())
+        .collect::<Vec<_>>(),
+    )
+    .execute(conn)?;
+  Ok(())
+}
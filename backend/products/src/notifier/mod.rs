@@ -0,0 +1,71 @@
+/*
+  Name: mod.rs
+
+  Description:
+  Delivery of notifications to users. Every notification lands in the in-app inbox with `db_add_to_inbox`, and is also
+  sent by email or to a webhook when the recipient asked for it. Email is only available when `SMTP_URL` is set, see
+  `EmailNotifier`.
+
+  Postconditions:
+  - Every file under the parent directory `./notifier` should be exported
+    glob style here
+*/
+
+mod email;
+pub use email::*;
+mod inbox;
+pub use inbox::*;
+mod webhook;
+pub use webhook::*;
+
+use crate::models::NewNotification;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A notification along with the channels it should reach besides the inbox
+#[derive(Debug, Clone)]
+pub struct Delivery {
+  pub notification: NewNotification,
+  pub email: Option<String>,
+  pub webhook_url: Option<String>,
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+  /// Delivers the notification over this channel, succeeds without doing anything if the delivery doesn't use it
+  async fn notify(&self, delivery: &Delivery) -> anyhow::Result<()>;
+}
+
+/// Every configured channel besides the inbox, a failing channel doesn't keep the others from delivering
+#[derive(Clone)]
+pub struct Notifiers(Vec<Arc<dyn Notifier>>);
+
+impl Notifiers {
+  pub fn new(notifiers: Vec<Arc<dyn Notifier>>) -> Self {
+    Notifiers(notifiers)
+  }
+
+  pub fn from_env() -> anyhow::Result<Self> {
+    let mut notifiers: Vec<Arc<dyn Notifier>> = vec![Arc::new(WebhookNotifier::new()?)];
+    match EmailNotifier::from_env()? {
+      Some(email) => notifiers.push(Arc::new(email)),
+      None => log::warn!("SMTP_URL is not set, notifications won't be sent by email"),
+    }
+    Ok(Notifiers(notifiers))
+  }
+
+  pub async fn notify(&self, deliveries: &[Delivery]) {
+    for delivery in deliveries {
+      for notifier in &self.0 {
+        if let Err(e) = notifier.notify(delivery).await {
+          log::error!(
+            "Failed to deliver `{}` notification to user {}: {}",
+            delivery.notification.kind,
+            delivery.notification.user_id,
+            e
+          );
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,43 @@
+/*
+  Name: webhook.rs
+
+  Description:
+  `Notifier` posting the notification as JSON to the url the recipient registered, through `webhooks::WebhookClient`
+  so the url can't reach the internal network
+*/
+
+use super::{Delivery, Notifier};
+use crate::webhooks::WebhookClient;
+use async_trait::async_trait;
+use std::time::Duration;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct WebhookNotifier {
+  client: WebhookClient,
+}
+
+impl WebhookNotifier {
+  pub fn new() -> anyhow::Result<Self> {
+    Ok(WebhookNotifier {
+      client: WebhookClient::new(WEBHOOK_TIMEOUT)?,
+    })
+  }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+  async fn notify(&self, delivery: &Delivery) -> anyhow::Result<()> {
+    let Some(webhook_url) = &delivery.webhook_url else {
+      return Ok(());
+    };
+    self
+      .client
+      .post(webhook_url)?
+      .json(&delivery.notification)
+      .send()
+      .await?
+      .error_for_status()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,51 @@
+/*
+  Name: listener.rs
+
+  Description:
+  The `LISTEN` side of `realtime`. Diesel can't wait on notifications, so a separate `tokio_postgres` connection is kept
+  open to `DATABASE_URL` for them.
+*/
+
+use super::{ShoppingListEvents, SHOPPING_LIST_CHANNEL};
+use futures_util::StreamExt;
+use postgres_native_tls::MakeTlsConnector;
+use std::time::Duration;
+use tokio_postgres::AsyncMessage;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Forwards notifications to the connected members until the server stops, reconnecting whenever the connection drops
+pub async fn listen(database_url: String, events: ShoppingListEvents) {
+  loop {
+    if let Err(e) = listen_once(&database_url, &events).await {
+      log::error!("Lost the shopping list event listener, reconnecting: {}", e);
+    }
+    events.reset();
+    actix_rt::time::sleep(RECONNECT_DELAY).await;
+  }
+}
+
+async fn listen_once(database_url: &str, events: &ShoppingListEvents) -> anyhow::Result<()> {
+  let tls = MakeTlsConnector::new(native_tls::TlsConnector::new()?);
+  let (client, mut connection) = tokio_postgres::connect(database_url, tls).await?;
+
+  // the connection only makes progress while it is polled, including for the `LISTEN` below
+  let events = events.clone();
+  let messages = actix_rt::spawn(async move {
+    let mut messages = futures_util::stream::poll_fn(move |cx| connection.poll_message(cx));
+    while let Some(message) = messages.next().await {
+      match message? {
+        AsyncMessage::Notification(notification) => events.publish(notification.payload()),
+        AsyncMessage::Notice(notice) => log::debug!("{}", notice),
+        _ => {}
+      }
+    }
+    Ok::<_, tokio_postgres::Error>(())
+  });
+
+  client
+    .batch_execute(&format!("LISTEN {}", SHOPPING_LIST_CHANNEL))
+    .await?;
+  messages.await??;
+  anyhow::bail!("connection closed")
+}
@@ -0,0 +1,182 @@
+/*
+  Name: mod.rs
+
+  Description:
+  Live updates of shared shopping lists. Handlers publish a `ShoppingListEvent` with `notify_shopping_list` inside
+  their transaction, Postgres hands it to every `products` instance once committed through `LISTEN/NOTIFY`, and each
+  instance forwards it to the members it has connected, see `listen`.
+
+  Postconditions:
+  - Every file under the parent directory `./realtime` should be exported
+    glob style here
+*/
+
+mod listener;
+pub use listener::*;
+
+use crate::models::{ShoppingListEvent, ShoppingListEventKind};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+pub const SHOPPING_LIST_CHANNEL: &str = "shopping_list_events";
+
+/// Postgres rejects payloads of 8000 bytes or more
+const MAX_PAYLOAD_BYTES: usize = 7900;
+/// Events buffered per connected member, a member falling further behind is disconnected to refetch the list
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Sends the event to the members of the list once the surrounding transaction commits
+pub fn notify_shopping_list(conn: &mut PgConnection, mut event: ShoppingListEvent) -> QueryResult<()> {
+  let serialize = |event: &ShoppingListEvent| {
+    serde_json::to_string(event).map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))
+  };
+  let mut payload = serialize(&event)?;
+  if payload.len() > MAX_PAYLOAD_BYTES {
+    event.item = None;
+    payload = serialize(&event)?;
+  }
+
+  diesel::sql_query("SELECT pg_notify($1, $2)")
+    .bind::<Text, _>(SHOPPING_LIST_CHANNEL)
+    .bind::<Text, _>(payload)
+    .execute(conn)?;
+  Ok(())
+}
+
+/// An event as received from Postgres, `payload` is the serialized `ShoppingListEvent`
+#[derive(Debug)]
+pub struct ReceivedEvent {
+  pub kind: ShoppingListEventKind,
+  pub member_id: Option<i32>,
+  pub payload: String,
+}
+
+#[derive(Deserialize)]
+struct EventHeader {
+  shopping_list_id: i32,
+  kind: ShoppingListEventKind,
+  member_id: Option<i32>,
+}
+
+/// The members connected to this instance, by shopping list
+#[derive(Clone, Default)]
+pub struct ShoppingListEvents {
+  channels: Arc<Mutex<HashMap<i32, broadcast::Sender<Arc<ReceivedEvent>>>>>,
+}
+
+impl ShoppingListEvents {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn subscribe(&self, shopping_list_id: i32) -> Subscription {
+    let receiver = self
+      .channels
+      .lock()
+      .unwrap()
+      .entry(shopping_list_id)
+      .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+      .subscribe();
+    Subscription {
+      events: self.clone(),
+      shopping_list_id,
+      receiver: Some(receiver),
+    }
+  }
+
+  fn publish(&self, payload: &str) {
+    let header = match serde_json::from_str::<EventHeader>(payload) {
+      Ok(header) => header,
+      Err(e) => {
+        log::error!("Malformed shopping list event `{}`: {}", payload, e);
+        return;
+      }
+    };
+
+    let mut channels = self.channels.lock().unwrap();
+    if let Some(sender) = channels.get(&header.shopping_list_id) {
+      let event = ReceivedEvent {
+        kind: header.kind,
+        member_id: header.member_id,
+        payload: payload.to_string(),
+      };
+      // nobody is listening anymore
+      if sender.send(Arc::new(event)).is_err() {
+        channels.remove(&header.shopping_list_id);
+      }
+    }
+  }
+
+  /// Drops the channels of members that disconnected, every remaining member missed events while `listen` was
+  /// reconnecting and is disconnected to refetch the list
+  fn reset(&self) {
+    self.channels.lock().unwrap().clear();
+  }
+}
+
+/// A connected member's feed of one shopping list, the list's channel is dropped along with its last subscription
+pub struct Subscription {
+  events: ShoppingListEvents,
+  shopping_list_id: i32,
+  /// Only taken when dropped
+  receiver: Option<broadcast::Receiver<Arc<ReceivedEvent>>>,
+}
+
+impl Subscription {
+  pub async fn recv(&mut self) -> Result<Arc<ReceivedEvent>, broadcast::error::RecvError> {
+    self.receiver.as_mut().expect("receiver is taken on drop").recv().await
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    let mut channels = self.events.channels.lock().unwrap();
+    // a receiver left over from before `reset` belongs to a replaced channel and doesn't count towards this one
+    drop(self.receiver.take());
+    if channels
+      .get(&self.shopping_list_id)
+      .is_some_and(|sender| sender.receiver_count() == 0)
+    {
+      channels.remove(&self.shopping_list_id);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_last_subscription_drops_channel() {
+    let events = ShoppingListEvents::new();
+    let first = events.subscribe(1);
+    let second = events.subscribe(1);
+    let other = events.subscribe(2);
+
+    drop(first);
+    assert!(events.channels.lock().unwrap().contains_key(&1));
+    drop(second);
+    assert!(!events.channels.lock().unwrap().contains_key(&1));
+    assert!(events.channels.lock().unwrap().contains_key(&2));
+    drop(other);
+    assert!(events.channels.lock().unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_subscription_after_reset() {
+    let events = ShoppingListEvents::new();
+    let stale = events.subscribe(1);
+    events.reset();
+    let current = events.subscribe(1);
+
+    // the stale subscription must not take the new channel down with it
+    drop(stale);
+    assert!(events.channels.lock().unwrap().contains_key(&1));
+    drop(current);
+    assert!(events.channels.lock().unwrap().is_empty());
+  }
+}
@@ -6,6 +6,49 @@ pub mod sql_types {
     pub struct Tsvector;
 }
 
+diesel::table! {
+    audit_log (id) {
+        id -> Int8,
+        created_at -> Timestamp,
+        actor_id -> Int4,
+        action -> Text,
+        resource -> Text,
+        resource_id -> Nullable<Text>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    brand_gs1_prefixes (prefix) {
+        prefix -> Text,
+        brand_id -> Int4,
+    }
+}
+
+diesel::table! {
+    brands (id) {
+        id -> Int4,
+        name -> Text,
+        manufacturer -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    categories (id) {
+        id -> Int4,
+        parent_id -> Nullable<Int4>,
+        name -> Text,
+        path -> Array<Int4>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     companies (id) {
         id -> Int4,
@@ -13,6 +56,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    current_prices (gtin, marketplace_id) {
+        gtin -> Text,
+        marketplace_id -> Int4,
+        price_report_id -> Int8,
+        reported_at -> Timestamptz,
+        price -> Numeric,
+        #[max_length = 3]
+        currency -> Bpchar,
+        promotion_type -> Nullable<Text>,
+        regular_price -> Nullable<Numeric>,
+        promotion_quantity -> Nullable<Int4>,
+        loyalty_required -> Bool,
+        valid_from -> Nullable<Timestamptz>,
+        valid_until -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    exchange_rates (currency, date) {
+        #[max_length = 3]
+        currency -> Bpchar,
+        date -> Date,
+        rate -> Numeric,
+    }
+}
+
 diesel::table! {
     iso_4217 (code) {
         #[max_length = 3]
@@ -36,6 +106,19 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    notifications (id) {
+        id -> Int8,
+        user_id -> Int4,
+        kind -> Text,
+        title -> Text,
+        body -> Text,
+        data -> Jsonb,
+        read_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     online_marketplaces (id) {
         id -> Int4,
@@ -71,6 +154,46 @@ diesel::table! {
         price -> Numeric,
         #[max_length = 3]
         currency -> Bpchar,
+        promotion_type -> Nullable<Text>,
+        regular_price -> Nullable<Numeric>,
+        promotion_quantity -> Nullable<Int4>,
+        loyalty_required -> Bool,
+        valid_from -> Nullable<Timestamptz>,
+        valid_until -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    price_watches (id) {
+        id -> Int4,
+        user_id -> Int4,
+        gtin -> Text,
+        marketplace_id -> Nullable<Int4>,
+        company_id -> Nullable<Int4>,
+        #[max_length = 3]
+        currency -> Bpchar,
+        target_price -> Nullable<Numeric>,
+        percent_drop -> Nullable<Numeric>,
+        reference_price -> Nullable<Numeric>,
+        last_notified_price -> Nullable<Numeric>,
+        last_notified_at -> Nullable<Timestamp>,
+        email -> Nullable<Text>,
+        webhook_url -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    product_listings (gtin, marketplace_id) {
+        gtin -> Text,
+        marketplace_id -> Int4,
+        url -> Text,
+        retailer_item_id -> Nullable<Text>,
+        in_stock -> Bool,
+        last_checked_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -88,6 +211,15 @@ diesel::table! {
         sellsinraw -> Bool,
         description -> Nullable<Text>,
         search_vector -> Nullable<Tsvector>,
+        brand_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    products_to_categories (gtin, category_id) {
+        gtin -> Text,
+        category_id -> Int4,
+        created_at -> Timestamp,
     }
 }
 
@@ -99,6 +231,10 @@ diesel::table! {
         image_url -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        content_hash -> Nullable<Text>,
+        content_type -> Nullable<Text>,
+        thumbnail_url -> Nullable<Text>,
+        is_primary -> Bool,
     }
 }
 
@@ -125,14 +261,35 @@ diesel::table! {
 }
 
 diesel::table! {
-    shopping_list_items (shopping_list_id, gtin) {
+    shopping_list_invitations (id) {
+        id -> Int4,
+        shopping_list_id -> Int4,
+        invitee -> Text,
+        role -> Text,
+        status -> Text,
+        invited_by -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    shopping_list_items (id) {
         shopping_list_id -> Int4,
         #[max_length = 255]
-        gtin -> Varchar,
+        gtin -> Nullable<Varchar>,
         amount -> Numeric,
         unit_id -> Nullable<Int4>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        id -> Int4,
+        name -> Nullable<Text>,
+        note -> Nullable<Text>,
+        checked -> Bool,
+        checked_by -> Nullable<Int4>,
+        checked_at -> Nullable<Timestamp>,
+        aisle -> Nullable<Text>,
+        position -> Int4,
     }
 }
 
@@ -140,6 +297,7 @@ diesel::table! {
     shopping_list_to_user (shopping_list_id, user_id) {
         shopping_list_id -> Int4,
         user_id -> Int4,
+        role -> Text,
     }
 }
 
@@ -150,33 +308,102 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        event_id -> Int8,
+        subscription_id -> Int4,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        last_attempt_at -> Nullable<Timestamp>,
+        last_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+        failed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_events (id) {
+        id -> Int8,
+        event_type -> Text,
+        payload -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_subscriptions (id) {
+        id -> Int4,
+        url -> Text,
+        secret -> Text,
+        event_types -> Array<Text>,
+        active -> Bool,
+        created_by -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(brand_gs1_prefixes -> brands (brand_id));
+diesel::joinable!(current_prices -> iso_4217 (currency));
+diesel::joinable!(current_prices -> marketplaces (marketplace_id));
+diesel::joinable!(current_prices -> products (gtin));
+diesel::joinable!(exchange_rates -> iso_4217 (currency));
 diesel::joinable!(marketplaces -> companies (company_id));
 diesel::joinable!(online_marketplaces -> marketplaces (id));
 diesel::joinable!(physical_marketplaces -> marketplaces (id));
 diesel::joinable!(price_report_to_marketplaces -> marketplaces (marketplace_id));
 diesel::joinable!(price_reports -> iso_4217 (currency));
 diesel::joinable!(price_reports -> products (gtin));
+diesel::joinable!(price_watches -> companies (company_id));
+diesel::joinable!(price_watches -> iso_4217 (currency));
+diesel::joinable!(price_watches -> marketplaces (marketplace_id));
+diesel::joinable!(price_watches -> products (gtin));
+diesel::joinable!(product_listings -> online_marketplaces (marketplace_id));
+diesel::joinable!(product_listings -> products (gtin));
+diesel::joinable!(products -> brands (brand_id));
+diesel::joinable!(products_to_categories -> categories (category_id));
+diesel::joinable!(products_to_categories -> products (gtin));
 diesel::joinable!(products_to_images -> products (gtin));
 diesel::joinable!(products_to_measures -> products (gtin));
 diesel::joinable!(products_to_measures -> units (unit_id));
+diesel::joinable!(shopping_list_invitations -> shopping_list (shopping_list_id));
 diesel::joinable!(shopping_list_items -> products (gtin));
 diesel::joinable!(shopping_list_items -> shopping_list (shopping_list_id));
 diesel::joinable!(shopping_list_items -> units (unit_id));
 diesel::joinable!(shopping_list_to_user -> shopping_list (shopping_list_id));
+diesel::joinable!(webhook_deliveries -> webhook_events (event_id));
+diesel::joinable!(webhook_deliveries -> webhook_subscriptions (subscription_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    brand_gs1_prefixes,
+    brands,
+    categories,
     companies,
+    current_prices,
+    exchange_rates,
     iso_4217,
     marketplaces,
+    notifications,
     online_marketplaces,
     physical_marketplaces,
     price_report_to_marketplaces,
     price_reports,
+    price_watches,
+    product_listings,
     products,
+    products_to_categories,
     products_to_images,
     products_to_measures,
     shopping_list,
+    shopping_list_invitations,
     shopping_list_items,
     shopping_list_to_user,
     units,
+    webhook_deliveries,
+    webhook_events,
+    webhook_subscriptions,
 );
@@ -28,6 +28,24 @@ use diesel::prelude::*;
 use diesel::ExpressionMethods;
 use std::str::FromStr;
 
+/// Id of the category at `path` from the top level down, e.g. `["Dairy", "Milk"]`
+fn category_id(conn: &mut PgConnection, path: &[&str]) -> i32 {
+  path
+    .iter()
+    .fold(None, |parent_id, name| {
+      let query = categories::table
+        .filter(categories::name.eq(name))
+        .select(categories::id)
+        .into_boxed();
+      let query = match parent_id {
+        Some(parent_id) => query.filter(categories::parent_id.eq(parent_id)),
+        None => query.filter(categories::parent_id.is_null()),
+      };
+      Some(query.first::<i32>(conn).unwrap())
+    })
+    .unwrap()
+}
+
 pub(super) fn run(pool: crate::Pool) {
   let mut conn = pool.get().unwrap();
 
@@ -39,6 +57,35 @@ pub(super) fn run(pool: crate::Pool) {
   if seed {
     log::debug!("Seeding development environment data");
 
+    // products pick up their brand from the GS1 prefix of their GTIN when inserted
+    let brands = [
+      ("Ferrero Rocher", "Ferrero", "0009800"),
+      ("Lunchables", "Kraft Heinz", "0044700"),
+      ("Tropicana", "Tropicana Brands Group", "0048500"),
+      ("Great Value", "Walmart", "0078742"),
+    ];
+    for (name, manufacturer, prefix) in brands {
+      let _ = diesel::insert_into(brands::table)
+        .values(NewBrand {
+          name,
+          manufacturer: Some(manufacturer),
+        })
+        .on_conflict_do_nothing()
+        .execute(&mut conn);
+      let brand_id = brands::table
+        .filter(brands::name.eq(name))
+        .select(brands::id)
+        .first::<i32>(&mut conn)
+        .unwrap();
+      let _ = diesel::insert_into(brand_gs1_prefixes::table)
+        .values(BrandGs1Prefix {
+          prefix: prefix.to_string(),
+          brand_id,
+        })
+        .on_conflict_do_nothing()
+        .execute(&mut conn);
+    }
+
     let products: Vec<NewProductPost> = vec![
       NewProductPost {
         new_product: NewProduct {
@@ -62,6 +109,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
 "
             .to_string(),
           ),
+          brand_id: None,
         },
         measures: vec![
           NewProductToMeasurePartial {
@@ -86,6 +134,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
           }
           .into(),
         ),
+        categories: Some(vec![category_id(&mut conn, &["Snacks", "Candy & Chocolate"])]),
       },
       NewProductPost {
         new_product: NewProduct {
@@ -93,6 +142,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
           sku: Some("13908431".to_string()),
           productname: "Lunchables Extra Cheese Pizza Kids Lunch Meal Kit, 10.6 oz Box".to_string(),
           description: None,
+          brand_id: None,
         },
         measures: NewProductToMeasurePartial {
           unit: UnitSymbol::Ounce,
@@ -113,6 +163,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
           ]
           .into(),
         ),
+        categories: Some(vec![category_id(&mut conn, &["Deli", "Lunch Kits"])]),
       },
       NewProductPost {
         new_product: NewProduct {
@@ -121,6 +172,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
           productname: "Tropicana Pure Premium 100% Orange Juice Original, No Pulp, No Sugar Added, 46 fl oz"
             .to_string(),
           description: None,
+          brand_id: None,
         },
         measures: NewProductToMeasurePartial {
           unit: UnitSymbol::FluidOunce,
@@ -136,6 +188,10 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
           }
           .into(),
         ),
+        categories: Some(vec![
+          category_id(&mut conn, &["Beverages", "Juice"]),
+          category_id(&mut conn, &["Breakfast"]),
+        ]),
       },
       NewProductPost {
         new_product: NewProduct {
@@ -143,6 +199,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
           sku: Some("34788345".to_string()),
           productname: "Great Value Light Greek Yogurt, Blueberry Nonfat Yogurt, 5.3 oz, 4 Count".to_string(),
           description: None,
+          brand_id: None,
         },
         measures: vec![
           NewProductToMeasurePartial {
@@ -167,6 +224,10 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
           }
           .into(),
         ),
+        categories: Some(vec![
+          category_id(&mut conn, &["Dairy", "Yogurt"]),
+          category_id(&mut conn, &["Breakfast"]),
+        ]),
       },
     ];
 
@@ -301,6 +362,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
         gtin: "009800124015".to_string(),
         price: bigdecimal::BigDecimal::from_str("12.39").unwrap(),
         currency: "USD".to_string(),
+        ..Default::default()
       },
       NewPriceReport {
         id: Some(2),
@@ -309,6 +371,7 @@ chocolate, a crisp wafer shell, and a whole hazelnut</li>
         gtin: "044700361146".to_string(),
         price: bigdecimal::BigDecimal::from_str("3.12").unwrap(),
         currency: "USD".to_string(),
+        ..Default::default()
       },
     ];
 
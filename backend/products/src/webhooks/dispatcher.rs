@@ -0,0 +1,416 @@
+/*
+  Name: dispatcher.rs
+
+  Description:
+  Background task draining `webhook_deliveries`. Failed deliveries are retried with exponential backoff and dead-lettered
+  after `MAX_ATTEMPTS`, they can be retried by hand through `/api/v1/webhooks/deliveries/{id}/retry`.
+*/
+
+use super::{sign, WebhookClient};
+use crate::models::{WebhookDelivery, WebhookEvent, WebhookSubscription};
+use crate::schema::*;
+use crate::Pool;
+use diesel::dsl::{now, IntervalDsl};
+use diesel::prelude::*;
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 50;
+/// How long claimed deliveries are hidden from other dispatchers, after that an interrupted delivery is picked up again
+const CLAIM_TIMEOUT_SECS: i64 = 60;
+pub const MAX_ATTEMPTS: i32 = 8;
+const BASE_BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 60 * 60;
+
+/// The body of every delivery, `id` is the same across redeliveries of an event so receivers can deduplicate
+#[derive(Serialize)]
+struct Envelope<'a> {
+  id: i64,
+  #[serde(rename = "type")]
+  event_type: &'a str,
+  created_at: chrono::NaiveDateTime,
+  data: &'a serde_json::Value,
+}
+
+struct Attempt {
+  status: Option<i32>,
+  /// `None` when the delivery succeeded
+  error: Option<String>,
+}
+
+/// Seconds to wait before the next attempt, 30s doubling up to an hour
+fn backoff(attempts: i32) -> i64 {
+  (BASE_BACKOFF_SECS << (attempts - 1).clamp(0, 16)).min(MAX_BACKOFF_SECS)
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+  Delivered,
+  /// Seconds until the next attempt
+  Retry(i64),
+  DeadLettered,
+}
+
+/// Where a delivery goes after its `attempts`th attempt
+fn outcome(attempts: i32, attempt: &Attempt) -> Outcome {
+  match (&attempt.error, attempts >= MAX_ATTEMPTS) {
+    (None, _) => Outcome::Delivered,
+    (Some(_), true) => Outcome::DeadLettered,
+    (Some(_), false) => Outcome::Retry(backoff(attempts)),
+  }
+}
+
+fn db_claim_due(conn: &mut PgConnection) -> QueryResult<Vec<(WebhookDelivery, WebhookEvent, WebhookSubscription)>> {
+  conn.transaction(|conn| {
+    let ids = webhook_deliveries::table
+      .filter(webhook_deliveries::delivered_at.is_null())
+      .filter(webhook_deliveries::failed_at.is_null())
+      .filter(webhook_deliveries::next_attempt_at.le(now))
+      .order(webhook_deliveries::next_attempt_at.asc())
+      .limit(BATCH_SIZE)
+      .select(webhook_deliveries::id)
+      .for_update()
+      .skip_locked()
+      .load::<i64>(conn)?;
+    diesel::update(webhook_deliveries::table.filter(webhook_deliveries::id.eq_any(&ids)))
+      .set(webhook_deliveries::next_attempt_at.eq(now + CLAIM_TIMEOUT_SECS.seconds()))
+      .execute(conn)?;
+
+    webhook_deliveries::table
+      .inner_join(webhook_events::table)
+      .inner_join(webhook_subscriptions::table)
+      .filter(webhook_deliveries::id.eq_any(&ids))
+      .select((
+        WebhookDelivery::as_select(),
+        WebhookEvent::as_select(),
+        WebhookSubscription::as_select(),
+      ))
+      .load(conn)
+  })
+}
+
+fn db_record_attempt(conn: &mut PgConnection, delivery: &WebhookDelivery, attempt: Attempt) -> QueryResult<()> {
+  let attempts = delivery.attempts + 1;
+  let target = webhook_deliveries::table.find(delivery.id);
+  let result = (
+    webhook_deliveries::attempts.eq(attempts),
+    webhook_deliveries::last_attempt_at.eq(now.nullable()),
+    webhook_deliveries::last_status.eq(attempt.status),
+    webhook_deliveries::last_error.eq(&attempt.error),
+  );
+
+  match outcome(attempts, &attempt) {
+    Outcome::Delivered => diesel::update(target)
+      .set((result, webhook_deliveries::delivered_at.eq(now.nullable())))
+      .execute(conn)?,
+    Outcome::DeadLettered => {
+      log::warn!(
+        "Webhook delivery {} to subscription {} failed {} times, giving up",
+        delivery.id,
+        delivery.subscription_id,
+        attempts
+      );
+      diesel::update(target)
+        .set((result, webhook_deliveries::failed_at.eq(now.nullable())))
+        .execute(conn)?
+    }
+    Outcome::Retry(secs) => diesel::update(target)
+      .set((result, webhook_deliveries::next_attempt_at.eq(now + secs.seconds())))
+      .execute(conn)?,
+  };
+  Ok(())
+}
+
+pub struct Dispatcher {
+  pool: Pool,
+  client: WebhookClient,
+}
+
+impl Dispatcher {
+  pub fn new(pool: Pool) -> anyhow::Result<Self> {
+    Ok(Dispatcher {
+      pool,
+      client: WebhookClient::new(WEBHOOK_TIMEOUT)?,
+    })
+  }
+
+  /// Polls for due deliveries until the server stops
+  pub async fn run(self) {
+    let mut interval = actix_rt::time::interval(POLL_INTERVAL);
+    loop {
+      interval.tick().await;
+      if let Err(e) = self.dispatch_due().await {
+        log::error!("Failed to dispatch webhooks: {}", e);
+      }
+    }
+  }
+
+  async fn dispatch_due(&self) -> anyhow::Result<()> {
+    loop {
+      let pool = self.pool.clone();
+      let due = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        Ok::<_, anyhow::Error>(db_claim_due(&mut conn)?)
+      })
+      .await??;
+      let claimed = due.len() as i64;
+
+      let attempts = futures_util::future::join_all(
+        due
+          .iter()
+          .map(|(delivery, event, subscription)| self.send(delivery, event, subscription)),
+      )
+      .await;
+
+      let pool = self.pool.clone();
+      actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        for ((delivery, _, _), attempt) in due.iter().zip(attempts) {
+          db_record_attempt(&mut conn, delivery, attempt)?;
+        }
+        Ok::<_, anyhow::Error>(())
+      })
+      .await??;
+
+      if claimed < BATCH_SIZE {
+        return Ok(());
+      }
+    }
+  }
+
+  async fn send(
+    &self,
+    delivery: &WebhookDelivery,
+    event: &WebhookEvent,
+    subscription: &WebhookSubscription,
+  ) -> Attempt {
+    let body = match serde_json::to_vec(&Envelope {
+      id: event.id,
+      event_type: &event.event_type,
+      created_at: event.created_at,
+      data: &event.payload,
+    }) {
+      Ok(body) => body,
+      Err(e) => {
+        return Attempt {
+          status: None,
+          error: Some(e.to_string()),
+        }
+      }
+    };
+    let timestamp = chrono::Utc::now().timestamp();
+    let signature = sign(&subscription.secret, timestamp, &body);
+
+    let request = match self.client.post(&subscription.url) {
+      Ok(request) => request,
+      Err(e) => {
+        return Attempt {
+          status: None,
+          error: Some(format!("`url` {}", e)),
+        }
+      }
+    };
+    let response = request
+      .header(CONTENT_TYPE, "application/json")
+      .header("X-GroceryWise-Event", &event.event_type)
+      .header("X-GroceryWise-Delivery", delivery.id.to_string())
+      .header("X-GroceryWise-Timestamp", timestamp.to_string())
+      .header("X-GroceryWise-Signature", format!("sha256={}", signature))
+      .body(body)
+      .send()
+      .await;
+
+    match response {
+      Ok(response) if response.status().is_success() => Attempt {
+        status: Some(response.status().as_u16().into()),
+        error: None,
+      },
+      Ok(response) => Attempt {
+        status: Some(response.status().as_u16().into()),
+        error: Some(format!("Endpoint responded with {}", response.status())),
+      },
+      Err(e) => Attempt {
+        status: None,
+        error: Some(e.to_string()),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::models::NewWebhookSubscription;
+  use crate::webhooks::{enqueue, PRODUCT_UPSERTED};
+
+  fn failed() -> Attempt {
+    Attempt {
+      status: Some(500),
+      error: Some("Endpoint responded with 500".to_string()),
+    }
+  }
+
+  #[test]
+  fn test_sign() {
+    assert_eq!(
+      sign("whsec_0123456789abcdef", 1700000000, br#"{"id":1}"#),
+      "22f267bc13c9c3f35f76035954c196f8ad4cf971af76120dcbcbbb84458514d0"
+    );
+  }
+
+  #[test]
+  fn test_backoff() {
+    assert_eq!(backoff(1), 30);
+    assert_eq!(backoff(2), 60);
+    assert_eq!(backoff(3), 120);
+    assert_eq!(backoff(7), 1920);
+    assert_eq!(backoff(8), MAX_BACKOFF_SECS);
+    assert_eq!(backoff(100), MAX_BACKOFF_SECS);
+    assert_eq!(backoff(0), 30);
+  }
+
+  #[test]
+  fn test_outcome() {
+    let delivered = Attempt {
+      status: Some(204),
+      error: None,
+    };
+    assert_eq!(outcome(1, &delivered), Outcome::Delivered);
+    assert_eq!(outcome(MAX_ATTEMPTS, &delivered), Outcome::Delivered);
+    assert_eq!(outcome(1, &failed()), Outcome::Retry(30));
+    assert_eq!(
+      outcome(MAX_ATTEMPTS - 1, &failed()),
+      Outcome::Retry(backoff(MAX_ATTEMPTS - 1))
+    );
+    assert_eq!(outcome(MAX_ATTEMPTS, &failed()), Outcome::DeadLettered);
+  }
+
+  /// A migrated database from `DATABASE_URL`, everything a test writes is rolled back
+  fn test_connection() -> PgConnection {
+    let mut conn = PgConnection::establish(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be set")).unwrap();
+    conn.begin_test_transaction().unwrap();
+    conn
+  }
+
+  fn enqueue_delivery(conn: &mut PgConnection) -> i64 {
+    let subscription_id = diesel::insert_into(webhook_subscriptions::table)
+      .values(NewWebhookSubscription {
+        url: "https://example.com/hook",
+        secret: "whsec_0123456789abcdef",
+        event_types: &[PRODUCT_UPSERTED.to_string()],
+        created_by: 0,
+      })
+      .returning(webhook_subscriptions::id)
+      .get_result::<i32>(conn)
+      .unwrap();
+    enqueue(conn, PRODUCT_UPSERTED, [serde_json::json!({})]).unwrap();
+    webhook_deliveries::table
+      .filter(webhook_deliveries::subscription_id.eq(subscription_id))
+      .select(webhook_deliveries::id)
+      .first::<i64>(conn)
+      .unwrap()
+  }
+
+  fn claim(conn: &mut PgConnection, id: i64) -> Option<WebhookDelivery> {
+    db_claim_due(conn)
+      .unwrap()
+      .into_iter()
+      .map(|(delivery, _, _)| delivery)
+      .find(|delivery| delivery.id == id)
+  }
+
+  fn reload(conn: &mut PgConnection, id: i64) -> WebhookDelivery {
+    webhook_deliveries::table
+      .find(id)
+      .select(WebhookDelivery::as_select())
+      .first(conn)
+      .unwrap()
+  }
+
+  #[test]
+  #[ignore = "needs a migrated database at DATABASE_URL"]
+  fn test_claim_hides_delivery_until_timeout() {
+    let mut conn = test_connection();
+    let id = enqueue_delivery(&mut conn);
+
+    let claimed = claim(&mut conn, id).expect("a new delivery is due");
+    assert_eq!(claimed.attempts, 0);
+    assert!(
+      claim(&mut conn, id).is_none(),
+      "a claimed delivery isn't handed out twice"
+    );
+
+    let delivery = reload(&mut conn, id);
+    assert_eq!(
+      (delivery.next_attempt_at - claimed.created_at).num_seconds(),
+      CLAIM_TIMEOUT_SECS
+    );
+  }
+
+  #[test]
+  #[ignore = "needs a migrated database at DATABASE_URL"]
+  fn test_retry_then_deliver() {
+    let mut conn = test_connection();
+    let id = enqueue_delivery(&mut conn);
+
+    let claimed = claim(&mut conn, id).unwrap();
+    db_record_attempt(&mut conn, &claimed, failed()).unwrap();
+    let delivery = reload(&mut conn, id);
+    assert_eq!(delivery.attempts, 1);
+    assert_eq!(delivery.last_status, Some(500));
+    assert_eq!(
+      (delivery.next_attempt_at - claimed.created_at).num_seconds(),
+      backoff(1)
+    );
+    assert!(delivery.failed_at.is_none() && delivery.delivered_at.is_none());
+
+    db_record_attempt(
+      &mut conn,
+      &delivery,
+      Attempt {
+        status: Some(200),
+        error: None,
+      },
+    )
+    .unwrap();
+    let delivery = reload(&mut conn, id);
+    assert_eq!(delivery.attempts, 2);
+    assert!(delivery.delivered_at.is_some());
+    diesel::update(webhook_deliveries::table.find(id))
+      .set(webhook_deliveries::next_attempt_at.eq(now - 1.seconds()))
+      .execute(&mut conn)
+      .unwrap();
+    assert!(
+      claim(&mut conn, id).is_none(),
+      "a delivered delivery is never claimed again"
+    );
+  }
+
+  #[test]
+  #[ignore = "needs a migrated database at DATABASE_URL"]
+  fn test_dead_letter_after_max_attempts() {
+    let mut conn = test_connection();
+    let id = enqueue_delivery(&mut conn);
+    diesel::update(webhook_deliveries::table.find(id))
+      .set(webhook_deliveries::attempts.eq(MAX_ATTEMPTS - 1))
+      .execute(&mut conn)
+      .unwrap();
+
+    let claimed = claim(&mut conn, id).unwrap();
+    db_record_attempt(&mut conn, &claimed, failed()).unwrap();
+    let delivery = reload(&mut conn, id);
+    assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+    assert!(delivery.failed_at.is_some());
+    diesel::update(webhook_deliveries::table.find(id))
+      .set(webhook_deliveries::next_attempt_at.eq(now - 1.seconds()))
+      .execute(&mut conn)
+      .unwrap();
+    assert!(
+      claim(&mut conn, id).is_none(),
+      "a dead-lettered delivery waits for a manual retry"
+    );
+  }
+}
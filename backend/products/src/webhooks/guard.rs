@@ -0,0 +1,197 @@
+/*
+  Name: guard.rs
+
+  Description:
+  Keeps outbound webhooks off the internal network. Webhook urls come from users, unchecked they could point the server
+  at loopback, private or link-local addresses, such as the cloud metadata endpoint. Urls are checked when they're saved,
+  and every request goes through `client`, whose resolver drops those addresses again in case DNS changed since.
+*/
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq, derive_more::Display)]
+pub enum UrlError {
+  #[display("must be an http(s) url")]
+  NotHttp,
+  #[display("must resolve to a public address")]
+  NotPublic,
+}
+
+impl std::error::Error for UrlError {}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+  let [a, b, c, _] = ip.octets();
+  !(ip.is_unspecified()
+    || ip.is_loopback()
+    || ip.is_private()
+    || ip.is_link_local()
+    || ip.is_broadcast()
+    || ip.is_documentation()
+    || ip.is_multicast()
+    || a == 0
+    // shared address space (100.64.0.0/10), used by carrier-grade NAT and some cloud metadata endpoints
+    || (a == 100 && (64..128).contains(&b))
+    // IETF protocol assignments (192.0.0.0/24)
+    || (a == 192 && b == 0 && c == 0)
+    // benchmarking (198.18.0.0/15)
+    || (a == 198 && (18..20).contains(&b))
+    // reserved (240.0.0.0/4)
+    || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+  if let Some(ip) = ip.to_ipv4_mapped() {
+    return is_public_v4(ip);
+  }
+  let segments = ip.segments();
+  !(ip.is_unspecified()
+    || ip.is_loopback()
+    || ip.is_multicast()
+    // unique local (fc00::/7)
+    || (segments[0] & 0xfe00) == 0xfc00
+    // link-local (fe80::/10) and the deprecated site-local (fec0::/10)
+    || (segments[0] & 0xffc0) == 0xfe80
+    || (segments[0] & 0xffc0) == 0xfec0
+    // documentation (2001:db8::/32)
+    || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+    // NAT64 (64:ff9b::/96) reaches whatever IPv4 address it embeds
+    || (segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] && !is_public_v4(Ipv4Addr::from(ip.to_bits() as u32))))
+}
+
+/// Whether the address is on the public internet
+pub fn is_public(ip: IpAddr) -> bool {
+  match ip {
+    IpAddr::V4(ip) => is_public_v4(ip),
+    IpAddr::V6(ip) => is_public_v6(ip),
+  }
+}
+
+/// Checks the scheme and a literal ip host, hostnames are left to the resolver
+fn check_literal(url: &reqwest::Url) -> Result<(), UrlError> {
+  if !matches!(url.scheme(), "http" | "https") {
+    return Err(UrlError::NotHttp);
+  }
+  match url.host() {
+    Some(url::Host::Ipv4(ip)) if !is_public_v4(ip) => Err(UrlError::NotPublic),
+    Some(url::Host::Ipv6(ip)) if !is_public_v6(ip) => Err(UrlError::NotPublic),
+    Some(_) => Ok(()),
+    None => Err(UrlError::NotHttp),
+  }
+}
+
+/// Checks a url before it's saved, resolving its host so users find out right away rather than on the first delivery
+pub async fn validate_url(url: &str) -> Result<reqwest::Url, UrlError> {
+  let url = reqwest::Url::parse(url).map_err(|_| UrlError::NotHttp)?;
+  check_literal(&url)?;
+  if let Some(url::Host::Domain(domain)) = url.host() {
+    let addrs = tokio::net::lookup_host((domain, url.port_or_known_default().unwrap_or(443)))
+      .await
+      .map_err(|_| UrlError::NotPublic)?
+      .collect::<Vec<_>>();
+    if addrs.is_empty() || addrs.iter().any(|addr| !is_public(addr.ip())) {
+      return Err(UrlError::NotPublic);
+    }
+  }
+  Ok(url)
+}
+
+/// Resolves hostnames to their public addresses only
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+  fn resolve(&self, name: Name) -> Resolving {
+    Box::pin(async move {
+      let addrs = tokio::net::lookup_host((name.as_str(), 0))
+        .await?
+        .filter(|addr| is_public(addr.ip()))
+        .collect::<Vec<SocketAddr>>();
+      if addrs.is_empty() {
+        return Err(format!("{} doesn't resolve to a public address", name.as_str()).into());
+      }
+      Ok(Box::new(addrs.into_iter()) as Addrs)
+    })
+  }
+}
+
+/// Sends webhooks, following no redirects since those could lead anywhere
+#[derive(Clone)]
+pub struct WebhookClient(reqwest::Client);
+
+impl WebhookClient {
+  pub fn new(timeout: Duration) -> reqwest::Result<Self> {
+    Ok(WebhookClient(
+      reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()?,
+    ))
+  }
+
+  /// A `POST` to the url, which must not be a literal internal address
+  pub fn post(&self, url: &str) -> Result<reqwest::RequestBuilder, UrlError> {
+    let url = reqwest::Url::parse(url).map_err(|_| UrlError::NotHttp)?;
+    check_literal(&url)?;
+    Ok(self.0.post(url))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_is_public() {
+    for ip in [
+      "93.184.215.14",
+      "2606:4700::1111",
+      "::ffff:93.184.215.14",
+      "64:ff9b::5db8:d70e",
+    ] {
+      assert!(is_public(ip.parse().unwrap()), "{} is public", ip);
+    }
+    for ip in [
+      "127.0.0.1",
+      "10.1.2.3",
+      "172.16.0.1",
+      "192.168.1.1",
+      "169.254.169.254",
+      "100.100.100.200",
+      "0.0.0.0",
+      "255.255.255.255",
+      "::1",
+      "::",
+      "fd00:ec2::254",
+      "fe80::1",
+      "::ffff:127.0.0.1",
+      "::ffff:169.254.169.254",
+      "64:ff9b::7f00:1",
+    ] {
+      assert!(!is_public(ip.parse().unwrap()), "{} isn't public", ip);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn test_validate_url() {
+    assert_eq!(validate_url("ftp://example.com").await, Err(UrlError::NotHttp));
+    assert_eq!(validate_url("not a url").await, Err(UrlError::NotHttp));
+    assert_eq!(
+      validate_url("http://169.254.169.254/latest/meta-data").await,
+      Err(UrlError::NotPublic)
+    );
+    assert_eq!(validate_url("http://[::1]:8080/hook").await, Err(UrlError::NotPublic));
+    assert_eq!(validate_url("https://localhost/hook").await, Err(UrlError::NotPublic));
+    assert!(validate_url("https://93.184.215.14/hook").await.is_ok());
+  }
+
+  #[test]
+  fn test_post_rejects_literal_internal_addresses() {
+    let client = WebhookClient::new(Duration::from_secs(1)).unwrap();
+    assert!(client.post("http://127.0.0.1:8082/api/v1/users").is_err());
+    assert!(client.post("http://[::ffff:10.0.0.1]/").is_err());
+    assert!(client.post("https://example.com/hook").is_ok());
+  }
+}
@@ -0,0 +1,90 @@
+/*
+  Name: mod.rs
+
+  Description:
+  Outbound webhooks for partners. Handlers record events with `enqueue` inside the transaction making the change, so an
+  event is only ever sent for a change that was committed, and `Dispatcher` delivers them in the background.
+
+  Postconditions:
+  - Every file under the parent directory `./webhooks` should be exported
+    glob style here
+*/
+
+mod dispatcher;
+pub use dispatcher::*;
+mod guard;
+pub use guard::*;
+
+use crate::models::{NewWebhookDelivery, NewWebhookEvent};
+use crate::schema::*;
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+
+/// A product was created or updated by an import, the payload is the product as submitted
+pub const PRODUCT_UPSERTED: &str = "product.upserted";
+/// A price was reported, the payload is the report and the marketplace it was reported at
+pub const PRICE_REPORT_CREATED: &str = "price_report.created";
+/// A shopping list was created, changed or deleted, the payload is the `shopping_list_id` along with the `action`
+pub const SHOPPING_LIST_UPDATED: &str = "shopping_list.updated";
+
+pub const EVENT_TYPES: [&str; 3] = [PRODUCT_UPSERTED, PRICE_REPORT_CREATED, SHOPPING_LIST_UPDATED];
+
+/// Records one event per payload and queues a delivery of each to every active subscription of `event_type`
+pub fn enqueue<T: Serialize>(
+  conn: &mut PgConnection,
+  event_type: &str,
+  payloads: impl IntoIterator<Item = T>,
+) -> QueryResult<()> {
+  let subscription_ids = webhook_subscriptions::table
+    .filter(webhook_subscriptions::active.eq(true))
+    .filter(webhook_subscriptions::event_types.contains(vec![event_type]))
+    .select(webhook_subscriptions::id)
+    .load::<i32>(conn)?;
+  if subscription_ids.is_empty() {
+    return Ok(());
+  }
+
+  let events = payloads
+    .into_iter()
+    .map(|payload| {
+      Ok(NewWebhookEvent {
+        event_type,
+        payload: serde_json::to_value(payload).map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?,
+      })
+    })
+    .collect::<QueryResult<Vec<_>>>()?;
+  let event_ids = diesel::insert_into(webhook_events::table)
+    .values(events)
+    .returning(webhook_events::id)
+    .get_results::<i64>(conn)?;
+
+  let deliveries: Vec<_> = event_ids
+    .iter()
+    .flat_map(|&event_id| {
+      subscription_ids.iter().map(move |&subscription_id| NewWebhookDelivery {
+        event_id,
+        subscription_id,
+      })
+    })
+    .collect();
+  diesel::insert_into(webhook_deliveries::table)
+    .values(deliveries)
+    .execute(conn)?;
+  Ok(())
+}
+
+/// Hex encoded HMAC-SHA256 of `<timestamp>.<body>`, what receivers compare `X-GroceryWise-Signature` against
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+  let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+  mac.update(timestamp.to_string().as_bytes());
+  mac.update(b".");
+  mac.update(body);
+  mac
+    .finalize()
+    .into_bytes()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
@@ -76,6 +76,12 @@ pub mod errors {
     Unauthorized,
 
     Forbidden,
+
+    #[display("PayloadTooLarge: {}", _0)]
+    PayloadTooLarge(String),
+
+    #[display("UnsupportedMediaType: {}", _0)]
+    UnsupportedMediaType(String),
   }
 
   // impl ResponseError trait allows to convert our errors into http responses with appropriate data
@@ -90,6 +96,8 @@ pub mod errors {
         ServiceError::Unauthorized => HttpResponse::Unauthorized().json("Not allowed"),
         ServiceError::Forbidden => HttpResponse::Forbidden().json("Not allowed"),
         ServiceError::Conflict(ref msg) => HttpResponse::Conflict().json(msg),
+        ServiceError::PayloadTooLarge(ref msg) => HttpResponse::PayloadTooLarge().json(msg),
+        ServiceError::UnsupportedMediaType(ref msg) => HttpResponse::UnsupportedMediaType().json(msg),
       }
     }
   }
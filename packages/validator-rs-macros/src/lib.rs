@@ -0,0 +1,238 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+  parse::Parser, parse_macro_input, punctuated::Punctuated, spanned::Spanned, Error, FnArg, GenericArgument, ItemFn,
+  LitStr, Meta, PathArguments, Token, Type,
+};
+
+/// Emits `security(...)` entries on the `#[utoipa::path]` below it, derived from the handler's `Authorized<R>`
+/// argument. Must be placed above `#[utoipa::path]`.
+///
+/// `Or` branches become seperate security requirements, `And` branches are merged into a single requirement and `Not`
+/// branches are dropped since OpenAPI has no way to express them.
+///
+/// ```ignore
+/// #[openapi_security]
+/// #[utoipa::path(context_path = V1_PATH)]
+/// #[delete("/{gtin}")]
+/// async fn delete_product(claims: Authorized<Or<DeleteAll, DeleteProduct>>) -> impl Responder { .. }
+/// ```
+///
+/// emits `security(("http" = ["delete:all"]), ("http" = ["delete:product"]))`. Pass `scheme = "..."` to use a security
+/// scheme other than `http`.
+#[proc_macro_attribute]
+pub fn openapi_security(attr: TokenStream, item: TokenStream) -> TokenStream {
+  let args = parse_macro_input!(attr with Punctuated::<Meta, Token![,]>::parse_terminated);
+  let mut item = parse_macro_input!(item as ItemFn);
+
+  match expand(args, &mut item) {
+    Ok(assertions) => quote! {
+      #(#assertions)*
+      #item
+    }
+    .into(),
+    Err(err) => err.to_compile_error().into(),
+  }
+}
+
+fn expand(args: Punctuated<Meta, Token![,]>, item: &mut ItemFn) -> syn::Result<Vec<proc_macro2::TokenStream>> {
+  let mut scheme = LitStr::new("http", Span::call_site());
+  for arg in args {
+    match arg {
+      Meta::NameValue(nv) if nv.path.is_ident("scheme") => match nv.value {
+        syn::Expr::Lit(syn::ExprLit {
+          lit: syn::Lit::Str(lit),
+          ..
+        }) => scheme = lit,
+        other => return Err(Error::new(other.span(), "expected string literal")),
+      },
+      other => {
+        return Err(Error::new(
+          other.span(),
+          "unknown argument, expected `scheme = \"...\"`",
+        ))
+      }
+    }
+  }
+
+  let requirement = find_requirement(item)?;
+  let mut leaves = Vec::new();
+  let dnf = to_dnf(&requirement, &mut leaves)?;
+
+  let requirements = dnf.iter().map(|scopes| {
+    let scopes = scopes.iter().map(|s| LitStr::new(s, Span::call_site()));
+    quote! { (#scheme = [#(#scopes),*]) }
+  });
+  let security: Meta = syn::parse_quote! { security(#(#requirements),*) };
+
+  let path_attr = item
+    .attrs
+    .iter_mut()
+    .find(|attr| {
+      let segments: Vec<_> = attr.path().segments.iter().map(|s| s.ident.to_string()).collect();
+      segments == ["utoipa", "path"]
+    })
+    .ok_or_else(|| {
+      Error::new(
+        item.sig.ident.span(),
+        "#[openapi_security] must be placed above #[utoipa::path]",
+      )
+    })?;
+
+  let mut path_args = match &path_attr.meta {
+    Meta::List(list) => Punctuated::<Meta, Token![,]>::parse_terminated.parse2(list.tokens.clone())?,
+    _ => Punctuated::new(),
+  };
+  path_args = path_args
+    .into_iter()
+    .filter(|m| !m.path().is_ident("security"))
+    .collect();
+  path_args.push(security);
+  *path_attr = syn::parse_quote! { #[utoipa::path(#path_args)] };
+
+  // the emitted scope strings are derived from the type names, make sure they agree with what the leaves validate
+  Ok(
+    leaves
+      .into_iter()
+      .map(|(ty, scope)| {
+        let message = format!("scope of `{}` does not match `{}`", quote!(#ty), scope);
+        quote! {
+          const _: () = ::std::assert!(::validator_rs::__private::str_eq(<#ty>::SCOPE, #scope), #message);
+        }
+      })
+      .collect(),
+  )
+}
+
+fn find_requirement(item: &ItemFn) -> syn::Result<Type> {
+  item
+    .sig
+    .inputs
+    .iter()
+    .filter_map(|arg| match arg {
+      FnArg::Typed(pat) => Some(&*pat.ty),
+      FnArg::Receiver(_) => None,
+    })
+    .find_map(|ty| match (last_segment(ty), generics(ty)) {
+      (Some(ident), Some(generics)) if ident == "Authorized" => generics.into_iter().next(),
+      _ => None,
+    })
+    .ok_or_else(|| Error::new(item.sig.span(), "expected an `Authorized<...>` argument"))
+}
+
+fn last_segment(ty: &Type) -> Option<String> {
+  match ty {
+    Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+    _ => None,
+  }
+}
+
+fn generics(ty: &Type) -> Option<Vec<Type>> {
+  match ty {
+    Type::Path(path) => match &path.path.segments.last()?.arguments {
+      PathArguments::AngleBracketed(args) => Some(
+        args
+          .args
+          .iter()
+          .filter_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty.clone()),
+            _ => None,
+          })
+          .collect(),
+      ),
+      PathArguments::None => Some(vec![]),
+      PathArguments::Parenthesized(_) => None,
+    },
+    _ => None,
+  }
+}
+
+/// Flattens the requirement into disjunctive normal form, which is how OpenAPI security requirements are structured
+fn to_dnf(ty: &Type, leaves: &mut Vec<(Type, String)>) -> syn::Result<Vec<Vec<String>>> {
+  if let Type::Tuple(tuple) = ty {
+    if tuple.elems.is_empty() {
+      return Ok(vec![vec![]]);
+    }
+  }
+
+  let ident = last_segment(ty).ok_or_else(|| Error::new(ty.span(), "unsupported requirement"))?;
+  let args = generics(ty).unwrap_or_default();
+
+  match (ident.as_str(), args.as_slice()) {
+    ("Or", [a, b]) => {
+      let mut dnf = to_dnf(a, leaves)?;
+      dnf.extend(to_dnf(b, leaves)?);
+      Ok(dnf)
+    }
+    ("And", [a, b]) => {
+      let (a, b) = (to_dnf(a, leaves)?, to_dnf(b, leaves)?);
+      Ok(
+        a.iter()
+          .flat_map(|x| {
+            b.iter().map(move |y| {
+              let mut scopes = x.clone();
+              scopes.extend(y.iter().filter(|s| !x.contains(s)).cloned());
+              scopes
+            })
+          })
+          .collect(),
+      )
+    }
+    ("Not", [_]) => Ok(vec![vec![]]),
+    (_, []) => {
+      let scope = scope_name(&ident);
+      leaves.push((ty.clone(), scope.clone()));
+      Ok(vec![vec![scope]])
+    }
+    _ => Err(Error::new(
+      ty.span(),
+      "unsupported requirement, expected `And`, `Or`, `Not` or a leaf",
+    )),
+  }
+}
+
+/// `CreatePriceReport` -> `create:price_report`
+fn scope_name(ident: &str) -> String {
+  let mut words: Vec<String> = Vec::new();
+  for c in ident.chars() {
+    if c.is_uppercase() || words.is_empty() {
+      words.push(String::new());
+    }
+    words.last_mut().unwrap().extend(c.to_lowercase());
+  }
+  match words.split_first() {
+    Some((verb, [])) => verb.clone(),
+    Some((verb, rest)) => format!("{}:{}", verb, rest.join("_")),
+    None => String::new(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn dnf(ty: &str) -> Vec<Vec<String>> {
+    to_dnf(&syn::parse_str(ty).unwrap(), &mut vec![]).unwrap()
+  }
+
+  #[test]
+  fn test_scope_name() {
+    assert_eq!(scope_name("CreateAll"), "create:all");
+    assert_eq!(scope_name("CreatePriceReport"), "create:price_report");
+  }
+
+  #[test]
+  fn test_dnf() {
+    assert_eq!(
+      dnf("Or<CreateAll, CreateProduct>"),
+      [["create:all"], ["create:product"]]
+    );
+    assert_eq!(
+      dnf("And<ReadAll, Or<CreateAll, CreateProduct>>"),
+      [["read:all", "create:all"], ["read:all", "create:product"]]
+    );
+    assert_eq!(dnf("And<ReadAll, Not<DeleteAll>>"), [["read:all"]]);
+    assert_eq!(dnf("()"), [Vec::<String>::new()]);
+  }
+}
@@ -0,0 +1,243 @@
+//! actix-web integration, lets handlers declare their permission requirements in the type signature instead of
+//! repeating the decode/validate boilerplate.
+//!
+//! ```ignore
+//! #[get("")]
+//! async fn get_users(claims: Authorized<Or<ReadAll, ReadUser>>) -> impl Responder {
+//!   format!("hello {}", claims.sub)
+//! }
+//! ```
+
+use crate::{Scope, ValidatorBuilder};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use std::fmt::Debug;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::pin::Pin;
+
+/// A type level permission requirement, see [`requirements!`](crate::requirements) for declaring the leaves.
+pub trait Requirement<T> {
+  fn scope() -> Scope<T>;
+}
+
+/// Always satisfied, useful for endpoints that only need a valid identity
+impl<T> Requirement<T> for () {
+  fn scope() -> Scope<T> {
+    Scope::And(vec![])
+  }
+}
+
+pub struct And<A, B>(PhantomData<(A, B)>);
+
+impl<T, A: Requirement<T>, B: Requirement<T>> Requirement<T> for And<A, B> {
+  fn scope() -> Scope<T> {
+    Scope::And(vec![A::scope(), B::scope()])
+  }
+}
+
+pub struct Or<A, B>(PhantomData<(A, B)>);
+
+impl<T, A: Requirement<T>, B: Requirement<T>> Requirement<T> for Or<A, B> {
+  fn scope() -> Scope<T> {
+    Scope::Or(vec![A::scope(), B::scope()])
+  }
+}
+
+pub struct Not<A>(PhantomData<A>);
+
+impl<T, A: Requirement<T>> Requirement<T> for Not<A> {
+  fn scope() -> Scope<T> {
+    Scope::Not(Box::new(A::scope()))
+  }
+}
+
+/// Resolves the caller's identity from a request, implemented by whichever service issues the tokens
+pub trait Authenticator {
+  type Claims;
+  type Permission: PartialEq + Clone + Debug;
+  /// Resolves to the claims, so lookups such as a database query don't block the worker thread
+  type Future: Future<Output = Result<Self::Claims, actix_web::Error>>;
+
+  fn authenticate(req: &HttpRequest) -> Self::Future;
+
+  fn permissions(claims: &Self::Claims) -> &[Self::Permission];
+}
+
+/// Extractor that authenticates the request with `A` then validates the claims against `R`
+pub struct Authorized<R, A: Authenticator> {
+  claims: A::Claims,
+  _requirement: PhantomData<R>,
+}
+
+impl<R, A: Authenticator> Authorized<R, A> {
+  pub fn into_inner(self) -> A::Claims {
+    self.claims
+  }
+}
+
+impl<R, A: Authenticator> Deref for Authorized<R, A> {
+  type Target = A::Claims;
+
+  fn deref(&self) -> &Self::Target {
+    &self.claims
+  }
+}
+
+impl<R, A> FromRequest for Authorized<R, A>
+where
+  R: Requirement<A::Permission> + 'static,
+  A: Authenticator + 'static,
+  A::Future: 'static,
+{
+  type Error = actix_web::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+  fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+    let claims = A::authenticate(req);
+    Box::pin(async move {
+      let claims = claims.await?;
+      ValidatorBuilder::<A::Permission>::new()
+        .with_scope(R::scope())
+        .validate::<A::Permission>(A::permissions(&claims))
+        .map_err(Into::into)
+        .map(|_| Authorized {
+          claims,
+          _requirement: PhantomData,
+        })
+    })
+  }
+}
+
+/// Declares unit structs usable as [`Requirement`] leaves.
+///
+/// ```ignore
+/// validator_rs::requirements! {
+///   PermissionName;
+///   CreateAll => PermissionName::CreateAll, "create:all";
+/// }
+/// ```
+///
+/// The scope string is what gets emitted into OpenAPI docs by `#[openapi_security]`, it must match the name derived
+/// from the struct (`CreateAll` -> `create:all`), which is checked at compile time.
+#[macro_export]
+macro_rules! requirements {
+  ($permission:ty; $($(#[$meta:meta])* $name:ident => $value:expr, $scope:literal;)*) => {
+    $(
+      $(#[$meta])*
+      pub struct $name;
+
+      #[allow(dead_code)]
+      impl $name {
+        pub const SCOPE: &'static str = $scope;
+      }
+
+      impl $crate::actix::Requirement<$permission> for $name {
+        fn scope() -> $crate::Scope<$permission> {
+          $crate::Scope::Value($value)
+        }
+      }
+    )*
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::test::TestRequest;
+  use std::future::{ready, Ready};
+
+  #[derive(Clone, PartialEq, Debug)]
+  enum PermissionName {
+    CreateAll,
+    CreateProduct,
+    ReadAll,
+  }
+
+  crate::requirements! {
+    PermissionName;
+    CreateAll => PermissionName::CreateAll, "create:all";
+    CreateProduct => PermissionName::CreateProduct, "create:product";
+    ReadAll => PermissionName::ReadAll, "read:all";
+  }
+
+  struct HeaderAuthenticator;
+
+  // permissions are passed as a comma seperated header to keep the tests free of any token handling
+  impl Authenticator for HeaderAuthenticator {
+    type Claims = Vec<PermissionName>;
+    type Permission = PermissionName;
+    type Future = Ready<Result<Self::Claims, actix_web::Error>>;
+
+    fn authenticate(req: &HttpRequest) -> Self::Future {
+      ready(
+        req
+          .headers()
+          .get("x-permissions")
+          .ok_or_else(|| actix_web::error::ErrorBadRequest("Invalid token"))
+          .map(|header| {
+            header
+              .to_str()
+              .unwrap_or_default()
+              .split(',')
+              .filter_map(|p| match p {
+                "create:all" => Some(PermissionName::CreateAll),
+                "create:product" => Some(PermissionName::CreateProduct),
+                "read:all" => Some(PermissionName::ReadAll),
+                _ => None,
+              })
+              .collect()
+          }),
+      )
+    }
+
+    fn permissions(claims: &Self::Claims) -> &[Self::Permission] {
+      claims
+    }
+  }
+
+  async fn extract<R: Requirement<PermissionName> + 'static>(
+    permissions: Option<&str>,
+  ) -> Result<Authorized<R, HeaderAuthenticator>, actix_web::Error> {
+    let mut req = TestRequest::default();
+    if let Some(permissions) = permissions {
+      req = req.insert_header(("x-permissions", permissions));
+    }
+    let (req, mut payload) = req.to_http_parts();
+    Authorized::<R, HeaderAuthenticator>::from_request(&req, &mut payload).await
+  }
+
+  #[actix_web::test]
+  async fn test_authorized_or() {
+    assert!(extract::<Or<CreateAll, CreateProduct>>(Some("create:product"))
+      .await
+      .is_ok());
+    assert!(extract::<Or<CreateAll, CreateProduct>>(Some("create:all"))
+      .await
+      .is_ok());
+    assert!(extract::<Or<CreateAll, CreateProduct>>(Some("read:all")).await.is_err());
+  }
+
+  #[actix_web::test]
+  async fn test_authorized_and_not() {
+    assert!(extract::<And<ReadAll, Not<CreateAll>>>(Some("read:all")).await.is_ok());
+    assert!(extract::<And<ReadAll, Not<CreateAll>>>(Some("read:all,create:all"))
+      .await
+      .is_err());
+  }
+
+  #[actix_web::test]
+  async fn test_authorized_injects_claims() {
+    let claims = extract::<()>(Some("read:all")).await.ok().unwrap();
+    assert_eq!(*claims, vec![PermissionName::ReadAll]);
+  }
+
+  #[actix_web::test]
+  async fn test_authorized_status_codes() {
+    let err = extract::<ReadAll>(None).await.err().unwrap();
+    assert_eq!(err.error_response().status(), 400);
+
+    let err = extract::<ReadAll>(Some("create:all")).await.err().unwrap();
+    assert_eq!(err.error_response().status(), 401);
+  }
+}
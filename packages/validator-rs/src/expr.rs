@@ -0,0 +1,204 @@
+//! Textual form of [`Scope`], so policies can live in the database or configuration instead of in code.
+//!
+//! ```text
+//! expr    := or
+//! or      := and ("|" and)*
+//! and     := unary ("&" unary)*
+//! unary   := "!" unary | primary
+//! primary := "(" expr ")" | "()" | permission
+//! ```
+//!
+//! `&` binds tighter than `|`, so `create:all | create:product & !banned` reads as
+//! `create:all | (create:product & !banned)`. A permission is any run of characters other than whitespace and
+//! `()|&!`, and is converted with `T`'s [`FromStr`]. `()` is the empty requirement, which is always satisfied.
+//!
+//! Any scope returned by the parser formats back to a string that parses into the same scope. Scopes built in code
+//! format to an equivalent expression, though single element groups are flattened and an empty `Or` (never satisfied)
+//! is written as `!()`.
+
+use crate::{Scope, RECURSION_LIMIT};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("{message} at position {position}")]
+pub struct ParseError {
+  pub position: usize,
+  pub message: String,
+}
+
+impl<T: FromStr> FromStr for Scope<T> {
+  type Err = ParseError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parser = Parser { input: s, position: 0 };
+    let scope = parser.parse_or(0)?;
+    parser.skip_whitespace();
+    match parser.peek() {
+      None => Ok(scope),
+      Some(c) => Err(parser.error(format!("Unexpected `{}`", c))),
+    }
+  }
+}
+
+struct Parser<'a> {
+  input: &'a str,
+  position: usize,
+}
+
+impl Parser<'_> {
+  fn error(&self, message: String) -> ParseError {
+    ParseError {
+      position: self.position,
+      message,
+    }
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.input[self.position..].chars().next()
+  }
+
+  fn skip_whitespace(&mut self) {
+    while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+      self.position += c.len_utf8();
+    }
+  }
+
+  fn eat(&mut self, expected: char) -> bool {
+    self.skip_whitespace();
+    if self.peek() == Some(expected) {
+      self.position += expected.len_utf8();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn check_depth(&self, depth: usize) -> Result<(), ParseError> {
+    if depth > RECURSION_LIMIT {
+      Err(self.error("Recursion Depth Exceeded".to_string()))
+    } else {
+      Ok(())
+    }
+  }
+
+  fn parse_or<T: FromStr>(&mut self, depth: usize) -> Result<Scope<T>, ParseError> {
+    self.check_depth(depth)?;
+    let mut scopes = vec![self.parse_and(depth + 1)?];
+    while self.eat('|') {
+      scopes.push(self.parse_and(depth + 1)?);
+    }
+    Ok(match scopes.len() {
+      1 => scopes.remove(0),
+      _ => Scope::Or(scopes),
+    })
+  }
+
+  fn parse_and<T: FromStr>(&mut self, depth: usize) -> Result<Scope<T>, ParseError> {
+    self.check_depth(depth)?;
+    let mut scopes = vec![self.parse_unary(depth + 1)?];
+    while self.eat('&') {
+      scopes.push(self.parse_unary(depth + 1)?);
+    }
+    Ok(match scopes.len() {
+      1 => scopes.remove(0),
+      _ => Scope::And(scopes),
+    })
+  }
+
+  fn parse_unary<T: FromStr>(&mut self, depth: usize) -> Result<Scope<T>, ParseError> {
+    self.check_depth(depth)?;
+    if self.eat('!') {
+      return Ok(Scope::Not(Box::new(self.parse_unary(depth + 1)?)));
+    }
+    if self.eat('(') {
+      if self.eat(')') {
+        return Ok(Scope::And(vec![]));
+      }
+      let scope = self.parse_or(depth + 1)?;
+      if !self.eat(')') {
+        return Err(self.error("Expected `)`".to_string()));
+      }
+      return Ok(scope);
+    }
+    self.parse_permission()
+  }
+
+  fn parse_permission<T: FromStr>(&mut self) -> Result<Scope<T>, ParseError> {
+    self.skip_whitespace();
+    let start = self.position;
+    let len = self.input[start..]
+      .find(|c: char| c.is_whitespace() || "()|&!".contains(c))
+      .unwrap_or(self.input.len() - start);
+    if len == 0 {
+      return Err(match self.peek() {
+        Some(c) => self.error(format!("Unexpected `{}`", c)),
+        None => self.error("Unexpected end of input".to_string()),
+      });
+    }
+
+    let name = &self.input[start..start + len];
+    let value = name.parse().map_err(|_| ParseError {
+      position: start,
+      message: format!("Unknown permission `{}`", name),
+    })?;
+    self.position += len;
+    Ok(Scope::Value(value))
+  }
+}
+
+impl<T: Display> Display for Scope<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Scope::Value(value) => write!(f, "{}", value),
+      Scope::And(scopes) if scopes.is_empty() => write!(f, "()"),
+      Scope::Or(scopes) if scopes.is_empty() => write!(f, "!()"),
+      Scope::And(scopes) => write_joined(
+        f,
+        scopes,
+        " & ",
+        |s| matches!(s, Scope::And(v) | Scope::Or(v) if v.len() > 1),
+      ),
+      Scope::Or(scopes) => write_joined(f, scopes, " | ", |s| matches!(s, Scope::Or(v) if v.len() > 1)),
+      Scope::Not(scope) => match **scope {
+        Scope::And(ref v) | Scope::Or(ref v) if v.len() > 1 => write!(f, "!({})", scope),
+        _ => write!(f, "!{}", scope),
+      },
+    }
+  }
+}
+
+fn write_joined<T: Display>(
+  f: &mut fmt::Formatter<'_>,
+  scopes: &[Scope<T>],
+  separator: &str,
+  needs_parens: impl Fn(&Scope<T>) -> bool,
+) -> fmt::Result {
+  for (i, scope) in scopes.iter().enumerate() {
+    if i > 0 {
+      f.write_str(separator)?;
+    }
+    if needs_parens(scope) {
+      write!(f, "({})", scope)?;
+    } else {
+      write!(f, "{}", scope)?;
+    }
+  }
+  Ok(())
+}
+
+#[cfg(feature = "serde")]
+impl<T: Display> serde::Serialize for Scope<T> {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: FromStr> serde::Deserialize<'de> for Scope<T> {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(serde::de::Error::custom)
+  }
+}
@@ -1,6 +1,33 @@
 use std::fmt::Debug;
+use trace::{Trace, TraceNode};
 
-#[derive(Clone)]
+#[cfg(feature = "actix-web")]
+pub mod actix;
+pub mod expr;
+pub mod trace;
+
+#[cfg(feature = "macros")]
+pub use validator_rs_macros::openapi_security;
+
+#[doc(hidden)]
+pub mod __private {
+  pub const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+      return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+      if a[i] != b[i] {
+        return false;
+      }
+      i += 1;
+    }
+    true
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Scope<T> {
   Value(T),
   And(Vec<Scope<T>>),
@@ -26,6 +53,10 @@ pub enum ValidatorError {
   Unauthorized(String),
   #[error("Recursion Depth Exceeded")]
   RecursionDepthExceeded,
+  /// Returned instead of `Unauthorized` in debug builds, carries the evaluation trace with permissions rendered by
+  /// their `Debug` implementation
+  #[error("Unauthorized\n{}", ._0)]
+  Denied(Trace<String>),
 }
 
 #[cfg(feature = "actix-web")]
@@ -34,6 +65,7 @@ impl actix_web::error::ResponseError for ValidatorError {
     use actix_web::{http::StatusCode, HttpResponse};
     match self {
       ValidatorError::Unauthorized(msg) => HttpResponse::build(StatusCode::UNAUTHORIZED).body(msg.clone()),
+      ValidatorError::Denied(trace) => HttpResponse::build(StatusCode::UNAUTHORIZED).json(trace),
       ValidatorError::RecursionDepthExceeded => HttpResponse::InternalServerError().json("Internal Server Error"),
     }
   }
@@ -108,42 +140,82 @@ impl<T: PartialEq + Clone + Debug> ValidatorBuilder<T> {
     self
   }
 
+  #[allow(clippy::ptr_arg)]
   fn check_requirements(&self, permissions: &Vec<T>) -> Result<bool, ValidatorError> {
+    self.trace_requirements(permissions).map(|trace| trace.passed)
+  }
+
+  fn trace_requirements(&self, permissions: &[T]) -> Result<Trace<T>, ValidatorError> {
     match &self.required_scopes {
-      Some(req) => self.check_requirement(req, permissions, 0),
-      None => Ok(true),
+      Some(req) => self.trace_requirement(req, permissions, 0),
+      None => Ok(Trace {
+        passed: true,
+        node: TraceNode::And(vec![]),
+      }),
     }
   }
 
   #[allow(clippy::only_used_in_recursion)]
-  fn check_requirement(
+  fn trace_requirement(
     &self,
     requirement: &Scope<T>,
-    permissions: &Vec<T>,
+    permissions: &[T],
     depth: usize,
-  ) -> Result<bool, ValidatorError> {
+  ) -> Result<Trace<T>, ValidatorError> {
     if depth > RECURSION_LIMIT {
       return Err(ValidatorError::RecursionDepthExceeded);
     }
 
-    match requirement {
-      Scope::Value(value) => Ok(permissions.contains(value)),
-      Scope::And(requirements) => requirements.iter().try_fold(true, |acc, r| {
-        self.check_requirement(r, permissions, depth + 1).map(|val| acc && val)
-      }),
-      Scope::Or(requirements) => requirements.iter().try_fold(false, |acc, r| {
-        self.check_requirement(r, permissions, depth + 1).map(|val| acc || val)
-      }),
-      Scope::Not(requirement) => self
-        .check_requirement(requirement, permissions, depth + 1)
-        .map(|val| !val),
-    }
+    let trace_all = |requirements: &[Scope<T>]| {
+      requirements
+        .iter()
+        .map(|r| self.trace_requirement(r, permissions, depth + 1))
+        .collect::<Result<Vec<_>, _>>()
+    };
+
+    Ok(match requirement {
+      Scope::Value(value) => Trace {
+        passed: permissions.contains(value),
+        node: TraceNode::Value(value.clone()),
+      },
+      Scope::And(requirements) => {
+        let traces = trace_all(requirements)?;
+        Trace {
+          passed: traces.iter().all(|t| t.passed),
+          node: TraceNode::And(traces),
+        }
+      }
+      Scope::Or(requirements) => {
+        let traces = trace_all(requirements)?;
+        Trace {
+          passed: traces.iter().any(|t| t.passed),
+          node: TraceNode::Or(traces),
+        }
+      }
+      Scope::Not(requirement) => {
+        let trace = self.trace_requirement(requirement, permissions, depth + 1)?;
+        Trace {
+          passed: !trace.passed,
+          node: TraceNode::Not(Box::new(trace)),
+        }
+      }
+    })
+  }
+
+  /// Evaluates every branch of the requirements against `permissions`, unlike [`validate`](Self::validate) this
+  /// reports which branches passed and failed rather than a single verdict
+  pub fn explain<U: Into<T> + Clone>(&self, permissions: &[U]) -> Result<Trace<T>, ValidatorError> {
+    let converted_permissions: Vec<T> = permissions.iter().map(|p| p.clone().into()).collect();
+    self.trace_requirements(&converted_permissions)
   }
 
   pub fn validate<U: Into<T> + Clone>(self, permissions: &[U]) -> Result<(), ValidatorError> {
     let converted_permissions: Vec<T> = permissions.iter().map(|p| p.clone().into()).collect();
     if self.check_requirements(&converted_permissions)? {
       Ok(())
+    } else if cfg!(debug_assertions) {
+      let trace = self.trace_requirements(&converted_permissions)?;
+      Err(ValidatorError::Denied(trace.map(&|p| format!("{:?}", p))))
     } else {
       Err(ValidatorError::Unauthorized("Unauthorized".to_string()))
     }
@@ -237,8 +309,6 @@ mod tests {
     );
   }
 
-  // LOL, hackathon moment
-  #[ignore]
   #[test]
   #[cfg(debug_assertions)]
   fn test_debug_error_messages() {
@@ -248,20 +318,37 @@ mod tests {
     let user_permissions = vec![PermissionName::ReadAll];
 
     let result = builder.validate(&user_permissions);
-    assert!(matches!(result, Err(ValidatorError::Unauthorized(_))));
-    if let Err(ValidatorError::Unauthorized(msg)) = result {
-      print!("{}", msg);
-      assert!(msg.contains("Missing required permission:"));
+    assert!(matches!(result, Err(ValidatorError::Denied(_))));
+    if let Err(ValidatorError::Denied(trace)) = result {
+      assert_eq!(trace.failures(), vec!["CreateAll"]);
+      assert_eq!(
+        trace.to_string(),
+        "[fail] all of\n  [pass] ReadAll\n  [fail] CreateAll\n"
+      );
     }
+  }
 
-    let builder: ValidatorBuilder<PermissionName> = ValidatorBuilder::new().with_scope(PermissionName::ReadAll);
-    let user_permissions = vec![PermissionName::ReadAll, PermissionName::CreateAll];
+  #[test]
+  fn test_explain() {
+    let scope: Scope<PermissionName> = "create:all | read:all & !delete:all".parse().unwrap();
+    let builder: ValidatorBuilder<PermissionName> = ValidatorBuilder::new().with_scope(scope);
+
+    let trace = builder
+      .explain(&[PermissionName::ReadAll, PermissionName::DeleteAll])
+      .unwrap();
+    assert!(!trace.passed);
+    assert_eq!(
+      trace.failures(),
+      vec![&PermissionName::CreateAll, &PermissionName::DeleteAll]
+    );
+    assert_eq!(
+      trace.to_string(),
+      "[fail] any of\n  [fail] create:all\n  [fail] all of\n    [pass] read:all\n    [fail] none of\n      [pass] delete:all\n"
+    );
 
-    let result = builder.validate(&user_permissions);
-    assert!(matches!(result, Err(ValidatorError::Unauthorized(_))));
-    if let Err(ValidatorError::Unauthorized(msg)) = result {
-      assert!(msg.contains("Extra permission not expected:"));
-    }
+    let trace = builder.explain(&[PermissionName::ReadAll]).unwrap();
+    assert!(trace.passed);
+    assert!(trace.failures().is_empty());
   }
 
   #[test]
@@ -305,4 +392,97 @@ mod tests {
     print!("{:?}", result);
     assert!(matches!(result, Err(ValidatorError::RecursionDepthExceeded)));
   }
+
+  impl std::str::FromStr for PermissionName {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+      match s {
+        "create:all" => Ok(PermissionName::CreateAll),
+        "read:all" => Ok(PermissionName::ReadAll),
+        "update:all" => Ok(PermissionName::UpdateAll),
+        "delete:all" => Ok(PermissionName::DeleteAll),
+        _ => Err(()),
+      }
+    }
+  }
+
+  impl std::fmt::Display for PermissionName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      f.write_str(match self {
+        PermissionName::CreateAll => "create:all",
+        PermissionName::ReadAll => "read:all",
+        PermissionName::UpdateAll => "update:all",
+        PermissionName::DeleteAll => "delete:all",
+      })
+    }
+  }
+
+  #[test]
+  fn test_parse_expression() {
+    let scope: Scope<PermissionName> = "create:all | (read:all & !delete:all)".parse().unwrap();
+    assert_eq!(
+      scope,
+      Scope::Or(vec![
+        Scope::Value(PermissionName::CreateAll),
+        Scope::And(vec![
+          Scope::Value(PermissionName::ReadAll),
+          Scope::Not(Box::new(Scope::Value(PermissionName::DeleteAll))),
+        ]),
+      ])
+    );
+
+    // & binds tighter than |
+    let unparenthesized: Scope<PermissionName> = "create:all | read:all & !delete:all".parse().unwrap();
+    assert_eq!(scope, unparenthesized);
+
+    let builder: ValidatorBuilder<PermissionName> = ValidatorBuilder::new().with_scope(scope);
+    assert_eq!(builder.check_requirements(&vec![PermissionName::ReadAll]), Ok(true));
+    assert_eq!(
+      builder.check_requirements(&vec![PermissionName::ReadAll, PermissionName::DeleteAll]),
+      Ok(false)
+    );
+  }
+
+  #[test]
+  fn test_expression_round_trip() {
+    for expr in [
+      "create:all",
+      "()",
+      "!!read:all",
+      "create:all | read:all & !delete:all",
+      "(create:all | read:all) & !(update:all & delete:all)",
+      "(create:all | read:all) | update:all",
+      "read:all & (create:all & update:all)",
+    ] {
+      let scope: Scope<PermissionName> = expr.parse().unwrap();
+      assert_eq!(scope.to_string(), expr);
+      assert_eq!(scope.to_string().parse::<Scope<PermissionName>>(), Ok(scope));
+    }
+
+    let scope: Scope<PermissionName> = Scope::Or(vec![]);
+    assert_eq!(scope.to_string(), "!()");
+  }
+
+  #[test]
+  fn test_parse_expression_errors() {
+    let parse = |s: &str| s.parse::<Scope<PermissionName>>().unwrap_err();
+    assert_eq!(parse("create:all |").message, "Unexpected end of input");
+    assert_eq!(parse("create:all | banned").position, 13);
+    assert_eq!(parse("create:all | banned").message, "Unknown permission `banned`");
+    assert_eq!(parse("(create:all").message, "Expected `)`");
+    assert_eq!(parse("create:all)").message, "Unexpected `)`");
+    assert_eq!(parse(&"(".repeat(RECURSION_LIMIT)).message, "Recursion Depth Exceeded");
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_expression_serde() {
+    use serde::de::value::{Error, StrDeserializer};
+    use serde::Deserialize;
+
+    let scope = Scope::<PermissionName>::deserialize(StrDeserializer::<Error>::new("read:all & !delete:all")).unwrap();
+    assert_eq!(scope.to_string(), "read:all & !delete:all");
+    assert!(Scope::<PermissionName>::deserialize(StrDeserializer::<Error>::new("read:all &")).is_err());
+  }
 }
@@ -0,0 +1,86 @@
+//! Evaluation traces, records which branches of a [`Scope`](crate::Scope) passed or failed for a permission set.
+
+use std::fmt::{self, Display};
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Trace<T> {
+  pub passed: bool,
+  #[cfg_attr(feature = "serde", serde(flatten))]
+  pub node: TraceNode<T>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum TraceNode<T> {
+  Value(T),
+  And(Vec<Trace<T>>),
+  Or(Vec<Trace<T>>),
+  Not(Box<Trace<T>>),
+}
+
+impl<T> Trace<T> {
+  pub fn map<U>(self, f: &impl Fn(T) -> U) -> Trace<U> {
+    let node = match self.node {
+      TraceNode::Value(value) => TraceNode::Value(f(value)),
+      TraceNode::And(traces) => TraceNode::And(traces.into_iter().map(|t| t.map(f)).collect()),
+      TraceNode::Or(traces) => TraceNode::Or(traces.into_iter().map(|t| t.map(f)).collect()),
+      TraceNode::Not(trace) => TraceNode::Not(Box::new(trace.map(f))),
+    };
+    Trace {
+      passed: self.passed,
+      node,
+    }
+  }
+
+  /// Permissions that were required but missing, or present but forbidden by a `Not`
+  pub fn failures(&self) -> Vec<&T> {
+    let mut failures = Vec::new();
+    if !self.passed {
+      self.collect_failures(true, &mut failures);
+    }
+    failures
+  }
+
+  fn collect_failures<'a>(&'a self, expected: bool, failures: &mut Vec<&'a T>) {
+    match &self.node {
+      TraceNode::Value(value) => failures.push(value),
+      TraceNode::And(traces) | TraceNode::Or(traces) => traces
+        .iter()
+        .filter(|t| t.passed != expected)
+        .for_each(|t| t.collect_failures(expected, failures)),
+      TraceNode::Not(trace) => trace.collect_failures(!expected, failures),
+    }
+  }
+
+  fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result
+  where
+    T: Display,
+  {
+    let mark = if self.passed { "pass" } else { "fail" };
+    let children = match &self.node {
+      TraceNode::Value(value) => return writeln!(f, "{:indent$}[{}] {}", "", mark, value, indent = depth * 2),
+      TraceNode::And(traces) => {
+        writeln!(f, "{:indent$}[{}] all of", "", mark, indent = depth * 2)?;
+        traces.iter().collect::<Vec<_>>()
+      }
+      TraceNode::Or(traces) => {
+        writeln!(f, "{:indent$}[{}] any of", "", mark, indent = depth * 2)?;
+        traces.iter().collect()
+      }
+      TraceNode::Not(trace) => {
+        writeln!(f, "{:indent$}[{}] none of", "", mark, indent = depth * 2)?;
+        vec![&**trace]
+      }
+    };
+    children.into_iter().try_for_each(|t| t.fmt_indented(f, depth + 1))
+  }
+}
+
+/// Renders the trace as an indented tree, one line per branch
+impl<T: Display> Display for Trace<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.fmt_indented(f, 0)
+  }
+}